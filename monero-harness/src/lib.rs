@@ -28,7 +28,7 @@ use crate::image::{
 use anyhow::{anyhow, bail, Result};
 use monero_rpc::{
     monerod,
-    wallet::{self, GetAddress, Refreshed, Transfer},
+    wallet::{self, GetAddress, Refreshed, Transfer, TransferPriority},
 };
 use std::time::Duration;
 use testcontainers::{clients::Cli, core::Port, Container, Docker, RunArgs};
@@ -262,7 +262,9 @@ impl<'c> MoneroWalletRpc {
 
     /// Sends amount to address
     pub async fn transfer(&self, address: &str, amount: u64) -> Result<Transfer> {
-        self.client().transfer(0, amount, address).await
+        self.client()
+            .transfer(0, amount, address, TransferPriority::Default)
+            .await
     }
 
     pub async fn address(&self) -> Result<GetAddress> {
@@ -12,6 +12,13 @@ pub struct Client {
 }
 
 impl Client {
+    pub fn new(url: Url) -> Self {
+        Self {
+            inner: reqwest::Client::new(),
+            url,
+        }
+    }
+
     /// New local host monerod RPC client.
     pub fn localhost(port: u16) -> Self {
         let url = format!("http://127.0.0.1:{}/json_rpc", port);
@@ -1,7 +1,8 @@
 use crate::rpc::{Request, Response};
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use reqwest::Url;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{fmt, str::FromStr};
 use tracing::debug;
 
 /// JSON RPC client for monero-wallet-rpc.
@@ -48,6 +49,29 @@ impl Client {
         Ok(r.result)
     }
 
+    /// Create a new subaddress of the account by index.
+    pub async fn create_address(&self, account_index: u32, label: &str) -> Result<CreateAddress> {
+        let params = CreateAddressParams {
+            account_index,
+            label: label.to_owned(),
+        };
+        let request = Request::new("create_address", params);
+
+        let response = self
+            .inner
+            .post(self.url.clone())
+            .json(&request)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        debug!("create address RPC response: {}", response);
+
+        let r = serde_json::from_str::<Response<CreateAddress>>(&response)?;
+        Ok(r.result)
+    }
+
     /// Gets the balance of account by index.
     pub async fn get_balance(&self, index: u32) -> Result<u64> {
         let params = GetBalanceParams {
@@ -201,12 +225,13 @@ impl Client {
         account_index: u32,
         amount: u64,
         address: &str,
+        priority: TransferPriority,
     ) -> Result<Transfer> {
         let dest = vec![Destination {
             amount,
             address: address.to_owned(),
         }];
-        self.multi_transfer(account_index, dest).await
+        self.multi_transfer(account_index, dest, priority).await
     }
 
     /// Transfers moneroj from `account_index` to `destinations`.
@@ -214,11 +239,13 @@ impl Client {
         &self,
         account_index: u32,
         destinations: Vec<Destination>,
+        priority: TransferPriority,
     ) -> Result<Transfer> {
         let params = TransferParams {
             account_index,
             destinations,
             get_tx_key: true,
+            priority,
         };
         let request = Request::new("transfer", params);
 
@@ -287,6 +314,7 @@ impl Client {
 
     pub async fn generate_from_keys(
         &self,
+        filename: &str,
         address: &str,
         spend_key: &str,
         view_key: &str,
@@ -294,7 +322,7 @@ impl Client {
     ) -> Result<GenerateFromKeys> {
         let params = GenerateFromKeysParams {
             restore_height,
-            filename: view_key.into(),
+            filename: filename.into(),
             address: address.into(),
             spendkey: spend_key.into(),
             viewkey: view_key.into(),
@@ -336,10 +364,31 @@ impl Client {
         Ok(r.result)
     }
 
+    /// Get the incoming transfers of the wallet.
+    pub async fn get_incoming_transfers(&self) -> Result<Vec<TransferEntry>> {
+        let params = GetTransfersParams { incoming: true };
+        let request = Request::new("get_transfers", params);
+
+        let response = self
+            .inner
+            .post(self.url.clone())
+            .json(&request)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        debug!("get_transfers RPC response: {}", response);
+
+        let r = serde_json::from_str::<Response<GetTransfers>>(&response)?;
+        Ok(r.result.incoming)
+    }
+
     /// Transfers the complete balance of the account to `address`.
-    pub async fn sweep_all(&self, address: &str) -> Result<SweepAll> {
+    pub async fn sweep_all(&self, address: &str, priority: TransferPriority) -> Result<SweepAll> {
         let params = SweepAllParams {
             address: address.into(),
+            priority,
         };
         let request = Request::new("sweep_all", params);
 
@@ -367,6 +416,30 @@ struct GetAddressParams {
 #[derive(Deserialize, Debug, Clone)]
 pub struct GetAddress {
     pub address: String,
+    #[serde(default)]
+    pub addresses: Vec<SubaddressInfo>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubaddressInfo {
+    pub address: String,
+    pub address_index: u32,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub used: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct CreateAddressParams {
+    account_index: u32,
+    label: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CreateAddress {
+    pub address: String,
+    pub address_index: u32,
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -435,6 +508,68 @@ struct TransferParams {
     destinations: Vec<Destination>,
     // Return the transaction key after sending.
     get_tx_key: bool,
+    // Priority of the transaction, determines the fee.
+    priority: TransferPriority,
+}
+
+/// The priority of a transaction, higher priorities pay higher fees to be
+/// confirmed faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferPriority {
+    /// Let the wallet choose the priority.
+    Default,
+    Unimportant,
+    Normal,
+    Elevated,
+}
+
+impl TransferPriority {
+    /// The value monero-wallet-rpc expects for this priority.
+    pub fn as_u32(self) -> u32 {
+        match self {
+            TransferPriority::Default => 0,
+            TransferPriority::Unimportant => 1,
+            TransferPriority::Normal => 2,
+            TransferPriority::Elevated => 3,
+        }
+    }
+}
+
+impl Serialize for TransferPriority {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u32(self.as_u32())
+    }
+}
+
+impl FromStr for TransferPriority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "default" | "0" => Ok(TransferPriority::Default),
+            "unimportant" | "1" => Ok(TransferPriority::Unimportant),
+            "normal" | "2" => Ok(TransferPriority::Normal),
+            "elevated" | "3" => Ok(TransferPriority::Elevated),
+            _ => Err(anyhow!(
+                "Unknown transfer priority {}, expected one of default, unimportant, normal or elevated",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TransferPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferPriority::Default => write!(f, "default"),
+            TransferPriority::Unimportant => write!(f, "unimportant"),
+            TransferPriority::Normal => write!(f, "normal"),
+            TransferPriority::Elevated => write!(f, "elevated"),
+        }
+    }
 }
 
 #[derive(Serialize, Debug, Clone)]
@@ -500,22 +635,69 @@ pub struct Refreshed {
 #[derive(Debug, Clone, Serialize)]
 pub struct SweepAllParams {
     pub address: String,
+    pub priority: TransferPriority,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SweepAll {
     amount_list: Vec<u64>,
-    fee_list: Vec<u64>,
+    pub fee_list: Vec<u64>,
     multisig_txset: String,
     pub tx_hash_list: Vec<String>,
     unsigned_txset: String,
     weight_list: Vec<u32>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct GetTransfersParams {
+    #[serde(rename = "in")]
+    incoming: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GetTransfers {
+    #[serde(rename = "in", default)]
+    incoming: Vec<TransferEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TransferEntry {
+    pub txid: String,
+    pub amount: u64,
+    pub confirmations: u32,
+    pub height: u32,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn priority_is_serialized_as_rpc_value() {
+        let params = SweepAllParams {
+            address: String::from("ADDRESS"),
+            priority: TransferPriority::Elevated,
+        };
+
+        let json = serde_json::to_value(&params).unwrap();
+
+        assert_eq!(json["priority"], 3);
+    }
+
+    #[test]
+    fn priority_outside_accepted_set_is_rejected() {
+        assert_eq!(
+            "Normal".parse::<TransferPriority>().unwrap(),
+            TransferPriority::Normal
+        );
+        assert_eq!(
+            "1".parse::<TransferPriority>().unwrap(),
+            TransferPriority::Unimportant
+        );
+        assert!("4".parse::<TransferPriority>().is_err());
+        assert!("fast".parse::<TransferPriority>().is_err());
+    }
+
     #[test]
     fn can_deserialize_sweep_all_response() {
         let response = r#"{
@@ -533,4 +715,68 @@ mod tests {
 
         let _: Response<SweepAll> = serde_json::from_str(&response).unwrap();
     }
+
+    #[test]
+    fn can_deserialize_get_address_response() {
+        let response = r#"{
+          "id": "0",
+          "jsonrpc": "2.0",
+          "result": {
+            "address": "55LTR8KniP4LQGJSPtbYDacR7dz8RBFnsfAKMaMuwUNYX6aQbBcovzDPyrQF9KXF9tVU6Xk3K8no1BywnJX6GvZX8yJsXvt",
+            "addresses": [{
+              "address": "55LTR8KniP4LQGJSPtbYDacR7dz8RBFnsfAKMaMuwUNYX6aQbBcovzDPyrQF9KXF9tVU6Xk3K8no1BywnJX6GvZX8yJsXvt",
+              "address_index": 0,
+              "label": "Primary account",
+              "used": true
+            }]
+          }
+        }"#;
+
+        let response: Response<GetAddress> = serde_json::from_str(&response).unwrap();
+
+        assert_eq!(response.result.addresses[0].label, "Primary account");
+    }
+
+    #[test]
+    fn can_deserialize_get_transfers_response() {
+        let response = r#"{
+          "id": "0",
+          "jsonrpc": "2.0",
+          "result": {
+            "in": [{
+              "address": "55LTR8KniP4LQGJSPtbYDacR7dz8RBFnsfAKMaMuwUNYX6aQbBcovzDPyrQF9KXF9tVU6Xk3K8no1BywnJX6GvZX8yJsXvt",
+              "amount": 200000000000,
+              "confirmations": 3,
+              "double_spend_seen": false,
+              "fee": 21650200000,
+              "height": 153624,
+              "note": "",
+              "payment_id": "0000000000000000",
+              "subaddr_index": {"major": 0, "minor": 0},
+              "suggested_confirmations_threshold": 1,
+              "timestamp": 1535918400,
+              "txid": "c36258a276018c3a4bc1f195a7fb530f50cd63a4fa765fb7c6f7f49fc051762a",
+              "type": "in",
+              "unlock_time": 0
+            }]
+          }
+        }"#;
+
+        let response: Response<GetTransfers> = serde_json::from_str(&response).unwrap();
+
+        assert_eq!(response.result.incoming[0].confirmations, 3);
+    }
+
+    #[test]
+    fn can_deserialize_empty_get_transfers_response() {
+        let response = r#"{
+          "id": "0",
+          "jsonrpc": "2.0",
+          "result": {}
+        }"#;
+
+        let response: Response<GetTransfers> = serde_json::from_str(&response).unwrap();
+
+        assert!(response.result.incoming.is_empty());
+    }
 }
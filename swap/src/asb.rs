@@ -1,7 +1,11 @@
+mod allowlist;
 pub mod command;
 pub mod config;
 mod fixed_rate;
+mod pause;
 mod rate;
 
+pub use self::allowlist::{PeerAllowlist, PeerNotAllowed};
 pub use self::fixed_rate::FixedRate;
+pub use self::pause::{MakerPaused, PauseSwitch};
 pub use self::rate::Rate;
@@ -0,0 +1,51 @@
+use libp2p::PeerId;
+use std::collections::HashSet;
+
+/// The peers that are allowed to swap with us.
+///
+/// An empty allowlist allows all peers.
+#[derive(Clone, Debug, Default)]
+pub struct PeerAllowlist(HashSet<PeerId>);
+
+impl PeerAllowlist {
+    pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Self {
+        Self(peers.into_iter().collect())
+    }
+
+    pub fn ensure_allowed(&self, peer: &PeerId) -> Result<(), PeerNotAllowed> {
+        if self.0.is_empty() || self.0.contains(peer) {
+            return Ok(());
+        }
+
+        Err(PeerNotAllowed { peer: *peer })
+    }
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Peer {peer} is not on the allowlist of peers we swap with")]
+pub struct PeerNotAllowed {
+    pub peer: PeerId,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_listed_peers_are_allowed() {
+        let listed = PeerId::random();
+        let unknown = PeerId::random();
+
+        let allowlist = PeerAllowlist::new(vec![listed]);
+
+        assert!(allowlist.ensure_allowed(&listed).is_ok());
+        assert!(allowlist.ensure_allowed(&unknown).is_err());
+    }
+
+    #[test]
+    fn empty_allowlist_allows_all_peers() {
+        let allowlist = PeerAllowlist::default();
+
+        assert!(allowlist.ensure_allowed(&PeerId::random()).is_ok());
+    }
+}
@@ -1,7 +1,12 @@
-use crate::bitcoin::Amount;
-use bitcoin::util::amount::ParseAmountError;
-use bitcoin::Denomination;
+use crate::bitcoin::{parse_btc, Address, Amount, Txid};
+use crate::monero;
+use crate::trace::Format;
+use ::bitcoin::consensus::deserialize;
+use ::bitcoin::util::psbt::PartiallySignedTransaction;
+use ::bitcoin::OutPoint;
+use anyhow::Result;
 use std::path::PathBuf;
+use uuid::Uuid;
 
 #[derive(structopt::StructOpt, Debug)]
 #[structopt(
@@ -16,6 +21,20 @@ pub struct Arguments {
     )]
     pub config: Option<PathBuf>,
 
+    #[structopt(
+        long = "log-format",
+        help = "The format of the log output, either human or json.",
+        default_value = "human"
+    )]
+    pub log_format: Format,
+
+    #[structopt(
+        long = "log-file",
+        help = "Write the log output to the given file instead of stderr.",
+        parse(from_os_str)
+    )]
+    pub log_file: Option<PathBuf>,
+
     #[structopt(subcommand)]
     pub cmd: Command,
 }
@@ -24,12 +43,147 @@ pub struct Arguments {
 #[structopt(name = "xmr_btc-swap", about = "XMR BTC atomic swap")]
 pub enum Command {
     Start {
+        #[structopt(long = "min-buy-btc", help = "The minimum amount of BTC the ASB is willing to buy.", default_value="0", parse(try_from_str = parse_btc))]
+        min_buy: Amount,
         #[structopt(long = "max-buy-btc", help = "The maximum amount of BTC the ASB is willing to buy.", default_value="0.005", parse(try_from_str = parse_btc))]
         max_buy: Amount,
+        #[structopt(
+            long = "advertise-liquidity",
+            help = "Include a signed statement of the available XMR in every quote."
+        )]
+        advertise_liquidity: bool,
+        #[structopt(
+            long = "fidelity-bond",
+            help = "The id of a Bitcoin transaction to advertise as fidelity bond, requires --advertise-liquidity.",
+            requires = "advertise-liquidity"
+        )]
+        fidelity_bond: Option<Txid>,
+        #[structopt(
+            long = "record-quotes",
+            help = "Record the spot prices quoted to peers, see the quotes command."
+        )]
+        record_quotes: bool,
+        #[structopt(
+            long = "max-recorded-quotes",
+            help = "The maximum number of recorded quotes to keep.",
+            default_value = "10000"
+        )]
+        max_recorded_quotes: usize,
+        #[structopt(
+            long = "max-quote-age-days",
+            help = "Recorded quotes older than this many days are deleted.",
+            default_value = "90"
+        )]
+        max_quote_age_days: u64,
     },
     History,
+    /// Abort a swap in which the Bitcoin has not been locked yet. If the ASB is
+    /// running, it aborts the swap once it notices the request.
+    Abort {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+        #[structopt(
+            long = "force",
+            help = "Confirm that the swap should be aborted, this cannot be undone."
+        )]
+        force: bool,
+    },
+    /// Generate a cold signing key and print its public key, to be set as
+    /// cold_signing_key in the config of the ASB
+    GenerateColdKey {
+        #[structopt(
+            long = "key-file",
+            help = "The file to write the private key to, it must not exist yet.",
+            parse(from_os_str)
+        )]
+        key_file: PathBuf,
+    },
+    /// Sign a redeem transaction written to the cold-sign directory by the
+    /// ASB, on the machine holding the cold signing key
+    SignRedeem {
+        #[structopt(
+            long = "psbt",
+            help = "The base64 encoded PSBT written by the ASB.",
+            parse(try_from_str = parse_psbt)
+        )]
+        psbt: PartiallySignedTransaction,
+        #[structopt(
+            long = "key-file",
+            help = "The file holding the cold signing key, as written by generate-cold-key.",
+            parse(from_os_str)
+        )]
+        key_file: PathBuf,
+    },
+    /// Show the spot prices quoted to peers, if recorded
+    Quotes,
+    /// Print the address including the peer id that Bob has to dial
+    Address,
+    /// Withdraw Bitcoin from the internal wallet to an external address
+    WithdrawBtc {
+        #[structopt(long = "address", help = "The address to receive the Bitcoin.")]
+        address: Address,
+        #[structopt(
+            long = "amount",
+            help = "The amount of BTC to withdraw.",
+            parse(try_from_str = parse_btc),
+            required_unless = "all"
+        )]
+        amount: Option<Amount>,
+        #[structopt(
+            long = "all",
+            help = "Withdraw all Bitcoin that is not reserved for an ongoing swap.",
+            conflicts_with = "amount"
+        )]
+        all: bool,
+        #[structopt(
+            long = "coin",
+            help = "Only spend this coin, given as <txid>:<vout>. Can be given multiple times.",
+            conflicts_with = "all"
+        )]
+        coins: Vec<OutPoint>,
+    },
+    /// Withdraw Monero from the internal wallet to an external address
+    WithdrawXmr {
+        #[structopt(long = "address", help = "The address to receive the Monero.")]
+        address: monero::Address,
+        #[structopt(
+            long = "amount",
+            help = "The amount of XMR to withdraw, with at most 12 decimal places.",
+            parse(try_from_str = monero::Amount::parse_xmr),
+            required_unless = "all"
+        )]
+        amount: Option<monero::Amount>,
+        #[structopt(
+            long = "all",
+            help = "Withdraw all Monero that is not reserved for an ongoing swap.",
+            conflicts_with = "amount"
+        )]
+        all: bool,
+    },
+    /// Print the balance of a view-only Monero wallet, e.g. to monitor the
+    /// Monero of the ASB on a machine that does not hold the spend key
+    WatchXmr {
+        #[structopt(long = "address", help = "The main address of the wallet to monitor.")]
+        address: monero::Address,
+        #[structopt(
+            long = "view-key",
+            help = "The hex encoded private view key of the wallet to monitor."
+        )]
+        view_key: monero::PrivateViewKey,
+        #[structopt(
+            long = "restore-height",
+            help = "The block height to scan from when the view-only wallet is created.",
+            default_value = "0"
+        )]
+        restore_height: u32,
+    },
 }
 
-fn parse_btc(s: &str) -> Result<Amount, ParseAmountError> {
-    Amount::from_str_in(s, Denomination::Bitcoin)
+fn parse_psbt(str: &str) -> Result<PartiallySignedTransaction> {
+    let psbt = deserialize(&base64::decode(str)?)?;
+
+    Ok(psbt)
 }
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Switches the ASB between serving spot prices and rejecting new swaps,
+/// shared between the event loop and whatever toggles it at runtime.
+///
+/// Swaps that are already in flight are not affected by pausing.
+#[derive(Clone, Debug, Default)]
+pub struct PauseSwitch(Arc<AtomicBool>);
+
+impl PauseSwitch {
+    pub fn pause(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub fn ensure_not_paused(&self) -> Result<(), MakerPaused> {
+        if self.is_paused() {
+            return Err(MakerPaused);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Maker temporarily unavailable, not accepting new swaps")]
+pub struct MakerPaused;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_swaps_are_rejected_while_paused() {
+        let switch = PauseSwitch::default();
+        let event_loop_switch = switch.clone();
+
+        assert!(event_loop_switch.ensure_not_paused().is_ok());
+
+        switch.pause();
+        assert!(event_loop_switch.ensure_not_paused().is_err());
+
+        switch.resume();
+        assert!(event_loop_switch.ensure_not_paused().is_ok());
+    }
+}
@@ -12,27 +12,37 @@
 #![forbid(unsafe_code)]
 #![allow(non_snake_case)]
 
-use anyhow::{Context, Result};
+use ::bitcoin::consensus::serialize;
+use anyhow::{bail, Context, Result};
 use bdk::descriptor::Segwitv0;
 use bdk::keys::DerivableKey;
+use monero_rpc::monerod;
+use monero_rpc::wallet::BlockHeight;
 use prettytable::{row, Table};
 use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 use swap::asb::command::{Arguments, Command};
 use swap::asb::config::{
-    initial_setup, query_user_for_initial_testnet_config, read_config, Config, ConfigNotInitialized,
+    initial_setup, query_user_for_initial_testnet_config, read_config, Config,
+    ConfigNotInitialized, Consolidation,
 };
-use swap::database::Database;
+#[cfg(unix)]
+use swap::asb::PauseSwitch;
+use swap::database::{Database, QuoteRetention, Swap};
 use swap::env::GetConfig;
 use swap::fs::default_config_path;
 use swap::monero::Amount;
-use swap::protocol::alice::{run, EventLoop};
+use swap::protocol::alice::{force_abort, request_abort, run, sign_redeem, AliceState, EventLoop};
+use swap::protocol::urgency;
 use swap::seed::Seed;
 use swap::trace::init_tracing;
 use swap::{bitcoin, env, kraken, monero};
 use tracing::{info, warn};
 use tracing_subscriber::filter::LevelFilter;
+use url::Url;
+use uuid::Uuid;
 
 #[macro_use]
 extern crate prettytable;
@@ -41,16 +51,75 @@ const DEFAULT_WALLET_NAME: &str = "asb-wallet";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_tracing(LevelFilter::DEBUG).expect("initialize tracing");
-
     let opt = Arguments::from_args();
 
+    init_tracing(LevelFilter::DEBUG, opt.log_format, opt.log_file.as_deref())
+        .expect("initialize tracing");
+
     let config_path = if let Some(config_path) = opt.config {
         config_path
     } else {
         default_config_path()?
     };
 
+    // The cold signing commands run on a machine without the ASB, hence neither
+    // read the config nor open the database
+    match opt.cmd {
+        Command::GenerateColdKey { key_file } => {
+            if key_file.exists() {
+                bail!("Key file {} already exists", key_file.display());
+            }
+
+            let env_config = env::Testnet::get_config();
+            let cold_signing_key = ::bitcoin::PrivateKey {
+                compressed: true,
+                network: env_config.bitcoin_network,
+                key: ::bitcoin::secp256k1::SecretKey::from_slice(
+                    &bitcoin::SecretKey::new_random(&mut rand::thread_rng()).to_bytes(),
+                )?,
+            };
+            std::fs::write(&key_file, cold_signing_key.to_wif())
+                .with_context(|| format!("Failed to write key file {}", key_file.display()))?;
+
+            let public_key =
+                cold_signing_key.public_key(&::bitcoin::secp256k1::Secp256k1::signing_only());
+            info!(
+                "Wrote cold signing key to {}, set `cold_signing_key = \"{}\"` in the [bitcoin] section of the config to seal new swaps for it",
+                key_file.display(),
+                public_key
+            );
+
+            return Ok(());
+        }
+        Command::SignRedeem { psbt, key_file } => {
+            let env_config = env::Testnet::get_config();
+
+            for output in &psbt.global.unsigned_tx.output {
+                let address = bitcoin::Address::from_script(
+                    &output.script_pubkey,
+                    env_config.bitcoin_network,
+                )
+                .context("Redeem transaction pays to an unknown script")?;
+                info!(
+                    "Signing redeem of {} to {}",
+                    bitcoin::Amount::from_sat(output.value),
+                    address
+                );
+            }
+
+            let cold_signing_key = std::fs::read_to_string(&key_file)
+                .with_context(|| format!("Failed to read key file {}", key_file.display()))?;
+            let cold_signing_key = ::bitcoin::PrivateKey::from_wif(cold_signing_key.trim())
+                .context("Key file does not hold a WIF encoded private key")?;
+            let psbt = sign_redeem(psbt, &cold_signing_key)?;
+
+            println!("{}", base64::encode(serialize(&psbt)));
+
+            return Ok(());
+        }
+        _ => {}
+    }
+
     let config = match read_config(config_path.clone())? {
         Ok(config) => config,
         Err(ConfigNotInitialized {}) => {
@@ -66,17 +135,51 @@ async fn main() -> Result<()> {
 
     let db_path = config.data.dir.join("database");
 
-    let db = Database::open(config.data.dir.join(db_path).as_path())
-        .context("Could not open database")?;
+    let db = match (
+        Database::open(config.data.dir.join(db_path).as_path()),
+        &opt.cmd,
+    ) {
+        (Ok(db), _) => db,
+        // A running ASB holds the database, the swap picks the request up while
+        // it waits for the Bitcoin lock
+        (
+            Err(error),
+            Command::Abort {
+                swap_id,
+                force: true,
+            },
+        ) => {
+            warn!(
+                "Could not open database, assuming the ASB is running: {:#}",
+                error
+            );
+            request_abort(&config.data.dir.join("abort"), *swap_id)?;
+            info!(
+                "Requested the running ASB to abort swap {}, it does so unless the Bitcoin is already locked",
+                swap_id
+            );
+
+            return Ok(());
+        }
+        (Err(error), _) => return Err(error.context("Could not open database")),
+    };
 
     let wallet_data_dir = config.data.dir.join("wallet");
 
     match opt.cmd {
-        Command::Start { max_buy } => {
+        Command::Start {
+            min_buy,
+            max_buy,
+            advertise_liquidity,
+            fidelity_bond,
+            record_quotes,
+            max_recorded_quotes,
+            max_quote_age_days,
+        } => {
             let seed = Seed::from_file_or_generate(&config.data.dir)
                 .expect("Could not retrieve/initialize seed");
 
-            let env_config = env::Testnet::get_config();
+            let env_config = env_config_for(&config);
 
             let (bitcoin_wallet, monero_wallet) = init_wallets(
                 config.clone(),
@@ -91,22 +194,64 @@ async fn main() -> Result<()> {
                 bitcoin_wallet.new_address().await?
             );
 
-            let kraken_rate_updates = kraken::connect()?;
+            let bitcoin_wallet = Arc::new(bitcoin_wallet);
+            if let Some(consolidation) = config.bitcoin.consolidation {
+                tokio::spawn(consolidate_periodically(
+                    bitcoin_wallet.clone(),
+                    consolidation,
+                ));
+            }
+
+            let kraken_rate_updates = kraken::connect(config.kraken.pairs())?;
+            let allowlist = config.network.allowlist();
 
             let (event_loop, mut swap_receiver) = EventLoop::new(
-                config.network.listen,
+                config.network.listen.clone(),
                 seed,
                 env_config,
-                Arc::new(bitcoin_wallet),
+                bitcoin_wallet.clone(),
                 Arc::new(monero_wallet),
                 Arc::new(db),
                 kraken_rate_updates,
+                min_buy,
                 max_buy,
+                allowlist,
             )
             .unwrap();
 
+            let mut event_loop = event_loop
+                .with_punish(config.bitcoin.punish)
+                .with_cold_signing(
+                    config.bitcoin.cold_signing_key,
+                    config.data.dir.join("cold-sign"),
+                )
+                .with_abort_requests(config.data.dir.join("abort"));
+            if advertise_liquidity {
+                event_loop = event_loop.with_liquidity_proof(fidelity_bond);
+            }
+            if let Some(max_concurrent) = config.resume.max_concurrent_swaps {
+                event_loop = event_loop.with_resume_concurrency(max_concurrent);
+            }
+            if record_quotes {
+                event_loop = event_loop.with_quote_history(QuoteRetention {
+                    max_count: max_recorded_quotes,
+                    max_age: Duration::from_secs(max_quote_age_days * 24 * 60 * 60),
+                });
+            }
+
+            #[cfg(unix)]
+            {
+                let pause = event_loop.pause_switch();
+                tokio::spawn(async move {
+                    if let Err(error) = toggle_pause_on_signals(pause).await {
+                        warn!("Cannot pause on signals: {:#}", error);
+                    }
+                });
+            }
+
             tokio::spawn(async move {
                 while let Some(swap) = swap_receiver.recv().await {
+                    let bitcoin_wallet = bitcoin_wallet.clone();
                     tokio::spawn(async move {
                         let swap_id = swap.swap_id;
                         match run(swap).await {
@@ -117,31 +262,366 @@ async fn main() -> Result<()> {
                                 tracing::error!(%swap_id, "Swap failed with {:#}", e)
                             }
                         }
+
+                        // The histories of finished swaps are only evicted once
+                        // the limit is reached, this shows how close we are.
+                        tracing::debug!(
+                            watched_scripts = bitcoin_wallet.watched_scripts().await,
+                            "Watching Bitcoin transaction scripts"
+                        );
                     });
                 }
             });
 
             info!("Our peer id is {}", event_loop.peer_id());
+            info!(
+                "Bob can swap with us at {}",
+                config.network.dialable_address(event_loop.peer_id())
+            );
 
             event_loop.run().await;
         }
         Command::History => {
+            let seed = Seed::from_file_or_generate(&config.data.dir)
+                .expect("Could not retrieve/initialize seed");
+
+            let env_config = env_config_for(&config);
+
+            // The history is still listed if the wallet is unavailable, e.g.
+            // because the ASB is running and holds it.
+            let bitcoin_wallet = match bitcoin::Wallet::new(
+                &config.bitcoin.electrum_rpc_urls(),
+                &wallet_data_dir,
+                seed.derive_extended_private_key(env_config.bitcoin_network)?,
+                env_config,
+            )
+            .await
+            {
+                Ok(bitcoin_wallet) => Some(bitcoin_wallet),
+                Err(error) => {
+                    warn!(
+                        "Cannot tell the urgency of swaps, failed to open the Bitcoin wallet: {:#}",
+                        error
+                    );
+                    None
+                }
+            };
+
             let mut table = Table::new();
 
-            table.add_row(row!["SWAP ID", "STATE"]);
+            match &bitcoin_wallet {
+                Some(_) => table.add_row(row!["SWAP ID", "STATE", "URGENCY", "LAST ERROR"]),
+                None => table.add_row(row!["SWAP ID", "STATE", "LAST ERROR"]),
+            };
 
             for (swap_id, state) in db.all()? {
-                table.add_row(row![swap_id, state]);
+                let failure = db
+                    .get_failure(swap_id)?
+                    .map(|failure| failure.to_string())
+                    .unwrap_or_default();
+
+                match &bitcoin_wallet {
+                    Some(bitcoin_wallet) => {
+                        let urgency = urgency_of(swap_id, state.clone(), bitcoin_wallet).await;
+                        table.add_row(row![swap_id, state, urgency, failure]);
+                    }
+                    None => {
+                        table.add_row(row![swap_id, state, failure]);
+                    }
+                }
+            }
+
+            // Print the table to stdout
+            table.printstd();
+
+            let unreadable = db.unreadable()?;
+            if !unreadable.is_empty() {
+                println!("{} swaps could not be read:", unreadable.len());
+                for swap in unreadable {
+                    println!("  {}", swap);
+                }
+            }
+        }
+        Command::Abort { swap_id, force } => {
+            if !force {
+                bail!(
+                    "Aborting a swap cannot be undone, pass --force to abort swap {}",
+                    swap_id
+                )
+            }
+
+            let seed = Seed::from_file_or_generate(&config.data.dir)
+                .expect("Could not retrieve/initialize seed");
+
+            let env_config = env_config_for(&config);
+
+            let bitcoin_wallet = bitcoin::Wallet::new(
+                &config.bitcoin.electrum_rpc_urls(),
+                &wallet_data_dir,
+                seed.derive_extended_private_key(env_config.bitcoin_network)?,
+                env_config,
+            )
+            .await?;
+
+            let state = db.get_state(swap_id)?.try_into_alice()?.into();
+            let state = force_abort(swap_id, state, &bitcoin_wallet, &db).await?;
+
+            info!("Swap {} is aborted, {}", swap_id, state);
+        }
+        Command::GenerateColdKey { .. } | Command::SignRedeem { .. } => {
+            unreachable!("cold signing commands are handled before reading the config")
+        }
+        Command::Address => {
+            let seed = Seed::from_file_or_generate(&config.data.dir)
+                .expect("Could not retrieve/initialize seed");
+            let peer_id = seed.derive_libp2p_identity().public().into_peer_id();
+
+            if config.network.listens_on_all_interfaces() {
+                warn!("Listening on all interfaces, replace the IP address with the public one of this host");
+            }
+
+            println!("{}", config.network.dialable_address(peer_id));
+        }
+        Command::Quotes => {
+            let mut table = Table::new();
+
+            table.add_row(row!["TIME", "PEER ID", "BTC", "XMR", "PRICE"]);
+
+            for quote in db.quotes()? {
+                table.add_row(row![
+                    quote.timestamp.format("%F %T UTC"),
+                    quote.peer_id,
+                    quote.btc,
+                    quote.xmr,
+                    quote.ask
+                ]);
             }
 
             // Print the table to stdout
             table.printstd();
         }
+        Command::WithdrawBtc {
+            address,
+            amount,
+            all: _,
+            coins,
+        } => {
+            let seed = Seed::from_file_or_generate(&config.data.dir)
+                .expect("Could not retrieve/initialize seed");
+
+            let env_config = env_config_for(&config);
+
+            if address.network != env_config.bitcoin_network {
+                bail!(
+                    "The given bitcoin address is on network {:?}, expected address of network {:?}.",
+                    address.network,
+                    env_config.bitcoin_network
+                )
+            }
+
+            let bitcoin_wallet = bitcoin::Wallet::new(
+                &config.bitcoin.electrum_rpc_urls(),
+                &wallet_data_dir,
+                seed.derive_extended_private_key(env_config.bitcoin_network)?,
+                env_config,
+            )
+            .await?;
+            let bitcoin_wallet = with_configured_bitcoind(
+                bitcoin_wallet,
+                config.bitcoin.bitcoind_rpc_url.clone(),
+                env_config,
+            )
+            .await?;
+            bitcoin_wallet.sync().await?;
+
+            let amount = match amount {
+                Some(amount) => amount,
+                None => bitcoin_wallet.max_giveable_to(&address).await?,
+            };
+
+            let (txid, fee) = bitcoin_wallet
+                .withdraw(address.clone(), amount, &coins)
+                .await?;
+
+            println!("Withdrew {} to {}", amount, address);
+            println!("Transaction: {}", txid);
+            println!("Fee: {}", fee);
+        }
+        Command::WithdrawXmr {
+            address,
+            amount,
+            all: _,
+        } => {
+            let env_config = env_config_for(&config);
+
+            monero::validate_address(&address, env_config.monero_network)?;
+
+            let reserved = db
+                .all()?
+                .into_iter()
+                .filter_map(|(_, swap)| match swap {
+                    Swap::Alice(state) => Some(AliceState::from(state).reserved_xmr()),
+                    Swap::Bob(_) => None,
+                })
+                .fold(Amount::ZERO, |total, reserved| total + reserved);
+
+            let monero_wallet = open_monero_wallet(&config, env_config).await?;
+
+            let withdrawal = monero_wallet.withdraw(address, amount, reserved).await?;
+
+            if reserved > Amount::ZERO {
+                info!("Kept {} reserved for active swaps", reserved);
+            }
+            for tx_hash in withdrawal.tx_hashes {
+                println!("Transaction: {}", tx_hash);
+            }
+            println!("Fee: {}", withdrawal.fee);
+        }
+        Command::WatchXmr {
+            address,
+            view_key,
+            restore_height,
+        } => {
+            let env_config = env_config_for(&config);
+
+            monero::validate_address(&address, env_config.monero_network)?;
+
+            let monero_wallet = monero::Wallet::open_or_create_view_only(
+                config.monero.wallet_rpc_url.clone(),
+                address,
+                view_key,
+                BlockHeight {
+                    height: restore_height,
+                },
+                env_config,
+            )
+            .await?;
+            monero_wallet.refresh().await?;
+
+            println!("Balance: {}", monero_wallet.get_balance().await?);
+        }
     };
 
     Ok(())
 }
 
+/// The urgency of the given swap for the history, `unknown` if it cannot be
+/// determined.
+async fn urgency_of(swap_id: Uuid, swap: Swap, bitcoin_wallet: &bitcoin::Wallet) -> String {
+    match urgency::classify(swap, bitcoin_wallet).await {
+        Ok(urgency) => urgency
+            .map(|urgency| urgency.to_string())
+            .unwrap_or_default(),
+        Err(error) => {
+            warn!(%swap_id, "Failed to classify urgency of swap: {:#}", error);
+            "unknown".to_owned()
+        }
+    }
+}
+
+/// The defaults of the network with the overrides of the config applied.
+fn env_config_for(config: &Config) -> env::Config {
+    let env_config = env::Testnet::get_config();
+
+    env::Config {
+        bitcoin_electrum: config.bitcoin.electrum.apply(env_config.bitcoin_electrum),
+        bitcoin_lock_deadline: config
+            .bitcoin
+            .lock_deadline_secs
+            .map_or(env_config.bitcoin_lock_deadline, Duration::from_secs),
+        ..env_config
+    }
+}
+
+/// How often the ASB checks whether its Bitcoin coins should be consolidated.
+const CONSOLIDATION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+async fn consolidate_periodically(
+    bitcoin_wallet: Arc<bitcoin::Wallet>,
+    consolidation: Consolidation,
+) {
+    let mut interval = tokio::time::interval(CONSOLIDATION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match bitcoin_wallet
+            .consolidate(consolidation.min_utxos, consolidation.max_fee_rate)
+            .await
+        {
+            Ok(Some(txid)) => info!(%txid, "Consolidated Bitcoin coins"),
+            Ok(None) => {}
+            Err(error) => warn!("Failed to consolidate Bitcoin coins: {:#}", error),
+        }
+    }
+}
+
+/// Stop accepting new swaps on SIGUSR1 and accept them again on SIGUSR2,
+/// swaps in flight continue either way.
+#[cfg(unix)]
+async fn toggle_pause_on_signals(pause: PauseSwitch) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut pause_signal = signal(SignalKind::user_defined1())?;
+    let mut resume_signal = signal(SignalKind::user_defined2())?;
+
+    loop {
+        tokio::select! {
+            _ = pause_signal.recv() => {
+                pause.pause();
+                info!("Paused, rejecting new swaps until SIGUSR2 is received");
+            }
+            _ = resume_signal.recv() => {
+                pause.resume();
+                info!("Resumed accepting new swaps");
+            }
+        }
+    }
+}
+
+/// Publish transactions through the configured bitcoind node, if any.
+async fn with_configured_bitcoind(
+    bitcoin_wallet: bitcoin::Wallet,
+    bitcoind_rpc_url: Option<Url>,
+    env_config: env::Config,
+) -> Result<bitcoin::Wallet> {
+    let url = match bitcoind_rpc_url {
+        Some(url) => url,
+        None => return Ok(bitcoin_wallet),
+    };
+
+    let bitcoind = bitcoin::bitcoind::Client::connect(url, env_config.bitcoin_network)
+        .await
+        .context("Failed to use bitcoind for publishing transactions")?;
+    info!("Publishing Bitcoin transactions through bitcoind");
+
+    Ok(bitcoin_wallet.with_bitcoind(bitcoind))
+}
+
+async fn open_monero_wallet(config: &Config, env_config: env::Config) -> Result<monero::Wallet> {
+    let wallet = monero::Wallet::open_or_create_with_retries(
+        config.monero.wallet_rpc_url.clone(),
+        DEFAULT_WALLET_NAME.to_string(),
+        env_config,
+        config.monero.wallet_open_attempts,
+        Duration::from_secs(config.monero.wallet_open_retry_delay_secs),
+    )
+    .await?;
+
+    let wallet = match config.monero.explorer_url.clone() {
+        Some(explorer) => wallet.with_explorer(explorer),
+        None => wallet,
+    };
+
+    Ok(match &config.monero.daemon_host {
+        Some(host) => wallet.with_daemon(monerod::Client::new(monero::daemon_url(
+            host,
+            env_config.monero_network,
+        )?)),
+        None => wallet,
+    })
+}
+
 async fn init_wallets(
     config: Config,
     bitcoin_wallet_data_dir: &Path,
@@ -149,11 +629,23 @@ async fn init_wallets(
     env_config: env::Config,
 ) -> Result<(bitcoin::Wallet, monero::Wallet)> {
     let bitcoin_wallet = bitcoin::Wallet::new(
-        config.bitcoin.electrum_rpc_url,
+        &config.bitcoin.electrum_rpc_urls(),
         bitcoin_wallet_data_dir,
         key,
         env_config,
     )
+    .await?
+    .with_finality_tiers(config.bitcoin.finality_tiers.clone())
+    .with_fee_bumping(config.bitcoin.fee_bumping);
+    let bitcoin_wallet = match config.bitcoin.explorer_url.clone() {
+        Some(explorer) => bitcoin_wallet.with_explorer(explorer),
+        None => bitcoin_wallet,
+    };
+    let bitcoin_wallet = with_configured_bitcoind(
+        bitcoin_wallet,
+        config.bitcoin.bitcoind_rpc_url.clone(),
+        env_config,
+    )
     .await?;
 
     bitcoin_wallet.sync().await?;
@@ -164,12 +656,7 @@ async fn init_wallets(
         bitcoin_balance
     );
 
-    let monero_wallet = monero::Wallet::open_or_create(
-        config.monero.wallet_rpc_url.clone(),
-        DEFAULT_WALLET_NAME.to_string(),
-        env_config,
-    )
-    .await?;
+    let monero_wallet = open_monero_wallet(&config, env_config).await?;
 
     let balance = monero_wallet.get_balance().await?;
     if balance == Amount::ZERO {
@@ -6,7 +6,8 @@ async fn main() -> Result<()> {
         tracing_subscriber::fmt().with_env_filter("debug").finish(),
     )?;
 
-    let mut ticker = swap::kraken::connect().context("Failed to connect to kraken")?;
+    let mut ticker =
+        swap::kraken::connect(Default::default()).context("Failed to connect to kraken")?;
 
     loop {
         match ticker.wait_for_update().await? {
@@ -13,6 +13,9 @@
 #![allow(non_snake_case)]
 
 use anyhow::{bail, Context, Result};
+use libp2p::{Multiaddr, PeerId};
+use monero_rpc::monerod;
+use monero_rpc::wallet::{BlockHeight, TransferPriority};
 use prettytable::{row, Table};
 use std::cmp::min;
 use std::future::Future;
@@ -21,15 +24,28 @@ use std::sync::Arc;
 use std::time::Duration;
 use structopt::StructOpt;
 use swap::bitcoin::{Amount, TxLock};
-use swap::cli::command::{AliceConnectParams, Arguments, Command, Data, MoneroParams};
-use swap::database::Database;
+use swap::cli::command::{
+    AliceConnectParams, Arguments, Command, Data, MoneroParams, ResumeConnectParams,
+    DEFAULT_ALICE_MULTIADDR, DEFAULT_ALICE_PEER_ID,
+};
+use swap::cli::inspect::inspect;
+use swap::cli::recover::recover;
+use swap::cli::status::{watched_transactions, StatusReport};
+use swap::cli::swap_log::SwapLog;
+use swap::database::{Counterparty, Database};
 use swap::env::{Config, GetConfig};
+use swap::explorer::ExplorerUrl;
 use swap::network::quote::BidQuote;
-use swap::protocol::bob;
-use swap::protocol::bob::{Builder, EventLoop};
+use swap::protocol::bob::{
+    BobState, Builder, ConsolePrompt, DesktopNotifier, EventLoop, NoopNotifier, Notifier,
+    RefundAddressPolicy,
+};
+use swap::protocol::{bob, urgency};
 use swap::seed::Seed;
+use swap::trace::{init_tracing, Format};
 use swap::{bitcoin, env, monero};
 use tracing::{debug, error, info, warn, Level};
+use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::FmtSubscriber;
 use url::Url;
 use uuid::Uuid;
@@ -41,30 +57,20 @@ extern crate prettytable;
 async fn main() -> Result<()> {
     let args = Arguments::from_args();
 
-    let is_terminal = atty::is(atty::Stream::Stderr);
-    let base_subscriber = |level| {
-        FmtSubscriber::builder()
-            .with_writer(std::io::stderr)
-            .with_ansi(is_terminal)
-            .with_target(false)
-            .with_env_filter(format!("swap={}", level))
+    let level = if args.debug {
+        Level::DEBUG
+    } else {
+        Level::INFO
     };
 
-    if args.debug {
-        let subscriber = base_subscriber(Level::DEBUG)
-            .with_timer(tracing_subscriber::fmt::time::ChronoLocal::with_format(
-                "%F %T".to_owned(),
-            ))
-            .finish();
-
-        tracing::subscriber::set_global_default(subscriber)?;
+    if args.log_format != Format::Human || args.log_file.is_some() {
+        init_tracing(
+            LevelFilter::from_level(level),
+            args.log_format,
+            args.log_file.as_deref(),
+        )?;
     } else {
-        let subscriber = base_subscriber(Level::INFO)
-            .without_time()
-            .with_level(false)
-            .finish();
-
-        tracing::subscriber::set_global_default(subscriber)?;
+        init_interactive_tracing(level)?;
     }
 
     let data: Data = args.data;
@@ -78,61 +84,102 @@ async fn main() -> Result<()> {
 
     let env_config = env::Testnet::get_config();
 
+    let notifier: Arc<dyn Notifier> = if args.notify {
+        Arc::new(DesktopNotifier)
+    } else {
+        Arc::new(NoopNotifier)
+    };
+    let fee_bumping = args
+        .bump_fee_after
+        .map(|after_blocks| bitcoin::wallet::FeeBumping {
+            after_blocks,
+            max_fee_rate: args.max_bump_fee_rate,
+        });
+
     match args.cmd {
         Command::BuyXmr {
             connect_params:
                 AliceConnectParams {
                     peer_id: alice_peer_id,
                     multiaddr: alice_addr,
+                    dial_timeout_secs,
                 },
             monero_params:
                 MoneroParams {
                     receive_monero_address,
                     monero_daemon_host,
+                    monero_priority,
                 },
             electrum_rpc_url,
+            deposit_addresses,
+            require_liquidity_proof,
+            lock_deadline,
+            refund_address,
         } => {
-            if receive_monero_address.network != env_config.monero_network {
-                bail!(
-                    "Given monero address is on network {:?}, expected address on network {:?}",
-                    receive_monero_address.network,
-                    env_config.monero_network
-                )
-            }
-
-            let bitcoin_wallet =
-                init_bitcoin_wallet(electrum_rpc_url, seed, data_dir.clone(), env_config).await?;
+            monero::validate_address(&receive_monero_address, env_config.monero_network)?;
+            let refund_address_policy =
+                RefundAddressPolicy::new(refund_address, env_config.bitcoin_network)?;
+
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir.clone(),
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?
+            .with_fee_bumping(fee_bumping);
             let (monero_wallet, _process) =
-                init_monero_wallet(data_dir, monero_daemon_host, env_config).await?;
+                init_monero_wallet(data_dir, monero_daemon_host, env_config, monero_priority)
+                    .await?;
             let bitcoin_wallet = Arc::new(bitcoin_wallet);
-            let (event_loop, mut event_loop_handle) = EventLoop::new(
+            let (event_loop, event_loop_handle) = EventLoop::new(
                 &seed.derive_libp2p_identity(),
                 alice_peer_id,
-                alice_addr,
+                alice_addr.clone(),
                 bitcoin_wallet.clone(),
+                env_config,
             )?;
+            let mut event_loop_handle =
+                event_loop_handle.with_dial_timeout(Duration::from_secs(dial_timeout_secs));
             let handle = tokio::spawn(event_loop.run());
 
             let send_bitcoin = determine_btc_to_swap(
-                event_loop_handle.request_quote(),
+                verify_liquidity(
+                    event_loop_handle.request_quote(),
+                    alice_peer_id,
+                    require_liquidity_proof,
+                ),
                 bitcoin_wallet.balance(),
-                bitcoin_wallet.new_address(),
-                async {
-                    while bitcoin_wallet.balance().await? == Amount::ZERO {
-                        bitcoin_wallet.sync().await?;
+                bitcoin_wallet.new_addresses(deposit_addresses.get()),
+                |addresses| {
+                    let bitcoin_wallet = bitcoin_wallet.clone();
 
-                        tokio::time::sleep(Duration::from_secs(1)).await;
-                    }
+                    async move {
+                        while bitcoin_wallet.balance_of(&addresses).await? == Amount::ZERO {
+                            bitcoin_wallet.sync().await?;
+
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                        }
 
-                    bitcoin_wallet.balance().await
+                        bitcoin_wallet.balance().await
+                    }
                 },
                 bitcoin_wallet.max_giveable(TxLock::script_size()),
             )
             .await?;
 
+            let swap_id = db.new_swap_id();
+            db.insert_counterparty(swap_id, Counterparty {
+                peer_id: alice_peer_id,
+                address: alice_addr,
+            })
+            .await?;
+
             let swap = Builder::new(
                 db,
-                Uuid::new_v4(),
+                swap_id,
                 bitcoin_wallet.clone(),
                 Arc::new(monero_wallet),
                 env_config,
@@ -140,6 +187,10 @@ async fn main() -> Result<()> {
                 receive_monero_address,
             )
             .with_init_params(send_bitcoin)
+            .with_notifier(notifier)
+            .with_verifier(Arc::new(ConsolePrompt))
+            .with_lock_deadline(lock_deadline)
+            .with_refund_address_policy(refund_address_policy)
             .build()?;
 
             let swap = bob::run(swap);
@@ -152,48 +203,126 @@ async fn main() -> Result<()> {
                 }
             }
         }
-        Command::History => {
+        Command::History { electrum_rpc_url } => {
             let mut table = Table::new();
 
-            table.add_row(row!["SWAP ID", "STATE"]);
+            match electrum_rpc_url {
+                Some(electrum_rpc_url) => {
+                    let bitcoin_wallet = init_bitcoin_wallet(
+                        electrum_rpc_url,
+                        seed,
+                        data_dir,
+                        env_config,
+                        args.fee_rate,
+                        args.bitcoin_explorer_url.clone(),
+                    )
+                    .await?;
+
+                    table.add_row(row!["SWAP ID", "STATE", "URGENCY", "LAST ERROR"]);
+
+                    for (swap_id, state) in db.all()? {
+                        let urgency = match urgency::classify(state.clone(), &bitcoin_wallet).await
+                        {
+                            Ok(urgency) => urgency
+                                .map(|urgency| urgency.to_string())
+                                .unwrap_or_default(),
+                            Err(error) => {
+                                warn!(%swap_id, "Failed to classify urgency of swap: {:#}", error);
+                                "unknown".to_owned()
+                            }
+                        };
+                        let failure = last_error(&db, swap_id)?;
+
+                        table.add_row(row![swap_id, state, urgency, failure]);
+                    }
+                }
+                None => {
+                    table.add_row(row!["SWAP ID", "STATE", "LAST ERROR"]);
+
+                    for (swap_id, state) in db.all()? {
+                        let failure = last_error(&db, swap_id)?;
 
-            for (swap_id, state) in db.all()? {
-                table.add_row(row![swap_id, state]);
+                        table.add_row(row![swap_id, state, failure]);
+                    }
+                }
             }
 
             // Print the table to stdout
             table.printstd();
+
+            let unreadable = db.unreadable()?;
+            if !unreadable.is_empty() {
+                println!("{} swaps could not be read:", unreadable.len());
+                for swap in unreadable {
+                    println!("  {}", swap);
+                }
+            }
         }
         Command::Resume {
             swap_id,
             connect_params:
-                AliceConnectParams {
+                ResumeConnectParams {
                     peer_id: alice_peer_id,
                     multiaddr: alice_addr,
+                    dial_timeout_secs,
                 },
             monero_params:
                 MoneroParams {
                     receive_monero_address,
                     monero_daemon_host,
+                    monero_priority,
                 },
             electrum_rpc_url,
+            lock_deadline,
+            refund_address,
         } => {
-            if receive_monero_address.network != env_config.monero_network {
-                bail!("The given monero address is on network {:?}, expected address of network {:?}.", receive_monero_address.network, env_config.monero_network)
+            monero::validate_address(&receive_monero_address, env_config.monero_network)?;
+            let refund_address_policy =
+                RefundAddressPolicy::new(refund_address, env_config.bitcoin_network)?;
+
+            // The refund address is part of the transactions both parties
+            // agreed on once the swap was set up.
+            let state: BobState = db.get_state(swap_id)?.try_into_bob()?.into();
+            if refund_address_policy != RefundAddressPolicy::Fresh
+                && !matches!(state, BobState::Started { .. })
+            {
+                bail!(
+                    "Cannot change the refund address of swap {}, it was already agreed on with the other party",
+                    swap_id
+                )
             }
 
-            let bitcoin_wallet =
-                init_bitcoin_wallet(electrum_rpc_url, seed, data_dir.clone(), env_config).await?;
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir.clone(),
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?
+            .with_fee_bumping(fee_bumping);
             let (monero_wallet, _process) =
-                init_monero_wallet(data_dir, monero_daemon_host, env_config).await?;
+                init_monero_wallet(data_dir, monero_daemon_host, env_config, monero_priority)
+                    .await?;
             let bitcoin_wallet = Arc::new(bitcoin_wallet);
 
-            let (event_loop, event_loop_handle) = EventLoop::new(
+            let (alice_peer_id, alice_addr, fallback_addr) =
+                resume_counterparty(db.get_counterparty(swap_id)?, alice_peer_id, alice_addr);
+
+            let (mut event_loop, event_loop_handle) = EventLoop::new(
                 &seed.derive_libp2p_identity(),
                 alice_peer_id,
                 alice_addr,
                 bitcoin_wallet.clone(),
+                env_config,
             )?;
+            if let Some(fallback_addr) = fallback_addr {
+                event_loop.add_alice_address(fallback_addr);
+            }
+            let event_loop = event_loop.with_counterparty_updates(db.clone(), swap_id);
+            let event_loop_handle =
+                event_loop_handle.with_dial_timeout(Duration::from_secs(dial_timeout_secs));
             let handle = tokio::spawn(event_loop.run());
 
             let swap = Builder::new(
@@ -205,6 +334,10 @@ async fn main() -> Result<()> {
                 event_loop_handle,
                 receive_monero_address,
             )
+            .with_notifier(notifier)
+            .with_verifier(Arc::new(ConsolePrompt))
+            .with_lock_deadline(lock_deadline)
+            .with_refund_address_policy(refund_address_policy)
             .build()?;
 
             let swap = bob::run(swap);
@@ -217,13 +350,88 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        Command::Abandon {
+            swap_id,
+            electrum_rpc_url,
+        } => {
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir,
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?;
+
+            let resume_state = db.get_state(swap_id)?.try_into_bob()?.into();
+            let state = bob::abandon(swap_id, resume_state, Arc::new(bitcoin_wallet), db).await?;
+
+            info!("Swap {} is abandoned, {}", swap_id, state);
+        }
+        Command::Status {
+            swap_id,
+            electrum_rpc_url,
+        } => {
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir,
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?;
+
+            let state = db.get_state(swap_id)?.try_into_bob()?.into();
+            let transactions = watched_transactions(&state, db.bitcoin_transactions(swap_id)?);
+            let transactions = inspect(&bitcoin_wallet, &transactions).await?;
+
+            let report = StatusReport::new(swap_id, &state, &transactions)
+                .with_failure(db.get_failure(swap_id)?)
+                .with_explorer(bitcoin_wallet.explorer().cloned());
+
+            println!("{}", report);
+        }
+        Command::PrintSwapLog { swap_id } => {
+            let log = SwapLog::new(swap_id, db.state_history(swap_id)?, db.get_state(swap_id)?)?;
+
+            print!("{}", log);
+        }
+        Command::Inspect {
+            swap_id,
+            electrum_rpc_url,
+        } => {
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir,
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?;
+
+            let transactions = db.bitcoin_transactions(swap_id)?;
+
+            for summary in inspect(&bitcoin_wallet, &transactions).await? {
+                println!("{}", summary);
+            }
+        }
         Command::Cancel {
             swap_id,
             force,
             electrum_rpc_url,
         } => {
-            let bitcoin_wallet =
-                init_bitcoin_wallet(electrum_rpc_url, seed, data_dir, env_config).await?;
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir,
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?;
 
             let resume_state = db.get_state(swap_id)?.try_into_bob()?.into();
             let cancel =
@@ -247,27 +455,116 @@ async fn main() -> Result<()> {
             force,
             electrum_rpc_url,
         } => {
-            let bitcoin_wallet =
-                init_bitcoin_wallet(electrum_rpc_url, seed, data_dir, env_config).await?;
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir,
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?;
 
             let resume_state = db.get_state(swap_id)?.try_into_bob()?.into();
 
             bob::refund(swap_id, resume_state, Arc::new(bitcoin_wallet), db, force).await??;
         }
+        Command::Recover {
+            swap_id,
+            electrum_rpc_url,
+            dry_run,
+        } => {
+            let bitcoin_wallet = init_bitcoin_wallet(
+                electrum_rpc_url,
+                seed,
+                data_dir,
+                env_config,
+                args.fee_rate,
+                args.bitcoin_explorer_url.clone(),
+            )
+            .await?;
+
+            let state = db.get_state(swap_id)?.try_into_bob()?.into();
+            let plan = recover(swap_id, state, Arc::new(bitcoin_wallet), db, dry_run).await?;
+
+            println!("{}", plan);
+
+            if dry_run {
+                info!("Dry run, no transaction was published");
+            }
+        }
+        Command::RecoverXmr {
+            swap_id,
+            monero_params:
+                MoneroParams {
+                    receive_monero_address,
+                    monero_daemon_host,
+                    monero_priority,
+                },
+            from_height,
+        } => {
+            monero::validate_address(&receive_monero_address, env_config.monero_network)?;
+
+            let (monero_wallet, _process) =
+                init_monero_wallet(data_dir, monero_daemon_host, env_config, monero_priority)
+                    .await?;
+
+            let resume_state = db.get_state(swap_id)?.try_into_bob()?.into();
+
+            bob::recover_xmr(
+                swap_id,
+                resume_state,
+                &monero_wallet,
+                env_config,
+                receive_monero_address,
+                from_height.map(|height| BlockHeight { height }),
+                db,
+            )
+            .await?;
+        }
     };
     Ok(())
 }
 
+/// Human-readable logs for a user watching the terminal. Timestamps and levels
+/// are only shown when debugging.
+fn init_interactive_tracing(level: Level) -> Result<()> {
+    let is_terminal = atty::is(atty::Stream::Stderr);
+    let builder = FmtSubscriber::builder()
+        .with_writer(std::io::stderr)
+        .with_ansi(is_terminal)
+        .with_target(false)
+        .with_env_filter(format!("swap={}", level));
+
+    if level == Level::DEBUG {
+        let subscriber = builder
+            .with_timer(tracing_subscriber::fmt::time::ChronoLocal::with_format(
+                "%F %T".to_owned(),
+            ))
+            .finish();
+
+        tracing::subscriber::set_global_default(subscriber)?;
+    } else {
+        let subscriber = builder.without_time().with_level(false).finish();
+
+        tracing::subscriber::set_global_default(subscriber)?;
+    }
+
+    Ok(())
+}
+
 async fn init_bitcoin_wallet(
     electrum_rpc_url: Url,
     seed: Seed,
     data_dir: PathBuf,
     env_config: Config,
+    fee_rate: Option<f32>,
+    explorer: Option<ExplorerUrl>,
 ) -> Result<bitcoin::Wallet> {
     let wallet_dir = data_dir.join("wallet");
 
     let wallet = bitcoin::Wallet::new(
-        electrum_rpc_url.clone(),
+        &[electrum_rpc_url],
         &wallet_dir,
         seed.derive_extended_private_key(env_config.bitcoin_network)?,
         env_config,
@@ -275,6 +572,15 @@ async fn init_bitcoin_wallet(
     .await
     .context("Failed to initialize Bitcoin wallet")?;
 
+    let wallet = match fee_rate {
+        Some(fee_rate) => wallet.with_fee_rate_override(fee_rate),
+        None => wallet,
+    };
+    let wallet = match explorer {
+        Some(explorer) => wallet.with_explorer(explorer),
+        None => wallet,
+    };
+
     wallet.sync().await?;
 
     Ok(wallet)
@@ -284,6 +590,7 @@ async fn init_monero_wallet(
     data_dir: PathBuf,
     monero_daemon_host: String,
     env_config: Config,
+    priority: TransferPriority,
 ) -> Result<(monero::Wallet, monero::WalletRpcProcess)> {
     let network = env_config.monero_network;
 
@@ -300,18 +607,53 @@ async fn init_monero_wallet(
         MONERO_BLOCKCHAIN_MONITORING_WALLET_NAME.to_string(),
         env_config,
     )
-    .await?;
+    .await?
+    .with_transfer_priority(priority)
+    .with_daemon(monerod::Client::new(monero::daemon_url(
+        &monero_daemon_host,
+        network,
+    )?));
 
     Ok((monero_wallet, monero_wallet_rpc_process))
 }
 
-async fn determine_btc_to_swap(
+async fn verify_liquidity(
+    request_quote: impl Future<Output = Result<BidQuote>>,
+    seller: PeerId,
+    require_liquidity_proof: bool,
+) -> Result<BidQuote> {
+    let bid_quote = request_quote.await?;
+
+    match bid_quote.verified_liquidity(&seller)? {
+        Some(liquidity) => {
+            info!(
+                "Seller advertises {} available{}",
+                liquidity.max_sell,
+                liquidity
+                    .fidelity_bond
+                    .map(|txid| format!(" with fidelity bond {}", txid))
+                    .unwrap_or_default()
+            );
+        }
+        None if require_liquidity_proof => {
+            bail!("Seller does not advertise its liquidity")
+        }
+        None => {}
+    }
+
+    Ok(bid_quote)
+}
+
+async fn determine_btc_to_swap<FD>(
     request_quote: impl Future<Output = Result<BidQuote>>,
     initial_balance: impl Future<Output = Result<bitcoin::Amount>>,
-    get_new_address: impl Future<Output = Result<bitcoin::Address>>,
-    wait_for_deposit: impl Future<Output = Result<bitcoin::Amount>>,
+    get_new_addresses: impl Future<Output = Result<Vec<bitcoin::Address>>>,
+    wait_for_deposit: impl FnOnce(Vec<bitcoin::Address>) -> FD,
     max_giveable: impl Future<Output = Result<bitcoin::Amount>>,
-) -> Result<bitcoin::Amount> {
+) -> Result<bitcoin::Amount>
+where
+    FD: Future<Output = Result<bitcoin::Amount>>,
+{
     debug!("Requesting quote");
 
     let bid_quote = request_quote.await.context("Failed to request quote")?;
@@ -322,13 +664,19 @@ async fn determine_btc_to_swap(
     let initial_balance = initial_balance.await?;
 
     let balance = if initial_balance == Amount::ZERO {
+        let addresses = get_new_addresses.await?;
+
         info!(
             "Please deposit the BTC you want to swap to {} (max {})",
-            get_new_address.await?,
+            addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect::<Vec<_>>()
+                .join(" or "),
             bid_quote.max_quantity
         );
 
-        let new_balance = wait_for_deposit
+        let new_balance = wait_for_deposit(addresses)
             .await
             .context("Failed to wait for Bitcoin deposit")?;
 
@@ -352,6 +700,50 @@ async fn determine_btc_to_swap(
     Ok(btc_swap_amount)
 }
 
+/// Why the swap failed in its latest state, empty if it did not fail.
+fn last_error(db: &Database, swap_id: Uuid) -> Result<String> {
+    Ok(db
+        .get_failure(swap_id)?
+        .map(|failure| failure.to_string())
+        .unwrap_or_default())
+}
+
+/// Determine whom to dial when resuming a swap.
+///
+/// The counterparty stored with the swap takes precedence. The supplied address
+/// is only used as fallback if it belongs to the same peer. Swaps without a
+/// stored counterparty dial the supplied one, or the default seller.
+fn resume_counterparty(
+    stored: Option<Counterparty>,
+    peer_id: Option<PeerId>,
+    address: Option<Multiaddr>,
+) -> (PeerId, Multiaddr, Option<Multiaddr>) {
+    match (stored, peer_id) {
+        (None, peer_id) => (
+            peer_id.unwrap_or_else(|| {
+                DEFAULT_ALICE_PEER_ID
+                    .parse()
+                    .expect("default alice peer id str is a valid PeerId")
+            }),
+            address.unwrap_or_else(|| {
+                DEFAULT_ALICE_MULTIADDR
+                    .parse()
+                    .expect("default alice multiaddr str is a valid Multiaddr")
+            }),
+            None,
+        ),
+        (Some(stored), Some(peer_id)) if stored.peer_id != peer_id => {
+            warn!(
+                "The swap was started with peer {}, ignoring the given peer {}",
+                stored.peer_id, peer_id
+            );
+
+            (stored.peer_id, stored.address, None)
+        }
+        (Some(stored), _) => (stored.peer_id, stored.address, address),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -366,8 +758,8 @@ mod tests {
         let amount = determine_btc_to_swap(
             async { Ok(quote_with_max(0.01)) },
             async { Ok(Amount::ZERO) },
-            get_dummy_address(),
-            async { Ok(Amount::from_btc(0.0001)?) },
+            get_dummy_addresses(),
+            |_| async { Ok(Amount::from_btc(0.0001)?) },
             async { Ok(Amount::from_btc(0.00009)?) },
         )
         .await
@@ -383,8 +775,8 @@ mod tests {
         let amount = determine_btc_to_swap(
             async { Ok(quote_with_max(0.01)) },
             async { Ok(Amount::ZERO) },
-            get_dummy_address(),
-            async { Ok(Amount::from_btc(0.1)?) },
+            get_dummy_addresses(),
+            |_| async { Ok(Amount::from_btc(0.1)?) },
             async { Ok(Amount::from_btc(0.09)?) },
         )
         .await
@@ -401,7 +793,7 @@ mod tests {
             async { Ok(quote_with_max(0.01)) },
             async { Ok(Amount::from_btc(0.005)?) },
             async { panic!("should not request new address when initial balance is > 0") },
-            async { panic!("should not wait for deposit when initial balance > 0") },
+            |_| async { panic!("should not wait for deposit when initial balance > 0") },
             async { Ok(Amount::from_btc(0.0049)?) },
         )
         .await
@@ -418,7 +810,7 @@ mod tests {
             async { Ok(quote_with_max(0.01)) },
             async { Ok(Amount::from_btc(0.1)?) },
             async { panic!("should not request new address when initial balance is > 0") },
-            async { panic!("should not wait for deposit when initial balance > 0") },
+            |_| async { panic!("should not wait for deposit when initial balance > 0") },
             async { Ok(Amount::from_btc(0.09)?) },
         )
         .await
@@ -431,10 +823,65 @@ mod tests {
         BidQuote {
             price: Amount::from_btc(0.001).unwrap(),
             max_quantity: Amount::from_btc(btc).unwrap(),
+            liquidity_proof: None,
         }
     }
 
-    async fn get_dummy_address() -> Result<bitcoin::Address> {
-        Ok("1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6".parse()?)
+    async fn get_dummy_addresses() -> Result<Vec<bitcoin::Address>> {
+        Ok(vec!["1PdfytjS7C8wwd9Lq5o4x9aXA2YRqaCpH6".parse()?])
+    }
+
+    #[tokio::test]
+    async fn resumed_swap_redials_the_stored_address() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+        let swap_id = Uuid::new_v4();
+
+        let peer_id = PeerId::random();
+        let stored_addr = "/ip4/10.0.0.1/tcp/9939".parse::<Multiaddr>().unwrap();
+        let supplied_addr = "/ip4/10.0.0.2/tcp/9939".parse::<Multiaddr>().unwrap();
+
+        db.insert_counterparty(swap_id, Counterparty {
+            peer_id,
+            address: stored_addr.clone(),
+        })
+        .await
+        .unwrap();
+
+        let stored = db.get_counterparty(swap_id).unwrap();
+        let (dial_peer_id, dial_addr, fallback_addr) =
+            resume_counterparty(stored, Some(peer_id), Some(supplied_addr.clone()));
+
+        assert_eq!(dial_peer_id, peer_id);
+        assert_eq!(dial_addr, stored_addr);
+        assert_eq!(fallback_addr, Some(supplied_addr));
+    }
+
+    #[test]
+    fn stored_counterparty_is_dialed_without_supplied_peer() {
+        let stored = Counterparty {
+            peer_id: PeerId::random(),
+            address: "/ip4/10.0.0.1/tcp/9939".parse().unwrap(),
+        };
+
+        let (dial_peer_id, dial_addr, fallback_addr) =
+            resume_counterparty(Some(stored.clone()), None, None);
+
+        assert_eq!(dial_peer_id, stored.peer_id);
+        assert_eq!(dial_addr, stored.address);
+        assert_eq!(fallback_addr, None);
+    }
+
+    #[test]
+    fn supplied_address_is_used_without_stored_counterparty() {
+        let peer_id = PeerId::random();
+        let supplied_addr = "/ip4/10.0.0.2/tcp/9939".parse::<Multiaddr>().unwrap();
+
+        let (dial_peer_id, dial_addr, fallback_addr) =
+            resume_counterparty(None, Some(peer_id), Some(supplied_addr.clone()));
+
+        assert_eq!(dial_peer_id, peer_id);
+        assert_eq!(dial_addr, supplied_addr);
+        assert_eq!(fallback_addr, None);
     }
 }
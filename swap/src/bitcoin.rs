@@ -1,18 +1,26 @@
+pub mod bitcoind;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod wallet;
 
 mod cancel;
+mod electrum;
+#[cfg(test)]
+mod fake_electrum;
+mod fee_bump;
 mod lock;
 mod punish;
 mod redeem;
 mod refund;
+mod reservations;
 mod timelocks;
 
 pub use crate::bitcoin::cancel::{CancelTimelock, PunishTimelock, TxCancel};
 pub use crate::bitcoin::lock::TxLock;
 pub use crate::bitcoin::punish::TxPunish;
-pub use crate::bitcoin::redeem::TxRedeem;
+pub use crate::bitcoin::redeem::{sign_redeem_psbt, TxRedeem};
 pub use crate::bitcoin::refund::TxRefund;
-pub use crate::bitcoin::timelocks::{BlockHeight, ExpiredTimelocks};
+pub use crate::bitcoin::timelocks::{BlockHeight, ExpiredTimelocks, TimelockEpoch};
 pub use ::bitcoin::util::amount::Amount;
 pub use ::bitcoin::{Address, Network, Transaction, Txid};
 pub use ecdsa_fun::adaptor::EncryptedSignature;
@@ -23,8 +31,8 @@ pub use wallet::Wallet;
 use crate::bitcoin::wallet::ScriptStatus;
 use ::bitcoin::hashes::hex::ToHex;
 use ::bitcoin::hashes::Hash;
-use ::bitcoin::{secp256k1, SigHash};
-use anyhow::{bail, Context, Result};
+use ::bitcoin::{secp256k1, Denomination, SigHash};
+use anyhow::{anyhow, bail, Context, Result};
 use ecdsa_fun::adaptor::{Adaptor, HashTranscript};
 use ecdsa_fun::fun::Point;
 use ecdsa_fun::nonce::Deterministic;
@@ -45,6 +53,10 @@ use std::str::FromStr;
 // runs.
 pub const TX_FEE: u64 = 15_000;
 
+/// The dust threshold of a P2PKH output, the largest of the standard outputs
+/// the refund or punish transaction may pay to.
+pub const DUST_AMOUNT: u64 = 546;
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct SecretKey {
     inner: Scalar,
@@ -102,6 +114,17 @@ impl SecretKey {
     }
 }
 
+/// The role of a Bitcoin transaction in a swap.
+#[derive(Debug, Copy, Clone, strum::Display, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum TransactionKind {
+    Lock,
+    Redeem,
+    Cancel,
+    Refund,
+    Punish,
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PublicKey(Point);
 
@@ -225,17 +248,88 @@ pub fn current_epoch(
     tx_lock_status: ScriptStatus,
     tx_cancel_status: ScriptStatus,
 ) -> ExpiredTimelocks {
-    if tx_cancel_status.is_confirmed_with(punish_timelock) {
-        return ExpiredTimelocks::Punish;
-    }
+    timelock_epoch(
+        cancel_timelock,
+        punish_timelock,
+        tx_lock_status,
+        tx_cancel_status,
+    )
+    .epoch
+}
 
-    if tx_lock_status.is_confirmed_with(cancel_timelock) {
-        return ExpiredTimelocks::Cancel;
+pub fn timelock_epoch(
+    cancel_timelock: CancelTimelock,
+    punish_timelock: PunishTimelock,
+    tx_lock_status: ScriptStatus,
+    tx_cancel_status: ScriptStatus,
+) -> TimelockEpoch {
+    let epoch = if tx_cancel_status.is_confirmed_with(punish_timelock) {
+        ExpiredTimelocks::Punish
+    } else if tx_lock_status.is_confirmed_with(cancel_timelock) {
+        ExpiredTimelocks::Cancel
+    } else {
+        ExpiredTimelocks::None
+    };
+
+    TimelockEpoch {
+        epoch,
+        blocks_until_cancel: u32::from(cancel_timelock)
+            .saturating_sub(tx_lock_status.confirmations()),
+        blocks_until_punish: u32::from(punish_timelock)
+            .saturating_sub(tx_cancel_status.confirmations()),
     }
+}
+
+/// Parse an amount of bitcoin given in BTC.
+///
+/// The denomination can optionally be given as a suffix, i.e. `0.01` and
+/// `0.01 BTC` are parsed to the same amount. Amounts with more than 8 decimal
+/// places are rejected instead of being rounded.
+pub fn parse_btc(s: &str) -> Result<Amount> {
+    let mut parts = s.split_whitespace();
+
+    let amount = match (parts.next(), parts.next(), parts.next()) {
+        (Some(amount), None, None) => amount,
+        (Some(amount), Some(denomination), None) if denomination.eq_ignore_ascii_case("BTC") => {
+            amount
+        }
+        (Some(_), Some(denomination), None) => {
+            bail!("Expected an amount in BTC, got {}", denomination)
+        }
+        _ => bail!("Invalid Bitcoin amount {:?}", s),
+    };
+
+    Amount::from_str_in(amount, Denomination::Bitcoin)
+        .map_err(|e| anyhow!("Invalid Bitcoin amount {:?}: {}", s, e))
+}
 
-    ExpiredTimelocks::None
+/// Subtract `rhs` from `lhs`, failing instead of panicking if `rhs` is larger.
+///
+/// Use this for amounts that are partly provided by the counterparty or the
+/// electrum server.
+pub fn checked_sub(lhs: Amount, rhs: Amount) -> Result<Amount, AmountUnderflow> {
+    lhs.checked_sub(rhs).ok_or(AmountUnderflow { lhs, rhs })
+}
+
+/// Add up the given amounts, failing instead of panicking on overflow.
+pub fn checked_sum(amounts: impl IntoIterator<Item = Amount>) -> Result<Amount, AmountOverflow> {
+    amounts
+        .into_iter()
+        .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+        .ok_or(AmountOverflow)
+}
+
+#[derive(Clone, Copy, thiserror::Error, Debug)]
+#[error("cannot subtract {rhs} from {lhs}")]
+pub struct AmountUnderflow {
+    pub lhs: Amount,
+    pub rhs: Amount,
 }
 
+#[derive(Clone, Copy, thiserror::Error, Debug)]
+#[error("sum of amounts does not fit into a Bitcoin amount")]
+pub struct AmountOverflow;
+
 #[derive(Clone, Copy, thiserror::Error, Debug)]
 #[error("transaction does not spend anything")]
 pub struct NoInputs;
@@ -300,4 +394,109 @@ mod tests {
 
         assert_eq!(expired_timelock, ExpiredTimelocks::Punish)
     }
+
+    #[test]
+    fn timelock_epoch_before_cancel_counts_down_to_cancel() {
+        let epoch = timelock_epoch(
+            CancelTimelock::new(5),
+            PunishTimelock::new(5),
+            ScriptStatus::from_confirmations(2),
+            ScriptStatus::Unseen,
+        );
+
+        assert_eq!(epoch, TimelockEpoch {
+            epoch: ExpiredTimelocks::None,
+            blocks_until_cancel: 3,
+            blocks_until_punish: 5,
+        })
+    }
+
+    #[test]
+    fn timelock_epoch_in_cancel_counts_down_to_punish() {
+        let epoch = timelock_epoch(
+            CancelTimelock::new(5),
+            PunishTimelock::new(5),
+            ScriptStatus::from_confirmations(7),
+            ScriptStatus::from_confirmations(1),
+        );
+
+        assert_eq!(epoch, TimelockEpoch {
+            epoch: ExpiredTimelocks::Cancel,
+            blocks_until_cancel: 0,
+            blocks_until_punish: 4,
+        })
+    }
+
+    #[test]
+    fn timelock_epoch_in_punish_has_no_blocks_remaining() {
+        let epoch = timelock_epoch(
+            CancelTimelock::new(5),
+            PunishTimelock::new(5),
+            ScriptStatus::from_confirmations(12),
+            ScriptStatus::from_confirmations(6),
+        );
+
+        assert_eq!(epoch, TimelockEpoch {
+            epoch: ExpiredTimelocks::Punish,
+            blocks_until_cancel: 0,
+            blocks_until_punish: 0,
+        })
+    }
+
+    #[test]
+    fn parse_btc_with_and_without_denomination() {
+        let expected = Amount::from_sat(1_000_000);
+
+        assert_eq!(parse_btc("0.01").unwrap(), expected);
+        assert_eq!(parse_btc("0.01 BTC").unwrap(), expected);
+        assert_eq!(parse_btc(" 0.01 btc ").unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_btc_rejects_malformed_input() {
+        assert!(parse_btc("").is_err());
+        assert!(parse_btc("abc").is_err());
+        assert!(parse_btc("-0.01").is_err());
+        assert!(parse_btc("0.01 XMR").is_err());
+        assert!(parse_btc("0.01 BTC BTC").is_err());
+    }
+
+    #[test]
+    fn parse_btc_rejects_amounts_more_precise_than_a_satoshi() {
+        let error = parse_btc("0.000000001").unwrap_err();
+
+        assert!(error.to_string().contains("0.000000001"));
+    }
+
+    #[test]
+    fn subtracting_more_than_available_fails() {
+        let sent = Amount::from_sat(1_000);
+        let fees = Amount::from_sat(1_001);
+
+        assert!(checked_sub(sent, fees).is_err());
+        assert_eq!(checked_sub(fees, sent).unwrap(), Amount::from_sat(1));
+        assert_eq!(checked_sub(sent, sent).unwrap(), Amount::ZERO);
+    }
+
+    #[test]
+    fn summing_near_max_amounts_fails() {
+        let near_max = Amount::from_sat(u64::MAX - 1);
+
+        assert_eq!(
+            checked_sum(vec![near_max, Amount::from_sat(1)]).unwrap(),
+            Amount::from_sat(u64::MAX)
+        );
+        assert!(checked_sum(vec![near_max, Amount::from_sat(2)]).is_err());
+        assert_eq!(checked_sum(vec![]).unwrap(), Amount::ZERO);
+    }
+
+    #[test]
+    fn display_btc_dust_and_max_roundtrips() {
+        let dust = Amount::from_sat(546);
+        let max = Amount::from_sat(21_000_000 * 100_000_000);
+
+        assert_eq!(dust.to_string(), "0.00000546 BTC");
+        assert_eq!(parse_btc(&dust.to_string()).unwrap(), dust);
+        assert_eq!(parse_btc(&max.to_string()).unwrap(), max);
+    }
 }
@@ -12,6 +12,7 @@ use miniscript::{Descriptor, DescriptorTrait};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
 use std::ops::Add;
 
 /// Represent a timelock, expressed in relative block height as defined in
@@ -28,6 +29,18 @@ impl CancelTimelock {
     }
 }
 
+impl From<CancelTimelock> for u32 {
+    fn from(timelock: CancelTimelock) -> Self {
+        timelock.0
+    }
+}
+
+impl fmt::Display for CancelTimelock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} blocks", self.0)
+    }
+}
+
 impl Add<CancelTimelock> for BlockHeight {
     type Output = BlockHeight;
 
@@ -62,6 +75,12 @@ impl PunishTimelock {
     }
 }
 
+impl From<PunishTimelock> for u32 {
+    fn from(timelock: PunishTimelock) -> Self {
+        timelock.0
+    }
+}
+
 impl Add<PunishTimelock> for BlockHeight {
     type Output = BlockHeight;
 
@@ -151,15 +170,14 @@ impl TxCancel {
 
     pub fn complete_as_alice(
         self,
-        a: bitcoin::SecretKey,
+        (A, sig_a): (bitcoin::PublicKey, bitcoin::Signature),
         B: bitcoin::PublicKey,
         tx_cancel_sig_B: bitcoin::Signature,
     ) -> Result<Transaction> {
-        let sig_a = a.sign(self.digest());
         let sig_b = tx_cancel_sig_B;
 
         let tx_cancel = self
-            .add_signatures((a.public(), sig_a), (B, sig_b))
+            .add_signatures((A, sig_a), (B, sig_b))
             .expect("sig_{a,b} to be valid signatures for tx_cancel");
 
         Ok(tx_cancel)
@@ -0,0 +1,2179 @@
+use crate::bitcoin::timelocks::BlockHeight;
+use crate::bitcoin::wallet::{Confirmed, ScriptStatus, Watchable};
+use crate::bitcoin::Transaction;
+use crate::env;
+use crate::error::SwapError;
+use ::bitcoin::Txid;
+use anyhow::{anyhow, bail, Context, Result};
+use bdk::blockchain::{noop_progress, Blockchain, Capability, ElectrumBlockchain, Progress};
+use bdk::database::BatchDatabase;
+use bdk::electrum_client::{self, ElectrumApi, GetHistoryRes};
+use bdk::FeeRate;
+use bitcoin::Script;
+use reqwest::Url;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the latency of the configured electrum servers is measured again
+/// to pick the fastest one.
+const ELECTRUM_PROBE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The oldest version of the electrum protocol that provides all the methods
+/// the wallet relies on.
+const MIN_ELECTRUM_PROTOCOL_VERSION: &str = "1.4";
+
+/// The first delay before asking an electrum server that reported to be busy
+/// again. It doubles with every consecutive busy response.
+pub const SERVER_BUSY_INITIAL_DELAY: Duration = Duration::from_secs(5);
+
+const SERVER_BUSY_MAX_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// After this many consecutive busy responses the waiters are told to retry
+/// later.
+const SERVER_BUSY_MAX_CONSECUTIVE: u32 = 5;
+
+/// How long the history of a script is kept after its status was last
+/// requested, e.g. because the swap watching it was aborted.
+const SCRIPT_EVICTION_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// If the latest block did not change for this many average block intervals,
+/// header notifications may have stopped arriving and the current height is
+/// requested explicitly.
+const STALE_BLOCK_INTERVALS: u32 = 3;
+
+pub struct ElectrumServer {
+    pub url: Url,
+    /// `None` if the server could not be reached at startup, it is only
+    /// connected to again once all servers ranked before it failed.
+    pub client: Option<bdk::electrum_client::Client>,
+    timeout: Duration,
+}
+
+impl ElectrumServer {
+    /// Connect to the electrum server at `url`, requests fail if the server
+    /// does not answer within `timeout`.
+    pub fn connect(url: Url, timeout: Duration) -> Result<Self> {
+        let client = connect_electrum(&url, timeout)?;
+
+        Ok(Self {
+            url,
+            client: Some(client),
+            timeout,
+        })
+    }
+
+    /// A configured server that could not be reached, it is kept as a last
+    /// resort.
+    pub fn unreachable(url: Url, timeout: Duration) -> Self {
+        Self {
+            url,
+            client: None,
+            timeout,
+        }
+    }
+
+    /// Open `size` connections to this server for the [`ConnectionPool`],
+    /// each of them subscribed to header notifications.
+    fn open_pool(&self, size: usize) -> Result<ConnectionPool<bdk::electrum_client::Client>> {
+        let connections = (0..size.max(1))
+            .map(|_| {
+                let connection = connect_electrum(&self.url, self.timeout)?;
+                let latest_block = subscribe_to_headers(&connection)?;
+
+                Ok((connection, latest_block))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ConnectionPool::new(connections))
+    }
+
+    pub fn probe(&self) -> Result<()> {
+        let client = self
+            .client
+            .as_ref()
+            .with_context(|| format!("Electrum server {} is not connected", self.url))?;
+
+        client
+            .ping()
+            .map_err(|e| anyhow!("Failed to ping electrum server {}: {:?}", self.url, e))
+    }
+
+    /// Make sure the server speaks a recent enough version of the electrum
+    /// protocol.
+    ///
+    /// Servers that don't report their features are given the benefit of the
+    /// doubt.
+    fn check_protocol_version(&self) -> Result<()> {
+        let client = match &self.client {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let features = match client.server_features() {
+            Ok(features) => features,
+            Err(error) => {
+                tracing::debug!(url = %self.url, "Electrum server did not report its features: {:?}", error);
+                return Ok(());
+            }
+        };
+
+        tracing::info!(
+            url = %self.url,
+            server = %features.server_version,
+            protocol = %features.protocol_max,
+            "Connected to electrum server"
+        );
+
+        ensure_protocol_version(&features.protocol_max)
+    }
+}
+
+/// Connect to the electrum server at `url`, requests fail if the server does
+/// not answer within `timeout`.
+fn connect_electrum(url: &Url, timeout: Duration) -> Result<bdk::electrum_client::Client> {
+    // The electrum client takes the timeout in whole seconds, zero is not
+    // a valid socket timeout.
+    let timeout = u8::try_from(timeout.as_secs()).unwrap_or(u8::MAX).max(1);
+
+    // Workaround for https://github.com/bitcoindevkit/rust-electrum-client/issues/47.
+    let config = electrum_client::ConfigBuilder::default()
+        .retry(2)
+        .timeout(Some(timeout))
+        .map_err(|e| anyhow!("Failed to configure electrum rpc client: {:?}", e))?
+        .build();
+
+    let client = bdk::electrum_client::Client::from_config(url.as_str(), config)
+        .map_err(|e| SwapError::Network(anyhow!("Failed to init electrum rpc client: {:?}", e)))?;
+
+    Ok(client)
+}
+
+/// The electrum servers the bdk wallet syncs through, see [`Failover`].
+pub struct ElectrumFailover(std::sync::Mutex<Failover<ElectrumBlockchain, bdk::Error>>);
+
+impl ElectrumFailover {
+    pub fn new(ranking: Vec<Url>, timeout: Duration) -> Self {
+        let failover = Failover::new(
+            ranking,
+            move |url| {
+                let client = connect_electrum(url, timeout)
+                    .map_err(|error| bdk::Error::Generic(format!("{:#}", error)))?;
+
+                Ok(ElectrumBlockchain::from(client))
+            },
+            |error| {
+                !matches!(
+                    error,
+                    bdk::Error::Electrum(electrum_client::Error::Protocol(_))
+                )
+            },
+        );
+
+        Self(std::sync::Mutex::new(failover))
+    }
+
+    fn request<T>(
+        &self,
+        request: impl FnMut(&ElectrumBlockchain) -> Result<T, bdk::Error>,
+    ) -> Result<T, bdk::Error> {
+        self.0
+            .lock()
+            .expect("failover lock not to be poisoned")
+            .request(request)
+    }
+}
+
+impl Blockchain for ElectrumFailover {
+    fn get_capabilities(&self) -> HashSet<Capability> {
+        self.request(|blockchain| Ok(blockchain.get_capabilities()))
+            .unwrap_or_default()
+    }
+
+    fn setup<D: BatchDatabase, P: 'static + Progress>(
+        &self,
+        stop_gap: Option<usize>,
+        database: &mut D,
+        progress_update: P,
+    ) -> Result<(), bdk::Error> {
+        // The progress is only reported for the first attempt.
+        let mut progress_update = Some(progress_update);
+
+        self.request(|blockchain| match progress_update.take() {
+            Some(progress_update) => blockchain.setup(stop_gap, database, progress_update),
+            None => blockchain.setup(stop_gap, database, noop_progress()),
+        })
+    }
+
+    fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, bdk::Error> {
+        self.request(|blockchain| blockchain.get_tx(txid))
+    }
+
+    fn broadcast(&self, tx: &Transaction) -> Result<(), bdk::Error> {
+        self.request(|blockchain| blockchain.broadcast(tx))
+    }
+
+    fn get_height(&self) -> Result<u32, bdk::Error> {
+        self.request(|blockchain| blockchain.get_height())
+    }
+
+    fn estimate_fee(&self, target: usize) -> Result<FeeRate, bdk::Error> {
+        self.request(|blockchain| blockchain.estimate_fee(target))
+    }
+}
+
+/// Requests to a ranking of servers, starting with the first one.
+///
+/// A server failing a request for a reason that another server might not fail
+/// for, e.g. because it cannot be reached, is moved to the end of the ranking
+/// and the request is retried on the next one until every server failed once.
+struct Failover<C, E> {
+    ranking: Vec<Url>,
+    /// The connection to the first server of the ranking, established on
+    /// demand.
+    connection: Option<C>,
+    connect: Box<dyn Fn(&Url) -> Result<C, E> + Send>,
+    is_server_failure: fn(&E) -> bool,
+}
+
+impl<C, E> Failover<C, E>
+where
+    E: fmt::Debug,
+{
+    fn new(
+        ranking: Vec<Url>,
+        connect: impl Fn(&Url) -> Result<C, E> + Send + 'static,
+        is_server_failure: fn(&E) -> bool,
+    ) -> Self {
+        assert!(!ranking.is_empty(), "failover needs at least one server");
+
+        Self {
+            ranking,
+            connection: None,
+            connect: Box::new(connect),
+            is_server_failure,
+        }
+    }
+
+    fn request<T>(&mut self, mut request: impl FnMut(&C) -> Result<T, E>) -> Result<T, E> {
+        let mut last_error = None;
+
+        for _ in 0..self.ranking.len() {
+            let connection = match self.connection.take() {
+                Some(connection) => Ok(connection),
+                None => (self.connect)(&self.ranking[0]),
+            };
+            let error = match connection {
+                Ok(connection) => {
+                    let response = request(&connection);
+                    self.connection = Some(connection);
+
+                    match response {
+                        Err(error) if (self.is_server_failure)(&error) => error,
+                        response => return response,
+                    }
+                }
+                Err(error) => error,
+            };
+
+            tracing::warn!(
+                url = %self.ranking[0],
+                "Electrum server failed, trying the next one: {:?}",
+                error
+            );
+            self.connection = None;
+            self.ranking.rotate_left(1);
+            last_error = Some(error);
+        }
+
+        Err(last_error.expect("failover has at least one server"))
+    }
+}
+
+fn ensure_protocol_version(protocol_max: &str) -> Result<()> {
+    let parse = |version: &str| {
+        version
+            .split('.')
+            .map(u32::from_str)
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let supported = parse(protocol_max)
+        .with_context(|| format!("Invalid electrum protocol version {}", protocol_max))?;
+    let required = parse(MIN_ELECTRUM_PROTOCOL_VERSION).expect("a valid protocol version");
+
+    if supported < required {
+        bail!(
+            "Electrum server only supports protocol version {}, at least {} is required",
+            protocol_max,
+            MIN_ELECTRUM_PROTOCOL_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+/// Open a connection pool to the first of the given servers that answers,
+/// moving the ones that don't to the end.
+fn open_first_reachable(
+    servers: &mut Vec<ElectrumServer>,
+    pool_size: usize,
+) -> Result<ConnectionPool<bdk::electrum_client::Client>> {
+    for _ in 0..servers.len() {
+        match servers[0].open_pool(pool_size) {
+            Ok(pool) => return Ok(pool),
+            Err(error) => {
+                tracing::warn!(url = %servers[0].url, "Electrum server did not answer: {:#}", error);
+                servers.rotate_left(1);
+            }
+        }
+    }
+
+    let urls = servers
+        .iter()
+        .map(|server| server.url.to_string())
+        .collect::<Vec<_>>();
+
+    bail!(
+        "Electrum servers {} did not answer, make sure they are reachable and responsive",
+        urls.join(", ")
+    )
+}
+
+/// Order the given servers by the time it takes them to answer the probe.
+///
+/// Servers failing the probe are kept as last resort, in their original order.
+pub fn rank_by_latency<S>(servers: Vec<S>, probe: impl Fn(&S) -> Result<()>) -> Vec<S> {
+    let mut measured = servers
+        .into_iter()
+        .map(|server| {
+            let start = Instant::now();
+            let latency = probe(&server).ok().map(|()| start.elapsed());
+
+            (server, latency)
+        })
+        .collect::<Vec<_>>();
+
+    measured.sort_by_key(|(_, latency)| (latency.is_none(), *latency));
+
+    measured.into_iter().map(|(server, _)| server).collect()
+}
+
+pub struct Client {
+    /// The configured servers, ordered by latency. The first one is in use.
+    servers: Vec<ElectrumServer>,
+    /// The connections to the server in use.
+    pool: ConnectionPool<bdk::electrum_client::Client>,
+    pool_size: usize,
+    last_probe: Instant,
+    interval: Duration,
+    script_histories: ScriptHistories,
+    history_retries: u32,
+    history_retry_delay: Duration,
+    max_batch_size: usize,
+    rate_limiter: RateLimiter,
+    busy_backoff: BusyBackoff,
+    reorgs: ReorgTracker,
+    status_cache: StatusCache,
+    block_progress: BlockProgress,
+}
+
+impl Client {
+    pub fn new(
+        servers: Vec<ElectrumServer>,
+        interval: Duration,
+        avg_block_time: Duration,
+        config: env::ElectrumConfig,
+    ) -> Result<Self> {
+        let mut servers = servers
+            .into_iter()
+            .filter(|server| match server.check_protocol_version() {
+                Ok(()) => true,
+                Err(error) => {
+                    tracing::warn!(url = %server.url, "Not using electrum server: {:#}", error);
+                    false
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if servers.is_empty() {
+            bail!("No electrum server with a supported protocol version configured");
+        }
+        let pool = open_first_reachable(&mut servers, config.pool_size)?;
+        let block_progress = BlockProgress::new(
+            pool.latest_block(),
+            Instant::now(),
+            avg_block_time * STALE_BLOCK_INTERVALS,
+        );
+
+        Ok(Self {
+            servers,
+            pool,
+            pool_size: config.pool_size,
+            last_probe: Instant::now(),
+            interval,
+            script_histories: ScriptHistories::new(config.empty_history_polls)
+                .with_max_scripts(config.max_watched_scripts),
+            history_retries: config.history_retries,
+            history_retry_delay: config.history_retry_delay,
+            max_batch_size: config.max_batch_size,
+            rate_limiter: RateLimiter::new(config.max_requests_per_second),
+            busy_backoff: BusyBackoff::default(),
+            reorgs: ReorgTracker::default(),
+            status_cache: StatusCache::new(config.status_cache_window),
+            block_progress,
+        })
+    }
+
+    /// The highest block any of the pooled connections was notified about.
+    pub fn latest_block(&self) -> BlockHeight {
+        self.pool.latest_block()
+    }
+
+    /// Demote the server in use and continue with the next one that can be
+    /// reached.
+    fn fail_over(&mut self) -> Result<()> {
+        if self.servers.len() < 2 {
+            return Ok(());
+        }
+
+        self.servers.rotate_left(1);
+        self.switched_primary()
+    }
+
+    /// Measure the latency of all servers again and switch to the fastest one.
+    fn reprobe(&mut self) -> Result<()> {
+        if self.servers.len() < 2 || self.last_probe.elapsed() <= ELECTRUM_PROBE_INTERVAL {
+            return Ok(());
+        }
+        self.last_probe = Instant::now();
+
+        let primary = self.servers[0].url.clone();
+        self.servers = rank_by_latency(std::mem::take(&mut self.servers), ElectrumServer::probe);
+
+        if self.servers[0].url != primary {
+            self.switched_primary()?;
+        }
+
+        Ok(())
+    }
+
+    fn switched_primary(&mut self) -> Result<()> {
+        self.pool = open_first_reachable(&mut self.servers, self.pool_size)?;
+        tracing::info!(url = %self.servers[0].url, "Switched electrum server");
+
+        // Histories might be stale after the switch, request them all again.
+        self.script_histories.activate_all();
+
+        Ok(())
+    }
+
+    /// The status of the given transaction if it was requested shortly
+    /// before.
+    fn cached_status_of_script<T>(&self, tx: &T) -> Option<ScriptStatus>
+    where
+        T: Watchable,
+    {
+        self.status_cache
+            .get(&(tx.id(), tx.script()), Instant::now(), self.latest_block())
+    }
+
+    /// Mark the script of the given transaction as watched and check out a
+    /// pooled connection to refresh the chain state with, unless none of them
+    /// is due yet.
+    ///
+    /// The history of the script is not fetched again if its status is cached
+    /// and the refresh brings no new block.
+    pub fn prepare_refresh<T>(&mut self, tx: &T) -> Result<Option<Refresh>>
+    where
+        T: Watchable,
+    {
+        let now = Instant::now();
+        let cached_script = self.cached_status_of_script(tx).map(|_| tx.script());
+        self.script_histories.request(tx.script(), now);
+
+        self.reprobe()?;
+
+        let connection = match self.pool.checkout(now, self.interval) {
+            Some(connection) => connection,
+            None => return Ok(None),
+        };
+
+        if !self.rate_limiter.try_acquire(now) {
+            tracing::debug!("Electrum request limit reached, skipping ping");
+            self.pool.checkin(&connection, None);
+
+            return Ok(None);
+        }
+
+        let check_height = self.block_progress.is_stale(now);
+        if check_height {
+            tracing::warn!(
+                "Latest known Bitcoin block {} did not change for a while, header notifications may have stopped arriving. Requesting the current height",
+                u32::from(self.latest_block())
+            );
+        }
+
+        self.script_histories
+            .evict_abandoned(now, SCRIPT_EVICTION_TIMEOUT);
+        self.script_histories.evict_least_recently_requested();
+
+        let scripts =
+            if !self.script_histories.has_active() || self.busy_backoff.is_backing_off(now) {
+                vec![]
+            } else if !self.rate_limiter.try_acquire(now) {
+                tracing::debug!("Electrum request limit reached, skipping script history update");
+                vec![]
+            } else {
+                self.script_histories
+                    .take_active()
+                    .into_iter()
+                    .collect::<Vec<_>>()
+            };
+
+        Ok(Some(Refresh {
+            connection,
+            scripts,
+            cached_script,
+            history_retries: self.history_retries,
+            history_retry_delay: self.history_retry_delay,
+            max_batch_size: self.max_batch_size,
+            check_height,
+        }))
+    }
+
+    /// Check out a pooled connection to fetch the history of the script of the
+    /// given transaction right away, no matter when the connection was
+    /// refreshed last. `None` if all connections are in use.
+    pub fn prepare_fetch<T>(&mut self, tx: &T) -> Option<Refresh>
+    where
+        T: Watchable,
+    {
+        let now = Instant::now();
+        self.script_histories.request(tx.script(), now);
+
+        let connection = self.pool.checkout(now, Duration::from_secs(0))?;
+
+        Some(Refresh {
+            connection,
+            scripts: vec![tx.script()],
+            cached_script: None,
+            history_retries: self.history_retries,
+            history_retry_delay: self.history_retry_delay,
+            max_batch_size: self.max_batch_size,
+            check_height: false,
+        })
+    }
+
+    /// Apply the outcome of a [`Refresh`] and return the connection to the
+    /// pool.
+    pub fn complete_refresh(&mut self, outcome: RefreshOutcome) -> Result<()> {
+        let RefreshOutcome {
+            connection,
+            scripts,
+            ping,
+            new_blocks,
+            current_height,
+            histories,
+        } = outcome;
+
+        if let Err(error) = ping {
+            tracing::debug!(?error, "Failed to ping electrum server");
+            self.pool.checkin(&connection, None);
+            self.script_histories.activate(scripts);
+
+            if let Err(error) = self.fail_over() {
+                tracing::warn!("Failed to switch to fallback electrum server: {:#}", error);
+            }
+
+            return Ok(());
+        }
+        self.pool.checkin(&connection, Some(Instant::now()));
+
+        let new_blocks = match new_blocks {
+            Ok(new_blocks) => new_blocks,
+            Err(error) => {
+                self.script_histories.activate(scripts);
+                return Err(error);
+            }
+        };
+        for new_block in new_blocks {
+            tracing::debug!(
+                "Got notification for new block at height {}",
+                u32::from(new_block)
+            );
+
+            if let Some(previous_block) = self.pool.observe(&connection, new_block) {
+                self.reorgs
+                    .observe(u32::from(previous_block), u32::from(new_block));
+            }
+        }
+
+        let height_checked = match current_height {
+            Some(Ok(current_height)) => {
+                if self.pool.correct(&connection, current_height) {
+                    tracing::info!(
+                        "Corrected stale latest Bitcoin block to {}",
+                        u32::from(current_height)
+                    );
+                }
+
+                true
+            }
+            Some(Err(error)) => {
+                tracing::warn!(
+                    "Failed to request the current Bitcoin block height: {:#}",
+                    error
+                );
+                false
+            }
+            None => false,
+        };
+        self.block_progress
+            .observe(self.pool.latest_block(), Instant::now(), height_checked);
+
+        let histories = match histories {
+            Some(histories) => histories,
+            None => return Ok(()),
+        };
+        let histories = match record_response(&mut self.busy_backoff, Instant::now(), histories) {
+            Ok(Some(histories)) => histories,
+            Ok(None) => {
+                // Keep the histories stale and ask again once the server recovered.
+                self.script_histories.activate(scripts);
+                return Ok(());
+            }
+            Err(error) => {
+                self.script_histories.activate(scripts);
+                return Err(error);
+            }
+        };
+
+        if histories.len() < scripts.len() {
+            tracing::warn!(
+                "Received {} out of {} script histories, the others stay stale until the next update",
+                histories.len(),
+                scripts.len()
+            );
+        }
+
+        let mut scripts = scripts.into_iter();
+        for (script, history) in scripts.by_ref().zip(histories) {
+            self.script_histories.update(script, history);
+        }
+        self.script_histories.activate(scripts);
+
+        Ok(())
+    }
+
+    /// The status of the given transaction according to the known script
+    /// histories, answered from the cache if it was requested shortly before.
+    pub fn status_of_script<T>(&mut self, tx: &T) -> Result<ScriptStatus>
+    where
+        T: Watchable,
+    {
+        if let Some(status) = self.cached_status_of_script(tx) {
+            return Ok(status);
+        }
+
+        self.fresh_status_of_script(tx)
+    }
+
+    /// Like [`Client::status_of_script`] but replaces a cached status, e.g.
+    /// right after the history of the script was fetched.
+    pub fn fresh_status_of_script<T>(&mut self, tx: &T) -> Result<ScriptStatus>
+    where
+        T: Watchable,
+    {
+        let status = self.status_from_history(tx)?;
+        self.status_cache.insert(
+            (tx.id(), tx.script()),
+            status,
+            Instant::now(),
+            self.latest_block(),
+        );
+
+        Ok(status)
+    }
+
+    fn status_from_history<T>(&self, tx: &T) -> Result<ScriptStatus>
+    where
+        T: Watchable,
+    {
+        let txid = tx.id();
+        let history = self.script_histories.history(&tx.script());
+
+        let history_of_tx = history
+            .iter()
+            .filter(|entry| entry.tx_hash == txid)
+            .collect::<Vec<_>>();
+
+        match history_of_tx.as_slice() {
+            [] => Ok(ScriptStatus::Unseen),
+            [remaining @ .., last] => {
+                if !remaining.is_empty() {
+                    tracing::warn!("Found more than a single history entry for script. This is highly unexpected and those history entries will be ignored.")
+                }
+
+                if last.height <= 0 {
+                    Ok(ScriptStatus::InMempool)
+                } else {
+                    Ok(ScriptStatus::Confirmed(
+                        Confirmed::from_inclusion_and_latest_block(
+                            u32::try_from(last.height)?,
+                            u32::from(self.latest_block()),
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Whether the chain advanced far enough past the last reorg to trust
+    /// `conf_target` confirmations.
+    pub fn allows_finality(&self, conf_target: u32) -> bool {
+        self.reorgs
+            .allows_finality(u32::from(self.latest_block()), conf_target)
+    }
+
+    /// Keep the history of the given script while the returned guard is
+    /// alive, even if its status is not requested for a while.
+    pub fn watch(&self, script: Script) -> WatchGuard {
+        self.script_histories.watchers.watch(script)
+    }
+
+    /// The number of scripts whose histories are currently kept.
+    pub fn watched_scripts(&self) -> usize {
+        self.script_histories.len()
+    }
+}
+
+/// Connections to the electrum server in use.
+///
+/// Connections are handed out round-robin so concurrent status checks don't
+/// queue up behind a single one. Every connection is subscribed to header
+/// notifications on its own, the latest block is the highest any of them has
+/// been notified about.
+struct ConnectionPool<C> {
+    connections: Vec<PooledConnection<C>>,
+    /// Where to start looking for the next connection to hand out.
+    next: usize,
+}
+
+struct PooledConnection<C> {
+    client: Arc<C>,
+    latest_block: BlockHeight,
+    last_refresh: Option<Instant>,
+    in_use: bool,
+}
+
+impl<C> ConnectionPool<C> {
+    fn new(connections: Vec<(C, BlockHeight)>) -> Self {
+        Self {
+            connections: connections
+                .into_iter()
+                .map(|(client, latest_block)| PooledConnection {
+                    client: Arc::new(client),
+                    latest_block,
+                    last_refresh: None,
+                    in_use: false,
+                })
+                .collect(),
+            next: 0,
+        }
+    }
+
+    /// Hand out the next connection that is not in use and was not refreshed
+    /// within `interval`.
+    fn checkout(&mut self, now: Instant, interval: Duration) -> Option<Arc<C>> {
+        let len = self.connections.len();
+        let index = (0..len)
+            .map(|offset| (self.next + offset) % len)
+            .find(|index| {
+                let connection = &self.connections[*index];
+
+                !connection.in_use
+                    && connection.last_refresh.map_or(true, |last_refresh| {
+                        now.saturating_duration_since(last_refresh) > interval
+                    })
+            })?;
+        self.next = (index + 1) % len;
+
+        let connection = &mut self.connections[index];
+        connection.in_use = true;
+
+        Some(connection.client.clone())
+    }
+
+    /// Return a connection to the pool, `refreshed_at` is set if the refresh
+    /// succeeded.
+    ///
+    /// Connections of a pool that was replaced in the meantime are dropped.
+    fn checkin(&mut self, client: &Arc<C>, refreshed_at: Option<Instant>) {
+        if let Some(connection) = self.get_mut(client) {
+            connection.in_use = false;
+
+            if refreshed_at.is_some() {
+                connection.last_refresh = refreshed_at;
+            }
+        }
+    }
+
+    /// Record a block the given connection was notified about.
+    ///
+    /// Returns the latest block the connection knew about before.
+    fn observe(&mut self, client: &Arc<C>, new_block: BlockHeight) -> Option<BlockHeight> {
+        let connection = self.get_mut(client)?;
+
+        Some(std::mem::replace(&mut connection.latest_block, new_block))
+    }
+
+    fn latest_block(&self) -> BlockHeight {
+        self.connections
+            .iter()
+            .map(|connection| connection.latest_block)
+            .max()
+            .unwrap_or_else(|| BlockHeight::new(0))
+    }
+
+    /// Record the current height the given connection reported when asked
+    /// explicitly instead of through a notification.
+    ///
+    /// Returns whether the latest block was stale, i.e. lower than the current
+    /// height.
+    fn correct(&mut self, client: &Arc<C>, current_height: BlockHeight) -> bool {
+        if current_height <= self.latest_block() {
+            return false;
+        }
+
+        self.observe(client, current_height).is_some()
+    }
+
+    fn get_mut(&mut self, client: &Arc<C>) -> Option<&mut PooledConnection<C>> {
+        self.connections
+            .iter_mut()
+            .find(|connection| Arc::ptr_eq(&connection.client, client))
+    }
+}
+
+/// A refresh of the chain state over one of the pooled connections.
+///
+/// Talks to the electrum server and therefore runs without holding the lock
+/// on the [`Client`].
+pub struct Refresh {
+    connection: Arc<bdk::electrum_client::Client>,
+    scripts: Vec<Script>,
+    /// The script whose status is cached, its history is only fetched if a
+    /// new block arrived.
+    cached_script: Option<Script>,
+    history_retries: u32,
+    history_retry_delay: Duration,
+    max_batch_size: usize,
+    /// Request the current height in case header notifications stopped
+    /// arriving.
+    check_height: bool,
+}
+
+pub struct RefreshOutcome {
+    connection: Arc<bdk::electrum_client::Client>,
+    scripts: Vec<Script>,
+    ping: Result<(), electrum_client::Error>,
+    new_blocks: Result<Vec<BlockHeight>>,
+    /// Only requested if the latest block is stale.
+    current_height: Option<Result<BlockHeight>>,
+    /// Only requested if there are scripts to update.
+    histories: Option<Result<Vec<Vec<GetHistoryRes>>>>,
+}
+
+impl Refresh {
+    pub fn run(self) -> RefreshOutcome {
+        let Refresh {
+            connection,
+            scripts,
+            cached_script,
+            history_retries,
+            history_retry_delay,
+            max_batch_size,
+            check_height,
+        } = self;
+
+        let ping = connection.ping();
+        let new_blocks = match &ping {
+            Ok(()) => pop_header_notifications(&connection),
+            Err(_) => Ok(vec![]),
+        };
+        let current_height = if ping.is_ok() && check_height {
+            Some(subscribe_to_headers(&connection))
+        } else {
+            None
+        };
+        // Notifications are drained before the cached status is trusted
+        let no_new_block = matches!(&new_blocks, Ok(new_blocks) if new_blocks.is_empty())
+            && current_height.is_none();
+        let scripts = match cached_script {
+            Some(cached_script) if no_new_block => scripts
+                .into_iter()
+                .filter(|script| *script != cached_script)
+                .collect(),
+            _ => scripts,
+        };
+        let histories = if ping.is_ok() && new_blocks.is_ok() && !scripts.is_empty() {
+            Some(fetch_histories(
+                &scripts,
+                history_retries,
+                history_retry_delay,
+                |scripts| {
+                    fetch_in_batches(scripts, max_batch_size, |batch| {
+                        connection
+                            .batch_script_get_history(batch.iter())
+                            .map_err(|e| {
+                                if is_server_busy(&e) {
+                                    anyhow!(ElectrumServerBusy)
+                                } else {
+                                    anyhow!("Failed to get script histories {:?}", e)
+                                }
+                            })
+                    })
+                },
+            ))
+        } else {
+            None
+        };
+
+        RefreshOutcome {
+            connection,
+            scripts,
+            ping,
+            new_blocks,
+            current_height,
+            histories,
+        }
+    }
+}
+
+impl RefreshOutcome {
+    /// Whether the history of at least one script was fetched.
+    pub fn fetched_history(&self) -> bool {
+        matches!(&self.histories, Some(Ok(histories)) if !histories.is_empty())
+    }
+}
+
+fn pop_header_notifications(electrum: &bdk::electrum_client::Client) -> Result<Vec<BlockHeight>> {
+    std::iter::from_fn(|| electrum.block_headers_pop().transpose())
+        .map(|notification| {
+            let notification =
+                notification.map_err(|e| anyhow!("Failed to pop header notification: {:?}", e))?;
+
+            BlockHeight::try_from(notification)
+        })
+        .collect()
+}
+
+/// Request the histories of the given scripts in batches of at most
+/// `max_batch_size` scripts and merge the responses in request order.
+///
+/// Merging stops at the first incomplete response to keep histories aligned
+/// with the scripts they belong to.
+fn fetch_in_batches(
+    scripts: &[Script],
+    max_batch_size: usize,
+    fetch: impl Fn(&[Script]) -> Result<Vec<Vec<GetHistoryRes>>>,
+) -> Result<Vec<Vec<GetHistoryRes>>> {
+    let mut histories = Vec::with_capacity(scripts.len());
+
+    for batch in scripts.chunks(max_batch_size.max(1)) {
+        let mut response = fetch(batch)?;
+        let is_complete = response.len() >= batch.len();
+        response.truncate(batch.len());
+        histories.extend(response);
+
+        if !is_complete {
+            break;
+        }
+    }
+
+    Ok(histories)
+}
+
+/// Request the histories of the given scripts, trying again up to `retries`
+/// times if the server fails or answers with fewer histories than requested.
+///
+/// Responses are ordered like the requested scripts, an incomplete response
+/// therefore holds the histories of the first scripts. If no attempt yields
+/// all histories, the most complete response is returned.
+fn fetch_histories(
+    scripts: &[Script],
+    retries: u32,
+    retry_delay: Duration,
+    fetch: impl Fn(&[Script]) -> Result<Vec<Vec<GetHistoryRes>>>,
+) -> Result<Vec<Vec<GetHistoryRes>>> {
+    let mut most_complete: Option<Vec<Vec<GetHistoryRes>>> = None;
+    let mut last_error = None;
+
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            // Runs on a blocking thread, see `Refresh`
+            std::thread::sleep(retry_delay);
+        }
+
+        match fetch(scripts) {
+            // Retrying right away only adds to the load of a busy server.
+            Err(error) if error.is::<ElectrumServerBusy>() => return Err(error),
+            Ok(mut histories) if histories.len() >= scripts.len() => {
+                histories.truncate(scripts.len());
+                return Ok(histories);
+            }
+            Ok(histories) => {
+                tracing::debug!(
+                    attempt,
+                    "Expected {} history entries, received {}",
+                    scripts.len(),
+                    histories.len()
+                );
+
+                let is_more_complete = most_complete
+                    .as_ref()
+                    .map_or(true, |most_complete| histories.len() > most_complete.len());
+                if is_more_complete {
+                    most_complete = Some(histories);
+                }
+            }
+            Err(error) => {
+                tracing::debug!(attempt, "Failed to get script histories: {:#}", error);
+                last_error = Some(error);
+            }
+        }
+    }
+
+    match (most_complete, last_error) {
+        (Some(histories), _) => Ok(histories),
+        (None, Some(error)) => Err(error),
+        (None, None) => unreachable!("at least one attempt is made"),
+    }
+}
+
+/// The histories of the scripts we are watching.
+struct ScriptHistories {
+    entries: BTreeMap<Script, ScriptHistory>,
+    /// Scripts whose status has been requested since the last update of the
+    /// script histories.
+    active: BTreeSet<Script>,
+    /// How many consecutive empty histories replace a confirmed one.
+    empty_history_polls: u32,
+    /// How many histories are kept before evicting the least recently
+    /// requested ones.
+    max_scripts: usize,
+    watchers: Watchers,
+}
+
+struct ScriptHistory {
+    history: Vec<GetHistoryRes>,
+    last_requested: Instant,
+    /// Consecutive empty histories received while a confirmed one is known.
+    empty_responses: u32,
+}
+
+impl ScriptHistories {
+    fn new(empty_history_polls: u32) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            active: BTreeSet::new(),
+            empty_history_polls,
+            max_scripts: usize::MAX,
+            watchers: Watchers::default(),
+        }
+    }
+
+    fn with_max_scripts(self, max_scripts: usize) -> Self {
+        Self {
+            max_scripts,
+            ..self
+        }
+    }
+
+    /// The number of scripts whose histories are kept.
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn request(&mut self, script: Script, now: Instant) {
+        self.entries
+            .entry(script.clone())
+            .or_insert_with(|| ScriptHistory {
+                history: vec![],
+                last_requested: now,
+                empty_responses: 0,
+            })
+            .last_requested = now;
+        self.active.insert(script);
+    }
+
+    fn history(&self, script: &Script) -> &[GetHistoryRes] {
+        self.entries
+            .get(script)
+            .map(|entry| entry.history.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Replace the history of the given script.
+    ///
+    /// A confirmed history is only replaced by an empty one once enough
+    /// consecutive updates agree, servers that are briefly out of sync
+    /// answer with an empty history for transactions they don't know yet.
+    fn update(&mut self, script: Script, history: Vec<GetHistoryRes>) {
+        let entry = match self.entries.get_mut(&script) {
+            Some(entry) => entry,
+            None => return,
+        };
+
+        let was_confirmed = entry.history.iter().any(|entry| entry.height > 0);
+        if history.is_empty() && was_confirmed {
+            entry.empty_responses += 1;
+
+            if entry.empty_responses < self.empty_history_polls {
+                tracing::debug!(
+                    %script,
+                    "Ignoring empty history of script with a confirmed transaction ({}/{})",
+                    entry.empty_responses,
+                    self.empty_history_polls
+                );
+                return;
+            }
+        }
+
+        entry.empty_responses = 0;
+        entry.history = history;
+    }
+
+    fn has_active(&self) -> bool {
+        !self.active.is_empty()
+    }
+
+    fn take_active(&mut self) -> BTreeSet<Script> {
+        std::mem::take(&mut self.active)
+    }
+
+    fn activate(&mut self, scripts: impl IntoIterator<Item = Script>) {
+        self.active.extend(scripts);
+    }
+
+    fn activate_all(&mut self) {
+        self.active.extend(self.entries.keys().cloned());
+    }
+
+    /// Forget about scripts nobody requested within the given timeout.
+    fn evict_abandoned(&mut self, now: Instant, timeout: Duration) {
+        let active = &mut self.active;
+        let watchers = &self.watchers;
+
+        self.entries.retain(|script, entry| {
+            let abandoned = now.saturating_duration_since(entry.last_requested) > timeout
+                && !watchers.is_watched(script);
+
+            if abandoned {
+                tracing::debug!(%script, "Evicting history of script that is no longer watched");
+                active.remove(script);
+            }
+
+            !abandoned
+        });
+    }
+
+    /// Evict the least recently requested histories beyond the maximum number
+    /// of scripts.
+    ///
+    /// Scripts somebody is waiting on or that are due for an update are kept,
+    /// even if that exceeds the maximum.
+    fn evict_least_recently_requested(&mut self) {
+        let excess = self.entries.len().saturating_sub(self.max_scripts);
+        if excess == 0 {
+            return;
+        }
+
+        let mut evictable = self
+            .entries
+            .iter()
+            .filter(|(script, _)| {
+                !self.active.contains(*script) && !self.watchers.is_watched(script)
+            })
+            .map(|(script, entry)| (entry.last_requested, script.clone()))
+            .collect::<Vec<_>>();
+        evictable.sort();
+
+        for (_, script) in evictable.into_iter().take(excess) {
+            self.entries.remove(&script);
+        }
+
+        tracing::debug!(
+            watched_scripts = self.entries.len(),
+            "Evicted histories of scripts beyond the maximum of {}",
+            self.max_scripts
+        );
+    }
+}
+
+/// The number of waiters per script, their histories are never evicted.
+#[derive(Clone, Default)]
+struct Watchers(Arc<std::sync::Mutex<BTreeMap<Script, usize>>>);
+
+impl Watchers {
+    /// Register a waiter for the given script until the returned guard is
+    /// dropped.
+    fn watch(&self, script: Script) -> WatchGuard {
+        *self
+            .0
+            .lock()
+            .expect("watchers lock not to be poisoned")
+            .entry(script.clone())
+            .or_default() += 1;
+
+        WatchGuard {
+            watchers: self.clone(),
+            script,
+        }
+    }
+
+    fn is_watched(&self, script: &Script) -> bool {
+        self.0
+            .lock()
+            .expect("watchers lock not to be poisoned")
+            .contains_key(script)
+    }
+}
+
+pub struct WatchGuard {
+    watchers: Watchers,
+    script: Script,
+}
+
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        let mut watchers = self
+            .watchers
+            .0
+            .lock()
+            .expect("watchers lock not to be poisoned");
+
+        if let Some(count) = watchers.get_mut(&self.script) {
+            *count -= 1;
+
+            if *count == 0 {
+                watchers.remove(&self.script);
+            }
+        }
+    }
+}
+
+fn subscribe_to_headers(electrum: &bdk::electrum_client::Client) -> Result<BlockHeight> {
+    let latest_block = electrum.block_headers_subscribe().map_err(|e| {
+        SwapError::Network(anyhow!(
+            "Electrum client failed to subscribe to header notifications: {:?}",
+            e
+        ))
+    })?;
+
+    BlockHeight::try_from(latest_block)
+}
+
+/// The electrum server repeatedly refused to answer because it is overloaded
+/// or rate limits us. Asking again later is expected to succeed.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Electrum server is busy, retry later")]
+pub struct ElectrumServerBusy;
+
+/// Whether the electrum server refused the request because it is overloaded
+/// or rate limits us.
+fn is_server_busy(error: &electrum_client::Error) -> bool {
+    let message = match error {
+        electrum_client::Error::Protocol(value) => value.to_string(),
+        _ => return false,
+    }
+    .to_lowercase();
+
+    [
+        "excessive resource usage",
+        "server busy",
+        "rate limit",
+        "too many requests",
+    ]
+    .iter()
+    .any(|needle| message.contains(needle))
+}
+
+/// Backs off from an electrum server that reported to be busy.
+#[derive(Debug, Default)]
+struct BusyBackoff {
+    until: Option<Instant>,
+    delay: Duration,
+    consecutive: u32,
+}
+
+impl BusyBackoff {
+    fn is_backing_off(&self, now: Instant) -> bool {
+        matches!(self.until, Some(until) if now < until)
+    }
+
+    /// Returns whether the server has been busy for too long.
+    fn busy(&mut self, now: Instant) -> bool {
+        self.delay = if self.consecutive == 0 {
+            SERVER_BUSY_INITIAL_DELAY
+        } else {
+            (self.delay * 2).min(SERVER_BUSY_MAX_DELAY)
+        };
+        self.consecutive += 1;
+        self.until = Some(now + self.delay);
+
+        tracing::debug!(
+            "Electrum server is busy, asking again in {}s",
+            self.delay.as_secs()
+        );
+
+        self.consecutive >= SERVER_BUSY_MAX_CONSECUTIVE
+    }
+
+    fn succeeded(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Update the backoff with the response of a request made while we were not
+/// backing off from a busy server.
+///
+/// Returns `None` if the server was busy. Once the server has been busy for
+/// too long, [`ElectrumServerBusy`] is returned.
+fn record_response<T>(
+    backoff: &mut BusyBackoff,
+    now: Instant,
+    response: Result<T>,
+) -> Result<Option<T>> {
+    match response {
+        Ok(response) => {
+            backoff.succeeded();
+            Ok(Some(response))
+        }
+        Err(error) if error.is::<ElectrumServerBusy>() => {
+            if backoff.busy(now) {
+                return Err(error);
+            }
+
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Remembers the height the chain went back to in the last reorg.
+///
+/// Confirmations of transactions are unreliable until the chain advanced past
+/// that height by the number of confirmations required.
+#[derive(Debug, Default)]
+struct ReorgTracker {
+    fork_height: Option<u32>,
+}
+
+impl ReorgTracker {
+    /// Only a height below the previous one is a reorg, the same height is
+    /// announced again e.g. by another pooled connection.
+    fn observe(&mut self, previous_height: u32, new_height: u32) {
+        if new_height >= previous_height {
+            return;
+        }
+
+        tracing::warn!(
+            "Bitcoin blockchain reorganisation detected, height went from {} to {}",
+            previous_height,
+            new_height
+        );
+
+        self.fork_height = Some(match self.fork_height {
+            Some(fork_height) => fork_height.max(new_height),
+            None => new_height,
+        });
+    }
+
+    fn allows_finality(&self, latest_height: u32, conf_target: u32) -> bool {
+        match self.fork_height {
+            Some(fork_height) => latest_height >= fork_height + conf_target,
+            None => true,
+        }
+    }
+}
+
+/// Tracks when the latest block last changed.
+///
+/// Header notifications can stop arriving while the electrum server still
+/// answers pings. The latest block then goes stale while the chain advances,
+/// confirmations are understated and swaps never reach finality.
+struct BlockProgress {
+    stale_after: Duration,
+    latest_block: BlockHeight,
+    last_change: Instant,
+}
+
+impl BlockProgress {
+    fn new(latest_block: BlockHeight, now: Instant, stale_after: Duration) -> Self {
+        Self {
+            stale_after,
+            latest_block,
+            last_change: now,
+        }
+    }
+
+    /// Record the latest block after a refresh.
+    ///
+    /// Requesting the current height explicitly counts as a change even if the
+    /// chain did not advance, so it is not requested again on every refresh.
+    fn observe(&mut self, latest_block: BlockHeight, now: Instant, height_checked: bool) {
+        if latest_block != self.latest_block || height_checked {
+            self.latest_block = latest_block;
+            self.last_change = now;
+        }
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_change) > self.stale_after
+    }
+}
+
+/// Recently computed statuses of transactions.
+///
+/// Many swaps poll the same transactions, answering from the cache saves
+/// requests to the electrum server. A cached status is only valid within the
+/// window and as long as no new block arrived.
+struct StatusCache {
+    window: Duration,
+    entries: HashMap<(Txid, Script), CachedStatus>,
+}
+
+struct CachedStatus {
+    status: ScriptStatus,
+    at: Instant,
+    latest_block: BlockHeight,
+}
+
+impl StatusCache {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get(
+        &self,
+        key: &(Txid, Script),
+        now: Instant,
+        latest_block: BlockHeight,
+    ) -> Option<ScriptStatus> {
+        self.entries
+            .get(key)
+            .filter(|cached| {
+                cached.latest_block == latest_block
+                    && now.saturating_duration_since(cached.at) <= self.window
+            })
+            .map(|cached| cached.status)
+    }
+
+    fn insert(
+        &mut self,
+        key: (Txid, Script),
+        status: ScriptStatus,
+        now: Instant,
+        latest_block: BlockHeight,
+    ) {
+        let window = self.window;
+        self.entries
+            .retain(|_, cached| now.saturating_duration_since(cached.at) <= window);
+
+        self.entries.insert(key, CachedStatus {
+            status,
+            at: now,
+            latest_block,
+        });
+    }
+}
+
+/// Limits the number of requests within a window of one second.
+#[derive(Debug)]
+struct RateLimiter {
+    max_per_second: u32,
+    window_start: Instant,
+    requests_in_window: u32,
+}
+
+impl RateLimiter {
+    fn new(max_per_second: u32) -> Self {
+        Self {
+            max_per_second,
+            window_start: Instant::now(),
+            requests_in_window: 0,
+        }
+    }
+
+    /// Returns whether another request may be made at the given point in time.
+    fn try_acquire(&mut self, now: Instant) -> bool {
+        if now.saturating_duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.requests_in_window = 0;
+        }
+
+        if self.requests_in_window >= self.max_per_second {
+            return false;
+        }
+
+        self.requests_in_window += 1;
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::fake_electrum::{transaction, FakeElectrum};
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::OutPoint;
+
+    #[test]
+    fn rate_limiter_throttles_burst_of_requests() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter {
+            max_per_second: 3,
+            window_start: start,
+            requests_in_window: 0,
+        };
+
+        let granted = (0..10)
+            .filter(|_| limiter.try_acquire(start + Duration::from_millis(100)))
+            .count();
+        assert_eq!(granted, 3);
+
+        let granted_after_window = limiter.try_acquire(start + Duration::from_secs(1));
+        assert!(granted_after_window);
+    }
+
+    #[test]
+    fn abandoned_scripts_are_evicted_and_no_longer_fetched() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let watched = Script::from(vec![1u8]);
+        let abandoned = Script::from(vec![2u8]);
+
+        let mut histories = ScriptHistories::new(1);
+        histories.request(watched.clone(), start);
+        histories.request(abandoned.clone(), start);
+        assert_eq!(histories.take_active().len(), 2);
+
+        histories.request(watched.clone(), start + Duration::from_secs(50));
+        histories.activate_all();
+        histories.evict_abandoned(start + Duration::from_secs(90), timeout);
+
+        assert_eq!(
+            histories.take_active().into_iter().collect::<Vec<_>>(),
+            vec![watched.clone()]
+        );
+        assert!(histories.entries.contains_key(&watched));
+        assert!(!histories.entries.contains_key(&abandoned));
+    }
+
+    #[test]
+    fn least_recently_requested_scripts_are_evicted_past_the_maximum() {
+        let start = Instant::now();
+        let watched = Script::from(vec![1u8]);
+        let completed = Script::from(vec![2u8]);
+        let recent = Script::from(vec![3u8]);
+        let due = Script::from(vec![4u8]);
+
+        let mut histories = ScriptHistories::new(1).with_max_scripts(3);
+        let _waiter = histories.watchers.watch(watched.clone());
+        histories.request(watched.clone(), start);
+        histories.request(completed.clone(), start + Duration::from_secs(1));
+        histories.request(recent.clone(), start + Duration::from_secs(2));
+        histories.take_active();
+        histories.request(due.clone(), start + Duration::from_secs(3));
+
+        histories.evict_least_recently_requested();
+
+        assert_eq!(histories.len(), 3);
+        assert!(histories.entries.contains_key(&watched));
+        assert!(!histories.entries.contains_key(&completed));
+        assert!(histories.entries.contains_key(&recent));
+        assert!(histories.entries.contains_key(&due));
+    }
+
+    #[test]
+    fn scripts_are_evictable_once_nobody_waits_on_them() {
+        let start = Instant::now();
+        let watched = Script::from(vec![1u8]);
+        let other = Script::from(vec![2u8]);
+
+        let mut histories = ScriptHistories::new(1).with_max_scripts(1);
+        let waiter = histories.watchers.watch(watched.clone());
+        histories.request(watched.clone(), start);
+        histories.request(other.clone(), start + Duration::from_secs(1));
+        histories.take_active();
+
+        histories.evict_least_recently_requested();
+        assert!(histories.entries.contains_key(&watched));
+        assert!(!histories.entries.contains_key(&other));
+
+        drop(waiter);
+        histories.request(other.clone(), start + Duration::from_secs(2));
+        histories.take_active();
+
+        histories.evict_least_recently_requested();
+        assert!(!histories.entries.contains_key(&watched));
+        assert!(histories.entries.contains_key(&other));
+    }
+
+    #[test]
+    fn spurious_empty_history_does_not_unconfirm_transaction() {
+        let now = Instant::now();
+        let script = Script::from(vec![1u8]);
+        let mut histories = ScriptHistories::new(3);
+        histories.request(script.clone(), now);
+        histories.update(script.clone(), vec![history_entry(100)]);
+
+        histories.update(script.clone(), vec![]);
+        histories.update(script.clone(), vec![]);
+        assert_eq!(heights(&histories, &script), vec![100]);
+
+        histories.update(script.clone(), vec![history_entry(100)]);
+        histories.update(script.clone(), vec![]);
+        histories.update(script.clone(), vec![]);
+        assert_eq!(heights(&histories, &script), vec![100]);
+
+        histories.update(script.clone(), vec![]);
+        assert!(histories.history(&script).is_empty());
+    }
+
+    #[test]
+    fn empty_history_replaces_unconfirmed_one_right_away() {
+        let now = Instant::now();
+        let script = Script::from(vec![1u8]);
+        let mut histories = ScriptHistories::new(3);
+        histories.request(script.clone(), now);
+        histories.update(script.clone(), vec![history_entry(0)]);
+
+        histories.update(script.clone(), vec![]);
+
+        assert!(histories.history(&script).is_empty());
+    }
+
+    #[test]
+    fn finality_is_not_declared_until_chain_advanced_past_reorg() {
+        let conf_target = 3;
+        let mut reorgs = ReorgTracker::default();
+        let mut latest_height = 100;
+        let mut allows_finality = |new_height: u32| {
+            reorgs.observe(latest_height, new_height);
+            latest_height = new_height;
+
+            reorgs.allows_finality(latest_height, conf_target)
+        };
+
+        assert!(allows_finality(101));
+        assert!(allows_finality(102));
+        assert!(!allows_finality(100), "reorg back to 100");
+        assert!(!allows_finality(101));
+        assert!(!allows_finality(102));
+        assert!(allows_finality(103));
+    }
+
+    #[test]
+    fn same_height_is_not_a_reorg() {
+        let mut reorgs = ReorgTracker::default();
+
+        reorgs.observe(100, 100);
+
+        assert!(reorgs.allows_finality(100, 1));
+    }
+
+    #[test]
+    fn protocol_versions_below_minimum_are_rejected() {
+        assert!(ensure_protocol_version("1.4").is_ok());
+        assert!(ensure_protocol_version("1.4.2").is_ok());
+        assert!(ensure_protocol_version("1.10").is_ok());
+        assert!(ensure_protocol_version("1.2").is_err());
+        assert!(ensure_protocol_version("one").is_err());
+    }
+
+    #[test]
+    fn electrum_server_with_old_protocol_version_is_not_used() {
+        let url = serve_electrum(serde_json::json!({
+            "server_version": "ElectrumX 1.8.5",
+            "genesis_hash": "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+            "protocol_min": "1.0",
+            "protocol_max": "1.2",
+            "hash_function": "sha256",
+            "pruning": null
+        }));
+        let server = ElectrumServer::connect(url, Duration::from_secs(1)).unwrap();
+
+        let error = server.check_protocol_version().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Electrum server only supports protocol version 1.2, at least 1.4 is required"
+        );
+
+        let error = Client::new(
+            vec![server],
+            Duration::from_secs(1),
+            Duration::from_secs(600),
+            electrum_config(Duration::from_secs(1)),
+        )
+        .map(|_| ())
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No electrum server with a supported protocol version configured"
+        );
+    }
+
+    #[test]
+    fn unresponsive_electrum_server_times_out() {
+        // Connections are accepted by the OS but never answered.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("tcp://{}", listener.local_addr().unwrap())).unwrap();
+        let server = ElectrumServer::connect(url.clone(), Duration::from_secs(1)).unwrap();
+
+        let start = Instant::now();
+        let error = Client::new(
+            vec![server],
+            Duration::from_secs(1),
+            Duration::from_secs(600),
+            electrum_config(Duration::from_secs(1)),
+        )
+        .map(|_| ())
+        .unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert!(format!("{:#}", error).contains(url.as_str()));
+    }
+
+    #[test]
+    fn concurrent_refreshes_use_distinct_connections() {
+        let now = Instant::now();
+        let interval = Duration::from_secs(5);
+        let mut pool = ConnectionPool::new(vec![
+            ("first", BlockHeight::new(100)),
+            ("second", BlockHeight::new(100)),
+        ]);
+
+        let first = pool.checkout(now, interval).unwrap();
+        let second = pool.checkout(now, interval).unwrap();
+
+        assert_eq!((*first, *second), ("first", "second"));
+        assert!(
+            pool.checkout(now, interval).is_none(),
+            "all connections in use"
+        );
+
+        pool.checkin(&first, Some(now));
+        pool.checkin(&second, None);
+
+        let retried = pool.checkout(now, interval).unwrap();
+        assert_eq!(*retried, "second", "failed refresh is retried right away");
+        assert!(
+            pool.checkout(now, interval).is_none(),
+            "first is not due yet"
+        );
+
+        pool.checkin(&retried, Some(now));
+        let later = now + interval + Duration::from_secs(1);
+        assert_eq!(*pool.checkout(later, interval).unwrap(), "first");
+    }
+
+    #[test]
+    fn latest_block_is_highest_across_connections() {
+        let mut pool = ConnectionPool::new(vec![
+            ("first", BlockHeight::new(100)),
+            ("second", BlockHeight::new(101)),
+        ]);
+        assert_eq!(pool.latest_block(), BlockHeight::new(101));
+
+        let first = pool
+            .checkout(Instant::now(), Duration::from_secs(0))
+            .unwrap();
+        let previous = pool.observe(&first, BlockHeight::new(102));
+
+        assert_eq!(previous, Some(BlockHeight::new(100)));
+        assert_eq!(pool.latest_block(), BlockHeight::new(102));
+    }
+
+    #[test]
+    fn stale_latest_block_is_corrected_by_requesting_current_height() {
+        let electrum = FakeElectrum::default();
+        let server = ElectrumServer::connect(electrum.serve(), Duration::from_secs(1)).unwrap();
+        let avg_block_time = Duration::from_millis(100);
+        let mut client = Client::new(
+            vec![server],
+            Duration::from_secs(0),
+            avg_block_time,
+            electrum_config(Duration::from_secs(1)),
+        )
+        .unwrap();
+        let tx = (Txid::from_inner([1u8; 32]), Script::from(vec![0x51]));
+
+        electrum.stop_notifications();
+        for _ in 0..5 {
+            electrum.mine_block();
+        }
+        refresh(&mut client, &tx);
+        assert_eq!(client.latest_block(), BlockHeight::new(0));
+
+        std::thread::sleep(avg_block_time * STALE_BLOCK_INTERVALS + Duration::from_millis(50));
+        refresh(&mut client, &tx);
+        assert_eq!(client.latest_block(), BlockHeight::new(5));
+    }
+
+    #[test]
+    fn rapid_status_queries_fetch_history_once() {
+        let electrum = FakeElectrum::default();
+        let server = ElectrumServer::connect(electrum.serve(), Duration::from_secs(1)).unwrap();
+        let mut client = Client::new(
+            vec![server],
+            Duration::from_secs(0),
+            Duration::from_secs(600),
+            electrum_config(Duration::from_secs(60)),
+        )
+        .unwrap();
+        let mut transaction = transaction(vec![OutPoint::default()], vec![1_000]);
+        transaction.output[0].script_pubkey = Script::from(vec![0x51]);
+        let tx = (
+            transaction.txid(),
+            transaction.output[0].script_pubkey.clone(),
+        );
+        electrum.add_to_mempool(transaction);
+
+        refresh(&mut client, &tx);
+        let first = client.status_of_script(&tx).unwrap();
+        refresh(&mut client, &tx);
+        let second = client.status_of_script(&tx).unwrap();
+
+        assert_eq!(first, ScriptStatus::InMempool);
+        assert_eq!(second, ScriptStatus::InMempool);
+        assert_eq!(electrum.history_requests(), 1);
+
+        electrum.mine_block();
+        refresh(&mut client, &tx);
+        let mined = client.status_of_script(&tx).unwrap();
+
+        assert!(
+            mined.is_confirmed_with(1),
+            "new block invalidates the cache"
+        );
+        assert_eq!(electrum.history_requests(), 2);
+    }
+
+    #[test]
+    fn current_height_of_idle_chain_is_no_correction() {
+        let start = Instant::now();
+        let stale_after = Duration::from_secs(30);
+        let mut pool = ConnectionPool::new(vec![("connection", BlockHeight::new(100))]);
+        let mut progress = BlockProgress::new(pool.latest_block(), start, stale_after);
+
+        let later = start + stale_after + Duration::from_secs(1);
+        let connection = pool.checkout(later, Duration::from_secs(0)).unwrap();
+        assert!(!pool.correct(&connection, BlockHeight::new(100)));
+        progress.observe(pool.latest_block(), later, true);
+
+        assert_eq!(pool.latest_block(), BlockHeight::new(100));
+        assert!(!progress.is_stale(later + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn rate_limit_responses_are_detected() {
+        assert!(is_server_busy(&busy_error()));
+        assert!(!is_server_busy(&electrum_client::Error::Protocol(
+            serde_json::Value::String("unknown method".to_owned())
+        )));
+    }
+
+    #[test]
+    fn busy_server_is_asked_again_after_backing_off() {
+        let mut backoff = BusyBackoff::default();
+        let start = Instant::now();
+        let requests = std::cell::Cell::new(0);
+        let mock = || {
+            requests.set(requests.get() + 1);
+
+            match requests.get() {
+                1 => Err(anyhow!(ElectrumServerBusy)),
+                _ => Ok("history"),
+            }
+        };
+
+        let first = request_with_backoff(&mut backoff, start, mock).unwrap();
+        let while_backing_off = request_with_backoff(&mut backoff, start, mock).unwrap();
+        let after_backing_off =
+            request_with_backoff(&mut backoff, start + SERVER_BUSY_INITIAL_DELAY, mock).unwrap();
+
+        assert_eq!(first, None);
+        assert_eq!(while_backing_off, None);
+        assert_eq!(after_backing_off, Some("history"));
+        assert_eq!(requests.get(), 2);
+    }
+
+    #[test]
+    fn persistently_busy_server_asks_waiters_to_retry_later() {
+        let mut backoff = BusyBackoff::default();
+        let mut now = Instant::now();
+
+        for _ in 1..SERVER_BUSY_MAX_CONSECUTIVE {
+            let response = request_with_backoff(&mut backoff, now, || {
+                Err::<(), _>(anyhow!(ElectrumServerBusy))
+            });
+            assert!(response.unwrap().is_none());
+            now += SERVER_BUSY_MAX_DELAY;
+        }
+
+        let error = request_with_backoff(&mut backoff, now, || {
+            Err::<(), _>(anyhow!(ElectrumServerBusy))
+        })
+        .unwrap_err();
+        assert!(error.is::<ElectrumServerBusy>());
+    }
+
+    #[test]
+    fn large_script_sets_are_requested_in_batches() {
+        let scripts = (0..10u8).map(|i| Script::from(vec![i])).collect::<Vec<_>>();
+        let batch_sizes = std::cell::RefCell::new(vec![]);
+
+        let histories = fetch_in_batches(&scripts, 4, |batch| {
+            batch_sizes.borrow_mut().push(batch.len());
+
+            Ok(batch
+                .iter()
+                .map(|script| vec![history_entry(i32::from(script.as_bytes()[0]))])
+                .collect())
+        })
+        .unwrap();
+
+        assert_eq!(batch_sizes.into_inner(), vec![4, 4, 2]);
+        assert_eq!(
+            histories
+                .iter()
+                .map(|history| history[0].height)
+                .collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn batching_stops_at_incomplete_response() {
+        let scripts = (0..6u8).map(|i| Script::from(vec![i])).collect::<Vec<_>>();
+        let requests = std::cell::Cell::new(0);
+
+        let histories = fetch_in_batches(&scripts, 2, |batch| {
+            requests.set(requests.get() + 1);
+
+            match requests.get() {
+                2 => Ok(vec![vec![]]),
+                _ => Ok(batch.iter().map(|_| vec![]).collect()),
+            }
+        })
+        .unwrap();
+
+        assert_eq!(requests.get(), 2);
+        assert_eq!(histories.len(), 3);
+    }
+
+    #[test]
+    fn mismatched_history_response_is_retried() {
+        let scripts = vec![Script::from(vec![1]), Script::from(vec![2])];
+        let attempts = std::cell::Cell::new(0);
+
+        let histories = fetch_histories(&scripts, 3, Duration::from_secs(0), |_| {
+            attempts.set(attempts.get() + 1);
+
+            match attempts.get() {
+                1 => Ok(vec![vec![history_entry(1)]]),
+                _ => Ok(vec![vec![history_entry(1)], vec![history_entry(2)]]),
+            }
+        })
+        .unwrap();
+
+        assert_eq!(attempts.get(), 2);
+        assert_eq!(histories.len(), 2);
+        assert_eq!(histories[1][0].height, 2);
+    }
+
+    #[test]
+    fn most_complete_response_is_used_once_retries_are_exhausted() {
+        let scripts = vec![
+            Script::from(vec![1]),
+            Script::from(vec![2]),
+            Script::from(vec![3]),
+        ];
+        let attempts = std::cell::Cell::new(0);
+
+        let histories = fetch_histories(&scripts, 2, Duration::from_secs(0), |_| {
+            attempts.set(attempts.get() + 1);
+
+            match attempts.get() {
+                1 => Ok(vec![vec![history_entry(1)]]),
+                2 => Ok(vec![vec![history_entry(1)], vec![history_entry(2)]]),
+                _ => Err(anyhow!("connection reset")),
+            }
+        })
+        .unwrap();
+
+        assert_eq!(attempts.get(), 3);
+        assert_eq!(histories.len(), 2);
+        assert!(
+            fetch_histories(&scripts, 1, Duration::from_secs(0), |_| Err(anyhow!(
+                "connection reset"
+            )))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn retries_wait_for_the_retry_delay() {
+        let scripts = vec![Script::from(vec![1]), Script::from(vec![2])];
+        let start = Instant::now();
+
+        let histories = fetch_histories(&scripts, 2, Duration::from_millis(50), |_| {
+            Ok(vec![vec![history_entry(1)]])
+        })
+        .unwrap();
+
+        assert_eq!(histories.len(), 1);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn fastest_server_is_ranked_first() {
+        let servers = vec![
+            MockServer {
+                name: "down",
+                latency: None,
+            },
+            MockServer {
+                name: "slow",
+                latency: Some(Duration::from_millis(60)),
+            },
+            MockServer {
+                name: "fast",
+                latency: Some(Duration::from_millis(1)),
+            },
+            MockServer {
+                name: "medium",
+                latency: Some(Duration::from_millis(30)),
+            },
+        ];
+
+        let ranked = rank_by_latency(servers, MockServer::probe)
+            .into_iter()
+            .map(|server| server.name)
+            .collect::<Vec<_>>();
+
+        assert_eq!(ranked, vec!["fast", "medium", "slow", "down"]);
+    }
+
+    struct MockServer {
+        name: &'static str,
+        latency: Option<Duration>,
+    }
+
+    impl MockServer {
+        fn probe(&self) -> Result<()> {
+            match self.latency {
+                Some(latency) => {
+                    std::thread::sleep(latency);
+                    Ok(())
+                }
+                None => bail!("{} is down", self.name),
+            }
+        }
+    }
+
+    fn heights(histories: &ScriptHistories, script: &Script) -> Vec<i32> {
+        histories
+            .history(script)
+            .iter()
+            .map(|entry| entry.height)
+            .collect()
+    }
+
+    fn history_entry(height: i32) -> GetHistoryRes {
+        GetHistoryRes {
+            height,
+            tx_hash: Txid::default(),
+            fee: None,
+        }
+    }
+
+    fn request_with_backoff<T>(
+        backoff: &mut BusyBackoff,
+        now: Instant,
+        request: impl FnOnce() -> Result<T>,
+    ) -> Result<Option<T>> {
+        if backoff.is_backing_off(now) {
+            return Ok(None);
+        }
+
+        record_response(backoff, now, request())
+    }
+
+    fn busy_error() -> electrum_client::Error {
+        electrum_client::Error::Protocol(serde_json::Value::String(
+            "excessive resource usage".to_owned(),
+        ))
+    }
+
+    /// Serves an electrum server that answers every request with the given
+    /// result.
+    fn serve_electrum(result: serde_json::Value) -> Url {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("tcp://{}", listener.local_addr().unwrap())).unwrap();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let reader = BufReader::new(stream.try_clone().unwrap());
+
+                for line in reader.lines() {
+                    let request: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+                    let response = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": result,
+                    });
+
+                    writeln!(stream, "{}", response).unwrap();
+                }
+            }
+        });
+
+        url
+    }
+
+    /// A single connection to the electrum server, without delays between
+    /// retries, caching the status of a transaction for the given window.
+    fn electrum_config(status_cache_window: Duration) -> env::ElectrumConfig {
+        use crate::env::GetConfig;
+
+        env::ElectrumConfig {
+            max_requests_per_second: 10,
+            history_retry_delay: Duration::from_secs(0),
+            pool_size: 1,
+            status_cache_window,
+            ..env::Regtest::get_config().bitcoin_electrum
+        }
+    }
+
+    /// Refresh the chain state the way the wallet does when asked for a
+    /// status.
+    fn refresh(client: &mut Client, tx: &(Txid, Script)) {
+        if let Some(refresh) = client.prepare_refresh(tx).unwrap() {
+            let outcome = refresh.run();
+            client.complete_refresh(outcome).unwrap();
+        }
+    }
+}
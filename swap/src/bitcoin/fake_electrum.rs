@@ -0,0 +1,324 @@
+use crate::bitcoin::{Transaction, Txid};
+use ::bitcoin::{OutPoint, Script, TxOut};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An electrum server serving a chain kept in memory, just enough for a
+/// [`Wallet`](crate::bitcoin::Wallet) to connect to it, watch scripts and
+/// publish transactions.
+#[derive(Clone, Default)]
+pub struct FakeElectrum {
+    chain: Arc<std::sync::Mutex<FakeChain>>,
+    /// Close every connection on the next request, like a server that
+    /// went offline.
+    down: Arc<std::sync::atomic::AtomicBool>,
+    ping_latency: Duration,
+}
+
+#[derive(Default)]
+struct FakeChain {
+    height: u32,
+    /// The height transactions were mined at, 0 for the mempool.
+    transactions: HashMap<Txid, (Transaction, u32)>,
+    subscribers: Vec<std::net::TcpStream>,
+    /// Stop notifying subscribers about new blocks, like a server whose
+    /// notifications silently stopped arriving.
+    silent: bool,
+    /// The answer to fee estimates in BTC/kvB, 10 sat/vB if not set.
+    fee_estimate: Option<f64>,
+    history_requests: usize,
+    broadcasts: usize,
+}
+
+/// The regtest genesis block header, its content does not matter to the
+/// wallet.
+const FAKE_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2002000000";
+
+impl FakeElectrum {
+    pub fn serve(&self) -> Url {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("tcp://{}", listener.local_addr().unwrap())).unwrap();
+
+        let electrum = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let electrum = electrum.clone();
+                let stream = stream.unwrap();
+
+                std::thread::spawn(move || electrum.answer(stream));
+            }
+        });
+
+        url
+    }
+
+    /// Another server serving the same chain, answering pings after the
+    /// given latency.
+    pub fn replica(&self, ping_latency: Duration) -> Self {
+        Self {
+            chain: self.chain.clone(),
+            down: Default::default(),
+            ping_latency,
+        }
+    }
+
+    pub fn go_down(&self) {
+        self.down.store(true, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    fn answer(&self, stream: std::net::TcpStream) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        for line in reader.lines() {
+            if self.down.load(std::sync::atomic::Ordering::SeqCst) {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                return;
+            }
+
+            let request: serde_json::Value = match line {
+                Ok(line) => serde_json::from_str(&line).unwrap(),
+                Err(_) => return,
+            };
+            if request["method"] == "server.ping" {
+                std::thread::sleep(self.ping_latency);
+            }
+
+            // Answer while holding the chain to not interleave with header
+            // notifications.
+            let mut chain = self.chain.lock().unwrap();
+            let response = match chain.answer(&request, &stream) {
+                Ok(result) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "result": result,
+                }),
+                Err(message) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "error": { "code": 1, "message": message },
+                }),
+            };
+
+            if writeln!(&mut &stream, "{}", response).is_err() {
+                return;
+            }
+        }
+    }
+
+    pub fn add_to_mempool(&self, transaction: Transaction) {
+        self.chain
+            .lock()
+            .unwrap()
+            .transactions
+            .insert(transaction.txid(), (transaction, 0));
+    }
+
+    /// Mine a block including the whole mempool and notify the
+    /// subscribers about it.
+    pub fn mine_block(&self) {
+        self.mine(true);
+    }
+
+    /// Mine a block leaving the mempool as it is, e.g. because its fee
+    /// rates are too low.
+    pub fn mine_empty_block(&self) {
+        self.mine(false);
+    }
+
+    fn mine(&self, include_mempool: bool) {
+        use std::io::Write;
+
+        let mut chain = self.chain.lock().unwrap();
+        chain.height += 1;
+
+        let height = chain.height;
+        for (_, mined_at) in chain.transactions.values_mut() {
+            if *mined_at == 0 && include_mempool {
+                *mined_at = height;
+            }
+        }
+
+        if chain.silent {
+            return;
+        }
+
+        let notification = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "blockchain.headers.subscribe",
+            "params": [chain.header()],
+        });
+        chain
+            .subscribers
+            .retain(|subscriber| writeln!(&mut &*subscriber, "{}", notification).is_ok());
+    }
+
+    pub fn stop_notifications(&self) {
+        self.chain.lock().unwrap().silent = true;
+    }
+
+    pub fn set_fee_estimate(&self, btc_per_kvb: f64) {
+        self.chain.lock().unwrap().fee_estimate = Some(btc_per_kvb);
+    }
+
+    pub fn history_requests(&self) -> usize {
+        self.chain.lock().unwrap().history_requests
+    }
+
+    pub fn broadcasts(&self) -> usize {
+        self.chain.lock().unwrap().broadcasts
+    }
+
+    /// The transactions spending an output of the given one.
+    pub fn spending(&self, txid: Txid) -> Vec<Transaction> {
+        self.chain
+            .lock()
+            .unwrap()
+            .transactions
+            .values()
+            .map(|(transaction, _)| transaction)
+            .filter(|transaction| {
+                transaction
+                    .input
+                    .iter()
+                    .any(|input| input.previous_output.txid == txid)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl FakeChain {
+    fn header(&self) -> serde_json::Value {
+        serde_json::json!({ "height": self.height, "hex": FAKE_HEADER })
+    }
+
+    fn answer(
+        &mut self,
+        request: &serde_json::Value,
+        stream: &std::net::TcpStream,
+    ) -> Result<serde_json::Value, String> {
+        use ::bitcoin::consensus::encode::{deserialize, serialize_hex};
+        use ::bitcoin::hashes::hex::{FromHex, ToHex};
+        use bdk::electrum_client::ToElectrumScriptHash;
+
+        let params = &request["params"];
+        let result = match request["method"].as_str().unwrap_or_default() {
+            "server.features" => serde_json::json!({
+                "server_version": "ElectrumX 1.16.0",
+                "genesis_hash": "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                "protocol_min": "1.4",
+                "protocol_max": "1.4",
+                "hash_function": "sha256",
+                "pruning": null
+            }),
+            "server.ping" => serde_json::Value::Null,
+            "blockchain.headers.subscribe" => {
+                self.subscribers.push(stream.try_clone().unwrap());
+                self.header()
+            }
+            "blockchain.scripthash.get_history" => {
+                self.history_requests += 1;
+
+                let script_hash = params[0].as_str().unwrap_or_default();
+                let pays_to_script = |output: &TxOut| {
+                    output.script_pubkey.to_electrum_scripthash().to_hex() == script_hash
+                };
+                let spends_from_script = |input: &::bitcoin::TxIn| {
+                    self.transactions
+                        .get(&input.previous_output.txid)
+                        .and_then(|(spent, _)| {
+                            spent.output.get(input.previous_output.vout as usize)
+                        })
+                        .map_or(false, pays_to_script)
+                };
+                let history = self
+                    .transactions
+                    .iter()
+                    .filter(|(_, (transaction, _))| {
+                        transaction.output.iter().any(pays_to_script)
+                            || transaction.input.iter().any(spends_from_script)
+                    })
+                    .map(|(txid, (_, height))| {
+                        serde_json::json!({ "tx_hash": txid.to_string(), "height": height })
+                    })
+                    .collect::<Vec<_>>();
+
+                serde_json::json!(history)
+            }
+            "blockchain.transaction.get" => {
+                let txid = params[0].as_str().unwrap_or_default();
+                let (transaction, _) = Txid::from_str(txid)
+                    .ok()
+                    .and_then(|txid| self.transactions.get(&txid))
+                    .ok_or_else(|| format!("unknown transaction {}", txid))?;
+
+                serde_json::json!(serialize_hex(transaction))
+            }
+            "blockchain.transaction.broadcast" => {
+                self.broadcasts += 1;
+
+                let transaction: Transaction =
+                    Vec::<u8>::from_hex(params[0].as_str().unwrap_or_default())
+                        .ok()
+                        .and_then(|bytes| deserialize(&bytes).ok())
+                        .ok_or("invalid transaction")?;
+                let txid = transaction.txid();
+
+                // Replace conflicting transactions in the mempool.
+                let spent = transaction
+                    .input
+                    .iter()
+                    .map(|input| input.previous_output)
+                    .collect::<Vec<_>>();
+                self.transactions.retain(|other, (conflicting, height)| {
+                    *other == txid
+                        || *height != 0
+                        || !conflicting
+                            .input
+                            .iter()
+                            .any(|input| spent.contains(&input.previous_output))
+                });
+                self.transactions.entry(txid).or_insert((transaction, 0));
+
+                serde_json::json!(txid.to_string())
+            }
+            "blockchain.block.header" => serde_json::json!(FAKE_HEADER),
+            "blockchain.estimatefee" => {
+                serde_json::json!(self.fee_estimate.unwrap_or(0.0001))
+            }
+            "blockchain.relayfee" => serde_json::json!(0.00001),
+            method => return Err(format!("unsupported method {}", method)),
+        };
+
+        Ok(result)
+    }
+}
+
+/// A transaction spending the given outputs to outputs of the given amounts
+/// with empty scripts.
+pub fn transaction(inputs: Vec<OutPoint>, outputs: Vec<u64>) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: 0,
+        input: inputs
+            .into_iter()
+            .map(|previous_output| ::bitcoin::TxIn {
+                previous_output,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            })
+            .collect(),
+        output: outputs
+            .into_iter()
+            .map(|value| TxOut {
+                value,
+                script_pubkey: Script::new(),
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,187 @@
+use crate::bitcoin::reservations::UtxoReservations;
+use crate::bitcoin::wallet::MIN_FEE_RATE_SAT_PER_VB;
+use crate::bitcoin::{Amount, Transaction};
+use ::bitcoin::util::psbt::PartiallySignedTransaction;
+use ::bitcoin::Txid;
+use anyhow::{Context, Result};
+use bdk::database::BatchDatabase;
+use bitcoin::Script;
+use serde::{Deserialize, Serialize};
+
+/// When to bump the fee of a transaction that does not confirm.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FeeBumping {
+    /// Bump the fee once the transaction has been waiting in the mempool for
+    /// this many blocks, and again after every further this many blocks.
+    pub after_blocks: u32,
+    /// The highest fee rate in sat/vB a bump may pay.
+    pub max_fee_rate: f32,
+}
+
+impl FeeBumping {
+    /// The fee rate to bump a transaction to that has been waiting in the
+    /// mempool for `waiting_blocks` and has already been bumped `bumps` times,
+    /// if a bump is due.
+    ///
+    /// Every bump doubles the fee rate, up to the configured maximum.
+    pub fn next_fee_rate(
+        &self,
+        waiting_blocks: u32,
+        bumps: u32,
+        base_fee_rate: f32,
+    ) -> Option<f32> {
+        let due_after = self.after_blocks.max(1).saturating_mul(bumps + 1);
+        if waiting_blocks < due_after {
+            return None;
+        }
+
+        let previous = base_fee_rate * 2f32.powi(bumps as i32);
+        if previous >= self.max_fee_rate {
+            return None;
+        }
+
+        Some((previous * 2.0).min(self.max_fee_rate))
+    }
+}
+
+/// A conservative estimate of the size of a transaction spending a single
+/// P2WPKH output to a single P2WPKH output.
+pub const CPFP_CHILD_VBYTES: u64 = 110;
+
+/// Build a transaction spending the output of `parent` to this wallet with a
+/// fee that makes both transactions, together with the given unconfirmed
+/// ancestors of `parent` and their fees, pay `fee_rate`.
+pub fn build_cpfp_tx<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    reserved_utxos: &mut UtxoReservations,
+    parent: &Transaction,
+    parent_fee: Amount,
+    ancestors: &[(Transaction, Amount)],
+    script: Script,
+    fee_rate: f32,
+) -> Result<PartiallySignedTransaction>
+where
+    D: BatchDatabase,
+{
+    let parent_txid = parent.txid();
+    let output = wallet
+        .list_unspent()?
+        .into_iter()
+        .find(|utxo| utxo.outpoint.txid == parent_txid)
+        .with_context(|| format!("Transaction {} has no output to this wallet", parent_txid))?;
+
+    let child_fee = cpfp_child_fee(parent, parent_fee, ancestors, fee_rate);
+
+    let mut tx_builder = wallet.build_tx();
+    tx_builder.add_utxos(&[output.outpoint])?;
+    tx_builder.manually_selected_only();
+    tx_builder.set_single_recipient(script);
+    tx_builder.fee_absolute(child_fee);
+    // Later bumps replace the child.
+    tx_builder.enable_rbf();
+    let (psbt, _details) = tx_builder.finish().with_context(|| {
+        format!(
+            "Output of transaction {} does not cover a fee of {}",
+            parent_txid,
+            Amount::from_sat(child_fee)
+        )
+    })?;
+
+    reserved_utxos.reserve(&psbt.global.unsigned_tx);
+
+    Ok(psbt)
+}
+
+/// Replace `child`, built by [`build_cpfp_tx`] for `parent`, by one paying a
+/// fee that makes the package pay `fee_rate`.
+///
+/// The replacement pays at least the fee of `child` plus its own relay fee, as
+/// required by BIP 125.
+pub fn build_cpfp_replacement<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    reserved_utxos: &mut UtxoReservations,
+    parent: &Transaction,
+    parent_fee: Amount,
+    ancestors: &[(Transaction, Amount)],
+    child: Txid,
+    fee_rate: f32,
+) -> Result<PartiallySignedTransaction>
+where
+    D: BatchDatabase,
+{
+    let previous_fee = wallet
+        .list_transactions(false)?
+        .into_iter()
+        .find(|details| details.txid == child)
+        .with_context(|| format!("Transaction {} is not known to the wallet", child))?
+        .fees;
+    let child_fee = cpfp_child_fee(parent, parent_fee, ancestors, fee_rate)
+        .max(previous_fee + CPFP_CHILD_VBYTES * MIN_FEE_RATE_SAT_PER_VB as u64);
+
+    let mut tx_builder = wallet.build_fee_bump(child)?;
+    tx_builder.maintain_single_recipient()?;
+    tx_builder.fee_absolute(child_fee);
+    let (psbt, _details) = tx_builder.finish().with_context(|| {
+        format!(
+            "Output of transaction {} does not cover a fee of {}",
+            parent.txid(),
+            Amount::from_sat(child_fee)
+        )
+    })?;
+
+    reserved_utxos.reserve(&psbt.global.unsigned_tx);
+
+    Ok(psbt)
+}
+
+/// The fee a child of `parent` has to pay for the package of both, together
+/// with the given unconfirmed ancestors of `parent`, to pay `fee_rate`.
+fn cpfp_child_fee(
+    parent: &Transaction,
+    parent_fee: Amount,
+    ancestors: &[(Transaction, Amount)],
+    fee_rate: f32,
+) -> u64 {
+    let package_vbytes = vbytes(parent)
+        + ancestors
+            .iter()
+            .map(|(ancestor, _)| vbytes(ancestor))
+            .sum::<u64>();
+    let paid_fee = parent_fee.as_sat() + ancestors.iter().map(|(_, fee)| fee.as_sat()).sum::<u64>();
+
+    let package_fee = (fee_rate * (package_vbytes + CPFP_CHILD_VBYTES) as f32).ceil() as u64;
+
+    package_fee
+        .saturating_sub(paid_fee)
+        .max(CPFP_CHILD_VBYTES * MIN_FEE_RATE_SAT_PER_VB as u64)
+}
+
+pub fn vbytes(transaction: &Transaction) -> u64 {
+    (transaction.get_weight() as u64 + 3) / 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fee_bumps_escalate_up_to_the_maximum_fee_rate() {
+        let policy = FeeBumping {
+            after_blocks: 3,
+            max_fee_rate: 30.0,
+        };
+        let base_fee_rate = 5.0;
+
+        let mut bumps = 0;
+        let mut bumped_to = vec![];
+        for waiting_blocks in 0..20 {
+            if let Some(fee_rate) = policy.next_fee_rate(waiting_blocks, bumps, base_fee_rate) {
+                bumped_to.push((waiting_blocks, fee_rate));
+                bumps += 1;
+            }
+        }
+
+        assert_eq!(bumped_to, vec![(3, 10.0), (6, 20.0), (9, 30.0)]);
+    }
+}
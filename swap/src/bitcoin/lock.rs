@@ -1,6 +1,6 @@
-use crate::bitcoin::wallet::Watchable;
+use crate::bitcoin::wallet::{BitcoinWallet, Watchable};
 use crate::bitcoin::{
-    build_shared_output_descriptor, Address, Amount, PublicKey, Transaction, Wallet, TX_FEE,
+    build_shared_output_descriptor, Address, Amount, PublicKey, Transaction, TX_FEE,
 };
 use ::bitcoin::util::psbt::PartiallySignedTransaction;
 use ::bitcoin::{OutPoint, TxIn, TxOut, Txid};
@@ -18,7 +18,12 @@ pub struct TxLock {
 }
 
 impl TxLock {
-    pub async fn new(wallet: &Wallet, amount: Amount, A: PublicKey, B: PublicKey) -> Result<Self> {
+    pub async fn new(
+        wallet: &dyn BitcoinWallet,
+        amount: Amount,
+        A: PublicKey,
+        B: PublicKey,
+    ) -> Result<Self> {
         let lock_output_descriptor = build_shared_output_descriptor(A.0, B.0);
         let address = lock_output_descriptor
             .address(wallet.get_network().await)
@@ -73,6 +78,28 @@ impl TxLock {
             .expect("transaction contains lock output")
     }
 
+    /// A lock transaction without inputs, to build the transactions spending
+    /// it in tests.
+    #[cfg(test)]
+    pub(crate) fn unfunded(amount: Amount, A: PublicKey, B: PublicKey) -> Self {
+        let output_descriptor = build_shared_output_descriptor(A.0, B.0);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: amount.as_sat(),
+                script_pubkey: output_descriptor.script_pubkey(),
+            }],
+        };
+
+        Self {
+            inner: PartiallySignedTransaction::from_unsigned_tx(transaction)
+                .expect("transaction without inputs is unsigned"),
+            output_descriptor,
+        }
+    }
+
     pub fn build_spend_transaction(
         &self,
         spend_address: &Address,
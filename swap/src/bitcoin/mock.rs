@@ -0,0 +1,211 @@
+use crate::bitcoin::wallet::{BitcoinWallet, ScriptStatus, Watchable};
+use crate::bitcoin::{Address, Amount, Network, SecretKey, Transaction, Txid};
+use crate::error::SwapError;
+use ::bitcoin::util::psbt::PartiallySignedTransaction;
+use ::bitcoin::{OutPoint, TxIn, TxOut};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use rand::thread_rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// An in-memory [`BitcoinWallet`] whose view of the blockchain is set by the
+/// test.
+///
+/// Transactions are unseen until the test sets their status or they are
+/// published through the wallet, which puts them into the mempool. Published
+/// transactions are considered final right away.
+///
+/// [`BitcoinWallet::status_of_script`] can be made to lag behind, like the
+/// script histories of the real wallet before a script was fetched.
+/// [`BitcoinWallet::fetch_status_of_script`] always reports the actual status.
+#[derive(Debug)]
+pub struct MockWallet {
+    address: Address,
+    statuses: Mutex<HashMap<Txid, ScriptStatus>>,
+    stale_statuses: Mutex<HashMap<Txid, ScriptStatus>>,
+    transactions: Mutex<HashMap<Txid, Transaction>>,
+    published: Mutex<Vec<(String, Txid)>>,
+    failing_broadcasts: Mutex<u32>,
+}
+
+impl Default for MockWallet {
+    fn default() -> Self {
+        let key = ::bitcoin::PublicKey::from(SecretKey::new_random(&mut thread_rng()).public());
+
+        Self {
+            address: Address::p2wpkh(&key, Network::Regtest).expect("key is compressed"),
+            statuses: Default::default(),
+            stale_statuses: Default::default(),
+            transactions: Default::default(),
+            published: Default::default(),
+            failing_broadcasts: Default::default(),
+        }
+    }
+}
+
+impl MockWallet {
+    /// Set the status of a transaction, e.g. to confirm it or to simulate a
+    /// reorg by setting it back to [`ScriptStatus::Unseen`].
+    pub fn set_status(&self, txid: Txid, status: ScriptStatus) {
+        self.stale_statuses.lock().unwrap().remove(&txid);
+        self.statuses.lock().unwrap().insert(txid, status);
+    }
+
+    /// Let [`BitcoinWallet::status_of_script`] report an outdated status for
+    /// the transaction until its status is set again.
+    pub fn set_stale_status(&self, txid: Txid, status: ScriptStatus) {
+        self.stale_statuses.lock().unwrap().insert(txid, status);
+    }
+
+    /// Fail the next `n` broadcasts without publishing the transactions.
+    pub fn fail_next_broadcasts(&self, n: u32) {
+        *self.failing_broadcasts.lock().unwrap() = n;
+    }
+
+    /// The kind and id of every transaction published so far, in order.
+    pub fn published(&self) -> Vec<(String, Txid)> {
+        self.published.lock().unwrap().clone()
+    }
+
+    fn status(&self, txid: Txid) -> ScriptStatus {
+        self.statuses
+            .lock()
+            .unwrap()
+            .get(&txid)
+            .copied()
+            .unwrap_or(ScriptStatus::Unseen)
+    }
+
+    fn publish(&self, transaction: Transaction, kind: &str) -> Result<Txid, SwapError> {
+        let txid = transaction.txid();
+
+        {
+            let mut failing_broadcasts = self.failing_broadcasts.lock().unwrap();
+            if *failing_broadcasts > 0 {
+                *failing_broadcasts -= 1;
+
+                return Err(SwapError::wallet(anyhow!(
+                    "Failed to broadcast Bitcoin {} transaction {}",
+                    kind,
+                    txid
+                )));
+            }
+        }
+
+        self.published.lock().unwrap().push((kind.to_owned(), txid));
+        self.transactions.lock().unwrap().insert(txid, transaction);
+        self.statuses
+            .lock()
+            .unwrap()
+            .entry(txid)
+            .or_insert(ScriptStatus::InMempool);
+
+        Ok(txid)
+    }
+}
+
+#[async_trait]
+impl BitcoinWallet for MockWallet {
+    async fn get_network(&self) -> Network {
+        Network::Regtest
+    }
+
+    async fn new_address(&self) -> Result<Address> {
+        Ok(self.address.clone())
+    }
+
+    async fn is_our_address(&self, address: &Address) -> Result<bool> {
+        Ok(*address == self.address)
+    }
+
+    async fn send_to_address(
+        &self,
+        address: Address,
+        amount: Amount,
+    ) -> Result<PartiallySignedTransaction> {
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Default::default(),
+                sequence: 0xFFFF_FFFF,
+                witness: Vec::new(),
+            }],
+            output: vec![TxOut {
+                value: amount.as_sat(),
+                script_pubkey: address.script_pubkey(),
+            }],
+        };
+
+        PartiallySignedTransaction::from_unsigned_tx(transaction).context("Failed to build PSBT")
+    }
+
+    async fn unpublished_fee(&self, _: &Transaction) -> Result<Amount> {
+        Ok(Amount::ZERO)
+    }
+
+    async fn release_utxos(&self, _: &Transaction) {}
+
+    async fn sign_and_finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction> {
+        Ok(psbt.extract_tx())
+    }
+
+    async fn broadcast<'a>(
+        &'a self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, BoxFuture<'a, Result<()>>), SwapError> {
+        let txid = self.publish(transaction, kind)?;
+
+        Ok((txid, Box::pin(async { Ok(()) })))
+    }
+
+    async fn ensure_broadcast<'a>(
+        &'a self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, BoxFuture<'a, Result<()>>), SwapError> {
+        let txid = transaction.txid();
+
+        if self.status(txid) == ScriptStatus::Unseen {
+            self.publish(transaction, kind)?;
+        }
+
+        Ok((txid, Box::pin(async { Ok(()) })))
+    }
+
+    async fn get_raw_transaction(&self, txid: Txid) -> Result<Transaction> {
+        self.transactions
+            .lock()
+            .unwrap()
+            .get(&txid)
+            .cloned()
+            .ok_or_else(|| anyhow!("Could not get raw tx with id: {}", txid))
+    }
+
+    async fn status_of_script(&self, tx: &(dyn Watchable + Sync)) -> Result<ScriptStatus> {
+        let stale = self.stale_statuses.lock().unwrap().get(&tx.id()).copied();
+
+        Ok(stale.unwrap_or_else(|| self.status(tx.id())))
+    }
+
+    async fn fetch_status_of_script(&self, tx: &(dyn Watchable + Sync)) -> Result<ScriptStatus> {
+        Ok(self.status(tx.id()))
+    }
+
+    async fn watch_until_status<'a>(
+        &'a self,
+        tx: &'a (dyn Watchable + Sync),
+        mut status_fn: Box<dyn FnMut(ScriptStatus) -> bool + Send + 'a>,
+    ) -> Result<()> {
+        while !status_fn(self.status(tx.id())) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        Ok(())
+    }
+}
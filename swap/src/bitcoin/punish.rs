@@ -38,6 +38,10 @@ impl TxPunish {
         }
     }
 
+    pub fn txid(&self) -> Txid {
+        self.inner.txid()
+    }
+
     pub fn digest(&self) -> SigHash {
         self.digest
     }
@@ -45,16 +49,15 @@ impl TxPunish {
     pub fn complete(
         self,
         tx_punish_sig_bob: bitcoin::Signature,
-        a: bitcoin::SecretKey,
+        (A, sig_a): (bitcoin::PublicKey, bitcoin::Signature),
         B: bitcoin::PublicKey,
     ) -> Result<Transaction> {
-        let sig_a = a.sign(self.digest());
         let sig_b = tx_punish_sig_bob;
 
         let satisfier = {
             let mut satisfier = HashMap::with_capacity(2);
 
-            let A = a.public().into();
+            let A = A.into();
             let B = B.into();
 
             // The order in which these are inserted doesn't matter
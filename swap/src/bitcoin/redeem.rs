@@ -1,11 +1,18 @@
+//! The redeem transaction spends the lock output to Alice.
+//!
+//! Instead of signing it inline, the ASB can export the redeem transaction as
+//! PSBT that already carries the decrypted signature of Bob, have it signed on
+//! another machine with [`sign_redeem_psbt`] and finalize it for broadcast.
+
 use crate::bitcoin::wallet::Watchable;
 use crate::bitcoin::{
     verify_encsig, verify_sig, Address, EmptyWitnessStack, EncryptedSignature, NoInputs,
     NotThreeWitnesses, PublicKey, SecretKey, TooManyInputs, Transaction, TxLock,
 };
 use ::bitcoin::util::bip143::SigHashCache;
-use ::bitcoin::{SigHash, SigHashType, Txid};
-use anyhow::{bail, Context, Result};
+use ::bitcoin::util::psbt::PartiallySignedTransaction;
+use ::bitcoin::{SigHash, SigHashType, TxOut, Txid};
+use anyhow::{bail, ensure, Context, Result};
 use bitcoin::Script;
 use ecdsa_fun::adaptor::{Adaptor, HashTranscript};
 use ecdsa_fun::fun::Scalar;
@@ -20,6 +27,7 @@ pub struct TxRedeem {
     inner: Transaction,
     digest: SigHash,
     lock_output_descriptor: Descriptor<::bitcoin::PublicKey>,
+    lock_output: TxOut,
     watch_script: Script,
 }
 
@@ -40,6 +48,10 @@ impl TxRedeem {
             inner: tx_redeem,
             digest,
             lock_output_descriptor: tx_lock.output_descriptor.clone(),
+            lock_output: TxOut {
+                value: tx_lock.lock_amount().as_sat(),
+                script_pubkey: tx_lock.script_pubkey(),
+            },
             watch_script: redeem_address.script_pubkey(),
         }
     }
@@ -97,6 +109,82 @@ impl TxRedeem {
         Ok(self.inner)
     }
 
+    /// Export the redeem transaction as PSBT carrying the decrypted signature
+    /// of Bob, to be signed with [`sign_redeem_psbt`].
+    pub fn export_psbt(
+        self,
+        encrypted_signature: EncryptedSignature,
+        s_a: Scalar,
+        B: PublicKey,
+    ) -> Result<PartiallySignedTransaction> {
+        verify_encsig(
+            B,
+            PublicKey::from(s_a.clone()),
+            &self.digest(),
+            &encrypted_signature,
+        )
+        .context("Invalid encrypted signature received")?;
+
+        let adaptor = Adaptor::<HashTranscript<Sha256>, Deterministic<Sha256>>::default();
+        let sig_b = adaptor.decrypt_signature(&s_a, encrypted_signature);
+
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(self.inner)
+            .context("Failed to build PSBT of Bitcoin redeem transaction")?;
+
+        let input = &mut psbt.inputs[0];
+        input.witness_utxo = Some(self.lock_output);
+        input.witness_script = Some(self.lock_output_descriptor.explicit_script());
+        input.sighash_type = Some(SigHashType::All);
+        input
+            .partial_sigs
+            .insert(B.into(), serialize_signature(sig_b));
+
+        Ok(psbt)
+    }
+
+    /// Finalize a PSBT exported with [`TxRedeem::export_psbt`] once it has
+    /// been signed with the key `a`.
+    pub fn finalize_psbt(
+        mut self,
+        psbt: PartiallySignedTransaction,
+        A: PublicKey,
+        B: PublicKey,
+    ) -> Result<Transaction> {
+        ensure!(
+            psbt.global.unsigned_tx.txid() == self.txid(),
+            "PSBT is not the redeem transaction of this swap"
+        );
+        let input = match psbt.inputs.as_slice() {
+            [input] => input,
+            [] => bail!(NoInputs),
+            [inputs @ ..] => bail!(TooManyInputs(inputs.len())),
+        };
+
+        let mut satisfier = HashMap::with_capacity(2);
+
+        for key in &[A, B] {
+            let pk = ::bitcoin::PublicKey::from(*key);
+            let (_sighash_type, sig) = input
+                .partial_sigs
+                .get(&pk)
+                .and_then(|sig| sig.split_last())
+                .context("PSBT is missing a signature")?;
+            let sig = bitcoin::secp256k1::Signature::from_der(sig)
+                .context("PSBT contains a malformed signature")?;
+            verify_sig(key, &self.digest(), &Signature::from(sig))
+                .context("PSBT contains a signature that does not verify")?;
+
+            // The order in which these are inserted doesn't matter
+            satisfier.insert(pk, (sig, SigHashType::All));
+        }
+
+        self.lock_output_descriptor
+            .satisfy(&mut self.inner.input[0], satisfier)
+            .context("Failed to sign Bitcoin redeem transaction")?;
+
+        Ok(self.inner)
+    }
+
     pub fn extract_signature_by_key(
         &self,
         candidate_transaction: Transaction,
@@ -135,6 +223,60 @@ impl TxRedeem {
     }
 }
 
+/// Sign a redeem PSBT exported with [`TxRedeem::export_psbt`] with the key
+/// `a`, on the machine approving the redeem.
+///
+/// The digest is recomputed from the PSBT instead of being trusted, after
+/// checking that the spent output is locked to the witness script.
+pub fn sign_redeem_psbt(
+    mut psbt: PartiallySignedTransaction,
+    a: &SecretKey,
+) -> Result<PartiallySignedTransaction> {
+    let input = match psbt.inputs.as_slice() {
+        [input] => input,
+        [] => bail!(NoInputs),
+        [inputs @ ..] => bail!(TooManyInputs(inputs.len())),
+    };
+
+    let spent_output = input
+        .witness_utxo
+        .clone()
+        .context("PSBT is missing the spent lock output")?;
+    let witness_script = input
+        .witness_script
+        .clone()
+        .context("PSBT is missing the witness script of the lock output")?;
+    ensure!(
+        spent_output.script_pubkey == witness_script.to_v0_p2wsh(),
+        "Spent output of PSBT is not locked to its witness script"
+    );
+
+    let digest = SigHashCache::new(&psbt.global.unsigned_tx).signature_hash(
+        0,
+        &witness_script,
+        spent_output.value,
+        SigHashType::All,
+    );
+    let sig_a = a.sign(digest);
+
+    psbt.inputs[0]
+        .partial_sigs
+        .insert(a.public().into(), serialize_signature(sig_a));
+
+    Ok(psbt)
+}
+
+/// Serialize a signature as DER followed by the sighash type, as expected in
+/// the partial signatures of a PSBT.
+fn serialize_signature(sig: Signature) -> Vec<u8> {
+    let sig: bitcoin::secp256k1::Signature = sig.into();
+
+    let mut serialized = sig.serialize_der().to_vec();
+    serialized.push(SigHashType::All as u8);
+
+    serialized
+}
+
 impl Watchable for TxRedeem {
     fn id(&self) -> Txid {
         self.txid()
@@ -144,3 +286,76 @@ impl Watchable for TxRedeem {
         self.watch_script.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{Amount, Network};
+    use ::bitcoin::consensus::{deserialize, serialize};
+    use rand::thread_rng;
+
+    #[test]
+    fn redeem_psbt_round_trips_through_external_signer() {
+        let a = SecretKey::new_random(&mut thread_rng());
+        let b = SecretKey::new_random(&mut thread_rng());
+        let s_a = Scalar::random(&mut thread_rng());
+
+        let tx_lock = TxLock::unfunded(Amount::from_sat(100_000), a.public(), b.public());
+        let redeem_address =
+            Address::p2wpkh(&::bitcoin::PublicKey::from(a.public()), Network::Regtest).unwrap();
+        let tx_redeem = TxRedeem::new(&tx_lock, &redeem_address);
+        let encsig = b.encsign(PublicKey::from(s_a.clone()), tx_redeem.digest());
+
+        // The external signer only ever sees the serialized PSBT
+        let external_signer = |exported: String| {
+            let psbt = deserialize(&base64::decode(exported).unwrap()).unwrap();
+            let psbt = sign_redeem_psbt(psbt, &a).unwrap();
+            base64::encode(serialize(&psbt))
+        };
+
+        let exported = TxRedeem::new(&tx_lock, &redeem_address)
+            .export_psbt(encsig, s_a, b.public())
+            .unwrap();
+        let signed = external_signer(base64::encode(serialize(&exported)));
+        let signed = deserialize(&base64::decode(signed).unwrap()).unwrap();
+
+        let digest = tx_redeem.digest();
+        let transaction = tx_redeem
+            .finalize_psbt(signed, a.public(), b.public())
+            .unwrap();
+
+        assert_eq!(transaction.input[0].witness.len(), 3);
+        let sigs = transaction.input[0].witness[..2]
+            .iter()
+            .map(|sig| {
+                Signature::from(
+                    bitcoin::secp256k1::Signature::from_der(&sig[..sig.len() - 1]).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        for key in &[a.public(), b.public()] {
+            assert!(sigs.iter().any(|sig| verify_sig(key, &digest, sig).is_ok()));
+        }
+    }
+
+    #[test]
+    fn unsigned_redeem_psbt_cannot_be_finalized() {
+        let a = SecretKey::new_random(&mut thread_rng());
+        let b = SecretKey::new_random(&mut thread_rng());
+        let s_a = Scalar::random(&mut thread_rng());
+
+        let tx_lock = TxLock::unfunded(Amount::from_sat(100_000), a.public(), b.public());
+        let redeem_address =
+            Address::p2wpkh(&::bitcoin::PublicKey::from(a.public()), Network::Regtest).unwrap();
+        let tx_redeem = TxRedeem::new(&tx_lock, &redeem_address);
+        let encsig = b.encsign(PublicKey::from(s_a.clone()), tx_redeem.digest());
+
+        let exported = TxRedeem::new(&tx_lock, &redeem_address)
+            .export_psbt(encsig, s_a, b.public())
+            .unwrap();
+
+        assert!(tx_redeem
+            .finalize_psbt(exported, a.public(), b.public())
+            .is_err());
+    }
+}
@@ -81,15 +81,14 @@ impl TxRefund {
         &self,
         published_refund_tx: bitcoin::Transaction,
         s_a: monero::Scalar,
-        a: bitcoin::SecretKey,
+        (A, tx_refund_encsig): (bitcoin::PublicKey, bitcoin::EncryptedSignature),
         S_b_bitcoin: bitcoin::PublicKey,
     ) -> Result<monero::PrivateKey> {
         let s_a = monero::PrivateKey { scalar: s_a };
 
         let tx_refund_sig = self
-            .extract_signature_by_key(published_refund_tx, a.public())
+            .extract_signature_by_key(published_refund_tx, A)
             .context("Failed to extract signature from Bitcoin refund tx")?;
-        let tx_refund_encsig = a.encsign(S_b_bitcoin, self.digest());
 
         let s_b = bitcoin::recover(S_b_bitcoin, tx_refund_sig, tx_refund_encsig)
             .context("Failed to recover Monero secret key from Bitcoin signature")?;
@@ -0,0 +1,156 @@
+use crate::bitcoin::Transaction;
+use anyhow::{Context, Result};
+use bitcoin::OutPoint;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long coins stay reserved for a transaction that is neither published
+/// nor released. Long enough for a swap to lock its Bitcoin after the user
+/// confirmed the amounts.
+const UTXO_RESERVATION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// UTXOs committed to transactions that were built but are not yet known to be
+/// spent, e.g. the lock transactions of concurrently running swaps.
+///
+/// Coin selection skips them so that two transactions built from the same
+/// wallet never spend the same coin.
+///
+/// The reservations are persisted next to the wallet, so they also hold for
+/// other processes using it, e.g. the `withdraw-btc` command of the ASB. They
+/// expire after [`UTXO_RESERVATION_TTL`] in case the transaction is neither
+/// published nor released, e.g. because the process building it stopped.
+#[derive(Debug, Default)]
+pub struct UtxoReservations {
+    /// The reserved coins and when they were reserved.
+    reserved: HashMap<OutPoint, SystemTime>,
+    tree: Option<bdk::sled::Tree>,
+}
+
+impl UtxoReservations {
+    pub fn load(tree: bdk::sled::Tree) -> Result<Self> {
+        let reserved = tree
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.context("Failed to read UTXO reservation")?;
+                let outpoint: OutPoint = ::bitcoin::consensus::deserialize(&key)
+                    .context("Failed to decode UTXO reservation")?;
+                // Reservations written before they expired carry no time
+                let reserved_at = match <[u8; 8]>::try_from(value.as_ref()) {
+                    Ok(secs) => UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(secs)),
+                    Err(_) => SystemTime::now(),
+                };
+
+                Ok((outpoint, reserved_at))
+            })
+            .collect::<Result<_>>()?;
+
+        let mut reservations = Self {
+            reserved,
+            tree: Some(tree),
+        };
+        reservations.expire(SystemTime::now());
+
+        Ok(reservations)
+    }
+
+    pub fn reserve(&mut self, transaction: &Transaction) {
+        let now = SystemTime::now();
+
+        for input in &transaction.input {
+            self.reserved.insert(input.previous_output, now);
+            self.persist(&input.previous_output, Some(now));
+        }
+    }
+
+    pub fn release(&mut self, transaction: &Transaction) {
+        for input in &transaction.input {
+            self.reserved.remove(&input.previous_output);
+            self.persist(&input.previous_output, None);
+        }
+    }
+
+    /// Drop the reservations of all coins not among the given ones.
+    pub fn retain(&mut self, unspent: HashSet<OutPoint>) {
+        let spent = self
+            .reserved
+            .keys()
+            .filter(|outpoint| !unspent.contains(outpoint))
+            .copied()
+            .collect::<Vec<_>>();
+
+        for outpoint in spent {
+            self.reserved.remove(&outpoint);
+            self.persist(&outpoint, None);
+        }
+    }
+
+    /// Drop the reservations made more than [`UTXO_RESERVATION_TTL`] before
+    /// `now`.
+    pub fn expire(&mut self, now: SystemTime) {
+        let expired = self
+            .reserved
+            .iter()
+            .filter(|(_, reserved_at)| {
+                now.duration_since(**reserved_at)
+                    .map_or(false, |age| age > UTXO_RESERVATION_TTL)
+            })
+            .map(|(outpoint, _)| *outpoint)
+            .collect::<Vec<_>>();
+
+        for outpoint in expired {
+            tracing::debug!(%outpoint, "UTXO reservation expired");
+            self.reserved.remove(&outpoint);
+            self.persist(&outpoint, None);
+        }
+    }
+
+    fn persist(&self, outpoint: &OutPoint, reserved_at: Option<SystemTime>) {
+        let tree = match &self.tree {
+            Some(tree) => tree,
+            None => return,
+        };
+        let key = ::bitcoin::consensus::serialize(outpoint);
+
+        let result = match reserved_at {
+            Some(reserved_at) => {
+                let secs = reserved_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                tree.insert(key, secs.to_be_bytes().to_vec()).map(|_| ())
+            }
+            None => tree.remove(key).map(|_| ()),
+        };
+        if let Err(error) = result.and_then(|()| tree.flush().map(|_| ())) {
+            tracing::warn!(%outpoint, "Failed to persist UTXO reservation: {:#}", error);
+        }
+    }
+
+    pub fn unspendable(&self) -> Vec<OutPoint> {
+        self.reserved.keys().copied().collect()
+    }
+
+    pub fn is_reserved(&self, outpoint: &OutPoint) -> bool {
+        self.reserved.contains_key(outpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::fake_electrum::transaction;
+
+    #[test]
+    fn reservations_expire_after_their_ttl() {
+        let transaction = transaction(vec![OutPoint::default()], vec![1_000]);
+        let mut reserved_utxos = UtxoReservations::default();
+        reserved_utxos.reserve(&transaction);
+
+        reserved_utxos.expire(SystemTime::now() + UTXO_RESERVATION_TTL / 2);
+        assert!(reserved_utxos.is_reserved(&OutPoint::default()));
+
+        reserved_utxos.expire(SystemTime::now() + UTXO_RESERVATION_TTL * 2);
+        assert!(!reserved_utxos.is_reserved(&OutPoint::default()));
+    }
+}
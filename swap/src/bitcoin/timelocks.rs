@@ -55,3 +55,18 @@ pub enum ExpiredTimelocks {
     Cancel,
     Punish,
 }
+
+/// The current epoch of a swap together with the number of blocks until the
+/// timelocks expire.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimelockEpoch {
+    pub epoch: ExpiredTimelocks,
+    /// Blocks until the cancel timelock expires, counted from the inclusion
+    /// of the lock transaction. The full timelock remains while the lock
+    /// transaction is unconfirmed.
+    pub blocks_until_cancel: u32,
+    /// Blocks until the punish timelock expires, counted from the inclusion
+    /// of the cancel transaction. The full timelock remains while the cancel
+    /// transaction is unconfirmed.
+    pub blocks_until_punish: u32,
+}
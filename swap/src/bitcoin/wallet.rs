@@ -1,70 +1,233 @@
-use crate::bitcoin::timelocks::BlockHeight;
-use crate::bitcoin::{Address, Amount, Transaction};
+pub use crate::bitcoin::electrum::ElectrumServerBusy;
+pub use crate::bitcoin::fee_bump::FeeBumping;
+
+use crate::bitcoin::electrum::{
+    rank_by_latency, Client, ElectrumFailover, ElectrumServer, SERVER_BUSY_INITIAL_DELAY,
+};
+use crate::bitcoin::fee_bump::{build_cpfp_replacement, build_cpfp_tx, vbytes};
+use crate::bitcoin::reservations::UtxoReservations;
+use crate::bitcoin::{bitcoind, checked_sub, checked_sum, Address, Amount, Transaction};
 use crate::env;
+use crate::error::SwapError;
+use crate::explorer::ExplorerUrl;
 use ::bitcoin::util::psbt::PartiallySignedTransaction;
 use ::bitcoin::Txid;
 use anyhow::{anyhow, bail, Context, Result};
-use bdk::blockchain::{noop_progress, Blockchain, ElectrumBlockchain};
+use async_trait::async_trait;
+use bdk::blockchain::{noop_progress, Blockchain};
+use bdk::database::{BatchDatabase, Database};
 use bdk::descriptor::Segwitv0;
-use bdk::electrum_client::{self, ElectrumApi, GetHistoryRes};
 use bdk::keys::DerivableKey;
 use bdk::{FeeRate, KeychainKind};
-use bitcoin::Script;
+use bitcoin::util::bip32::ChildNumber;
+use bitcoin::{OutPoint, Script, TxOut};
+use futures::future::BoxFuture;
+use miniscript::DescriptorTrait;
+use rand::Rng;
 use reqwest::Url;
-use std::collections::BTreeMap;
-use std::convert::TryFrom;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, SystemTime};
 use tokio::sync::Mutex;
 
 const SLED_TREE_NAME: &str = "default_tree";
+const RESERVED_UTXOS_TREE_NAME: &str = "reserved_utxos";
+
+/// Consolidating coins is not urgent, it only has to confirm within about an
+/// hour.
+const CONSOLIDATION_TARGET_BLOCKS: usize = 6;
+
+/// The fee rate used unless overridden by the user.
+const DEFAULT_FEE_RATE_SAT_PER_VB: f32 = 5.0;
+
+/// The lowest fee rate we are willing to use, transactions paying less are not
+/// relayed by most nodes.
+pub(in crate::bitcoin) const MIN_FEE_RATE_SAT_PER_VB: f32 = 1.0;
+
+/// The highest fee rate we are willing to use, protecting against accidentally
+/// burning funds on fees.
+const MAX_FEE_RATE_SAT_PER_VB: f32 = 500.0;
+
+/// The fee rate Bitcoin Core uses to decide whether an output is dust, i.e.
+/// costs more to spend than it is worth.
+const DUST_RELAY_FEE_SAT_PER_VB: u64 = 3;
+
+/// How often the status of a transaction is polled while watching it, before
+/// adding jitter.
+const STATUS_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many addresses of each keychain are derived when checking whether an
+/// address belongs to the wallet, in addition to the ones already revealed.
+const ADDRESS_GAP_LIMIT: u32 = 20;
 
 pub struct Wallet {
     client: Arc<Mutex<Client>>,
-    wallet: Arc<Mutex<bdk::Wallet<ElectrumBlockchain, bdk::sled::Tree>>>,
-    finality_confirmations: u32,
+    wallet: Arc<Mutex<bdk::Wallet<ElectrumFailover, bdk::sled::Tree>>>,
+    /// A separate connection for requests that don't need the wallet, so they
+    /// don't have to wait for a running sync.
+    electrum: Arc<Mutex<ElectrumFailover>>,
+    reserved_utxos: Arc<Mutex<UtxoReservations>>,
+    fees: Arc<Mutex<HashMap<Txid, Amount>>>,
+    finality: FinalityPolicy,
+    fee_rate_override: Option<f32>,
+    poll_jitter: Duration,
+    /// Publish transactions through this node instead of electrum.
+    bitcoind: Option<bitcoind::Client>,
+    fee_bumping: Option<FeeBumping>,
+    explorer: Option<ExplorerUrl>,
 }
 
 impl Wallet {
+    /// Create a wallet talking to the given electrum servers.
+    ///
+    /// The servers are ranked by their latency, the fastest one is used and
+    /// the others serve as fallbacks in case it becomes unresponsive. Servers
+    /// that cannot be reached at startup are ranked last.
     pub async fn new(
-        electrum_rpc_url: Url,
+        electrum_rpc_urls: &[Url],
+        wallet_dir: &Path,
+        key: impl DerivableKey<Segwitv0> + Clone,
+        env_config: env::Config,
+    ) -> Result<Self, SwapError> {
+        Self::try_new(electrum_rpc_urls, wallet_dir, key, env_config)
+            .await
+            .map_err(SwapError::wallet)
+    }
+
+    async fn try_new(
+        electrum_rpc_urls: &[Url],
         wallet_dir: &Path,
         key: impl DerivableKey<Segwitv0> + Clone,
         env_config: env::Config,
     ) -> Result<Self> {
-        // Workaround for https://github.com/bitcoindevkit/rust-electrum-client/issues/47.
-        let config = electrum_client::ConfigBuilder::default().retry(2).build();
+        let timeout = env_config.bitcoin_electrum.timeout;
+        let servers = electrum_rpc_urls
+            .iter()
+            .map(|url| match ElectrumServer::connect(url.clone(), timeout) {
+                Ok(server) => server,
+                Err(error) => {
+                    tracing::warn!(%url, "Failed to connect to electrum server, keeping it as a last resort: {:#}", error);
+                    ElectrumServer::unreachable(url.clone(), timeout)
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if servers.iter().all(|server| server.client.is_none()) {
+            return Err(SwapError::Network(anyhow!(
+                "Failed to connect to any of the electrum servers {:?}",
+                electrum_rpc_urls
+            ))
+            .into());
+        }
 
-        let client =
-            bdk::electrum_client::Client::from_config(electrum_rpc_url.as_str(), config.clone())
-                .map_err(|e| anyhow!("Failed to init electrum rpc client: {:?}", e))?;
+        let servers = rank_by_latency(servers, ElectrumServer::probe);
+        let ranking = servers
+            .iter()
+            .map(|server| server.url.clone())
+            .collect::<Vec<_>>();
+        tracing::debug!(url = %ranking[0], "Using electrum server with the lowest latency");
 
-        let db = bdk::sled::open(wallet_dir)?.open_tree(SLED_TREE_NAME)?;
+        let sled = bdk::sled::open(wallet_dir)?;
+        let db = sled.open_tree(SLED_TREE_NAME)?;
+        let reserved_utxos = UtxoReservations::load(sled.open_tree(RESERVED_UTXOS_TREE_NAME)?)?;
 
         let bdk_wallet = bdk::Wallet::new(
             bdk::template::BIP84(key.clone(), KeychainKind::External),
             Some(bdk::template::BIP84(key, KeychainKind::Internal)),
             env_config.bitcoin_network,
             db,
-            ElectrumBlockchain::from(client),
+            ElectrumFailover::new(ranking.clone(), timeout),
         )?;
 
-        let electrum = bdk::electrum_client::Client::from_config(electrum_rpc_url.as_str(), config)
-            .map_err(|e| anyhow!("Failed to init electrum rpc client {:?}", e))?;
-
         Ok(Self {
             wallet: Arc::new(Mutex::new(bdk_wallet)),
+            electrum: Arc::new(Mutex::new(ElectrumFailover::new(ranking, timeout))),
+            reserved_utxos: Arc::new(Mutex::new(reserved_utxos)),
+            fees: Arc::new(Mutex::new(HashMap::new())),
             client: Arc::new(Mutex::new(Client::new(
-                electrum,
+                servers,
                 env_config.bitcoin_sync_interval(),
+                env_config.bitcoin_avg_block_time,
+                env_config.bitcoin_electrum,
             )?)),
-            finality_confirmations: env_config.bitcoin_finality_confirmations,
+            finality: FinalityPolicy::flat(env_config.bitcoin_finality_confirmations),
+            fee_rate_override: None,
+            poll_jitter: env_config.bitcoin_electrum.status_poll_jitter,
+            bitcoind: None,
+            fee_bumping: None,
+            explorer: ExplorerUrl::bitcoin(env_config.bitcoin_network),
         })
     }
 
+    /// Use the given fee rate in sat/vB for all transactions instead of
+    /// estimating one.
+    pub fn with_fee_rate_override(self, sat_per_vb: f32) -> Self {
+        let fee_rate = clamp_fee_rate(sat_per_vb);
+
+        tracing::warn!(
+            "Using a manually configured fee rate of {} sat/vB for all Bitcoin transactions",
+            fee_rate
+        );
+
+        Self {
+            fee_rate_override: Some(fee_rate),
+            ..self
+        }
+    }
+
+    /// Publish transactions through the given bitcoind node, electrum is
+    /// still used to watch them and to publish if bitcoind fails to.
+    pub fn with_bitcoind(self, bitcoind: bitcoind::Client) -> Self {
+        Self {
+            bitcoind: Some(bitcoind),
+            ..self
+        }
+    }
+
+    /// Log links to published transactions on the given explorer instead of
+    /// the default one of the network.
+    pub fn with_explorer(self, explorer: ExplorerUrl) -> Self {
+        Self {
+            explorer: Some(explorer),
+            ..self
+        }
+    }
+
+    /// The explorer published transactions are linked to, if any.
+    pub fn explorer(&self) -> Option<&ExplorerUrl> {
+        self.explorer.as_ref()
+    }
+
+    /// Bump the fees of transactions that don't confirm in time, disabled if
+    /// `None`.
+    pub fn with_fee_bumping(self, fee_bumping: Option<FeeBumping>) -> Self {
+        Self {
+            fee_bumping,
+            ..self
+        }
+    }
+
+    /// Require more confirmations for transactions moving larger amounts.
+    pub fn with_finality_tiers(self, tiers: Vec<FinalityTier>) -> Self {
+        Self {
+            finality: FinalityPolicy {
+                tiers,
+                ..self.finality
+            },
+            ..self
+        }
+    }
+
+    /// The number of confirmations after which a transaction moving the given
+    /// amount is considered final.
+    pub fn finality_confirmations(&self, amount: Amount) -> u32 {
+        self.finality.confirmations_for(amount)
+    }
+
     pub async fn balance(&self) -> Result<Amount> {
         let balance = self
             .wallet
@@ -87,34 +250,114 @@ impl Wallet {
         Ok(address)
     }
 
+    /// Whether the given script pays to an address of this wallet.
+    pub async fn is_mine(&self, script: &Script) -> Result<bool> {
+        let is_mine = self.wallet.lock().await.is_mine(script)?;
+
+        Ok(is_mine)
+    }
+
+    /// Whether the given address belongs to one of the keychains of this
+    /// wallet, including change addresses and addresses that were not revealed
+    /// yet but are within the gap limit after the last revealed one.
+    pub async fn is_our_address(&self, address: &Address) -> Result<bool> {
+        is_our_address(&*self.wallet.lock().await, address)
+    }
+
+    /// Reveal the next `n` addresses of this wallet.
+    pub async fn new_addresses(&self, n: usize) -> Result<Vec<Address>> {
+        let wallet = self.wallet.lock().await;
+
+        (0..n)
+            .map(|_| {
+                wallet
+                    .get_new_address()
+                    .context("Failed to get new Bitcoin address")
+            })
+            .collect()
+    }
+
+    /// Calculates the combined balance of all unspent outputs paying to one of
+    /// the given addresses.
+    pub async fn balance_of(&self, addresses: &[Address]) -> Result<Amount> {
+        let utxos = self
+            .wallet
+            .lock()
+            .await
+            .list_unspent()
+            .context("Failed to list unspent outputs")?;
+
+        Ok(sum_of_outputs_to(
+            addresses,
+            utxos.iter().map(|utxo| &utxo.txout),
+        ))
+    }
+
     pub async fn get_tx(&self, txid: Txid) -> Result<Option<Transaction>> {
-        let tx = self.wallet.lock().await.client().get_tx(&txid)?;
+        let tx = run_blocking(self.electrum.clone(), move |client| {
+            Ok(client.get_tx(&txid)?)
+        })
+        .await?;
 
         Ok(tx)
     }
 
+    /// The fee paid by the given transaction.
+    ///
+    /// Transactions that are no longer known to the wallet, e.g. of swaps
+    /// resumed on a fresh wallet, are fetched from electrum together with the
+    /// transactions they spend to compute the fee.
     pub async fn transaction_fee(&self, txid: Txid) -> Result<Amount> {
-        let fees = self
+        if let Some(fee) = self.fees.lock().await.get(&txid) {
+            return Ok(*fee);
+        }
+
+        let known_fee = self
             .wallet
             .lock()
             .await
             .list_transactions(true)?
             .iter()
             .find(|tx| tx.txid == txid)
-            .ok_or_else(|| {
-                anyhow!("Could not find tx in bdk wallet when trying to determine fees")
-            })?
-            .fees;
+            .map(|details| Amount::from_sat(details.fees));
+
+        let fee = match known_fee {
+            Some(fee) => fee,
+            None => {
+                run_blocking(self.electrum.clone(), move |client| {
+                    let get_tx = |txid: Txid| {
+                        client
+                            .get_tx(&txid)?
+                            .with_context(|| format!("Could not find transaction {}", txid))
+                    };
+
+                    fee_of(&get_tx(txid)?, get_tx)
+                        .context("Failed to compute fee from the spent transactions")
+                })
+                .await?
+            }
+        };
 
-        Ok(Amount::from_sat(fees))
+        self.fees.lock().await.insert(txid, fee);
+
+        Ok(fee)
     }
 
     pub async fn sync(&self) -> Result<()> {
-        self.wallet
-            .lock()
-            .await
-            .sync(noop_progress(), None)
-            .context("Failed to sync balance of Bitcoin wallet")?;
+        let unspent = run_blocking(self.wallet.clone(), |wallet| {
+            wallet
+                .sync(noop_progress(), None)
+                .context("Failed to sync balance of Bitcoin wallet")?;
+
+            Ok(wallet.list_unspent()?)
+        })
+        .await?;
+
+        // Reservations of coins that have been spent in the meantime are of no
+        // use anymore.
+        let mut reserved_utxos = self.reserved_utxos.lock().await;
+        reserved_utxos.retain(unspent.iter().map(|utxo| utxo.outpoint).collect());
+        reserved_utxos.expire(SystemTime::now());
 
         Ok(())
     }
@@ -124,35 +367,182 @@ impl Wallet {
         address: Address,
         amount: Amount,
     ) -> Result<PartiallySignedTransaction> {
-        let wallet = self.wallet.lock().await;
+        let script = address.script_pubkey();
+        ensure_not_dust(amount, script.len(), script.is_witness_program())?;
 
-        let mut tx_builder = wallet.build_tx();
-        tx_builder.add_recipient(address.script_pubkey(), amount.as_sat());
-        tx_builder.fee_rate(self.select_feerate());
-        let (psbt, _details) = tx_builder.finish()?;
+        let wallet = self.wallet.lock().await;
+        let mut reserved_utxos = self.reserved_utxos.lock().await;
+
+        let psbt = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            script,
+            amount,
+            self.select_feerate(),
+        )?;
 
         Ok(psbt)
     }
 
+    /// Like [`Wallet::send_to_address`] but only spends the given coins.
+    ///
+    /// Fails if the coins do not cover the amount and the fees or if one of
+    /// them is already used by another transaction.
+    pub async fn send_to_address_with_coin_control(
+        &self,
+        address: Address,
+        amount: Amount,
+        coins: &[OutPoint],
+    ) -> Result<PartiallySignedTransaction> {
+        let script = address.script_pubkey();
+        ensure_not_dust(amount, script.len(), script.is_witness_program())?;
+
+        let wallet = self.wallet.lock().await;
+        let mut reserved_utxos = self.reserved_utxos.lock().await;
+
+        build_tx_from_coins(
+            &wallet,
+            &mut reserved_utxos,
+            coins,
+            script,
+            amount,
+            self.select_feerate(),
+        )
+    }
+
+    /// Sweep all coins that are not reserved for in-flight swaps into a single
+    /// output of this wallet.
+    ///
+    /// Nothing is done unless there are at least `min_utxos` such coins and the
+    /// fee rate electrum estimates does not exceed `max_fee_rate` sat/vB.
+    pub async fn consolidate(&self, min_utxos: usize, max_fee_rate: f32) -> Result<Option<Txid>> {
+        let fee_rate = self
+            .estimate_fee_rate(CONSOLIDATION_TARGET_BLOCKS)
+            .await
+            .context("Failed to estimate the fee rate for consolidating coins")?;
+        if fee_rate.as_sat_vb() > max_fee_rate {
+            tracing::debug!(
+                "Fee rate of {} sat/vB is too high to consolidate coins",
+                fee_rate.as_sat_vb()
+            );
+            return Ok(None);
+        }
+
+        let address = self.new_address().await?;
+
+        let psbt = {
+            let wallet = self.wallet.lock().await;
+            let mut reserved_utxos = self.reserved_utxos.lock().await;
+
+            build_consolidation_tx(
+                &wallet,
+                &mut reserved_utxos,
+                min_utxos,
+                address.script_pubkey(),
+                fee_rate,
+            )?
+        };
+        let psbt = match psbt {
+            Some(psbt) => psbt,
+            None => return Ok(None),
+        };
+
+        let transaction = self.sign_and_finalize(psbt).await?;
+        let (txid, _) = self.broadcast(transaction, "consolidation").await?;
+
+        Ok(Some(txid))
+    }
+
+    /// Make the inputs of the given transaction available for coin selection
+    /// again, e.g. because it could not be published.
+    pub async fn release_utxos(&self, transaction: &Transaction) {
+        self.reserved_utxos.lock().await.release(transaction);
+    }
+
+    /// Calculates the fee of a transaction that spends coins of this wallet
+    /// but has not been published yet.
+    pub async fn unpublished_fee(&self, transaction: &Transaction) -> Result<Amount> {
+        let transactions = self.wallet.lock().await.list_transactions(true)?;
+        let get_tx = |txid: Txid| {
+            transactions
+                .iter()
+                .find(|tx| tx.txid == txid)
+                .and_then(|tx| tx.transaction.clone())
+                .with_context(|| format!("Could not find transaction {}", txid))
+        };
+
+        fee_of(transaction, get_tx).context("Failed to compute fee from the spent transactions")
+    }
+
     /// Calculates the maximum "giveable" amount of this wallet.
     ///
     /// We define this as the maximum amount we can pay to a single output,
     /// already accounting for the fees we need to spend to get the
-    /// transaction confirmed.
+    /// transaction confirmed. Coins reserved for in-flight swaps are not
+    /// included.
+    ///
+    /// The output is assumed to be a segwit output, as all the outputs we lock
+    /// funds in are.
     pub async fn max_giveable(&self, locking_script_size: usize) -> Result<Amount> {
         let wallet = self.wallet.lock().await;
+        let reserved_utxos = self.reserved_utxos.lock().await;
+
+        max_giveable(
+            &wallet,
+            &reserved_utxos,
+            locking_script_size,
+            true,
+            self.select_feerate(),
+        )
+    }
 
-        let mut tx_builder = wallet.build_tx();
+    /// Like [`Wallet::max_giveable`], but for an output paying to the given
+    /// address, e.g. to withdraw all funds of the wallet.
+    pub async fn max_giveable_to(&self, address: &Address) -> Result<Amount> {
+        let wallet = self.wallet.lock().await;
+        let reserved_utxos = self.reserved_utxos.lock().await;
+        let script = address.script_pubkey();
+
+        max_giveable(
+            &wallet,
+            &reserved_utxos,
+            script.len(),
+            script.is_witness_program(),
+            self.select_feerate(),
+        )
+    }
 
-        let dummy_script = Script::from(vec![0u8; locking_script_size]);
-        tx_builder.set_single_recipient(dummy_script);
-        tx_builder.drain_wallet();
-        tx_builder.fee_rate(self.select_feerate());
-        let (_, details) = tx_builder.finish().context("Failed to build transaction")?;
+    /// Send the given amount to an external address and publish the
+    /// transaction, e.g. to withdraw funds from the hot wallet.
+    ///
+    /// Coins reserved for in-flight swaps are not spent. If `coins` is not
+    /// empty, only those are spent. Returns the id and the fee of the
+    /// transaction.
+    pub async fn withdraw(
+        &self,
+        address: Address,
+        amount: Amount,
+        coins: &[OutPoint],
+    ) -> Result<(Txid, Amount)> {
+        let psbt = if coins.is_empty() {
+            self.send_to_address(address, amount).await?
+        } else {
+            self.send_to_address_with_coin_control(address, amount, coins)
+                .await?
+        };
+        let unsigned_tx = psbt.global.unsigned_tx.clone();
+        let transaction = match self.sign_and_finalize(psbt).await {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                self.release_utxos(&unsigned_tx).await;
+                return Err(error);
+            }
+        };
+        let (txid, _) = self.broadcast(transaction, "withdraw").await?;
 
-        let max_giveable = details.sent - details.fees;
+        let fee = self.transaction_fee(txid).await?;
 
-        Ok(Amount::from_sat(max_giveable))
+        Ok((txid, fee))
     }
 
     pub async fn get_network(&self) -> bitcoin::Network {
@@ -168,38 +558,230 @@ impl Wallet {
         &self,
         transaction: Transaction,
         kind: &str,
-    ) -> Result<(Txid, impl Future<Output = Result<()>> + '_)> {
+    ) -> Result<(Txid, impl Future<Output = Result<()>> + '_), SwapError> {
         let txid = transaction.txid();
 
         // to watch for confirmations, watching a single output is enough
+        let (script, amount) = self.watched_output(&transaction).await?;
         let watcher = self.wait_for_transaction_finality(
-            (txid, transaction.output[0].script_pubkey.clone()),
+            (txid, script),
+            amount,
             kind.to_owned(),
+            Some(transaction.clone()),
         );
 
-        self.wallet
-            .lock()
-            .await
-            .broadcast(transaction)
-            .with_context(|| {
-                format!("Failed to broadcast Bitcoin {} transaction {}", kind, txid)
-            })?;
+        self.publish_and_log(&transaction, kind).await?;
+
+        Ok((txid, watcher))
+    }
 
-        tracing::info!(%txid, "Published Bitcoin {} transaction", kind);
+    /// Like [`Wallet::broadcast`], but the transaction is not published again
+    /// if it is already in the mempool or confirmed, e.g. because an earlier
+    /// attempt only failed after publishing it.
+    ///
+    /// The status is fetched from electrum, the transaction may have been
+    /// published by an earlier run whose watched scripts are not known.
+    pub async fn ensure_broadcast(
+        &self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, impl Future<Output = Result<()>> + '_), SwapError> {
+        let txid = transaction.txid();
+        let (script, amount) = self.watched_output(&transaction).await?;
+        let watchable = (txid, script);
+
+        let status = self.fetch_status_of_script(&watchable).await?;
+
+        let watcher = self.wait_for_transaction_finality(
+            watchable,
+            amount,
+            kind.to_owned(),
+            Some(transaction.clone()),
+        );
+
+        match status {
+            ScriptStatus::Unseen => self.publish_and_log(&transaction, kind).await?,
+            status => {
+                tracing::info!(%txid, "Bitcoin {} transaction was already published and is {}", kind, status)
+            }
+        }
 
         Ok((txid, watcher))
     }
 
-    pub async fn sign_and_finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction> {
-        let (signed_psbt, finalized) = self.wallet.lock().await.sign(psbt, None)?;
+    async fn publish_and_log(
+        &self,
+        transaction: &Transaction,
+        kind: &str,
+    ) -> Result<(), SwapError> {
+        let txid = transaction.txid();
 
-        if !finalized {
-            bail!("PSBT is not finalized")
+        let result = self.publish(transaction).await;
+        if result.is_err() {
+            self.release_utxos(transaction).await;
         }
+        result
+            .with_context(|| format!("Failed to broadcast Bitcoin {} transaction {}", kind, txid))
+            .map_err(SwapError::wallet)?;
 
-        let tx = signed_psbt.extract_tx();
+        match &self.explorer {
+            Some(explorer) => {
+                tracing::info!(%txid, url = %explorer.transaction(txid), "Published Bitcoin {} transaction", kind)
+            }
+            None => tracing::info!(%txid, "Published Bitcoin {} transaction", kind),
+        }
 
-        Ok(tx)
+        Ok(())
+    }
+
+    async fn publish(&self, transaction: &Transaction) -> Result<()> {
+        let electrum = {
+            let transaction = transaction.clone();
+
+            run_blocking(self.electrum.clone(), move |client| {
+                client.broadcast(&transaction).map_err(anyhow::Error::from)
+            })
+        };
+
+        publish(transaction, self.bitcoind.as_ref(), electrum).await
+    }
+
+    /// Speed up the confirmation of the given transaction by spending its
+    /// output to this wallet with a fee high enough for both transactions,
+    /// together with the unconfirmed transactions it spends, to pay
+    /// `fee_rate` (child pays for parent).
+    ///
+    /// The transactions of a swap are either signed by both parties or
+    /// referenced by the transactions signed in advance, replacing them is
+    /// not an option. A cancel transaction stuck in the mempool is sped up
+    /// through the refund transaction spending it instead.
+    ///
+    /// The child of an earlier bump already spends the output, it is replaced
+    /// instead of spending the output again.
+    async fn bump_fee(
+        &self,
+        parent: &Transaction,
+        fee_rate: f32,
+        previous_child: Option<Txid>,
+    ) -> Result<Txid> {
+        let parent_fee = self.transaction_fee(parent.txid()).await?;
+        let ancestors = self.unconfirmed_ancestors(parent).await?;
+
+        // The wallet has to know about the unconfirmed output, or the child
+        // spending it, to build a child.
+        self.sync().await?;
+
+        let psbt = match previous_child {
+            None => {
+                let address = self.new_address().await?;
+                let wallet = self.wallet.lock().await;
+                let mut reserved_utxos = self.reserved_utxos.lock().await;
+
+                build_cpfp_tx(
+                    &wallet,
+                    &mut reserved_utxos,
+                    parent,
+                    parent_fee,
+                    &ancestors,
+                    address.script_pubkey(),
+                    fee_rate,
+                )?
+            }
+            Some(previous_child) => {
+                let wallet = self.wallet.lock().await;
+                let mut reserved_utxos = self.reserved_utxos.lock().await;
+
+                build_cpfp_replacement(
+                    &wallet,
+                    &mut reserved_utxos,
+                    parent,
+                    parent_fee,
+                    &ancestors,
+                    previous_child,
+                    fee_rate,
+                )?
+            }
+        };
+
+        let child = self.sign_and_finalize(psbt).await?;
+        if let Err(error) = self.publish(&child).await {
+            self.release_utxos(&child).await;
+            return Err(error);
+        }
+
+        Ok(child.txid())
+    }
+
+    /// The transactions spent by the given one that are not confirmed yet,
+    /// together with their fees.
+    async fn unconfirmed_ancestors(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Vec<(Transaction, Amount)>> {
+        let mut ancestors: Vec<(Transaction, Amount)> = Vec::new();
+
+        for input in &transaction.input {
+            let outpoint = input.previous_output;
+            if ancestors
+                .iter()
+                .any(|(ancestor, _)| ancestor.txid() == outpoint.txid)
+            {
+                continue;
+            }
+
+            let ancestor = self.get_raw_transaction(outpoint.txid).await?;
+            let script = ancestor
+                .output
+                .get(outpoint.vout as usize)
+                .with_context(|| {
+                    format!(
+                        "Transaction {} has no output {}",
+                        outpoint.txid, outpoint.vout
+                    )
+                })?
+                .script_pubkey
+                .clone();
+
+            if let ScriptStatus::Confirmed(_) = self
+                .fetch_status_of_script(&(outpoint.txid, script))
+                .await?
+            {
+                continue;
+            }
+
+            let fee = self.transaction_fee(outpoint.txid).await?;
+            ancestors.push((ancestor, fee));
+        }
+
+        Ok(ancestors)
+    }
+
+    pub async fn sign_and_finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction> {
+        let wallet = self.wallet.lock().await;
+        let signed_psbt = sign_psbt(&*wallet, psbt)?;
+
+        finalize_psbt(&*wallet, signed_psbt)
+    }
+
+    /// Add our signatures to the given PSBT.
+    ///
+    /// The PSBT may contain inputs we cannot sign for, e.g. if it was
+    /// constructed externally. It is returned as is for those inputs so that
+    /// it can be passed on to other signers before calling
+    /// [`Wallet::finalize_psbt`].
+    pub async fn sign_psbt(
+        &self,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction> {
+        sign_psbt(&*self.wallet.lock().await, psbt)
+    }
+
+    /// Finalize a PSBT once all signatures are present and extract the
+    /// transaction.
+    ///
+    /// Fails if any input still misses a signature.
+    pub async fn finalize_psbt(&self, psbt: PartiallySignedTransaction) -> Result<Transaction> {
+        finalize_psbt(&*self.wallet.lock().await, psbt)
     }
 
     pub async fn get_raw_transaction(&self, txid: Txid) -> Result<Transaction> {
@@ -208,243 +790,826 @@ impl Wallet {
             .ok_or_else(|| anyhow!("Could not get raw tx with id: {}", txid))
     }
 
+    /// The status of the given transaction.
+    ///
+    /// The electrum server is only asked once one of the pooled connections is
+    /// due for a refresh. The request is made without holding the lock on the
+    /// client, concurrent status checks are answered in the meantime.
     pub async fn status_of_script<T>(&self, tx: &T) -> Result<ScriptStatus>
     where
         T: Watchable,
     {
+        let refresh = self.client.lock().await.prepare_refresh(tx)?;
+
+        if let Some(refresh) = refresh {
+            let outcome = tokio::task::spawn_blocking(move || refresh.run())
+                .await
+                .context("Electrum request panicked")?;
+
+            self.client.lock().await.complete_refresh(outcome)?;
+        }
+
         self.client.lock().await.status_of_script(tx)
     }
 
+    /// The status of the given transaction, fetched from electrum right away.
+    ///
+    /// Unlike [`Wallet::status_of_script`] this does not rely on the known
+    /// script histories, which report a transaction as unseen as long as its
+    /// script was not fetched yet. Use this before acting on a transaction
+    /// not being on the blockchain.
+    pub async fn fetch_status_of_script<T>(&self, tx: &T) -> Result<ScriptStatus>
+    where
+        T: Watchable,
+    {
+        let fetch = loop {
+            if let Some(fetch) = self.client.lock().await.prepare_fetch(tx) {
+                break fetch;
+            }
+
+            // All connections are refreshing, one of them is back shortly.
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        };
+
+        let outcome = tokio::task::spawn_blocking(move || fetch.run())
+            .await
+            .context("Electrum request panicked")?;
+        let fetched = outcome.fetched_history();
+
+        let mut client = self.client.lock().await;
+        client.complete_refresh(outcome)?;
+        if !fetched {
+            bail!(
+                "Failed to fetch the status of Bitcoin transaction {} from electrum",
+                tx.id()
+            );
+        }
+
+        client.fresh_status_of_script(tx)
+    }
+
+    /// The number of scripts whose histories are currently kept, e.g. to
+    /// monitor the memory used for watching transactions.
+    pub async fn watched_scripts(&self) -> usize {
+        self.client.lock().await.watched_scripts()
+    }
+
     pub async fn watch_until_status<T>(
         &self,
         tx: &T,
         mut status_fn: impl FnMut(ScriptStatus) -> bool,
     ) -> Result<()>
+    where
+        T: Watchable,
+    {
+        self.watch_until(tx, |status, _| status_fn(status)).await
+    }
+
+    /// Like [`Wallet::watch_until_status`] but also gives `status_fn` access
+    /// to the state of the electrum client, e.g. to check for reorgs.
+    async fn watch_until<T>(
+        &self,
+        tx: &T,
+        mut status_fn: impl FnMut(ScriptStatus, &Client) -> bool,
+    ) -> Result<()>
     where
         T: Watchable,
     {
         let txid = tx.id();
+        // Keep the history of the script while we wait, also if the future is
+        // dropped midway.
+        let _watching = self.client.lock().await.watch(tx.script());
 
         let mut last_status = None;
 
         loop {
-            let new_status = self.client.lock().await.status_of_script(tx)?;
+            let new_status = match self.status_of_script(tx).await {
+                Ok(status) => status,
+                Err(error) if error.is::<ElectrumServerBusy>() => {
+                    tracing::warn!(%txid, "Electrum server is busy, waiting before checking the transaction again");
+                    tokio::time::sleep(SERVER_BUSY_INITIAL_DELAY).await;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
 
             if Some(new_status) != last_status {
                 tracing::debug!(%txid, "Transaction is {}", new_status);
             }
             last_status = Some(new_status);
 
-            if status_fn(new_status) {
+            let done = status_fn(new_status, &*self.client.lock().await);
+
+            if done {
                 break;
             }
 
-            tokio::time::sleep(Duration::from_secs(5)).await;
+            let delay = poll_delay(self.poll_jitter, &mut rand::thread_rng());
+            tokio::time::sleep(delay).await;
         }
 
         Ok(())
     }
 
-    async fn wait_for_transaction_finality<T>(&self, tx: T, kind: String) -> Result<()>
+    async fn wait_for_transaction_finality<T>(
+        &self,
+        tx: T,
+        amount: Amount,
+        kind: String,
+        transaction: Option<Transaction>,
+    ) -> Result<()>
     where
         T: Watchable,
     {
-        let conf_target = self.finality_confirmations;
+        let conf_target = self.finality_confirmations(amount);
         let txid = tx.id();
 
         tracing::info!(%txid, "Waiting for {} confirmation{} of Bitcoin {} transaction", conf_target, if conf_target > 1 { "s" } else { "" }, kind);
 
+        // Only transactions we know in full and that pay to this wallet can be
+        // bumped. Swap transactions cannot be replaced (RBF), they are signed by
+        // both parties in advance.
+        let fee_bumping = match (self.fee_bumping, transaction.as_ref()) {
+            (Some(policy), Some(transaction)) if self.pays_to_wallet(transaction).await? => {
+                Some(policy)
+            }
+            (Some(_), Some(_)) => {
+                tracing::info!(%txid, "Bitcoin {} transaction does not pay to this wallet, its fee cannot be bumped", kind);
+                None
+            }
+            _ => None,
+        };
+        let base_fee_rate = match fee_bumping.and(transaction.as_ref()) {
+            Some(transaction) => self.fee_rate_of(transaction).await.unwrap_or_else(|error| {
+                tracing::debug!(%txid, "Failed to determine the fee rate of Bitcoin {} transaction: {:#}", kind, error);
+                self.select_feerate().as_sat_vb()
+            }),
+            None => self.select_feerate().as_sat_vb(),
+        };
         let mut seen_confirmations = 0;
+        let mut in_mempool_since = None;
+        let mut bumps = 0;
+        let mut child = None;
 
-        self.watch_until_status(&tx, |status| match status {
-            ScriptStatus::Confirmed(inner) => {
-                let confirmations = inner.confirmations();
-
-                if confirmations > seen_confirmations {
-                    tracing::info!(%txid, "Bitcoin {} tx has {} out of {} confirmation{}", kind, confirmations, conf_target, if conf_target > 1 { "s" } else { "" });
-                    seen_confirmations = confirmations;
+        loop {
+            let mut bump_to = None;
+
+            self.watch_until(&tx, |status, client| match status {
+                ScriptStatus::Confirmed(inner) => {
+                    let confirmations = inner.confirmations();
+
+                    if confirmations > seen_confirmations {
+                        tracing::info!(%txid, "Bitcoin {} tx has {} out of {} confirmation{}", kind, confirmations, conf_target, if conf_target > 1 { "s" } else { "" });
+                        seen_confirmations = confirmations;
+                    }
+
+                    // Confirmations counted across a reorg are unreliable.
+                    inner.meets_target(conf_target) && client.allows_finality(conf_target)
+                },
+                ScriptStatus::InMempool => {
+                    let latest_block = u32::from(client.latest_block());
+                    let since = *in_mempool_since.get_or_insert(latest_block);
+
+                    bump_to = fee_bumping.and_then(|policy| {
+                        policy.next_fee_rate(latest_block.saturating_sub(since), bumps, base_fee_rate)
+                    });
+                    bump_to.is_some()
+                },
+                ScriptStatus::Unseen => false
+            })
+            .await?;
+
+            let (fee_rate, transaction) = match (bump_to, transaction.as_ref()) {
+                (Some(fee_rate), Some(transaction)) => (fee_rate, transaction),
+                _ => return Ok(()),
+            };
+            bumps += 1;
+
+            match self.bump_fee(transaction, fee_rate, child).await {
+                Ok(bumped) => {
+                    tracing::info!(
+                        %txid,
+                        child = %bumped,
+                        "Bumped fee of Bitcoin {} transaction to {} sat/vB",
+                        kind,
+                        fee_rate
+                    );
+                    child = Some(bumped);
                 }
+                Err(error) => tracing::warn!(
+                    %txid,
+                    "Failed to bump fee of Bitcoin {} transaction: {:#}",
+                    kind,
+                    error
+                ),
+            }
+        }
+    }
 
-                inner.meets_target(conf_target)
-            },
-            _ => false
+    /// The script of the output of the given transaction to watch for
+    /// confirmations and the amount paid to it, see [`watched_output`].
+    async fn watched_output(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<(Script, Amount), SwapError> {
+        let mut ours = HashSet::new();
+        for output in &transaction.output {
+            if self
+                .is_mine(&output.script_pubkey)
+                .await
+                .map_err(SwapError::wallet)?
+            {
+                ours.insert(output.script_pubkey.clone());
+            }
+        }
+
+        Ok(watched_output(transaction, |script| ours.contains(script)))
+    }
+
+    async fn pays_to_wallet(&self, transaction: &Transaction) -> Result<bool> {
+        for output in &transaction.output {
+            if self.is_mine(&output.script_pubkey).await? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// The fee rate in sat/vB the given published transaction pays, at least
+    /// the minimum relay fee rate.
+    async fn fee_rate_of(&self, transaction: &Transaction) -> Result<f32> {
+        let fee = self.transaction_fee(transaction.txid()).await?;
+        let fee_rate = fee.as_sat() as f32 / vbytes(transaction) as f32;
+
+        Ok(fee_rate.max(MIN_FEE_RATE_SAT_PER_VB))
+    }
+
+    /// The fee rate electrum estimates for a transaction to confirm within
+    /// the given number of blocks.
+    async fn estimate_fee_rate(&self, target_blocks: usize) -> Result<FeeRate> {
+        let fee_rate = run_blocking(self.electrum.clone(), move |client| {
+            Ok(client.estimate_fee(target_blocks)?)
         })
         .await?;
 
-        Ok(())
+        // Electrum answers with a negative fee rate if it has no estimate
+        if fee_rate.as_sat_vb() <= 0.0 {
+            bail!("No fee estimate for {} blocks available", target_blocks)
+        }
+
+        Ok(FeeRate::from_sat_per_vb(clamp_fee_rate(
+            fee_rate.as_sat_vb(),
+        )))
     }
 
     /// Selects an appropriate [`FeeRate`] to be used for getting transactions
     /// confirmed within a reasonable amount of time.
     fn select_feerate(&self) -> FeeRate {
-        // TODO: This should obviously not be a const :)
-        FeeRate::from_sat_per_vb(5.0)
+        select_feerate(self.fee_rate_override)
     }
 }
 
-/// Defines a watchable transaction.
+/// How long to wait before polling the status of a transaction again.
 ///
-/// For a transaction to be watchable, we need to know two things: Its
-/// transaction ID and the specific output script that is going to change.
-/// A transaction can obviously have multiple outputs but our protocol purposes,
-/// we are usually interested in a specific one.
-pub trait Watchable {
-    fn id(&self) -> Txid;
-    fn script(&self) -> Script;
+/// The interval is randomly extended by up to `jitter` so that many watchers
+/// started at the same time, e.g. when resuming swaps, do not poll in lockstep.
+fn poll_delay(jitter: Duration, rng: &mut impl Rng) -> Duration {
+    STATUS_POLL_INTERVAL + jitter.mul_f64(rng.gen::<f64>())
 }
 
-impl Watchable for (Txid, Script) {
-    fn id(&self) -> Txid {
-        self.0
-    }
-
-    fn script(&self) -> Script {
-        self.1.clone()
-    }
+fn sum_of_outputs_to<'a>(
+    addresses: &[Address],
+    outputs: impl Iterator<Item = &'a TxOut>,
+) -> Amount {
+    let scripts = addresses
+        .iter()
+        .map(|address| address.script_pubkey())
+        .collect::<Vec<_>>();
+
+    let sats = outputs
+        .filter(|output| scripts.contains(&output.script_pubkey))
+        .map(|output| output.value)
+        .sum();
+
+    Amount::from_sat(sats)
 }
 
-struct Client {
-    electrum: bdk::electrum_client::Client,
-    latest_block: BlockHeight,
-    last_ping: Instant,
-    interval: Duration,
-    script_history: BTreeMap<Script, Vec<GetHistoryRes>>,
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Amount {amount} is below the dust threshold ({} sats)", .dust_threshold.as_sat())]
+pub struct AmountBelowDustThreshold {
+    pub amount: Amount,
+    pub dust_threshold: Amount,
 }
 
-impl Client {
-    fn new(electrum: bdk::electrum_client::Client, interval: Duration) -> Result<Self> {
-        let latest_block = electrum.block_headers_subscribe().map_err(|e| {
-            anyhow!(
-                "Electrum client failed to subscribe to header notifications: {:?}",
-                e
-            )
-        })?;
-
-        Ok(Self {
-            electrum,
-            latest_block: BlockHeight::try_from(latest_block)?,
-            last_ping: Instant::now(),
-            interval,
-            script_history: Default::default(),
-        })
+fn ensure_not_dust(
+    amount: Amount,
+    script_len: usize,
+    is_witness_program: bool,
+) -> Result<(), AmountBelowDustThreshold> {
+    let dust_threshold = dust_threshold(script_len, is_witness_program);
+
+    if amount < dust_threshold {
+        return Err(AmountBelowDustThreshold {
+            amount,
+            dust_threshold,
+        });
     }
 
-    /// Ping the electrum server unless we already did within the set interval.
-    ///
-    /// Returns a boolean indicating whether we actually pinged the server.
-    fn ping(&mut self) -> bool {
-        if self.last_ping.elapsed() <= self.interval {
-            return false;
-        }
+    Ok(())
+}
 
-        match self.electrum.ping() {
-            Ok(()) => {
-                self.last_ping = Instant::now();
+/// The smallest amount an output with a locking script of the given length
+/// can carry without being dust, following Bitcoin Core's definition.
+fn dust_threshold(script_len: usize, is_witness_program: bool) -> Amount {
+    let script_len_prefix = if script_len < 0xfd { 1 } else { 3 };
+    let output_size = 8 + script_len_prefix + script_len as u64;
 
-                true
-            }
-            Err(error) => {
-                tracing::debug!(?error, "Failed to ping electrum server");
+    // outpoint, script length, sequence and the (discounted) signature script
+    let input_size = if is_witness_program {
+        32 + 4 + 1 + 4 + 107 / 4
+    } else {
+        32 + 4 + 1 + 4 + 107
+    };
 
-                false
-            }
+    Amount::from_sat((output_size + input_size) * DUST_RELAY_FEE_SAT_PER_VB)
+}
+
+/// The script of the output to watch for confirmations of the given
+/// transaction and the amount paid to it.
+///
+/// The first output not paying to this wallet is preferred, e.g. the lock
+/// output over the change of the lock transaction, as it carries the amount
+/// the finality depends on.
+fn watched_output(
+    transaction: &Transaction,
+    is_ours: impl Fn(&Script) -> bool,
+) -> (Script, Amount) {
+    let script = transaction
+        .output
+        .iter()
+        .find(|output| !is_ours(&output.script_pubkey))
+        .unwrap_or(&transaction.output[0])
+        .script_pubkey
+        .clone();
+    let amount = transaction
+        .output
+        .iter()
+        .filter(|output| output.script_pubkey == script)
+        .map(|output| output.value)
+        .sum();
+
+    (script, Amount::from_sat(amount))
+}
+
+/// Publish the given transaction through bitcoind if configured, through
+/// electrum otherwise or if bitcoind fails to publish it.
+async fn publish(
+    transaction: &Transaction,
+    bitcoind: Option<&bitcoind::Client>,
+    electrum: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    if let Some(bitcoind) = bitcoind {
+        match bitcoind.send_raw_transaction(transaction).await {
+            Ok(_) => return Ok(()),
+            Err(error) => tracing::warn!(
+                txid = %transaction.txid(),
+                "Failed to publish transaction through bitcoind, falling back to electrum: {:#}",
+                error
+            ),
         }
     }
 
-    fn drain_notifications(&mut self) -> Result<()> {
-        let pinged = self.ping();
+    electrum.await
+}
 
-        if !pinged {
-            return Ok(());
-        }
+/// Compute the fee of a transaction as the difference between the value of the
+/// outputs it spends and the value of its own outputs.
+fn fee_of(
+    transaction: &Transaction,
+    get_tx: impl Fn(Txid) -> Result<Transaction>,
+) -> Result<Amount> {
+    let inputs = transaction
+        .input
+        .iter()
+        .map(|input| {
+            let OutPoint { txid, vout } = input.previous_output;
+            let previous = get_tx(txid)?;
+            let output = previous
+                .output
+                .get(vout as usize)
+                .with_context(|| format!("Transaction {} has no output {}", txid, vout))?;
+
+            Ok(Amount::from_sat(output.value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let inputs = checked_sum(inputs).context("Failed to sum up the inputs")?;
+    let outputs = checked_sum(
+        transaction
+            .output
+            .iter()
+            .map(|output| Amount::from_sat(output.value)),
+    )
+    .context("Failed to sum up the outputs")?;
+
+    let fee = checked_sub(inputs, outputs).with_context(|| {
+        format!(
+            "Transaction {} spends more than its inputs",
+            transaction.txid()
+        )
+    })?;
+
+    Ok(fee)
+}
 
-        self.drain_blockheight_notifications()?;
-        self.update_script_histories()?;
+/// Transactions moving at least `min_amount` require `confirmations` to be
+/// considered final.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FinalityTier {
+    #[serde(with = "::bitcoin::util::amount::serde::as_btc")]
+    pub min_amount: Amount,
+    pub confirmations: u32,
+}
 
-        Ok(())
+/// How many confirmations a transaction needs depending on the amount it
+/// moves. Amounts below all tiers use the flat default.
+#[derive(Clone, Debug, PartialEq)]
+struct FinalityPolicy {
+    default: u32,
+    tiers: Vec<FinalityTier>,
+}
+
+impl FinalityPolicy {
+    fn flat(confirmations: u32) -> Self {
+        Self {
+            default: confirmations,
+            tiers: Vec::new(),
+        }
     }
 
-    fn status_of_script<T>(&mut self, tx: &T) -> Result<ScriptStatus>
-    where
-        T: Watchable,
-    {
-        let txid = tx.id();
-        let script = tx.script();
+    fn confirmations_for(&self, amount: Amount) -> u32 {
+        self.tiers
+            .iter()
+            .filter(|tier| amount >= tier.min_amount)
+            .map(|tier| tier.confirmations)
+            .fold(self.default, u32::max)
+    }
+}
+
+fn is_our_address<B, D>(wallet: &bdk::Wallet<B, D>, address: &Address) -> Result<bool>
+where
+    D: BatchDatabase,
+{
+    let script = address.script_pubkey();
 
-        if !self.script_history.contains_key(&script) {
-            self.script_history.insert(script.clone(), vec![]);
+    // Revealed addresses are cached by bdk together with a batch of the ones
+    // following them.
+    if wallet.is_mine(&script)? {
+        return Ok(true);
+    }
+
+    let secp = bitcoin::secp256k1::Secp256k1::verification_only();
+    for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+        let descriptor = match wallet.public_descriptor(*keychain)? {
+            Some(descriptor) => descriptor,
+            None => continue,
+        };
+        let next_index = match wallet.database().get_last_index(*keychain)? {
+            Some(last_revealed) => last_revealed + 1,
+            None => 0,
+        };
+
+        for index in next_index..next_index + ADDRESS_GAP_LIMIT {
+            let derived = descriptor
+                .derive(ChildNumber::from_normal_idx(index)?)
+                .translate_pk2(|key| key.derive_public_key(&secp))?;
+
+            if derived.script_pubkey() == script {
+                return Ok(true);
+            }
         }
+    }
 
-        self.drain_notifications()?;
+    Ok(false)
+}
 
-        let history = self.script_history.entry(script).or_default();
+fn build_tx_with_reserved_utxos<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    reserved_utxos: &mut UtxoReservations,
+    script: Script,
+    amount: Amount,
+    fee_rate: FeeRate,
+) -> Result<PartiallySignedTransaction>
+where
+    D: BatchDatabase,
+{
+    let mut tx_builder = wallet.build_tx();
+    tx_builder.add_recipient(script, amount.as_sat());
+    tx_builder.unspendable(reserved_utxos.unspendable());
+    tx_builder.fee_rate(fee_rate);
+    let (psbt, _details) = tx_builder.finish()?;
+
+    reserved_utxos.reserve(&psbt.global.unsigned_tx);
+
+    Ok(psbt)
+}
 
-        let history_of_tx = history
-            .iter()
-            .filter(|entry| entry.tx_hash == txid)
-            .collect::<Vec<_>>();
+fn max_giveable<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    reserved_utxos: &UtxoReservations,
+    locking_script_size: usize,
+    is_witness_program: bool,
+    fee_rate: FeeRate,
+) -> Result<Amount>
+where
+    D: BatchDatabase,
+{
+    let mut tx_builder = wallet.build_tx();
+
+    let dummy_script = Script::from(vec![0u8; locking_script_size]);
+    tx_builder.set_single_recipient(dummy_script);
+    tx_builder.drain_wallet();
+    tx_builder.unspendable(reserved_utxos.unspendable());
+    tx_builder.fee_rate(fee_rate);
+    let (_, details) = tx_builder.finish().context("Failed to build transaction")?;
+
+    let max_giveable = checked_sub(
+        Amount::from_sat(details.sent),
+        Amount::from_sat(details.fees),
+    )
+    .context("Wallet balance does not cover the fees")?;
+
+    ensure_not_dust(max_giveable, locking_script_size, is_witness_program)?;
+
+    Ok(max_giveable)
+}
 
-        match history_of_tx.as_slice() {
-            [] => Ok(ScriptStatus::Unseen),
-            [remaining @ .., last] => {
-                if !remaining.is_empty() {
-                    tracing::warn!("Found more than a single history entry for script. This is highly unexpected and those history entries will be ignored.")
-                }
+fn sign_psbt<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    psbt: PartiallySignedTransaction,
+) -> Result<PartiallySignedTransaction>
+where
+    D: BatchDatabase,
+{
+    let (signed_psbt, _finalized) = wallet.sign(psbt, None)?;
 
-                if last.height <= 0 {
-                    Ok(ScriptStatus::InMempool)
-                } else {
-                    Ok(ScriptStatus::Confirmed(
-                        Confirmed::from_inclusion_and_latest_block(
-                            u32::try_from(last.height)?,
-                            u32::from(self.latest_block),
-                        ),
-                    ))
-                }
-            }
-        }
+    Ok(signed_psbt)
+}
+
+fn finalize_psbt<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    psbt: PartiallySignedTransaction,
+) -> Result<Transaction>
+where
+    D: BatchDatabase,
+{
+    let (finalized_psbt, finalized) = wallet.finalize_psbt(psbt, None)?;
+
+    if !finalized {
+        bail!("PSBT is not finalized, signatures are missing")
     }
 
-    fn drain_blockheight_notifications(&mut self) -> Result<()> {
-        let latest_block = std::iter::from_fn(|| self.electrum.block_headers_pop().transpose())
-            .last()
-            .transpose()
-            .map_err(|e| anyhow!("Failed to pop header notification: {:?}", e))?;
+    Ok(finalized_psbt.extract_tx())
+}
 
-        if let Some(new_block) = latest_block {
-            tracing::debug!(
-                "Got notification for new block at height {}",
-                new_block.height
-            );
-            self.latest_block = BlockHeight::try_from(new_block)?;
-        }
+fn build_tx_from_coins<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    reserved_utxos: &mut UtxoReservations,
+    coins: &[OutPoint],
+    script: Script,
+    amount: Amount,
+    fee_rate: FeeRate,
+) -> Result<PartiallySignedTransaction>
+where
+    D: BatchDatabase,
+{
+    if let Some(coin) = coins.iter().find(|coin| reserved_utxos.is_reserved(coin)) {
+        bail!("Coin {} is already used by another transaction", coin);
+    }
 
-        Ok(())
+    let mut tx_builder = wallet.build_tx();
+    tx_builder.add_recipient(script, amount.as_sat());
+    tx_builder
+        .add_utxos(coins)
+        .context("Selected coin does not belong to this wallet")?;
+    tx_builder.manually_selected_only();
+    tx_builder.fee_rate(fee_rate);
+    let (psbt, _details) = tx_builder
+        .finish()
+        .with_context(|| format!("Selected coins do not cover {} and the fees", amount))?;
+
+    reserved_utxos.reserve(&psbt.global.unsigned_tx);
+
+    Ok(psbt)
+}
+
+fn build_consolidation_tx<B, D>(
+    wallet: &bdk::Wallet<B, D>,
+    reserved_utxos: &mut UtxoReservations,
+    min_utxos: usize,
+    script: Script,
+    fee_rate: FeeRate,
+) -> Result<Option<PartiallySignedTransaction>>
+where
+    D: BatchDatabase,
+{
+    let utxos = wallet
+        .list_unspent()?
+        .into_iter()
+        .map(|utxo| utxo.outpoint)
+        .filter(|outpoint| !reserved_utxos.is_reserved(outpoint))
+        .collect::<Vec<_>>();
+
+    if utxos.len() < min_utxos {
+        return Ok(None);
     }
 
-    fn update_script_histories(&mut self) -> Result<()> {
-        let histories = self
-            .electrum
-            .batch_script_get_history(self.script_history.keys())
-            .map_err(|e| anyhow!("Failed to get script histories {:?}", e))?;
+    let mut tx_builder = wallet.build_tx();
+    tx_builder.add_utxos(&utxos)?;
+    tx_builder.manually_selected_only();
+    tx_builder.set_single_recipient(script);
+    tx_builder.fee_rate(fee_rate);
+    let (psbt, _details) = tx_builder.finish()?;
 
-        if histories.len() != self.script_history.len() {
-            bail!(
-                "Expected {} history entries, received {}",
-                self.script_history.len(),
-                histories.len()
-            );
-        }
+    reserved_utxos.reserve(&psbt.global.unsigned_tx);
+
+    Ok(Some(psbt))
+}
 
-        let scripts = self.script_history.keys().cloned();
-        let histories = histories.into_iter();
+fn select_feerate(fee_rate_override: Option<f32>) -> FeeRate {
+    // TODO: The default should obviously not be a const :)
+    let sat_per_vb = fee_rate_override.unwrap_or(DEFAULT_FEE_RATE_SAT_PER_VB);
 
-        self.script_history = scripts.zip(histories).collect::<BTreeMap<_, _>>();
+    FeeRate::from_sat_per_vb(clamp_fee_rate(sat_per_vb))
+}
 
-        Ok(())
+fn clamp_fee_rate(sat_per_vb: f32) -> f32 {
+    sat_per_vb
+        .max(MIN_FEE_RATE_SAT_PER_VB)
+        .min(MAX_FEE_RATE_SAT_PER_VB)
+}
+
+/// The operations of the Bitcoin wallet that the swap protocols rely on.
+///
+/// [`Wallet`] is used outside of tests. Tests drive the swap state machines
+/// against a wallet whose view of the blockchain they control instead, see
+/// `MockWallet` in the `mock` module, which is only compiled for tests.
+#[async_trait]
+pub trait BitcoinWallet: Send + Sync {
+    async fn get_network(&self) -> bitcoin::Network;
+    async fn new_address(&self) -> Result<Address>;
+    async fn is_our_address(&self, address: &Address) -> Result<bool>;
+    async fn send_to_address(
+        &self,
+        address: Address,
+        amount: Amount,
+    ) -> Result<PartiallySignedTransaction>;
+    async fn unpublished_fee(&self, transaction: &Transaction) -> Result<Amount>;
+    async fn release_utxos(&self, transaction: &Transaction);
+    async fn sign_and_finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction>;
+    /// See [`Wallet::broadcast`].
+    async fn broadcast<'a>(
+        &'a self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, BoxFuture<'a, Result<()>>), SwapError>;
+    /// See [`Wallet::ensure_broadcast`].
+    async fn ensure_broadcast<'a>(
+        &'a self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, BoxFuture<'a, Result<()>>), SwapError>;
+    async fn get_raw_transaction(&self, txid: Txid) -> Result<Transaction>;
+    async fn status_of_script(&self, tx: &(dyn Watchable + Sync)) -> Result<ScriptStatus>;
+    /// See [`Wallet::fetch_status_of_script`].
+    async fn fetch_status_of_script(&self, tx: &(dyn Watchable + Sync)) -> Result<ScriptStatus>;
+    async fn watch_until_status<'a>(
+        &'a self,
+        tx: &'a (dyn Watchable + Sync),
+        status_fn: Box<dyn FnMut(ScriptStatus) -> bool + Send + 'a>,
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl BitcoinWallet for Wallet {
+    async fn get_network(&self) -> bitcoin::Network {
+        Wallet::get_network(self).await
+    }
+
+    async fn new_address(&self) -> Result<Address> {
+        Wallet::new_address(self).await
+    }
+
+    async fn is_our_address(&self, address: &Address) -> Result<bool> {
+        Wallet::is_our_address(self, address).await
+    }
+
+    async fn send_to_address(
+        &self,
+        address: Address,
+        amount: Amount,
+    ) -> Result<PartiallySignedTransaction> {
+        Wallet::send_to_address(self, address, amount).await
+    }
+
+    async fn unpublished_fee(&self, transaction: &Transaction) -> Result<Amount> {
+        Wallet::unpublished_fee(self, transaction).await
+    }
+
+    async fn release_utxos(&self, transaction: &Transaction) {
+        Wallet::release_utxos(self, transaction).await
+    }
+
+    async fn sign_and_finalize(&self, psbt: PartiallySignedTransaction) -> Result<Transaction> {
+        Wallet::sign_and_finalize(self, psbt).await
+    }
+
+    async fn broadcast<'a>(
+        &'a self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, BoxFuture<'a, Result<()>>), SwapError> {
+        let (txid, finality) = Wallet::broadcast(self, transaction, kind).await?;
+
+        Ok((txid, Box::pin(finality)))
+    }
+
+    async fn ensure_broadcast<'a>(
+        &'a self,
+        transaction: Transaction,
+        kind: &str,
+    ) -> Result<(Txid, BoxFuture<'a, Result<()>>), SwapError> {
+        let (txid, finality) = Wallet::ensure_broadcast(self, transaction, kind).await?;
+
+        Ok((txid, Box::pin(finality)))
+    }
+
+    async fn get_raw_transaction(&self, txid: Txid) -> Result<Transaction> {
+        Wallet::get_raw_transaction(self, txid).await
+    }
+
+    async fn status_of_script(&self, tx: &(dyn Watchable + Sync)) -> Result<ScriptStatus> {
+        Wallet::status_of_script(self, &(tx.id(), tx.script())).await
+    }
+
+    async fn fetch_status_of_script(&self, tx: &(dyn Watchable + Sync)) -> Result<ScriptStatus> {
+        Wallet::fetch_status_of_script(self, &(tx.id(), tx.script())).await
+    }
+
+    async fn watch_until_status<'a>(
+        &'a self,
+        tx: &'a (dyn Watchable + Sync),
+        status_fn: Box<dyn FnMut(ScriptStatus) -> bool + Send + 'a>,
+    ) -> Result<()> {
+        Wallet::watch_until_status(self, &(tx.id(), tx.script()), status_fn).await
     }
 }
 
+/// Defines a watchable transaction.
+///
+/// For a transaction to be watchable, we need to know two things: Its
+/// transaction ID and the specific output script that is going to change.
+/// A transaction can obviously have multiple outputs but our protocol purposes,
+/// we are usually interested in a specific one.
+pub trait Watchable {
+    fn id(&self) -> Txid;
+    fn script(&self) -> Script;
+}
+
+impl Watchable for (Txid, Script) {
+    fn id(&self) -> Txid {
+        self.0
+    }
+
+    fn script(&self) -> Script {
+        self.1.clone()
+    }
+}
+
+/// Run a blocking operation on the wallet or the electrum connection on the
+/// blocking thread pool.
+///
+/// Syncing or waiting for a slow electrum server can take a long time, running
+/// it on the async runtime would stall the other tasks scheduled on the same
+/// thread, e.g. status checks of concurrent swaps.
+///
+/// Dropping the returned future does not interrupt the operation. It runs to
+/// completion in the background and only then releases the lock, so the next
+/// caller never finds the wallet or the connection in the middle of a request.
+async fn run_blocking<W, T>(
+    resource: Arc<Mutex<W>>,
+    operation: impl FnOnce(&W) -> Result<T> + Send + 'static,
+) -> Result<T>
+where
+    W: Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(move || operation(&resource.blocking_lock()))
+        .await
+        .context("Blocking wallet operation panicked")?
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ScriptStatus {
     Unseen,
@@ -467,11 +1632,17 @@ pub struct Confirmed {
     ///
     /// Will be zero if the transaction is included in the latest block.
     depth: u32,
+    /// The height of the block the transaction is included in, unknown if the
+    /// status was only given as a number of confirmations.
+    inclusion_height: Option<u32>,
 }
 
 impl Confirmed {
     pub fn new(depth: u32) -> Self {
-        Self { depth }
+        Self {
+            depth,
+            inclusion_height: None,
+        }
     }
 
     /// Compute the depth of a transaction based on its inclusion height and the
@@ -483,7 +1654,19 @@ impl Confirmed {
     pub fn from_inclusion_and_latest_block(inclusion_height: u32, latest_block: u32) -> Self {
         let depth = latest_block.saturating_sub(inclusion_height);
 
-        Self { depth }
+        Self {
+            depth,
+            inclusion_height: Some(inclusion_height),
+        }
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// The height of the block the transaction is included in.
+    pub fn inclusion_height(&self) -> Option<u32> {
+        self.inclusion_height
     }
 
     pub fn confirmations(&self) -> u32 {
@@ -499,6 +1682,14 @@ impl Confirmed {
 }
 
 impl ScriptStatus {
+    /// The number of confirmations, zero if the script is not confirmed.
+    pub fn confirmations(&self) -> u32 {
+        match self {
+            ScriptStatus::Confirmed(confirmed) => confirmed.confirmations(),
+            ScriptStatus::Unseen | ScriptStatus::InMempool => 0,
+        }
+    }
+
     /// Check if the script has any confirmations.
     pub fn is_confirmed(&self) -> bool {
         matches!(self, ScriptStatus::Confirmed(_))
@@ -526,7 +1717,12 @@ impl fmt::Display for ScriptStatus {
             ScriptStatus::Unseen => write!(f, "unseen"),
             ScriptStatus::InMempool => write!(f, "in mempool"),
             ScriptStatus::Confirmed(inner) => {
-                write!(f, "confirmed with {} blocks", inner.confirmations())
+                write!(f, "confirmed with {} blocks", inner.confirmations())?;
+
+                match inner.inclusion_height() {
+                    Some(height) => write!(f, " in block {}", height),
+                    None => Ok(()),
+                }
             }
         }
     }
@@ -535,16 +1731,41 @@ impl fmt::Display for ScriptStatus {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bitcoin::fake_electrum::{transaction, FakeElectrum};
+    use crate::bitcoin::fee_bump::CPFP_CHILD_VBYTES;
+    use ::bitcoin::hashes::Hash;
+    use std::time::Instant;
 
     #[test]
     fn given_depth_0_should_meet_confirmation_target_one() {
-        let script = ScriptStatus::Confirmed(Confirmed { depth: 0 });
+        let script = ScriptStatus::Confirmed(Confirmed::new(0));
 
         let confirmed = script.is_confirmed_with(1);
 
         assert!(confirmed)
     }
 
+    #[test]
+    fn inclusion_height_is_retained() {
+        let confirmed = Confirmed::from_inclusion_and_latest_block(812_345, 812_350);
+
+        assert_eq!(confirmed.inclusion_height(), Some(812_345));
+        assert_eq!(confirmed.depth(), 5);
+        assert_eq!(confirmed.confirmations(), 6);
+        assert_eq!(
+            ScriptStatus::Confirmed(confirmed).to_string(),
+            "confirmed with 6 blocks in block 812345"
+        );
+    }
+
+    #[test]
+    fn inclusion_height_is_unknown_if_only_confirmations_are_given() {
+        let confirmed = Confirmed::new(5);
+
+        assert_eq!(confirmed.inclusion_height(), None);
+        assert_eq!(confirmed.confirmations(), 6);
+    }
+
     #[test]
     fn given_confirmations_1_should_meet_confirmation_target_one() {
         let script = ScriptStatus::from_confirmations(1);
@@ -563,4 +1784,1101 @@ mod tests {
 
         assert_eq!(confirmed.depth, 0)
     }
+
+    #[test]
+    fn balance_of_addresses_includes_deposit_to_second_address() {
+        let first = Address::p2wsh(&Script::from(vec![1]), bitcoin::Network::Regtest);
+        let second = Address::p2wsh(&Script::from(vec![2]), bitcoin::Network::Regtest);
+        let unrelated = Address::p2wsh(&Script::from(vec![3]), bitcoin::Network::Regtest);
+        let outputs = vec![
+            TxOut {
+                value: 10_000,
+                script_pubkey: second.script_pubkey(),
+            },
+            TxOut {
+                value: 50_000,
+                script_pubkey: unrelated.script_pubkey(),
+            },
+        ];
+
+        let balance = sum_of_outputs_to(&[first, second], outputs.iter());
+
+        assert_eq!(balance, Amount::from_sat(10_000));
+    }
+
+    #[test]
+    fn fee_rate_override_is_used_instead_of_default() {
+        let fee_rate = select_feerate(Some(42.0));
+
+        assert_eq!(fee_rate, FeeRate::from_sat_per_vb(42.0));
+        assert_eq!(
+            select_feerate(None),
+            FeeRate::from_sat_per_vb(DEFAULT_FEE_RATE_SAT_PER_VB)
+        );
+    }
+
+    #[test]
+    fn fee_rate_override_is_clamped() {
+        assert_eq!(
+            select_feerate(Some(0.1)),
+            FeeRate::from_sat_per_vb(MIN_FEE_RATE_SAT_PER_VB)
+        );
+        assert_eq!(
+            select_feerate(Some(10_000.0)),
+            FeeRate::from_sat_per_vb(MAX_FEE_RATE_SAT_PER_VB)
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_is_published_through_bitcoind_if_configured() {
+        let spending = transaction(vec![OutPoint::default()], vec![1_000]);
+        let (url, requests) = bitcoind::tests::serve_rpc(vec![
+            r#"{"result": {"chain": "regtest"}, "error": null, "id": "swap"}"#.to_owned(),
+            format!(
+                r#"{{"result": "{}", "error": null, "id": "swap"}}"#,
+                spending.txid()
+            ),
+        ]);
+        let bitcoind = bitcoind::Client::connect(url, bitcoin::Network::Regtest)
+            .await
+            .unwrap();
+        let _ = requests.recv().unwrap();
+
+        let electrum = std::future::ready(Err(anyhow!("published through electrum")));
+        publish(&spending, Some(&bitcoind), electrum).await.unwrap();
+
+        let request = requests.recv().unwrap();
+        assert_eq!(request["method"], "sendrawtransaction");
+        assert_eq!(
+            request["params"][0],
+            ::bitcoin::consensus::encode::serialize_hex(&spending)
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_is_published_through_electrum_if_bitcoind_fails() {
+        let spending = transaction(vec![OutPoint::default()], vec![1_000]);
+        let (url, requests) = bitcoind::tests::serve_rpc(vec![
+            r#"{"result": {"chain": "regtest"}, "error": null, "id": "swap"}"#,
+            r#"{"result": null, "error": {"code": -26, "message": "min relay fee not met"}, "id": "swap"}"#,
+        ]);
+        let bitcoind = bitcoind::Client::connect(url, bitcoin::Network::Regtest)
+            .await
+            .unwrap();
+        let _ = requests.recv().unwrap();
+        let published = std::sync::atomic::AtomicBool::new(false);
+
+        publish(&spending, Some(&bitcoind), async {
+            published.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(requests.recv().unwrap()["method"], "sendrawtransaction");
+        assert!(published.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn transaction_is_published_through_electrum_without_bitcoind() {
+        let spending = transaction(vec![OutPoint::default()], vec![1_000]);
+        let published = std::sync::atomic::AtomicBool::new(false);
+
+        publish(&spending, None, async {
+            published.store(true, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        })
+        .await
+        .unwrap();
+
+        assert!(published.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dust_threshold_matches_bitcoin_core() {
+        let p2wsh = Address::p2wsh(&Script::new(), bitcoin::Network::Regtest).script_pubkey();
+
+        assert_eq!(
+            dust_threshold(p2wsh.len(), p2wsh.is_witness_program()),
+            Amount::from_sat(330)
+        );
+        assert_eq!(dust_threshold(22, true), Amount::from_sat(294));
+        assert_eq!(dust_threshold(25, false), Amount::from_sat(546));
+    }
+
+    #[test]
+    fn amount_below_dust_threshold_is_rejected() {
+        let p2wsh = Address::p2wsh(&Script::new(), bitcoin::Network::Regtest).script_pubkey();
+
+        assert!(ensure_not_dust(Amount::from_sat(330), p2wsh.len(), true).is_ok());
+
+        let error = ensure_not_dust(Amount::from_sat(329), p2wsh.len(), true).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Amount 0.00000329 BTC is below the dust threshold (330 sats)"
+        );
+    }
+
+    fn funded_offline_wallet(
+        utxos: &[u64],
+    ) -> bdk::Wallet<bdk::blockchain::OfflineBlockchain, bdk::database::MemoryDatabase> {
+        offline_wallet_owning_outputs_of(Txid::from_inner([1u8; 32]), utxos)
+    }
+
+    /// An offline wallet owning the outputs of the transaction with the
+    /// given id, one for each of the given amounts.
+    fn offline_wallet_owning_outputs_of(
+        txid: Txid,
+        utxos: &[u64],
+    ) -> bdk::Wallet<bdk::blockchain::OfflineBlockchain, bdk::database::MemoryDatabase> {
+        use bdk::database::{BatchOperations, MemoryDatabase};
+
+        let key = ::bitcoin::util::bip32::ExtendedPrivKey::new_master(
+            bitcoin::Network::Regtest,
+            &[0u8; 32],
+        )
+        .unwrap();
+        let new_wallet = |database| {
+            bdk::Wallet::new_offline(
+                bdk::template::BIP84(key, KeychainKind::External),
+                Some(bdk::template::BIP84(key, KeychainKind::Internal)),
+                bitcoin::Network::Regtest,
+                database,
+            )
+            .unwrap()
+        };
+
+        let addresses = new_wallet(MemoryDatabase::new());
+        let mut database = MemoryDatabase::new();
+        for (vout, value) in utxos.iter().enumerate() {
+            let utxo = bdk::UTXO {
+                outpoint: OutPoint::new(txid, vout as u32),
+                txout: TxOut {
+                    value: *value,
+                    script_pubkey: addresses.get_new_address().unwrap().script_pubkey(),
+                },
+                keychain: KeychainKind::External,
+            };
+            database.set_utxo(&utxo).unwrap();
+        }
+
+        new_wallet(database)
+    }
+
+    #[test]
+    fn fee_is_computed_from_spent_outputs() {
+        let first_prevout = transaction(vec![], vec![30_000, 70_000]);
+        let second_prevout = transaction(vec![], vec![50_000]);
+        let spending = transaction(
+            vec![
+                OutPoint::new(first_prevout.txid(), 1),
+                OutPoint::new(second_prevout.txid(), 0),
+            ],
+            vec![100_000, 19_000],
+        );
+
+        let prevouts = vec![first_prevout, second_prevout]
+            .into_iter()
+            .map(|tx| (tx.txid(), tx))
+            .collect::<HashMap<_, _>>();
+        let fee = fee_of(&spending, |txid| {
+            prevouts
+                .get(&txid)
+                .cloned()
+                .ok_or_else(|| anyhow!("unknown transaction"))
+        })
+        .unwrap();
+
+        assert_eq!(fee, Amount::from_sat(1_000));
+    }
+
+    #[test]
+    fn fee_cannot_be_computed_without_prevouts() {
+        let spending = transaction(vec![OutPoint::default()], vec![1_000]);
+
+        let fee = fee_of(&spending, |_| Err(anyhow!("unknown transaction")));
+
+        assert!(fee.is_err());
+    }
+
+    #[test]
+    fn fee_of_crafted_transactions_is_an_error() {
+        let huge_prevout = transaction(vec![], vec![u64::MAX, 1]);
+        let overflowing_inputs = transaction(
+            vec![
+                OutPoint::new(huge_prevout.txid(), 0),
+                OutPoint::new(huge_prevout.txid(), 1),
+            ],
+            vec![1_000],
+        );
+        let overspending = transaction(vec![OutPoint::new(huge_prevout.txid(), 1)], vec![u64::MAX]);
+        let get_tx = |_| Ok(huge_prevout.clone());
+
+        assert!(fee_of(&overflowing_inputs, get_tx).is_err());
+        assert!(fee_of(&overspending, get_tx).is_err());
+    }
+
+    #[tokio::test]
+    async fn status_query_proceeds_while_sync_runs() {
+        let sync_duration = Duration::from_secs(2);
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let mut confirmed = transaction(vec![OutPoint::default()], vec![1_000]);
+        confirmed.output[0].script_pubkey = Script::from(vec![0x51]);
+        electrum.add_to_mempool(confirmed.clone());
+        electrum.mine_block();
+        let (sync_started, started) = tokio::sync::oneshot::channel();
+
+        // Holds the lock of the BDK wallet like a slow sync does
+        let start = Instant::now();
+        let sync = tokio::spawn(run_blocking(wallet.wallet.clone(), move |_| {
+            let _ = sync_started.send(());
+            std::thread::sleep(sync_duration);
+            Ok(())
+        }));
+        started.await.unwrap();
+        let status = wallet
+            .status_of_script(&(confirmed.txid(), confirmed.output[0].script_pubkey.clone()))
+            .await
+            .unwrap();
+
+        assert!(status.is_confirmed_with(1));
+        assert!(start.elapsed() < sync_duration);
+        assert!(
+            wallet.wallet.try_lock().is_err(),
+            "sync should still be running"
+        );
+        sync.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn wallet_is_usable_after_cancelling_slow_sync() {
+        let wallet = Arc::new(Mutex::new(Amount::from_sat(50_000)));
+
+        let slow_sync = run_blocking(wallet.clone(), |_| {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(())
+        });
+        let cancelled = tokio::time::timeout(Duration::from_millis(50), slow_sync).await;
+        assert!(cancelled.is_err(), "sync should have been cancelled");
+
+        let balance = tokio::time::timeout(
+            Duration::from_secs(5),
+            run_blocking(wallet, |balance| Ok(*balance)),
+        )
+        .await
+        .expect("wallet should be usable after cancelling sync")
+        .unwrap();
+        assert_eq!(balance, Amount::from_sat(50_000));
+    }
+
+    /// A wallet connected to the given fake electrum server.
+    async fn wallet_connected_to(electrum: &FakeElectrum) -> (Wallet, tempfile::TempDir) {
+        let wallet_dir = tempfile::tempdir().unwrap();
+        let wallet = wallet_in(wallet_dir.path(), electrum).await;
+
+        (wallet, wallet_dir)
+    }
+
+    /// Open the wallet stored in the given directory, e.g. again after the
+    /// process that used it before exited.
+    async fn wallet_in(wallet_dir: &Path, electrum: &FakeElectrum) -> Wallet {
+        wallet_with_servers(wallet_dir, &[electrum.serve()]).await
+    }
+
+    async fn wallet_with_servers(wallet_dir: &Path, electrum_rpc_urls: &[Url]) -> Wallet {
+        use crate::env::GetConfig;
+
+        let key = ::bitcoin::util::bip32::ExtendedPrivKey::new_master(
+            bitcoin::Network::Regtest,
+            &[0u8; 32],
+        )
+        .unwrap();
+
+        Wallet::new(
+            electrum_rpc_urls,
+            wallet_dir,
+            key,
+            env::Regtest::get_config(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn sync_fails_over_to_the_next_server_if_the_first_goes_down() {
+        let first = FakeElectrum::default();
+        let second = first.replica(Duration::from_millis(200));
+        let wallet_dir = tempfile::tempdir().unwrap();
+        let wallet = wallet_with_servers(wallet_dir.path(), &[first.serve(), second.serve()]).await;
+        wallet.sync().await.unwrap();
+
+        first.go_down();
+        let mut funding = transaction(vec![OutPoint::default()], vec![100_000]);
+        funding.output[0].script_pubkey = wallet.new_address().await.unwrap().script_pubkey();
+        second.add_to_mempool(funding);
+        second.mine_block();
+        wallet.sync().await.unwrap();
+
+        assert_eq!(wallet.balance().await.unwrap(), Amount::from_sat(100_000));
+    }
+
+    #[tokio::test]
+    async fn withdrawal_does_not_spend_coins_reserved_by_another_process() {
+        let electrum = FakeElectrum::default();
+        let wallet_dir = tempfile::tempdir().unwrap();
+        let swap_wallet = wallet_in(wallet_dir.path(), &electrum).await;
+        let mut funding = transaction(vec![OutPoint::default()], vec![100_000, 100_000]);
+        for output in funding.output.iter_mut() {
+            output.script_pubkey = swap_wallet.new_address().await.unwrap().script_pubkey();
+        }
+        electrum.add_to_mempool(funding.clone());
+        electrum.mine_block();
+        swap_wallet.sync().await.unwrap();
+        let swap_address = swap_wallet.new_address().await.unwrap();
+        let in_flight_swap = swap_wallet
+            .send_to_address(swap_address, Amount::from_sat(50_000))
+            .await
+            .unwrap();
+        drop(swap_wallet);
+
+        let withdraw_wallet = wallet_in(wallet_dir.path(), &electrum).await;
+        withdraw_wallet.sync().await.unwrap();
+        let address = withdraw_wallet.new_address().await.unwrap();
+        let amount = withdraw_wallet.max_giveable_to(&address).await.unwrap();
+        let (txid, _) = withdraw_wallet
+            .withdraw(address, amount, &[])
+            .await
+            .unwrap();
+
+        let reserved = in_flight_swap
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<HashSet<_>>();
+        let withdrawals = electrum.spending(funding.txid());
+        assert_eq!(withdrawals.len(), 1);
+        assert_eq!(withdrawals[0].txid(), txid);
+        assert!(withdrawals[0]
+            .input
+            .iter()
+            .all(|input| !reserved.contains(&input.previous_output)));
+        assert!(amount < Amount::from_sat(100_000));
+    }
+
+    #[tokio::test]
+    async fn withdrawal_only_spends_the_given_coins() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let mut funding = transaction(vec![OutPoint::default()], vec![100_000, 100_000]);
+        for output in funding.output.iter_mut() {
+            output.script_pubkey = wallet.new_address().await.unwrap().script_pubkey();
+        }
+        electrum.add_to_mempool(funding.clone());
+        electrum.mine_block();
+        wallet.sync().await.unwrap();
+
+        let coin = OutPoint::new(funding.txid(), 1);
+        let address = wallet.new_address().await.unwrap();
+        let (txid, _) = wallet
+            .withdraw(address, Amount::from_sat(50_000), &[coin])
+            .await
+            .unwrap();
+
+        let withdrawals = electrum.spending(funding.txid());
+        assert_eq!(withdrawals.len(), 1);
+        assert_eq!(withdrawals[0].txid(), txid);
+        assert_eq!(
+            withdrawals[0]
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect::<Vec<_>>(),
+            vec![coin]
+        );
+    }
+
+    #[tokio::test]
+    async fn consolidation_waits_for_the_estimated_fee_rate_to_drop() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let mut funding = transaction(vec![OutPoint::default()], vec![10_000, 10_000, 10_000]);
+        for output in funding.output.iter_mut() {
+            output.script_pubkey = wallet.new_address().await.unwrap().script_pubkey();
+        }
+        electrum.add_to_mempool(funding.clone());
+        electrum.mine_block();
+        wallet.sync().await.unwrap();
+
+        electrum.set_fee_estimate(0.0002);
+        let expensive = wallet.consolidate(3, 10.0).await.unwrap();
+        electrum.set_fee_estimate(0.00002);
+        let cheap = wallet.consolidate(3, 10.0).await.unwrap();
+
+        assert!(expensive.is_none());
+        let consolidations = electrum.spending(funding.txid());
+        assert_eq!(consolidations.len(), 1);
+        assert_eq!(Some(consolidations[0].txid()), cheap);
+        let fee = 30_000 - consolidations[0].output[0].value;
+        let fee_rate = fee as f32 / vbytes(&consolidations[0]) as f32;
+        assert!((2.0..3.0).contains(&fee_rate), "paid {} sat/vB", fee_rate);
+    }
+
+    #[tokio::test]
+    async fn fetched_status_includes_transactions_of_scripts_never_watched() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let mut confirmed = transaction(vec![OutPoint::default()], vec![1_000]);
+        confirmed.output[0].script_pubkey = Script::from(vec![0x51]);
+        electrum.add_to_mempool(confirmed.clone());
+        electrum.mine_block();
+
+        let status = wallet
+            .fetch_status_of_script(&(confirmed.txid(), confirmed.output[0].script_pubkey.clone()))
+            .await
+            .unwrap();
+
+        assert!(status.is_confirmed_with(1));
+        assert_eq!(electrum.history_requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn stuck_transaction_is_bumped_again_by_replacing_its_child() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let wallet = wallet.with_fee_bumping(Some(FeeBumping {
+            after_blocks: 1,
+            max_fee_rate: 100.0,
+        }));
+        let mut funding = transaction(vec![OutPoint::default()], vec![100_200]);
+        funding.output[0].script_pubkey = Script::from(vec![0x51]);
+        let mut parent = transaction(vec![OutPoint::new(funding.txid(), 0)], vec![100_000]);
+        parent.output[0].script_pubkey = wallet.new_address().await.unwrap().script_pubkey();
+        electrum.add_to_mempool(funding);
+        electrum.mine_block();
+        electrum.add_to_mempool(parent.clone());
+
+        let finality = wallet.wait_for_transaction_finality(
+            (parent.txid(), parent.output[0].script_pubkey.clone()),
+            Amount::from_sat(100_000),
+            "lock".to_owned(),
+            Some(parent.clone()),
+        );
+        let stuck = async {
+            while electrum.broadcasts() < 2 {
+                tokio::time::sleep(STATUS_POLL_INTERVAL).await;
+                electrum.mine_empty_block();
+            }
+            electrum.mine_block();
+        };
+        let (result, ()) = tokio::time::timeout(
+            Duration::from_secs(120),
+            futures::future::join(finality, stuck),
+        )
+        .await
+        .unwrap();
+        result.unwrap();
+
+        let children = electrum.spending(parent.txid());
+        assert_eq!(children.len(), 1, "the first child was replaced");
+        assert!(parent.output[0].value - children[0].output[0].value >= 2 * CPFP_CHILD_VBYTES);
+    }
+
+    #[tokio::test]
+    async fn stuck_cancel_transaction_is_bumped_through_the_refund_spending_it() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let wallet = wallet.with_fee_bumping(Some(FeeBumping {
+            after_blocks: 1,
+            max_fee_rate: 100.0,
+        }));
+        let mut funding = transaction(vec![OutPoint::default()], vec![100_400]);
+        funding.output[0].script_pubkey = Script::from(vec![0x51]);
+        let mut tx_cancel = transaction(vec![OutPoint::new(funding.txid(), 0)], vec![100_200]);
+        tx_cancel.output[0].script_pubkey = Script::from(vec![0x52]);
+        let mut tx_refund = transaction(vec![OutPoint::new(tx_cancel.txid(), 0)], vec![100_000]);
+        tx_refund.output[0].script_pubkey = wallet.new_address().await.unwrap().script_pubkey();
+        electrum.add_to_mempool(funding);
+        electrum.mine_block();
+        electrum.add_to_mempool(tx_cancel.clone());
+        electrum.add_to_mempool(tx_refund.clone());
+
+        let finality = wallet.wait_for_transaction_finality(
+            (tx_refund.txid(), tx_refund.output[0].script_pubkey.clone()),
+            Amount::from_sat(100_000),
+            "refund".to_owned(),
+            Some(tx_refund.clone()),
+        );
+        let stuck = async {
+            while electrum.broadcasts() < 1 {
+                tokio::time::sleep(STATUS_POLL_INTERVAL).await;
+                electrum.mine_empty_block();
+            }
+            electrum.mine_block();
+        };
+        let (result, ()) = tokio::time::timeout(
+            Duration::from_secs(120),
+            futures::future::join(finality, stuck),
+        )
+        .await
+        .unwrap();
+        result.unwrap();
+
+        let children = electrum.spending(tx_refund.txid());
+        assert_eq!(children.len(), 1);
+        let child_fee = tx_refund.output[0].value - children[0].output[0].value;
+        let base_fee_rate = 200.0 / vbytes(&tx_refund) as f32;
+        let package_vbytes = vbytes(&tx_cancel) + vbytes(&tx_refund) + CPFP_CHILD_VBYTES;
+        assert!(child_fee + 400 >= (2.0 * base_fee_rate * package_vbytes as f32) as u64);
+    }
+
+    #[tokio::test]
+    async fn stuck_transaction_not_paying_to_the_wallet_is_not_bumped() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let wallet = wallet.with_fee_bumping(Some(FeeBumping {
+            after_blocks: 1,
+            max_fee_rate: 100.0,
+        }));
+        let mut tx_cancel = transaction(vec![OutPoint::default()], vec![100_000]);
+        tx_cancel.output[0].script_pubkey = Script::from(vec![0x52]);
+        electrum.add_to_mempool(tx_cancel.clone());
+
+        let finality = wallet.wait_for_transaction_finality(
+            (tx_cancel.txid(), tx_cancel.output[0].script_pubkey.clone()),
+            Amount::from_sat(100_000),
+            "cancel".to_owned(),
+            Some(tx_cancel.clone()),
+        );
+        let stuck = async {
+            for _ in 0..3 {
+                tokio::time::sleep(STATUS_POLL_INTERVAL).await;
+                electrum.mine_empty_block();
+            }
+            electrum.mine_block();
+        };
+        let (result, ()) = tokio::time::timeout(
+            Duration::from_secs(120),
+            futures::future::join(finality, stuck),
+        )
+        .await
+        .unwrap();
+        result.unwrap();
+
+        assert_eq!(electrum.broadcasts(), 0);
+    }
+
+    #[tokio::test]
+    async fn transaction_known_to_electrum_is_not_broadcast_again() {
+        let electrum = FakeElectrum::default();
+        let (wallet, _wallet_dir) = wallet_connected_to(&electrum).await;
+        let mut published = transaction(vec![OutPoint::default()], vec![1_000]);
+        published.output[0].script_pubkey = Script::from(vec![0x51]);
+        let mut unpublished = transaction(vec![OutPoint::default()], vec![2_000]);
+        unpublished.output[0].script_pubkey = Script::from(vec![0x52]);
+        electrum.add_to_mempool(published.clone());
+
+        wallet.ensure_broadcast(published, "refund").await.unwrap();
+        assert_eq!(electrum.broadcasts(), 0);
+
+        wallet
+            .ensure_broadcast(unpublished, "refund")
+            .await
+            .unwrap();
+        assert_eq!(electrum.broadcasts(), 1);
+    }
+
+    #[test]
+    fn large_amounts_require_more_confirmations() {
+        let policy = FinalityPolicy {
+            default: 1,
+            tiers: vec![
+                FinalityTier {
+                    min_amount: Amount::from_btc(1.0).unwrap(),
+                    confirmations: 6,
+                },
+                FinalityTier {
+                    min_amount: Amount::from_btc(0.1).unwrap(),
+                    confirmations: 3,
+                },
+            ],
+        };
+
+        assert_eq!(policy.confirmations_for(Amount::from_sat(100_000)), 1);
+        assert_eq!(policy.confirmations_for(Amount::from_btc(0.1).unwrap()), 3);
+        assert_eq!(policy.confirmations_for(Amount::from_btc(5.0).unwrap()), 6);
+        assert_eq!(
+            FinalityPolicy::flat(2).confirmations_for(Amount::from_btc(5.0).unwrap()),
+            2
+        );
+    }
+
+    #[test]
+    fn poll_delays_of_many_watchers_spread_within_jitter() {
+        let jitter = Duration::from_secs(2);
+        let mut rng = rand::thread_rng();
+
+        let delays = (0..100)
+            .map(|_| poll_delay(jitter, &mut rng))
+            .collect::<Vec<_>>();
+
+        assert!(delays
+            .iter()
+            .all(|delay| *delay >= STATUS_POLL_INTERVAL && *delay < STATUS_POLL_INTERVAL + jitter));
+        let distinct = delays.iter().collect::<HashSet<_>>();
+        assert!(distinct.len() > 90, "watchers poll in lockstep");
+        let earliest = delays.iter().min().unwrap();
+        let latest = delays.iter().max().unwrap();
+        assert!(
+            *latest - *earliest > jitter / 2,
+            "polls are spread across the jitter"
+        );
+    }
+
+    #[test]
+    fn zero_jitter_polls_at_fixed_interval() {
+        let delay = poll_delay(Duration::from_secs(0), &mut rand::thread_rng());
+
+        assert_eq!(delay, STATUS_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn revealed_address_is_ours() {
+        let wallet = funded_offline_wallet(&[]);
+        let address = wallet.get_new_address().unwrap();
+
+        assert!(is_our_address(&wallet, &address).unwrap());
+    }
+
+    #[test]
+    fn address_within_gap_limit_is_ours_before_it_is_revealed() {
+        let wallet = funded_offline_wallet(&[]);
+        let other_instance = funded_offline_wallet(&[]);
+        let addresses = (0..5)
+            .map(|_| other_instance.get_new_address().unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(is_our_address(&wallet, addresses.last().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn unrelated_address_is_not_ours() {
+        let wallet = funded_offline_wallet(&[]);
+        let key = crate::bitcoin::SecretKey::new_random(&mut rand::thread_rng()).public();
+        let address = Address::p2wpkh(&key.into(), bitcoin::Network::Regtest).unwrap();
+
+        assert!(!is_our_address(&wallet, &address).unwrap());
+    }
+
+    #[test]
+    fn concurrent_swaps_select_disjoint_utxos() {
+        let wallet = funded_offline_wallet(&[100_000, 100_000]);
+        let mut reserved_utxos = UtxoReservations::default();
+        let lock_script = Script::from(vec![0u8; 34]);
+
+        let first_swap = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            lock_script.clone(),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+        let second_swap = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            lock_script.clone(),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+
+        let inputs_of = |psbt: &PartiallySignedTransaction| {
+            psbt.global
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect::<HashSet<_>>()
+        };
+        assert!(inputs_of(&first_swap).is_disjoint(&inputs_of(&second_swap)));
+
+        let third_swap = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            lock_script,
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        );
+        assert!(third_swap.is_err(), "all UTXOs are reserved");
+    }
+
+    #[test]
+    fn foreign_output_is_watched_with_its_own_amount() {
+        let mut transaction = transaction(vec![OutPoint::default()], vec![40_000, 1_000_000]);
+        let change = Script::from(vec![0x51]);
+        let lock = Script::from(vec![0x52]);
+        transaction.output[0].script_pubkey = change.clone();
+        transaction.output[1].script_pubkey = lock.clone();
+
+        assert_eq!(
+            watched_output(&transaction, |script| *script == change),
+            (lock, Amount::from_sat(1_000_000))
+        );
+        assert_eq!(
+            watched_output(&transaction, |_| true),
+            (change, Amount::from_sat(40_000))
+        );
+    }
+
+    #[test]
+    fn withdrawal_does_not_spend_coins_reserved_for_swaps() {
+        let wallet = funded_offline_wallet(&[100_000, 100_000, 100_000]);
+        let mut reserved_utxos = UtxoReservations::default();
+        let in_flight_swap = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(150_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+        let reserved = in_flight_swap
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<HashSet<_>>();
+        let external = Script::from(vec![1u8; 22]);
+
+        let withdrawal = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            external.clone(),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+
+        assert!(withdrawal
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .all(|input| !reserved.contains(&input.previous_output)));
+        assert!(withdrawal
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == external && output.value == 50_000));
+    }
+
+    #[test]
+    fn max_giveable_to_legacy_address_applies_its_dust_threshold() {
+        let wallet = funded_offline_wallet(&[600]);
+        let reserved_utxos = UtxoReservations::default();
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+
+        // 600 sat minus the fee are above the dust threshold of a P2WPKH
+        // output (294 sat) but below the one of a P2PKH output (546 sat).
+        let to_segwit = max_giveable(&wallet, &reserved_utxos, 22, true, fee_rate);
+        let to_legacy = max_giveable(&wallet, &reserved_utxos, 25, false, fee_rate);
+
+        assert!(to_segwit.is_ok());
+        assert!(to_legacy.is_err());
+    }
+
+    #[test]
+    fn max_giveable_excludes_reserved_coins() {
+        let wallet = funded_offline_wallet(&[100_000, 100_000]);
+        let mut reserved_utxos = UtxoReservations::default();
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+
+        let everything = max_giveable(&wallet, &reserved_utxos, 22, true, fee_rate).unwrap();
+        build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(50_000),
+            fee_rate,
+        )
+        .unwrap();
+        let remaining = max_giveable(&wallet, &reserved_utxos, 22, true, fee_rate).unwrap();
+
+        assert!(everything > Amount::from_sat(190_000));
+        assert!(remaining < Amount::from_sat(100_000));
+    }
+
+    /// A PSBT spending the only coin of the wallet, with the spent output
+    /// attached because the wallet does not know the funding transaction.
+    fn psbt_spending_own_coin(
+        wallet: &bdk::Wallet<bdk::blockchain::OfflineBlockchain, bdk::database::MemoryDatabase>,
+    ) -> PartiallySignedTransaction {
+        let mut psbt = build_tx_with_reserved_utxos(
+            wallet,
+            &mut UtxoReservations::default(),
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(wallet.list_unspent().unwrap().remove(0).txout);
+
+        psbt
+    }
+
+    #[test]
+    fn psbt_of_own_coins_can_be_signed_and_finalized() {
+        let wallet = funded_offline_wallet(&[100_000]);
+        let psbt = psbt_spending_own_coin(&wallet);
+
+        let signed_psbt = sign_psbt(&wallet, psbt).unwrap();
+        let transaction = finalize_psbt(&wallet, signed_psbt).unwrap();
+
+        assert!(!transaction.input[0].witness.is_empty());
+    }
+
+    #[test]
+    fn built_transaction_pays_the_fee_rate_override() {
+        let wallet = funded_offline_wallet(&[100_000]);
+        let mut psbt = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut UtxoReservations::default(),
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(50_000),
+            select_feerate(Some(42.0)),
+        )
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(wallet.list_unspent().unwrap().remove(0).txout);
+
+        let signed_psbt = sign_psbt(&wallet, psbt).unwrap();
+        let transaction = finalize_psbt(&wallet, signed_psbt).unwrap();
+
+        let outputs = transaction
+            .output
+            .iter()
+            .map(|output| output.value)
+            .sum::<u64>();
+        let fee_rate = (100_000 - outputs) as f32 / vbytes(&transaction) as f32;
+        assert!(
+            (fee_rate - 42.0).abs() < 1.0,
+            "transaction pays {} sat/vB",
+            fee_rate
+        );
+    }
+
+    #[test]
+    fn psbt_with_foreign_input_needs_external_signature() {
+        let wallet = funded_offline_wallet(&[100_000]);
+        let mut psbt = psbt_spending_own_coin(&wallet);
+        psbt.global.unsigned_tx.input.push(::bitcoin::TxIn {
+            previous_output: OutPoint::new(Txid::from_inner([2u8; 32]), 0),
+            script_sig: Script::new(),
+            sequence: 0xFFFF_FFFF,
+            witness: vec![],
+        });
+        psbt.inputs.push(Default::default());
+
+        let signed_psbt = sign_psbt(&wallet, psbt).unwrap();
+
+        let own_input = &signed_psbt.inputs[0];
+        assert!(!own_input.partial_sigs.is_empty() || own_input.final_script_witness.is_some());
+        assert!(signed_psbt.inputs[1].partial_sigs.is_empty());
+        assert!(finalize_psbt(&wallet, signed_psbt).is_err());
+    }
+
+    #[test]
+    fn consolidation_sweeps_fragmented_utxos_except_reserved_ones() {
+        let wallet = funded_offline_wallet(&[20_000; 5]);
+        let mut reserved_utxos = UtxoReservations::default();
+        let in_flight_swap = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(10_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+        let reserved = in_flight_swap
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<HashSet<_>>();
+        let consolidation_script = wallet.get_new_address().unwrap().script_pubkey();
+
+        let consolidation = build_consolidation_tx(
+            &wallet,
+            &mut reserved_utxos,
+            3,
+            consolidation_script,
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap()
+        .expect("enough fragmented UTXOs to consolidate");
+        let consolidation = consolidation.global.unsigned_tx;
+
+        assert_eq!(consolidation.input.len(), 5 - reserved.len());
+        assert_eq!(consolidation.output.len(), 1);
+        assert!(consolidation
+            .input
+            .iter()
+            .all(|input| !reserved.contains(&input.previous_output)));
+    }
+
+    #[test]
+    fn child_pays_for_parent_up_to_package_fee_rate() {
+        let parent = transaction(vec![OutPoint::new(Txid::from_inner([2u8; 32]), 0)], vec![
+            50_000,
+        ]);
+        let parent_vbytes = (parent.get_weight() as u64 + 3) / 4;
+        let parent_fee = Amount::from_sat(200);
+        let wallet = offline_wallet_owning_outputs_of(parent.txid(), &[50_000]);
+        let script = wallet.get_new_address().unwrap().script_pubkey();
+
+        let psbt = build_cpfp_tx(
+            &wallet,
+            &mut UtxoReservations::default(),
+            &parent,
+            parent_fee,
+            &[],
+            script,
+            10.0,
+        )
+        .unwrap();
+        let child = psbt.global.unsigned_tx;
+
+        assert_eq!(child.input.len(), 1);
+        assert_eq!(child.input[0].previous_output.txid, parent.txid());
+        let child_fee = 50_000 - child.output[0].value;
+        assert_eq!(
+            child_fee + parent_fee.as_sat(),
+            10 * (parent_vbytes + CPFP_CHILD_VBYTES)
+        );
+    }
+
+    #[test]
+    fn stuck_cancel_transaction_is_bumped_through_refund() {
+        let tx_cancel = transaction(vec![OutPoint::new(Txid::from_inner([2u8; 32]), 0)], vec![
+            50_200,
+        ]);
+        let tx_refund = transaction(vec![OutPoint::new(tx_cancel.txid(), 0)], vec![50_000]);
+        let cancel_vbytes = (tx_cancel.get_weight() as u64 + 3) / 4;
+        let refund_vbytes = (tx_refund.get_weight() as u64 + 3) / 4;
+        let fee = Amount::from_sat(200);
+        let wallet = offline_wallet_owning_outputs_of(tx_refund.txid(), &[50_000]);
+        let script = wallet.get_new_address().unwrap().script_pubkey();
+
+        let psbt = build_cpfp_tx(
+            &wallet,
+            &mut UtxoReservations::default(),
+            &tx_refund,
+            fee,
+            &[(tx_cancel, fee)],
+            script,
+            10.0,
+        )
+        .unwrap();
+        let child = psbt.global.unsigned_tx;
+
+        assert_eq!(child.input[0].previous_output.txid, tx_refund.txid());
+        let child_fee = 50_000 - child.output[0].value;
+        assert_eq!(
+            child_fee + 2 * fee.as_sat(),
+            10 * (cancel_vbytes + refund_vbytes + CPFP_CHILD_VBYTES)
+        );
+    }
+
+    #[test]
+    fn consolidation_is_skipped_below_threshold() {
+        let wallet = funded_offline_wallet(&[20_000; 2]);
+        let script = wallet.get_new_address().unwrap().script_pubkey();
+
+        let consolidation = build_consolidation_tx(
+            &wallet,
+            &mut UtxoReservations::default(),
+            3,
+            script,
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+
+        assert!(consolidation.is_none());
+    }
+
+    #[test]
+    fn coin_control_only_spends_selected_coins() {
+        let wallet = funded_offline_wallet(&[30_000, 30_000, 30_000]);
+        let selected = vec![
+            OutPoint::new(Txid::from_inner([1u8; 32]), 0),
+            OutPoint::new(Txid::from_inner([1u8; 32]), 2),
+        ];
+
+        let psbt = build_tx_from_coins(
+            &wallet,
+            &mut UtxoReservations::default(),
+            &selected,
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+
+        let spent = psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect::<HashSet<_>>();
+        assert_eq!(spent, selected.into_iter().collect());
+    }
+
+    #[test]
+    fn coin_control_fails_if_selected_coins_are_insufficient() {
+        let wallet = funded_offline_wallet(&[30_000, 30_000]);
+        let selected = vec![OutPoint::new(Txid::from_inner([1u8; 32]), 0)];
+
+        let result = build_tx_from_coins(
+            &wallet,
+            &mut UtxoReservations::default(),
+            &selected,
+            Script::from(vec![0u8; 34]),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn released_utxos_can_be_selected_again() {
+        let wallet = funded_offline_wallet(&[100_000]);
+        let mut reserved_utxos = UtxoReservations::default();
+        let lock_script = Script::from(vec![0u8; 34]);
+
+        let psbt = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            lock_script.clone(),
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        )
+        .unwrap();
+        reserved_utxos.release(&psbt.global.unsigned_tx);
+
+        let retried = build_tx_with_reserved_utxos(
+            &wallet,
+            &mut reserved_utxos,
+            lock_script,
+            Amount::from_sat(50_000),
+            FeeRate::from_sat_per_vb(1.0),
+        );
+        assert!(retried.is_ok());
+    }
 }
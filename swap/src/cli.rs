@@ -1 +1,5 @@
 pub mod command;
+pub mod inspect;
+pub mod recover;
+pub mod status;
+pub mod swap_log;
@@ -1,9 +1,15 @@
+use crate::bitcoin;
+use crate::explorer::ExplorerUrl;
 use crate::fs::default_data_dir;
+use crate::trace::Format;
 use anyhow::{Context, Result};
 use libp2p::core::Multiaddr;
 use libp2p::PeerId;
+use monero_rpc::wallet::TransferPriority;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::str::FromStr;
+use time::OffsetDateTime;
 use url::Url;
 use uuid::Uuid;
 
@@ -29,6 +35,52 @@ pub struct Arguments {
     #[structopt(long, help = "Activate debug logging.")]
     pub debug: bool,
 
+    #[structopt(
+        long = "log-format",
+        help = "The format of the log output, either human or json.",
+        default_value = "human"
+    )]
+    pub log_format: Format,
+
+    #[structopt(
+        long = "log-file",
+        help = "Write the log output to the given file instead of stderr.",
+        parse(from_os_str)
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Show a desktop notification when a swap completes or needs attention."
+    )]
+    pub notify: bool,
+
+    #[structopt(
+        long = "fee-rate",
+        help = "Use the given fee rate in sat/vB for all Bitcoin transactions instead of estimating one. Only use this if you know what you are doing."
+    )]
+    pub fee_rate: Option<f32>,
+
+    #[structopt(
+        long = "bump-fee-after",
+        help = "Bump the fee of swap transactions that are not confirmed after the given number of blocks, doubling it every time.",
+        value_name = "BLOCKS"
+    )]
+    pub bump_fee_after: Option<u32>,
+
+    #[structopt(
+        long = "max-bump-fee-rate",
+        help = "The highest fee rate in sat/vB to bump the fee of a swap transaction to.",
+        default_value = "100"
+    )]
+    pub max_bump_fee_rate: f32,
+
+    #[structopt(
+        long = "bitcoin-explorer-url",
+        help = "Log links to published Bitcoin transactions on this explorer, {txid} is replaced by the transaction id. Defaults to a public explorer of the network."
+    )]
+    pub bitcoin_explorer_url: Option<ExplorerUrl>,
+
     #[structopt(subcommand)]
     pub cmd: Command,
 }
@@ -48,9 +100,41 @@ pub enum Command {
 
         #[structopt(flatten)]
         monero_params: MoneroParams,
+
+        #[structopt(
+            long = "deposit-addresses",
+            help = "The number of Bitcoin addresses to show and watch for deposits, at least one",
+            default_value = "1"
+        )]
+        deposit_addresses: NonZeroUsize,
+
+        #[structopt(
+            long = "require-liquidity-proof",
+            help = "Refuse to swap with a seller that does not advertise its available XMR in a signed statement"
+        )]
+        require_liquidity_proof: bool,
+
+        #[structopt(
+            long = "lock-deadline",
+            help = "Abort the swap instead of locking the Bitcoin after this time, e.g. 2021-05-01T18:00:00Z",
+            parse(try_from_str = parse_deadline)
+        )]
+        lock_deadline: Option<OffsetDateTime>,
+
+        #[structopt(
+            long = "refund-address",
+            help = "Refund the Bitcoin to this address if the swap is cancelled, by default a new address of the internal wallet is used for every swap"
+        )]
+        refund_address: Option<bitcoin::Address>,
     },
     /// Show a list of past ongoing and completed swaps
-    History,
+    History {
+        #[structopt(
+            long = "electrum-rpc",
+            help = "Provide the Bitcoin Electrum RPC URL to show which swaps need attention"
+        )]
+        electrum_rpc_url: Option<Url>,
+    },
     /// Resume a swap
     Resume {
         #[structopt(
@@ -60,7 +144,7 @@ pub enum Command {
         swap_id: Uuid,
 
         #[structopt(flatten)]
-        connect_params: AliceConnectParams,
+        connect_params: ResumeConnectParams,
 
         #[structopt(long = "electrum-rpc",
         help = "Provide the Bitcoin Electrum RPC URL",
@@ -70,6 +154,19 @@ pub enum Command {
 
         #[structopt(flatten)]
         monero_params: MoneroParams,
+
+        #[structopt(
+            long = "lock-deadline",
+            help = "Abort the swap instead of locking the Bitcoin after this time, e.g. 2021-05-01T18:00:00Z",
+            parse(try_from_str = parse_deadline)
+        )]
+        lock_deadline: Option<OffsetDateTime>,
+
+        #[structopt(
+            long = "refund-address",
+            help = "Refund the Bitcoin to this address if the swap is cancelled, only possible if the swap has not been set up with the other party yet"
+        )]
+        refund_address: Option<bitcoin::Address>,
     },
     /// Try to cancel an ongoing swap (expert users only)
     Cancel {
@@ -88,6 +185,96 @@ pub enum Command {
         )]
         electrum_rpc_url: Url,
     },
+    /// Abandon a swap in which the Monero has not been locked yet and refund
+    /// the Bitcoin as soon as the timelocks allow
+    Abandon {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+
+        #[structopt(long = "electrum-rpc",
+        help = "Provide the Bitcoin Electrum RPC URL",
+        default_value = DEFAULT_ELECTRUM_RPC_URL
+        )]
+        electrum_rpc_url: Url,
+    },
+    /// Show the current progress of a swap
+    Status {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+
+        #[structopt(long = "electrum-rpc",
+        help = "Provide the Bitcoin Electrum RPC URL",
+        default_value = DEFAULT_ELECTRUM_RPC_URL
+        )]
+        electrum_rpc_url: Url,
+    },
+    /// Print the chronological log of the state transitions of a swap
+    PrintSwapLog {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+    },
+    /// Show the Bitcoin transactions of a swap
+    Inspect {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+
+        #[structopt(long = "electrum-rpc",
+        help = "Provide the Bitcoin Electrum RPC URL",
+        default_value = DEFAULT_ELECTRUM_RPC_URL
+        )]
+        electrum_rpc_url: Url,
+    },
+    /// Take the next step to recover a swap that is stuck, e.g. publish the
+    /// cancel or refund transaction (expert users only)
+    Recover {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+
+        #[structopt(long = "electrum-rpc",
+        help = "Provide the Bitcoin Electrum RPC URL",
+        default_value = DEFAULT_ELECTRUM_RPC_URL
+        )]
+        electrum_rpc_url: Url,
+
+        #[structopt(
+            long = "dry-run",
+            help = "Only show which transactions would be published, without publishing them"
+        )]
+        dry_run: bool,
+    },
+    /// Generate the Monero wallet of a swap whose BTC was redeemed again and
+    /// sweep the XMR, e.g. after the wallet file was lost (expert users only)
+    RecoverXmr {
+        #[structopt(
+            long = "swap-id",
+            help = "The swap id can be retrieved using the history subcommand"
+        )]
+        swap_id: Uuid,
+
+        #[structopt(flatten)]
+        monero_params: MoneroParams,
+
+        #[structopt(
+            long = "from-height",
+            help = "Scan the Monero blockchain from this height instead of the restore height stored with the swap, must not be later than the stored one"
+        )]
+        from_height: Option<u32>,
+    },
     /// Try to cancel a swap and refund my BTC (expert users only)
     Refund {
         #[structopt(
@@ -122,6 +309,42 @@ pub struct AliceConnectParams {
         help = "The multiaddr of a specific swap partner can be optionally provided"
     )]
     pub multiaddr: Multiaddr,
+
+    #[structopt(
+        long = "dial-timeout",
+        help = "Give up connecting to the swap partner after the given number of seconds",
+        default_value = "60",
+        value_name = "SECONDS"
+    )]
+    pub dial_timeout_secs: u64,
+}
+
+/// Whom to connect to when resuming a swap.
+///
+/// Unlike [`AliceConnectParams`], there are no defaults so that the
+/// counterparty stored with the swap is only overridden if the user asks for
+/// it.
+#[derive(structopt::StructOpt, Debug)]
+pub struct ResumeConnectParams {
+    #[structopt(
+        long = "seller-peer-id",
+        help = "The peer id of the swap partner, defaults to the one the swap was started with"
+    )]
+    pub peer_id: Option<PeerId>,
+
+    #[structopt(
+        long = "seller-addr",
+        help = "The multiaddr of the swap partner, tried if the one the swap was started with is unreachable"
+    )]
+    pub multiaddr: Option<Multiaddr>,
+
+    #[structopt(
+        long = "dial-timeout",
+        help = "Give up connecting to the swap partner after the given number of seconds",
+        default_value = "60",
+        value_name = "SECONDS"
+    )]
+    pub dial_timeout_secs: u64,
 }
 
 #[derive(structopt::StructOpt, Debug)]
@@ -138,6 +361,13 @@ pub struct MoneroParams {
         default_value = DEFAULT_STAGENET_MONERO_DAEMON_HOST
     )]
     pub monero_daemon_host: String,
+
+    #[structopt(
+        long = "monero-priority",
+        help = "The priority of the Monero transactions, one of default, unimportant, normal or elevated. Higher priorities pay higher fees to be confirmed faster.",
+        default_value = "default"
+    )]
+    pub monero_priority: TransferPriority,
 }
 
 #[derive(Clone, Debug)]
@@ -176,11 +406,24 @@ fn parse_monero_address(s: &str) -> Result<monero::Address> {
     })
 }
 
+fn parse_deadline(s: &str) -> Result<OffsetDateTime> {
+    OffsetDateTime::parse(s, time::Format::Rfc3339).with_context(|| {
+        format!(
+            "Failed to parse {} as a point in time, expected a format like 2021-05-01T18:00:00Z",
+            s
+        )
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::cli::command::{DEFAULT_ALICE_MULTIADDR, DEFAULT_ALICE_PEER_ID};
+    use crate::cli::command::{
+        parse_deadline, parse_monero_address, Arguments, DEFAULT_ALICE_MULTIADDR,
+        DEFAULT_ALICE_PEER_ID,
+    };
     use libp2p::core::Multiaddr;
     use libp2p::PeerId;
+    use structopt::StructOpt;
 
     #[test]
     fn parse_default_alice_peer_id_success() {
@@ -195,4 +438,56 @@ mod tests {
             .parse::<Multiaddr>()
             .expect("default alice multiaddr str is a valid Multiaddr>");
     }
+
+    #[test]
+    fn parse_lock_deadline() {
+        let deadline = parse_deadline("2021-05-01T18:00:00Z").unwrap();
+
+        assert_eq!(deadline.unix_timestamp(), 1_619_892_000);
+        assert!(parse_deadline("tomorrow").is_err());
+    }
+
+    #[test]
+    fn zero_deposit_addresses_are_rejected() {
+        let buy_xmr = |deposit_addresses: &str| {
+            Arguments::from_iter_safe(&[
+                "swap",
+                "buy-xmr",
+                "--receive-address",
+                "55LTR8KniP4LQGJSPtbYDacR7dz8RBFnsfAKMaMuwUNYX6aQbBcovzDPyrQF9KXF9tVU6Xk3K8no1BywnJX6GvZX8yJsXvt",
+                "--deposit-addresses",
+                deposit_addresses,
+            ])
+        };
+
+        assert!(buy_xmr("0").is_err());
+        assert!(buy_xmr("2").is_ok());
+    }
+
+    #[test]
+    fn parses_log_format_and_file() {
+        let args = Arguments::from_iter_safe(&[
+            "swap",
+            "--log-format",
+            "json",
+            "--log-file",
+            "/tmp/swap.log",
+            "history",
+        ])
+        .unwrap();
+
+        assert_eq!(args.log_format, Format::Json);
+        assert_eq!(args.log_file, Some(PathBuf::from("/tmp/swap.log")));
+        assert!(Arguments::from_iter_safe(&["swap", "--log-format", "xml", "history"]).is_err());
+    }
+
+    #[test]
+    fn malformed_monero_address_is_rejected() {
+        let error = parse_monero_address("not-a-monero-address").unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Failed to parse not-a-monero-address as a monero address, please make sure it is a valid address"
+        );
+    }
 }
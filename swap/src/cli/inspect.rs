@@ -0,0 +1,208 @@
+use crate::bitcoin;
+use crate::bitcoin::wallet::ScriptStatus;
+use crate::bitcoin::{Address, Amount, Network, Transaction, TransactionKind, Txid};
+use crate::database::SwapTransactions;
+use ::bitcoin::{OutPoint, Script};
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+/// A human-readable summary of a Bitcoin transaction of a swap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionSummary {
+    pub kind: TransactionKind,
+    pub txid: Txid,
+    pub details: Option<TransactionDetails>,
+}
+
+/// The decoded content of a published transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionDetails {
+    pub inputs: Vec<OutPoint>,
+    pub outputs: Vec<OutputSummary>,
+    pub status: ScriptStatus,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSummary {
+    pub amount: Amount,
+    pub script: Script,
+    pub address: Option<Address>,
+}
+
+impl TransactionDetails {
+    pub fn new(transaction: &Transaction, network: Network, status: ScriptStatus) -> Self {
+        let inputs = transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect();
+        let outputs = transaction
+            .output
+            .iter()
+            .map(|output| OutputSummary {
+                amount: Amount::from_sat(output.value),
+                script: output.script_pubkey.clone(),
+                address: Address::from_script(&output.script_pubkey, network),
+            })
+            .collect();
+
+        Self {
+            inputs,
+            outputs,
+            status,
+        }
+    }
+}
+
+/// Fetch and decode the given Bitcoin transactions, labeled by their kind.
+pub async fn inspect(
+    bitcoin_wallet: &bitcoin::Wallet,
+    transactions: &SwapTransactions,
+) -> Result<Vec<TransactionSummary>> {
+    let network = bitcoin_wallet.get_network().await;
+    let mut summaries = vec![];
+
+    for (kind, txid) in transactions.labeled() {
+        let details = match bitcoin_wallet.get_tx(txid).await? {
+            Some(transaction) => {
+                let watchable = watchable(txid, &transaction)?;
+                let status = bitcoin_wallet.status_of_script(&watchable).await?;
+
+                Some(TransactionDetails::new(&transaction, network, status))
+            }
+            None => None,
+        };
+
+        summaries.push(TransactionSummary {
+            kind,
+            txid,
+            details,
+        });
+    }
+
+    Ok(summaries)
+}
+
+/// To determine the status of a transaction, watching a single output is
+/// enough.
+fn watchable(txid: Txid, transaction: &Transaction) -> Result<(Txid, Script)> {
+    let output = transaction
+        .output
+        .first()
+        .ok_or_else(|| anyhow!("Transaction {} has no outputs", txid))?;
+
+    Ok((txid, output.script_pubkey.clone()))
+}
+
+impl fmt::Display for TransactionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} transaction {}", self.kind, self.txid)?;
+
+        let details = match &self.details {
+            Some(details) => details,
+            None => return writeln!(f, " (not published)"),
+        };
+
+        writeln!(f, " ({})", details.status)?;
+
+        writeln!(f, "  inputs:")?;
+        for input in &details.inputs {
+            writeln!(f, "    {}", input)?;
+        }
+
+        writeln!(f, "  outputs:")?;
+        for (index, output) in details.outputs.iter().enumerate() {
+            match &output.address {
+                Some(address) => writeln!(f, "    {}: {} to {}", index, output.amount, address)?,
+                None => writeln!(
+                    f,
+                    "    {}: {} to script {}",
+                    index, output.amount, output.script
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::hashes::Hash;
+    use ::bitcoin::{TxIn, TxOut};
+
+    #[test]
+    fn decodes_inputs_outputs_and_status() {
+        let address = Address::p2wsh(&Script::new(), Network::Regtest);
+        let previous_output = OutPoint::new(Txid::from_inner([1u8; 32]), 3);
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: Script::new(),
+                sequence: 0xFFFF_FFFF,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    value: 100_000,
+                    script_pubkey: address.script_pubkey(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: Script::new_op_return(&[]),
+                },
+            ],
+        };
+
+        let details = TransactionDetails::new(
+            &transaction,
+            Network::Regtest,
+            ScriptStatus::from_confirmations(2),
+        );
+
+        assert_eq!(details.inputs, vec![previous_output]);
+        assert_eq!(details.outputs[0].amount, Amount::from_sat(100_000));
+        assert_eq!(details.outputs[0].address, Some(address));
+        assert_eq!(details.outputs[1].address, None);
+        assert_eq!(details.status, ScriptStatus::from_confirmations(2));
+
+        let summary = TransactionSummary {
+            kind: TransactionKind::Lock,
+            txid: transaction.txid(),
+            details: Some(details),
+        };
+        let rendered = summary.to_string();
+
+        assert!(rendered.starts_with(&format!("lock transaction {}", transaction.txid())));
+        assert!(rendered.contains("confirmed with 2 blocks"));
+        assert!(rendered.contains("0.00100000 BTC"));
+    }
+
+    #[test]
+    fn transaction_without_outputs_is_an_error() {
+        let transaction = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+
+        let result = watchable(transaction.txid(), &transaction);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn unpublished_transaction_is_marked_as_such() {
+        let summary = TransactionSummary {
+            kind: TransactionKind::Cancel,
+            txid: Txid::from_inner([0u8; 32]),
+            details: None,
+        };
+
+        assert!(summary.to_string().ends_with("(not published)\n"));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::bitcoin;
+use crate::bitcoin::wallet::ScriptStatus;
+use crate::bitcoin::{ExpiredTimelocks, TimelockEpoch, TransactionKind, Txid};
+use crate::cli::inspect::{inspect, TransactionSummary};
+use crate::cli::status::{statuses, timelocks_of, watched_transactions, write_timelocks};
+use crate::database::Database;
+use crate::protocol::bob::{self, BobState};
+use crate::protocol::RecoveryAction;
+use anyhow::{bail, Result};
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The next action to recover a swap and the transactions it publishes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecoveryPlan {
+    pub swap_id: Uuid,
+    pub state: String,
+    pub action: Option<RecoveryAction>,
+    pub broadcasts: Vec<(TransactionKind, Txid)>,
+    pub timelocks: Option<TimelockEpoch>,
+}
+
+impl RecoveryPlan {
+    pub fn new(swap_id: Uuid, state: &BobState, transactions: &[TransactionSummary]) -> Self {
+        let statuses = statuses(transactions);
+        let timelocks = timelocks_of(state, &statuses);
+        let cancel_seen = statuses.iter().any(|(kind, _, status)| {
+            *kind == TransactionKind::Cancel && *status != ScriptStatus::Unseen
+        });
+
+        let action = next_action(state, timelocks, cancel_seen);
+        let broadcasts = transactions
+            .iter()
+            .filter(|summary| Some(summary.kind) == broadcast_kind(action))
+            .map(|summary| (summary.kind, summary.txid))
+            .collect();
+
+        Self {
+            swap_id,
+            state: state.to_string(),
+            action,
+            broadcasts,
+            timelocks,
+        }
+    }
+}
+
+/// The next action of the swap, given how far its timelocks progressed on
+/// chain.
+///
+/// The persisted state lags behind the blockchain: the cancel transaction may
+/// have been published by the seller, or the cancel timelock may not have
+/// expired yet.
+fn next_action(
+    state: &BobState,
+    timelocks: Option<TimelockEpoch>,
+    cancel_seen: bool,
+) -> Option<RecoveryAction> {
+    match state.is_recoverable() {
+        Some(RecoveryAction::PublishCancel) if cancel_seen => Some(RecoveryAction::PublishRefund),
+        Some(RecoveryAction::PublishCancel)
+            if timelocks.map(|timelocks| timelocks.epoch) == Some(ExpiredTimelocks::None) =>
+        {
+            Some(RecoveryAction::WaitForCancelTimelock)
+        }
+        action => action,
+    }
+}
+
+/// Take the next action to recover the swap.
+///
+/// If `dry_run` is set, the action is only determined but nothing is
+/// published.
+pub async fn recover(
+    swap_id: Uuid,
+    state: BobState,
+    bitcoin_wallet: Arc<bitcoin::Wallet>,
+    db: Database,
+    dry_run: bool,
+) -> Result<RecoveryPlan> {
+    let transactions = watched_transactions(&state, db.bitcoin_transactions(swap_id)?);
+    let transactions = inspect(&bitcoin_wallet, &transactions).await?;
+    let plan = RecoveryPlan::new(swap_id, &state, &transactions);
+
+    if dry_run {
+        return Ok(plan);
+    }
+
+    match plan.action {
+        Some(RecoveryAction::PublishCancel) => {
+            bob::cancel(swap_id, state, bitcoin_wallet, db, false).await??;
+        }
+        Some(RecoveryAction::PublishRefund) => {
+            // The cancel transaction may be on chain before the state recorded
+            // it, in which case the refund has to be forced.
+            let force = !matches!(state, BobState::BtcCancelled(..));
+            bob::refund(swap_id, state, bitcoin_wallet, db, force).await??;
+        }
+        action => bail!(
+            "Swap {} cannot be recovered by publishing a transaction, the next action is: {}",
+            swap_id,
+            describe(action)
+        ),
+    }
+
+    Ok(plan)
+}
+
+/// The kind of transaction published to take the given action.
+fn broadcast_kind(action: Option<RecoveryAction>) -> Option<TransactionKind> {
+    match action? {
+        RecoveryAction::PublishCancel => Some(TransactionKind::Cancel),
+        RecoveryAction::PublishRefund => Some(TransactionKind::Refund),
+        RecoveryAction::PublishPunish => Some(TransactionKind::Punish),
+        RecoveryAction::PublishRedeem => Some(TransactionKind::Redeem),
+        RecoveryAction::ClaimXmr
+        | RecoveryAction::WaitForCancelTimelock
+        | RecoveryAction::Nothing => None,
+    }
+}
+
+fn describe(action: Option<RecoveryAction>) -> &'static str {
+    match action {
+        None => "nothing, the swap is complete",
+        Some(RecoveryAction::PublishCancel) => "publish the cancel transaction",
+        Some(RecoveryAction::PublishRefund) => "publish the refund transaction",
+        Some(RecoveryAction::PublishPunish) => "publish the punish transaction",
+        Some(RecoveryAction::PublishRedeem) => "publish the redeem transaction",
+        Some(RecoveryAction::ClaimXmr) => {
+            "claim the Monero using the resume or recover-xmr command"
+        }
+        Some(RecoveryAction::WaitForCancelTimelock) => "wait for the cancel timelock to expire",
+        Some(RecoveryAction::Nothing) => "nothing, no Bitcoin is locked yet",
+    }
+}
+
+impl fmt::Display for RecoveryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Swap {}", self.swap_id)?;
+        writeln!(f, "State: {}", self.state)?;
+        writeln!(f, "Action: {}", describe(self.action))?;
+
+        for (kind, txid) in &self.broadcasts {
+            writeln!(f, "Publishes: {} {}", kind, txid)?;
+        }
+
+        write_timelocks(f, self.timelocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::inspect::TransactionDetails;
+    use crate::env::Regtest;
+    use crate::protocol::execution_setup;
+    use ::bitcoin::hashes::Hash;
+
+    async fn btc_locked() -> (BobState, Txid) {
+        let bitcoin_wallet = bitcoin::mock::MockWallet::default();
+        let (_, state2) = execution_setup(&bitcoin_wallet).await;
+        let (state3, _) = state2.lock_btc().await.unwrap();
+        let tx_lock_id = state3.tx_lock_id();
+
+        (BobState::BtcLocked(state3), tx_lock_id)
+    }
+
+    fn summary(kind: TransactionKind, txid: Txid, status: ScriptStatus) -> TransactionSummary {
+        TransactionSummary {
+            kind,
+            txid,
+            details: Some(TransactionDetails {
+                inputs: vec![],
+                outputs: vec![],
+                status,
+            }),
+        }
+    }
+
+    #[tokio::test]
+    async fn cancel_waits_for_the_cancel_timelock() {
+        let (state, tx_lock_id) = btc_locked().await;
+        let transactions = vec![summary(
+            TransactionKind::Lock,
+            tx_lock_id,
+            ScriptStatus::from_confirmations(1),
+        )];
+
+        let plan = RecoveryPlan::new(Uuid::nil(), &state, &transactions);
+
+        assert_eq!(plan.action, Some(RecoveryAction::WaitForCancelTimelock));
+        assert!(plan.broadcasts.is_empty());
+        assert!(plan
+            .to_string()
+            .contains("Action: wait for the cancel timelock to expire"));
+    }
+
+    #[tokio::test]
+    async fn cancel_is_published_once_the_cancel_timelock_expired() {
+        let (state, tx_lock_id) = btc_locked().await;
+        let tx_cancel_id = state.tx_cancel_id().unwrap();
+        let transactions = vec![
+            summary(
+                TransactionKind::Lock,
+                tx_lock_id,
+                ScriptStatus::from_confirmations(Regtest::CANCEL_TIMELOCK),
+            ),
+            TransactionSummary {
+                kind: TransactionKind::Cancel,
+                txid: tx_cancel_id,
+                details: None,
+            },
+        ];
+
+        let plan = RecoveryPlan::new(Uuid::nil(), &state, &transactions);
+
+        assert_eq!(plan.action, Some(RecoveryAction::PublishCancel));
+        assert_eq!(plan.broadcasts, vec![(
+            TransactionKind::Cancel,
+            tx_cancel_id
+        )]);
+    }
+
+    #[tokio::test]
+    async fn published_cancel_is_followed_by_the_refund() {
+        let (state, tx_lock_id) = btc_locked().await;
+        let tx_refund_id = state.tx_refund_id().unwrap();
+        let transactions = vec![
+            summary(
+                TransactionKind::Lock,
+                tx_lock_id,
+                ScriptStatus::from_confirmations(Regtest::CANCEL_TIMELOCK),
+            ),
+            summary(
+                TransactionKind::Cancel,
+                state.tx_cancel_id().unwrap(),
+                ScriptStatus::from_confirmations(1),
+            ),
+            TransactionSummary {
+                kind: TransactionKind::Refund,
+                txid: tx_refund_id,
+                details: None,
+            },
+        ];
+
+        let plan = RecoveryPlan::new(Uuid::nil(), &state, &transactions);
+
+        assert_eq!(plan.action, Some(RecoveryAction::PublishRefund));
+        assert_eq!(plan.broadcasts, vec![(
+            TransactionKind::Refund,
+            tx_refund_id
+        )]);
+    }
+
+    #[test]
+    fn complete_swap_publishes_nothing() {
+        let tx_lock_id = Txid::from_inner([1u8; 32]);
+        let state = BobState::XmrRedeemed { tx_lock_id };
+        let transactions = vec![TransactionSummary {
+            kind: TransactionKind::Lock,
+            txid: tx_lock_id,
+            details: None,
+        }];
+
+        let plan = RecoveryPlan::new(Uuid::nil(), &state, &transactions);
+
+        assert_eq!(plan.action, None);
+        assert!(plan.broadcasts.is_empty());
+        assert!(plan
+            .to_string()
+            .contains("Action: nothing, the swap is complete"));
+    }
+}
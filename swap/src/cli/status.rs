@@ -0,0 +1,257 @@
+use crate::bitcoin::wallet::ScriptStatus;
+use crate::bitcoin::{timelock_epoch, ExpiredTimelocks, TimelockEpoch, TransactionKind, Txid};
+use crate::cli::inspect::TransactionSummary;
+use crate::database::{Failure, SwapTransactions};
+use crate::explorer::ExplorerUrl;
+use crate::protocol::bob::BobState;
+use std::fmt;
+use uuid::Uuid;
+
+/// A snapshot of the progress of a swap, combining the persisted state with
+/// what is visible on the blockchain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusReport {
+    pub swap_id: Uuid,
+    pub state: String,
+    pub transactions: Vec<(TransactionKind, Txid, ScriptStatus)>,
+    pub timelocks: Option<TimelockEpoch>,
+    pub next_action: &'static str,
+    /// Why the swap failed in its current state, if it did.
+    pub failure: Option<Failure>,
+    /// Where to link the transactions to, if anywhere.
+    pub explorer: Option<ExplorerUrl>,
+}
+
+impl StatusReport {
+    pub fn new(swap_id: Uuid, state: &BobState, transactions: &[TransactionSummary]) -> Self {
+        let transactions = statuses(transactions);
+        let timelocks = timelocks_of(state, &transactions);
+
+        Self {
+            swap_id,
+            state: state.to_string(),
+            next_action: next_action(state, timelocks.map(|timelocks| timelocks.epoch)),
+            transactions,
+            timelocks,
+            failure: None,
+            explorer: None,
+        }
+    }
+
+    pub fn with_failure(self, failure: Option<Failure>) -> Self {
+        Self { failure, ..self }
+    }
+
+    pub fn with_explorer(self, explorer: Option<ExplorerUrl>) -> Self {
+        Self { explorer, ..self }
+    }
+}
+
+/// The recorded transactions of the swap together with its cancel
+/// transaction, which the seller may publish before it is recorded, and the
+/// refund transaction that follows it.
+pub fn watched_transactions(state: &BobState, recorded: SwapTransactions) -> SwapTransactions {
+    SwapTransactions {
+        cancel: recorded.cancel.or_else(|| state.tx_cancel_id()),
+        refund: recorded.refund.or_else(|| state.tx_refund_id()),
+        ..recorded
+    }
+}
+
+/// The status of each transaction, unpublished ones are unseen.
+pub(crate) fn statuses(
+    transactions: &[TransactionSummary],
+) -> Vec<(TransactionKind, Txid, ScriptStatus)> {
+    transactions
+        .iter()
+        .map(|summary| {
+            let status = summary
+                .details
+                .as_ref()
+                .map(|details| details.status)
+                .unwrap_or(ScriptStatus::Unseen);
+
+            (summary.kind, summary.txid, status)
+        })
+        .collect()
+}
+
+/// The timelock epoch of the swap, given the statuses of its transactions.
+pub(crate) fn timelocks_of(
+    state: &BobState,
+    transactions: &[(TransactionKind, Txid, ScriptStatus)],
+) -> Option<TimelockEpoch> {
+    let status_of = |kind: TransactionKind| {
+        transactions
+            .iter()
+            .find(|(candidate, ..)| *candidate == kind)
+            .map(|(.., status)| *status)
+            .unwrap_or(ScriptStatus::Unseen)
+    };
+
+    state.timelocks().map(|(cancel_timelock, punish_timelock)| {
+        timelock_epoch(
+            cancel_timelock,
+            punish_timelock,
+            status_of(TransactionKind::Lock),
+            status_of(TransactionKind::Cancel),
+        )
+    })
+}
+
+fn next_action(state: &BobState, expired_timelocks: Option<ExpiredTimelocks>) -> &'static str {
+    match (state, expired_timelocks) {
+        (BobState::BtcRefunded(..), _)
+        | (BobState::XmrRedeemed { .. }, _)
+        | (BobState::BtcPunished { .. }, _)
+        | (BobState::SafelyAborted, _) => "none, the swap is complete",
+        (BobState::Started { .. }, _) => "resume the swap to set it up with the seller",
+        (BobState::BtcRedeemed(..), _) => "resume the swap to redeem the Monero",
+        (_, Some(ExpiredTimelocks::Punish)) => {
+            "resume the swap, the Bitcoin may be punished by the seller"
+        }
+        (BobState::CancelTimelockExpired(..), _) | (_, Some(ExpiredTimelocks::Cancel)) => {
+            "cancel the swap using the cancel command"
+        }
+        (BobState::BtcCancelled(..), _) => "refund the Bitcoin using the refund command",
+        (BobState::ExecutionSetupDone(..), _) => "resume the swap to lock the Bitcoin",
+        (BobState::BtcLocked(..), _) | (BobState::XmrLockProofReceived { .. }, _) => {
+            "wait for the seller to lock the Monero"
+        }
+        (BobState::XmrLocked(..), _) => "resume the swap to send the encrypted signature",
+        (BobState::EncSigSent(..), _) => "wait for the seller to redeem the Bitcoin",
+    }
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Swap {}", self.swap_id)?;
+        writeln!(f, "State: {}", self.state)?;
+
+        writeln!(f, "Transactions:")?;
+        for (kind, txid, status) in &self.transactions {
+            match &self.explorer {
+                Some(explorer) => writeln!(
+                    f,
+                    "  {} {}: {} ({})",
+                    kind,
+                    txid,
+                    status,
+                    explorer.transaction(txid)
+                )?,
+                None => writeln!(f, "  {} {}: {}", kind, txid, status)?,
+            }
+        }
+
+        write_timelocks(f, self.timelocks)?;
+
+        if let Some(failure) = &self.failure {
+            writeln!(f, "Last error: {}", failure)?;
+        }
+
+        writeln!(f, "Next action: {}", self.next_action)
+    }
+}
+
+pub(crate) fn write_timelocks(
+    f: &mut fmt::Formatter<'_>,
+    timelocks: Option<TimelockEpoch>,
+) -> fmt::Result {
+    match timelocks {
+        Some(TimelockEpoch {
+            epoch: ExpiredTimelocks::None,
+            blocks_until_cancel,
+            ..
+        }) => writeln!(
+            f,
+            "Timelocks: none expired, cancel timelock expires in {} blocks",
+            blocks_until_cancel
+        )?,
+        Some(TimelockEpoch {
+            epoch: ExpiredTimelocks::Cancel,
+            blocks_until_punish,
+            ..
+        }) => writeln!(
+            f,
+            "Timelocks: cancel timelock expired, punish timelock expires in {} blocks",
+            blocks_until_punish
+        )?,
+        Some(TimelockEpoch {
+            epoch: ExpiredTimelocks::Punish,
+            ..
+        }) => writeln!(f, "Timelocks: punish timelock expired")?,
+        None => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::inspect::TransactionDetails;
+    use ::bitcoin::hashes::Hash;
+
+    #[test]
+    fn report_contains_statuses_and_next_action() {
+        let tx_lock_id = Txid::from_inner([1u8; 32]);
+        let state = BobState::XmrRedeemed { tx_lock_id };
+        let transactions = vec![TransactionSummary {
+            kind: TransactionKind::Lock,
+            txid: tx_lock_id,
+            details: Some(TransactionDetails {
+                inputs: vec![],
+                outputs: vec![],
+                status: ScriptStatus::from_confirmations(3),
+            }),
+        }];
+
+        let report = StatusReport::new(Uuid::nil(), &state, &transactions);
+        let rendered = report.to_string();
+
+        assert_eq!(report.transactions, vec![(
+            TransactionKind::Lock,
+            tx_lock_id,
+            ScriptStatus::from_confirmations(3)
+        )]);
+        assert_eq!(report.timelocks, None);
+        assert!(rendered.contains("State: xmr is redeemed"));
+        assert!(rendered.contains(&format!("lock {}: confirmed with 3 blocks", tx_lock_id)));
+        assert!(rendered.contains("Next action: none, the swap is complete"));
+    }
+
+    #[test]
+    fn unpublished_transactions_are_unseen() {
+        let tx_lock_id = Txid::from_inner([1u8; 32]);
+        let state = BobState::BtcPunished { tx_lock_id };
+        let transactions = vec![TransactionSummary {
+            kind: TransactionKind::Lock,
+            txid: tx_lock_id,
+            details: None,
+        }];
+
+        let report = StatusReport::new(Uuid::nil(), &state, &transactions);
+
+        assert_eq!(report.transactions[0].2, ScriptStatus::Unseen);
+        assert!(report.to_string().contains("unseen"));
+    }
+
+    #[test]
+    fn transactions_link_to_the_explorer() {
+        let tx_lock_id = Txid::from_inner([1u8; 32]);
+        let state = BobState::BtcPunished { tx_lock_id };
+        let transactions = vec![TransactionSummary {
+            kind: TransactionKind::Lock,
+            txid: tx_lock_id,
+            details: None,
+        }];
+
+        let report = StatusReport::new(Uuid::nil(), &state, &transactions)
+            .with_explorer(ExplorerUrl::bitcoin(::bitcoin::Network::Testnet));
+
+        assert!(report.to_string().contains(&format!(
+            "lock {}: unseen (https://mempool.space/testnet/tx/{})",
+            tx_lock_id, tx_lock_id
+        )));
+    }
+}
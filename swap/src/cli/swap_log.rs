@@ -0,0 +1,124 @@
+use crate::bitcoin::{TransactionKind, Txid};
+use crate::database::{Swap, Transition};
+use crate::protocol::bob::BobState;
+use anyhow::Result;
+use std::fmt;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// The chronological log of the state transitions of a swap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapLog {
+    pub swap_id: Uuid,
+    pub entries: Vec<LogEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    /// `None` if the swap was persisted before transitions were recorded.
+    pub timestamp: Option<OffsetDateTime>,
+    pub state: String,
+    pub transactions: Vec<(TransactionKind, Txid)>,
+}
+
+impl SwapLog {
+    /// Build the log from the recorded transitions, falling back to only the
+    /// latest state if none were recorded.
+    pub fn new(swap_id: Uuid, history: Vec<Transition>, latest: Swap) -> Result<Self> {
+        let entries = if history.is_empty() {
+            vec![LogEntry::new(None, latest)?]
+        } else {
+            history
+                .into_iter()
+                .map(|transition| LogEntry::new(Some(transition.timestamp), transition.state))
+                .collect::<Result<_>>()?
+        };
+
+        Ok(Self { swap_id, entries })
+    }
+}
+
+impl LogEntry {
+    fn new(timestamp: Option<OffsetDateTime>, state: Swap) -> Result<Self> {
+        let state = BobState::from(state.try_into_bob()?);
+
+        Ok(Self {
+            timestamp,
+            state: state.to_string(),
+            transactions: state.bitcoin_transactions(),
+        })
+    }
+}
+
+impl fmt::Display for SwapLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Swap {}", self.swap_id)?;
+
+        for entry in &self.entries {
+            match entry.timestamp {
+                Some(timestamp) => {
+                    writeln!(f, "{} {}", timestamp.format("%F %T UTC"), entry.state)?
+                }
+                None => writeln!(f, "(unknown time) {}", entry.state)?,
+            }
+
+            for (kind, txid) in &entry.transactions {
+                writeln!(f, "  {} {}", kind, txid)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::hashes::Hash;
+
+    #[test]
+    fn printed_log_follows_the_recorded_transitions() {
+        let tx_lock_id = Txid::from_inner([1u8; 32]);
+        let history = vec![
+            Transition {
+                timestamp: OffsetDateTime::from_unix_timestamp(1_600_000_000),
+                state: Swap::Bob(
+                    BobState::Started {
+                        btc_amount: crate::bitcoin::Amount::from_sat(100_000),
+                    }
+                    .into(),
+                ),
+            },
+            Transition {
+                timestamp: OffsetDateTime::from_unix_timestamp(1_600_000_060),
+                state: Swap::Bob(BobState::XmrRedeemed { tx_lock_id }.into()),
+            },
+        ];
+        let latest = history[1].state.clone();
+
+        let log = SwapLog::new(Uuid::nil(), history, latest).unwrap();
+        let rendered = log.to_string();
+        let lines = rendered.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines, vec![
+            "Swap 00000000-0000-0000-0000-000000000000".to_owned(),
+            "2020-09-13 12:26:40 UTC quote has been requested".to_owned(),
+            "2020-09-13 12:27:40 UTC xmr is redeemed".to_owned(),
+            format!("  lock {}", tx_lock_id),
+        ]);
+    }
+
+    #[test]
+    fn without_history_only_the_latest_state_is_printed() {
+        let latest = Swap::Bob(BobState::SafelyAborted.into());
+
+        let log = SwapLog::new(Uuid::nil(), vec![], latest).unwrap();
+
+        assert_eq!(log.entries, vec![LogEntry {
+            timestamp: None,
+            state: "safely aborted".to_owned(),
+            transactions: vec![],
+        }]);
+        assert!(log.to_string().contains("(unknown time) safely aborted"));
+    }
+}
@@ -1,11 +1,20 @@
 pub use alice::Alice;
 pub use bob::Bob;
 
+use crate::bitcoin::TransactionKind;
+use crate::protocol::alice::AliceState;
+use crate::protocol::bob::BobState;
+use crate::{bitcoin, monero};
 use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use libp2p::{Multiaddr, PeerId};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
 use uuid::Uuid;
 
 mod alice;
@@ -45,44 +54,629 @@ impl Swap {
             Swap::Alice(_) => bail!("Swap instance is not Bob"),
         }
     }
+
+    pub fn try_into_alice(self) -> Result<Alice> {
+        match self {
+            Swap::Alice(alice) => Ok(alice),
+            Swap::Bob(_) => bail!("Swap instance is not Alice"),
+        }
+    }
+
+    /// The Bitcoin transactions created in this state, labeled by their kind.
+    pub fn bitcoin_transactions(&self) -> Vec<(TransactionKind, bitcoin::Txid)> {
+        match self {
+            Swap::Alice(alice) => AliceState::from(alice.clone()).bitcoin_transactions(),
+            Swap::Bob(bob) => BobState::from(bob.clone()).bitcoin_transactions(),
+        }
+    }
+}
+
+/// The ids of the Bitcoin transactions of a swap.
+///
+/// They are recorded as the swap transitions, hence remain known once the swap
+/// is in a state that no longer holds them, e.g. after it completed.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct SwapTransactions {
+    pub lock: Option<bitcoin::Txid>,
+    pub redeem: Option<bitcoin::Txid>,
+    pub cancel: Option<bitcoin::Txid>,
+    pub refund: Option<bitcoin::Txid>,
+    pub punish: Option<bitcoin::Txid>,
+}
+
+impl SwapTransactions {
+    /// Record the given transactions, labeled by their kind as returned by
+    /// [`Swap::bitcoin_transactions`].
+    pub fn record(&mut self, transactions: &[(TransactionKind, bitcoin::Txid)]) {
+        for (kind, txid) in transactions {
+            let known = match kind {
+                TransactionKind::Lock => &mut self.lock,
+                TransactionKind::Redeem => &mut self.redeem,
+                TransactionKind::Cancel => &mut self.cancel,
+                TransactionKind::Refund => &mut self.refund,
+                TransactionKind::Punish => &mut self.punish,
+            };
+
+            *known = Some(*txid);
+        }
+    }
+
+    /// The known transactions, labeled by their kind.
+    pub fn labeled(&self) -> Vec<(TransactionKind, bitcoin::Txid)> {
+        vec![
+            (TransactionKind::Lock, self.lock),
+            (TransactionKind::Redeem, self.redeem),
+            (TransactionKind::Cancel, self.cancel),
+            (TransactionKind::Refund, self.refund),
+            (TransactionKind::Punish, self.punish),
+        ]
+        .into_iter()
+        .filter_map(|(kind, txid)| Some((kind, txid?)))
+        .collect()
+    }
+}
+
+impl From<&Swap> for SwapTransactions {
+    fn from(swap: &Swap) -> Self {
+        let mut transactions = SwapTransactions::default();
+        transactions.record(&swap.bitcoin_transactions());
+
+        transactions
+    }
+}
+
+/// The peer we are swapping with and the address we reached it at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Counterparty {
+    pub peer_id: PeerId,
+    pub address: Multiaddr,
+}
+
+/// A state a swap transitioned into and when that happened.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    pub timestamp: OffsetDateTime,
+    pub state: Swap,
+}
+
+/// A spot price quoted to a peer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quote {
+    pub timestamp: OffsetDateTime,
+    pub peer_id: PeerId,
+    /// The amount of Bitcoin the peer asked to swap.
+    pub btc: bitcoin::Amount,
+    /// The amount of Monero we offered in exchange.
+    pub xmr: monero::Amount,
+    /// The price of 1 XMR the quote is based on.
+    pub ask: bitcoin::Amount,
+}
+
+/// How many recorded quotes to keep, older ones are evicted first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuoteRetention {
+    pub max_count: usize,
+    pub max_age: Duration,
+}
+
+/// Why a swap stopped before reaching one of its final states.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub timestamp: OffsetDateTime,
+    /// The category of the [`SwapError`](crate::error::SwapError) the error
+    /// is classified as, e.g. `network`.
+    pub category: String,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(error: &anyhow::Error) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_utc(),
+            category: crate::error::category_of(error).to_owned(),
+            message: format!("{:#}", error),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} error: {}", self.category, self.message)
+    }
+}
+
+/// A stored swap that could not be read, e.g. because the record is corrupt or
+/// was written in an unknown format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnreadableSwap {
+    /// The id of the swap, unless the key of the record is unreadable too.
+    pub swap_id: Option<Uuid>,
+    pub key: Vec<u8>,
+    pub error: String,
+}
+
+impl Display for UnreadableSwap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.swap_id {
+            Some(swap_id) => write!(f, "{}", swap_id)?,
+            None => {
+                f.write_str("key ")?;
+                for byte in &self.key {
+                    write!(f, "{:02x}", byte)?;
+                }
+            }
+        }
+
+        write!(f, ": {}", self.error)
+    }
+}
+
+/// The operations the swap protocols need to persist their progress.
+///
+/// [`SledStore`] is used by default, other backends can be plugged in through
+/// [`Database::new`].
+#[async_trait]
+pub trait SwapStore: Send + Sync {
+    async fn insert_latest_state(&self, swap_id: Uuid, state: Swap) -> Result<()>;
+    fn get_state(&self, swap_id: Uuid) -> Result<Swap>;
+    /// All swaps that can be read, unreadable ones are skipped.
+    fn all(&self) -> Result<Vec<(Uuid, Swap)>>;
+    /// The swaps skipped by [`SwapStore::all`].
+    ///
+    /// Stores that cannot hold unreadable swaps return an empty list.
+    fn unreadable(&self) -> Result<Vec<UnreadableSwap>> {
+        Ok(Vec::new())
+    }
+    async fn insert_counterparty(&self, swap_id: Uuid, counterparty: Counterparty) -> Result<()>;
+    fn get_counterparty(&self, swap_id: Uuid) -> Result<Option<Counterparty>>;
+    /// The peer a swap is executed with, for swaps in which we did not dial
+    /// the peer and hence do not know an address of it.
+    async fn insert_peer_id(&self, swap_id: Uuid, peer_id: PeerId) -> Result<()>;
+    fn get_peer_id(&self, swap_id: Uuid) -> Result<Option<PeerId>>;
+    /// The index of the Monero subaddress that receives the funds of a swap.
+    async fn insert_monero_subaddress(&self, swap_id: Uuid, index: u32) -> Result<()>;
+    fn get_monero_subaddress(&self, swap_id: Uuid) -> Result<Option<u32>>;
+
+    /// All states the swap transitioned into, oldest first.
+    ///
+    /// Stores that do not record transitions return an empty history.
+    fn state_history(&self, _swap_id: Uuid) -> Result<Vec<Transition>> {
+        Ok(Vec::new())
+    }
+
+    /// Record a quote, evicting quotes beyond the given retention.
+    ///
+    /// Stores that do not record quotes ignore it.
+    async fn insert_quote(&self, _quote: Quote, _retention: QuoteRetention) -> Result<()> {
+        Ok(())
+    }
+
+    /// All recorded quotes, oldest first.
+    fn quotes(&self) -> Result<Vec<Quote>> {
+        Ok(Vec::new())
+    }
+
+    /// Record why the swap failed in its latest state, until it transitions
+    /// into another state.
+    ///
+    /// Stores that do not record failures ignore it.
+    async fn insert_failure(&self, _swap_id: Uuid, _failure: Failure) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_failure(&self, _swap_id: Uuid) -> Result<Option<Failure>> {
+        Ok(None)
+    }
+
+    /// The Bitcoin transactions of the swap, including the ones known in
+    /// earlier states.
+    ///
+    /// Stores that do not record them return the ones of the latest state.
+    fn bitcoin_transactions(&self, swap_id: Uuid) -> Result<SwapTransactions> {
+        Ok(SwapTransactions::from(&self.get_state(swap_id)?))
+    }
+}
+
+/// The state of a swap was about to overwrite the state of a different swap
+/// stored under the same id.
+#[derive(Debug, thiserror::Error)]
+#[error("Swap {swap_id} belongs to a different swap: {reason}")]
+pub struct ConflictingSwap {
+    pub swap_id: Uuid,
+    pub reason: String,
 }
 
-pub struct Database(sled::Db);
+/// Why the new state cannot be a later state of the stored swap, if it cannot.
+///
+/// The role of a swap never changes and neither do its Bitcoin transactions
+/// once they are known.
+fn conflict(stored: &Swap, transactions: &SwapTransactions, new: &Swap) -> Option<String> {
+    match (stored, new) {
+        (Swap::Alice(_), Swap::Bob(_)) => return Some("stored as Alice, not Bob".to_owned()),
+        (Swap::Bob(_), Swap::Alice(_)) => return Some("stored as Bob, not Alice".to_owned()),
+        _ => {}
+    }
+
+    let known = transactions.labeled();
+    new.bitcoin_transactions()
+        .into_iter()
+        .find_map(|(kind, txid)| {
+            let (_, stored_txid) = known.iter().find(|(known_kind, _)| *known_kind == kind)?;
+
+            if *stored_txid == txid {
+                return None;
+            }
+
+            Some(format!(
+                "stored {} transaction is {}, not {}",
+                kind, stored_txid, txid
+            ))
+        })
+}
+
+#[derive(Clone)]
+pub struct Database(Arc<dyn SwapStore>);
 
 impl Database {
+    /// Open the default, sled backed database at the given path.
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self::new(SledStore::open(path)?))
+    }
+
+    pub fn new(store: impl SwapStore + 'static) -> Self {
+        Database(Arc::new(store))
+    }
+
+    /// Store the latest state of a swap.
+    ///
+    /// Fails with [`ConflictingSwap`] instead of overwriting the state of a
+    /// different swap stored under the same id, i.e. one of the other role or
+    /// with other Bitcoin transactions.
+    pub async fn insert_latest_state(&self, swap_id: Uuid, state: Swap) -> Result<()> {
+        if let Ok(stored) = self.0.get_state(swap_id) {
+            let transactions = self.0.bitcoin_transactions(swap_id)?;
+
+            if let Some(reason) = conflict(&stored, &transactions, &state) {
+                tracing::error!(%swap_id, "Refusing to overwrite swap with the state of a different swap: {}", reason);
+                bail!(ConflictingSwap { swap_id, reason });
+            }
+        }
+
+        self.0.insert_latest_state(swap_id, state).await
+    }
+
+    /// A random swap id that is not used by any stored swap.
+    pub fn new_swap_id(&self) -> Uuid {
+        loop {
+            let swap_id = Uuid::new_v4();
+
+            if self.0.get_state(swap_id).is_err() {
+                return swap_id;
+            }
+        }
+    }
+
+    pub fn get_state(&self, swap_id: Uuid) -> Result<Swap> {
+        self.0.get_state(swap_id)
+    }
+
+    pub fn all(&self) -> Result<Vec<(Uuid, Swap)>> {
+        self.0.all()
+    }
+
+    pub fn unreadable(&self) -> Result<Vec<UnreadableSwap>> {
+        self.0.unreadable()
+    }
+
+    pub async fn insert_counterparty(
+        &self,
+        swap_id: Uuid,
+        counterparty: Counterparty,
+    ) -> Result<()> {
+        self.0.insert_counterparty(swap_id, counterparty).await
+    }
+
+    pub fn get_counterparty(&self, swap_id: Uuid) -> Result<Option<Counterparty>> {
+        self.0.get_counterparty(swap_id)
+    }
+
+    pub async fn insert_peer_id(&self, swap_id: Uuid, peer_id: PeerId) -> Result<()> {
+        self.0.insert_peer_id(swap_id, peer_id).await
+    }
+
+    pub fn get_peer_id(&self, swap_id: Uuid) -> Result<Option<PeerId>> {
+        self.0.get_peer_id(swap_id)
+    }
+
+    pub async fn insert_monero_subaddress(&self, swap_id: Uuid, index: u32) -> Result<()> {
+        self.0.insert_monero_subaddress(swap_id, index).await
+    }
+
+    pub fn get_monero_subaddress(&self, swap_id: Uuid) -> Result<Option<u32>> {
+        self.0.get_monero_subaddress(swap_id)
+    }
+
+    pub fn state_history(&self, swap_id: Uuid) -> Result<Vec<Transition>> {
+        self.0.state_history(swap_id)
+    }
+
+    pub async fn insert_quote(&self, quote: Quote, retention: QuoteRetention) -> Result<()> {
+        self.0.insert_quote(quote, retention).await
+    }
+
+    pub fn quotes(&self) -> Result<Vec<Quote>> {
+        self.0.quotes()
+    }
+
+    pub async fn insert_failure(&self, swap_id: Uuid, failure: Failure) -> Result<()> {
+        self.0.insert_failure(swap_id, failure).await
+    }
+
+    pub fn get_failure(&self, swap_id: Uuid) -> Result<Option<Failure>> {
+        self.0.get_failure(swap_id)
+    }
+
+    pub fn bitcoin_transactions(&self, swap_id: Uuid) -> Result<SwapTransactions> {
+        self.0.bitcoin_transactions(swap_id)
+    }
+}
+
+/// The format swaps are stored in, incremented with every change to [`Swap`]
+/// that breaks the deserialization of records written before.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// Upgrades a stored swap from the schema version at its index in
+/// [`MIGRATIONS`] to the next one.
+///
+/// A migration may be applied again to records it already upgraded if the
+/// database is closed while migrating, hence it has to leave such records
+/// unchanged.
+type Migration = fn(serde_cbor::Value) -> Result<serde_cbor::Value>;
+
+/// The current schema version is the number of migrations.
+const MIGRATIONS: &[Migration] = &[unversioned_to_v1];
+
+/// Swaps stored before the schema version was recorded are in the format of
+/// version 1.
+fn unversioned_to_v1(swap: serde_cbor::Value) -> Result<serde_cbor::Value> {
+    Ok(swap)
+}
+
+pub struct SledStore {
+    swaps: sled::Db,
+    counterparties: sled::Tree,
+    peers: sled::Tree,
+    monero_subaddresses: sled::Tree,
+    history: sled::Tree,
+    quotes: sled::Tree,
+    failures: sled::Tree,
+    transactions: sled::Tree,
+    meta: sled::Tree,
+}
+
+impl SledStore {
+    /// Open the database at the given path, upgrading the stored swaps to the
+    /// current schema version.
     pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with_migrations(path, MIGRATIONS)
+    }
+
+    fn open_with_migrations(path: &Path, migrations: &[Migration]) -> Result<Self> {
         tracing::debug!("Opening database at {}", path.display());
 
         let db =
             sled::open(path).with_context(|| format!("Could not open the DB at {:?}", path))?;
+        let counterparties = db
+            .open_tree("counterparties")
+            .context("Could not open the counterparties tree")?;
+        let peers = db
+            .open_tree("peers")
+            .context("Could not open the peers tree")?;
+        let monero_subaddresses = db
+            .open_tree("monero_subaddresses")
+            .context("Could not open the monero subaddresses tree")?;
+        let history = db
+            .open_tree("history")
+            .context("Could not open the history tree")?;
+        let quotes = db
+            .open_tree("quotes")
+            .context("Could not open the quotes tree")?;
+        let failures = db
+            .open_tree("failures")
+            .context("Could not open the failures tree")?;
+        let transactions = db
+            .open_tree("transactions")
+            .context("Could not open the transactions tree")?;
+        let meta = db
+            .open_tree("meta")
+            .context("Could not open the meta tree")?;
+
+        let store = SledStore {
+            swaps: db,
+            counterparties,
+            peers,
+            monero_subaddresses,
+            history,
+            quotes,
+            failures,
+            transactions,
+            meta,
+        };
+        store.migrate(migrations)?;
 
-        Ok(Database(db))
+        Ok(store)
     }
 
-    pub async fn insert_latest_state(&self, swap_id: Uuid, state: Swap) -> Result<()> {
+    fn read_all(&self) -> Result<(Vec<(Uuid, Swap)>, Vec<UnreadableSwap>)> {
+        let mut swaps = Vec::new();
+        let mut unreadable = Vec::new();
+
+        for item in self.swaps.iter() {
+            let (key, value) = item.context("Failed to retrieve swap from DB")?;
+            let swap_id = deserialize::<Uuid>(&key);
+            let swap = deserialize::<Swap>(&value);
+
+            match (swap_id, swap) {
+                (Ok(swap_id), Ok(swap)) => swaps.push((swap_id, swap)),
+                (swap_id, swap) => unreadable.push(UnreadableSwap {
+                    swap_id: swap_id.as_ref().ok().copied(),
+                    key: key.to_vec(),
+                    error: format!("{:#}", swap_id.and(swap).unwrap_err()),
+                }),
+            }
+        }
+
+        Ok((swaps, unreadable))
+    }
+
+    fn recorded_transactions(&self, key: &[u8]) -> Result<Option<SwapTransactions>> {
+        self.transactions
+            .get(key)?
+            .map(|encoded| deserialize(&encoded).context("Could not deserialize transactions"))
+            .transpose()
+    }
+
+    fn schema_version(&self) -> Result<Option<u32>> {
+        self.meta
+            .get(SCHEMA_VERSION_KEY)?
+            .map(|encoded| deserialize(&encoded).context("Could not deserialize schema version"))
+            .transpose()
+    }
+
+    fn migrate(&self, migrations: &[Migration]) -> Result<()> {
+        let current_version = migrations.len() as u32;
+        let stored_version = match self.schema_version()? {
+            Some(version) => version,
+            None if self.swaps.is_empty() => current_version,
+            None => 0,
+        };
+
+        if stored_version > current_version {
+            bail!(
+                "Database has schema version {} but this version only supports up to {}, downgrading is not supported",
+                stored_version,
+                current_version
+            );
+        }
+
+        for (from, migration) in migrations.iter().enumerate().skip(stored_version as usize) {
+            let to = from as u32 + 1;
+            tracing::info!("Migrating database to schema version {}", to);
+
+            for item in self.swaps.iter() {
+                let (key, value) = item.context("Failed to retrieve swap from DB")?;
+                let swap = match deserialize(&value) {
+                    Ok(swap) => swap,
+                    // Left for `all` to report.
+                    Err(_) => continue,
+                };
+                let swap = migration(swap)
+                    .with_context(|| format!("Failed to migrate swap to schema version {}", to))?;
+
+                self.swaps.insert(key, serialize(&swap)?)?;
+            }
+
+            // The history is informational, a transition that cannot be migrated is left as
+            // is instead of making the whole database unusable.
+            for item in self.history.iter() {
+                let (key, value) = item.context("Failed to retrieve transition from DB")?;
+                let transition = deserialize::<(i64, serde_cbor::Value)>(&value)
+                    .context("Could not deserialize transition")
+                    .and_then(|(timestamp, swap)| Ok((timestamp, migration(swap)?)));
+
+                match transition {
+                    Ok(transition) => {
+                        self.history.insert(key, serialize(&transition)?)?;
+                    }
+                    Err(error) => {
+                        let swap_id = key.get(..16).and_then(|id| Uuid::from_slice(id).ok());
+                        tracing::warn!(
+                            ?swap_id,
+                            "Skipping transition that cannot be migrated to schema version {}: {:#}",
+                            to,
+                            error
+                        );
+                    }
+                }
+            }
+
+            self.swaps.flush()?;
+            self.history.flush()?;
+            self.meta.insert(SCHEMA_VERSION_KEY, serialize(&to)?)?;
+        }
+
+        self.meta
+            .insert(SCHEMA_VERSION_KEY, serialize(&current_version)?)?;
+        self.meta.flush().context("Could not flush db")?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SwapStore for SledStore {
+    async fn insert_latest_state(&self, swap_id: Uuid, state: Swap) -> Result<()> {
         let key = serialize(&swap_id)?;
         let new_value = serialize(&state).context("Could not serialize new state value")?;
 
-        let old_value = self.0.get(&key)?;
+        let old_value = self.swaps.get(&key)?;
 
-        self.0
-            .compare_and_swap(key, old_value, Some(new_value))
+        self.swaps
+            .compare_and_swap(&key, old_value, Some(new_value))
             .context("Could not write in the DB")?
             .context("Stored swap somehow changed, aborting saving")?;
 
+        let mut transactions = self.recorded_transactions(&key)?.unwrap_or_default();
+        transactions.record(&state.bitcoin_transactions());
+        self.transactions
+            .insert(&key, serialize(&transactions)?)
+            .context("Could not write in the DB")?;
+        self.transactions
+            .flush_async()
+            .await
+            .context("Could not flush db")?;
+
+        // Keys are prefixed with the swap id and ordered by a monotonic id so
+        // that scanning the prefix yields the transitions in order.
+        let mut history_key = swap_id.as_bytes().to_vec();
+        history_key.extend_from_slice(&self.swaps.generate_id()?.to_be_bytes());
+        let history_value = serialize(&(OffsetDateTime::now_utc().unix_timestamp(), state))
+            .context("Could not serialize transition")?;
+
+        self.history
+            .insert(history_key, history_value)
+            .context("Could not write in the DB")?;
+        self.history
+            .flush_async()
+            .await
+            .context("Could not flush db")?;
+
+        // A failure is recorded for the latest state only.
+        if self
+            .failures
+            .remove(&key)
+            .context("Could not remove failure from the DB")?
+            .is_some()
+        {
+            self.failures
+                .flush_async()
+                .await
+                .context("Could not flush db")?;
+        }
+
         // TODO: see if this can be done through sled config
-        self.0
+        self.swaps
             .flush_async()
             .await
             .map(|_| ())
             .context("Could not flush db")
     }
 
-    pub fn get_state(&self, swap_id: Uuid) -> Result<Swap> {
+    fn get_state(&self, swap_id: Uuid) -> Result<Swap> {
         let key = serialize(&swap_id)?;
 
         let encoded = self
-            .0
+            .swaps
             .get(&key)?
             .ok_or_else(|| anyhow!("Swap with id {} not found in database", swap_id))?;
 
@@ -90,24 +684,263 @@ impl Database {
         Ok(state)
     }
 
-    pub fn all(&self) -> Result<Vec<(Uuid, Swap)>> {
-        self.0
+    fn all(&self) -> Result<Vec<(Uuid, Swap)>> {
+        let (swaps, unreadable) = self.read_all()?;
+
+        for swap in unreadable {
+            tracing::warn!("Skipping swap that cannot be read: {}", swap);
+        }
+
+        Ok(swaps)
+    }
+
+    fn unreadable(&self) -> Result<Vec<UnreadableSwap>> {
+        Ok(self.read_all()?.1)
+    }
+
+    async fn insert_counterparty(&self, swap_id: Uuid, counterparty: Counterparty) -> Result<()> {
+        let key = serialize(&swap_id)?;
+        let value = serialize(&(counterparty.peer_id.to_string(), counterparty.address))
+            .context("Could not serialize counterparty")?;
+
+        self.counterparties
+            .insert(key, value)
+            .context("Could not write in the DB")?;
+
+        self.counterparties
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    fn get_counterparty(&self, swap_id: Uuid) -> Result<Option<Counterparty>> {
+        let key = serialize(&swap_id)?;
+
+        let encoded = match self.counterparties.get(&key)? {
+            Some(encoded) => encoded,
+            None => return Ok(None),
+        };
+
+        let (peer_id, address) = deserialize::<(String, Multiaddr)>(&encoded)
+            .context("Could not deserialize counterparty")?;
+        let peer_id = peer_id
+            .parse()
+            .with_context(|| format!("Stored peer id {} is invalid", peer_id))?;
+
+        Ok(Some(Counterparty { peer_id, address }))
+    }
+
+    async fn insert_peer_id(&self, swap_id: Uuid, peer_id: PeerId) -> Result<()> {
+        let key = serialize(&swap_id)?;
+        let value = serialize(&peer_id.to_string()).context("Could not serialize peer id")?;
+
+        self.peers
+            .insert(key, value)
+            .context("Could not write in the DB")?;
+
+        self.peers
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    fn get_peer_id(&self, swap_id: Uuid) -> Result<Option<PeerId>> {
+        let key = serialize(&swap_id)?;
+
+        let encoded = match self.peers.get(&key)? {
+            Some(encoded) => encoded,
+            None => return Ok(None),
+        };
+
+        let peer_id = deserialize::<String>(&encoded).context("Could not deserialize peer id")?;
+        let peer_id = peer_id
+            .parse()
+            .with_context(|| format!("Stored peer id {} is invalid", peer_id))?;
+
+        Ok(Some(peer_id))
+    }
+
+    async fn insert_monero_subaddress(&self, swap_id: Uuid, index: u32) -> Result<()> {
+        let key = serialize(&swap_id)?;
+        let value = serialize(&index).context("Could not serialize subaddress index")?;
+
+        self.monero_subaddresses
+            .insert(key, value)
+            .context("Could not write in the DB")?;
+
+        self.monero_subaddresses
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    fn get_monero_subaddress(&self, swap_id: Uuid) -> Result<Option<u32>> {
+        let key = serialize(&swap_id)?;
+
+        let encoded = match self.monero_subaddresses.get(&key)? {
+            Some(encoded) => encoded,
+            None => return Ok(None),
+        };
+
+        let index =
+            deserialize::<u32>(&encoded).context("Could not deserialize subaddress index")?;
+
+        Ok(Some(index))
+    }
+
+    fn state_history(&self, swap_id: Uuid) -> Result<Vec<Transition>> {
+        self.history
+            .scan_prefix(swap_id.as_bytes())
+            .values()
+            .map(|value| {
+                let value = value.context("Failed to retrieve transition from DB")?;
+                let (timestamp, state) = deserialize::<(i64, Swap)>(&value)
+                    .context("Could not deserialize transition")?;
+
+                Ok(Transition {
+                    timestamp: OffsetDateTime::from_unix_timestamp(timestamp),
+                    state,
+                })
+            })
+            .collect()
+    }
+
+    async fn insert_quote(&self, quote: Quote, retention: QuoteRetention) -> Result<()> {
+        // Ordered by a monotonic id so that iterating yields the oldest first.
+        let key = self.swaps.generate_id()?.to_be_bytes();
+        let value = serialize(&StoredQuote::from(&quote)).context("Could not serialize quote")?;
+
+        self.quotes
+            .insert(key, value)
+            .context("Could not write in the DB")?;
+
+        let oldest_allowed = (quote.timestamp - retention.max_age).unix_timestamp();
+        let mut excess = self.quotes.len().saturating_sub(retention.max_count);
+
+        for item in self.quotes.iter() {
+            let (key, value) = item.context("Failed to retrieve quote from DB")?;
+            let stored =
+                deserialize::<StoredQuote>(&value).context("Could not deserialize quote")?;
+
+            if excess == 0 && stored.timestamp >= oldest_allowed {
+                break;
+            }
+
+            self.quotes
+                .remove(key)
+                .context("Could not remove quote from the DB")?;
+            excess = excess.saturating_sub(1);
+        }
+
+        self.quotes
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    fn quotes(&self) -> Result<Vec<Quote>> {
+        self.quotes
             .iter()
-            .map(|item| match item {
-                Ok((key, value)) => {
-                    let swap_id = deserialize::<Uuid>(&key);
-                    let swap = deserialize::<Swap>(&value).context("Failed to deserialize swap");
-
-                    match (swap_id, swap) {
-                        (Ok(swap_id), Ok(swap)) => Ok((swap_id, swap)),
-                        (Ok(_), Err(err)) => Err(err),
-                        _ => bail!("Failed to deserialize swap"),
-                    }
-                }
-                Err(err) => Err(err).context("Failed to retrieve swap from DB"),
+            .values()
+            .map(|value| {
+                let value = value.context("Failed to retrieve quote from DB")?;
+                let stored =
+                    deserialize::<StoredQuote>(&value).context("Could not deserialize quote")?;
+
+                stored.into_quote()
             })
             .collect()
     }
+
+    async fn insert_failure(&self, swap_id: Uuid, failure: Failure) -> Result<()> {
+        let key = serialize(&swap_id)?;
+        let value = serialize(&(
+            failure.timestamp.unix_timestamp(),
+            failure.category,
+            failure.message,
+        ))
+        .context("Could not serialize failure")?;
+
+        self.failures
+            .insert(key, value)
+            .context("Could not write in the DB")?;
+
+        self.failures
+            .flush_async()
+            .await
+            .map(|_| ())
+            .context("Could not flush db")
+    }
+
+    fn get_failure(&self, swap_id: Uuid) -> Result<Option<Failure>> {
+        let key = serialize(&swap_id)?;
+
+        let encoded = match self.failures.get(&key)? {
+            Some(encoded) => encoded,
+            None => return Ok(None),
+        };
+
+        let (timestamp, category, message) = deserialize::<(i64, String, String)>(&encoded)
+            .context("Could not deserialize failure")?;
+
+        Ok(Some(Failure {
+            timestamp: OffsetDateTime::from_unix_timestamp(timestamp),
+            category,
+            message,
+        }))
+    }
+
+    fn bitcoin_transactions(&self, swap_id: Uuid) -> Result<SwapTransactions> {
+        let key = serialize(&swap_id)?;
+
+        match self.recorded_transactions(&key)? {
+            Some(transactions) => Ok(transactions),
+            // Swaps stored before transactions were recorded
+            None => Ok(SwapTransactions::from(&self.get_state(swap_id)?)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredQuote {
+    timestamp: i64,
+    peer_id: String,
+    btc_sat: u64,
+    xmr_piconero: u64,
+    ask_sat: u64,
+}
+
+impl From<&Quote> for StoredQuote {
+    fn from(quote: &Quote) -> Self {
+        StoredQuote {
+            timestamp: quote.timestamp.unix_timestamp(),
+            peer_id: quote.peer_id.to_string(),
+            btc_sat: quote.btc.as_sat(),
+            xmr_piconero: quote.xmr.as_piconero(),
+            ask_sat: quote.ask.as_sat(),
+        }
+    }
+}
+
+impl StoredQuote {
+    fn into_quote(self) -> Result<Quote> {
+        let peer_id = self
+            .peer_id
+            .parse()
+            .with_context(|| format!("Stored peer id {} is invalid", self.peer_id))?;
+
+        Ok(Quote {
+            timestamp: OffsetDateTime::from_unix_timestamp(self.timestamp),
+            peer_id,
+            btc: bitcoin::Amount::from_sat(self.btc_sat),
+            xmr: monero::Amount::from_piconero(self.xmr_piconero),
+            ask: bitcoin::Amount::from_sat(self.ask_sat),
+        })
+    }
 }
 
 pub fn serialize<T>(t: &T) -> Result<Vec<u8>>
@@ -127,8 +960,19 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bitcoin::mock::MockWallet;
+    use crate::bitcoin::wallet::ScriptStatus;
     use crate::database::alice::{Alice, AliceEndState};
     use crate::database::bob::{Bob, BobEndState};
+    use crate::env::{GetConfig, Regtest};
+    use crate::monero::wallet::MoneroWallet;
+    use crate::protocol::bob::{
+        swap, AutoAccept, EventLoopHandle, NoopNotifier, RefundAddressPolicy,
+    };
+    use crate::protocol::execution_setup;
+    use ::bitcoin::hashes::Hash;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
 
     #[tokio::test]
     async fn can_write_and_read_to_multiple_keys() {
@@ -209,4 +1053,508 @@ mod tests {
         assert!(swaps.contains(&(swap_id_1, state_1)));
         assert!(swaps.contains(&(swap_id_2, state_2)));
     }
+
+    #[tokio::test]
+    async fn quotes_beyond_retention_are_evicted() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+        let retention = QuoteRetention {
+            max_count: 2,
+            max_age: Duration::from_secs(3600),
+        };
+        let quote = |timestamp| Quote {
+            timestamp: OffsetDateTime::from_unix_timestamp(timestamp),
+            peer_id: PeerId::random(),
+            btc: bitcoin::Amount::from_sat(100_000),
+            xmr: monero::Amount::from_piconero(40_000_000_000),
+            ask: bitcoin::Amount::from_sat(250_000),
+        };
+
+        let first = quote(1_600_000_000);
+        let second = quote(1_600_000_060);
+        let third = quote(1_600_000_120);
+        for quote in vec![first, second.clone(), third.clone()] {
+            db.insert_quote(quote, retention).await.unwrap();
+        }
+        assert_eq!(db.quotes().unwrap(), vec![second, third]);
+
+        let much_later = quote(1_600_000_120 + 3601);
+        db.insert_quote(much_later.clone(), retention)
+            .await
+            .unwrap();
+        assert_eq!(db.quotes().unwrap(), vec![much_later]);
+    }
+
+    #[tokio::test]
+    async fn unreadable_swaps_are_skipped() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let store = SledStore::open(db_dir.path()).unwrap();
+
+        let corrupt_swap_id = Uuid::new_v4();
+        store
+            .swaps
+            .insert(serialize(&corrupt_swap_id).unwrap(), &b"corrupt"[..])
+            .unwrap();
+        let db = Database::new(store);
+
+        let state = Swap::Bob(Bob::Done(BobEndState::SafelyAborted));
+        let swap_id = Uuid::new_v4();
+        db.insert_latest_state(swap_id, state.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(db.all().unwrap(), vec![(swap_id, state)]);
+        let unreadable = db.unreadable().unwrap();
+        assert_eq!(unreadable.len(), 1);
+        assert_eq!(unreadable[0].swap_id, Some(corrupt_swap_id));
+    }
+
+    #[test]
+    fn swaps_of_older_schema_versions_are_migrated_on_open() {
+        use serde_cbor::Value;
+
+        fn started(amount_field: &str) -> Value {
+            let text = |text: &str| Value::Text(text.to_owned());
+            let map = |key: &str, value| Value::Map(vec![(text(key), value)].into_iter().collect());
+
+            map(
+                "Bob",
+                map("Started", map(amount_field, Value::Integer(100_000))),
+            )
+        }
+
+        fn field<'a>(value: &'a mut Value, name: &str) -> Option<&'a mut Value> {
+            match value {
+                Value::Map(map) => map.get_mut(&Value::Text(name.to_owned())),
+                _ => None,
+            }
+        }
+
+        // A schema version 1 that stored the amount of started swaps under a
+        // different name.
+        fn rename_amount(mut swap: Value) -> Result<Value> {
+            let started = field(&mut swap, "Bob").and_then(|bob| field(bob, "Started"));
+            if let Some(Value::Map(started)) = started {
+                if let Some(amount) = started.remove(&Value::Text("amount_sat".to_owned())) {
+                    started.insert(Value::Text("btc_amount".to_owned()), amount);
+                }
+            }
+
+            Ok(swap)
+        }
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let swap_id = Uuid::new_v4();
+        {
+            let db = sled::open(db_dir.path()).unwrap();
+            let old_swap = started("amount_sat");
+            db.insert(serialize(&swap_id).unwrap(), serialize(&old_swap).unwrap())
+                .unwrap();
+            let mut history_key = swap_id.as_bytes().to_vec();
+            history_key.extend_from_slice(&0u64.to_be_bytes());
+            db.open_tree("history")
+                .unwrap()
+                .insert(history_key, serialize(&(0i64, old_swap)).unwrap())
+                .unwrap();
+            db.open_tree("meta")
+                .unwrap()
+                .insert(SCHEMA_VERSION_KEY, serialize(&1u32).unwrap())
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let db = Database::new(
+            SledStore::open_with_migrations(db_dir.path(), &[unversioned_to_v1, rename_amount])
+                .unwrap(),
+        );
+
+        let expected = Swap::Bob(Bob::Started {
+            btc_amount: ::bitcoin::Amount::from_sat(100_000),
+        });
+        assert_eq!(db.all().unwrap(), vec![(swap_id, expected.clone())]);
+        assert_eq!(db.state_history(swap_id).unwrap()[0].state, expected);
+        drop(db);
+
+        let db = SledStore::open_with_migrations(db_dir.path(), &[unversioned_to_v1]);
+        assert!(
+            db.is_err(),
+            "opening a database of a newer schema version must fail"
+        );
+    }
+
+    #[test]
+    fn corrupt_transitions_are_skipped_when_migrating() {
+        use serde_cbor::Value;
+
+        fn reject_text(swap: Value) -> Result<Value> {
+            match swap {
+                Value::Text(_) => bail!("Unexpected text"),
+                swap => Ok(swap),
+            }
+        }
+
+        let history_key = |swap_id: Uuid| {
+            let mut key = swap_id.as_bytes().to_vec();
+            key.extend_from_slice(&0u64.to_be_bytes());
+            key
+        };
+
+        let db_dir = tempfile::tempdir().unwrap();
+        let swap_id = Uuid::new_v4();
+        let swap = Swap::Bob(Bob::Started {
+            btc_amount: ::bitcoin::Amount::from_sat(100_000),
+        });
+        {
+            let db = sled::open(db_dir.path()).unwrap();
+            db.insert(serialize(&swap_id).unwrap(), serialize(&swap).unwrap())
+                .unwrap();
+            let history = db.open_tree("history").unwrap();
+            history
+                .insert(history_key(swap_id), serialize(&(0i64, &swap)).unwrap())
+                .unwrap();
+            history
+                .insert(history_key(Uuid::new_v4()), vec![0xff, 0x00])
+                .unwrap();
+            history
+                .insert(
+                    history_key(Uuid::new_v4()),
+                    serialize(&(0i64, Value::Text("corrupt".to_owned()))).unwrap(),
+                )
+                .unwrap();
+            db.open_tree("meta")
+                .unwrap()
+                .insert(SCHEMA_VERSION_KEY, serialize(&1u32).unwrap())
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let db = SledStore::open_with_migrations(db_dir.path(), &[unversioned_to_v1, reject_text])
+            .unwrap();
+
+        assert_eq!(db.schema_version().unwrap(), Some(2));
+        assert_eq!(db.state_history(swap_id).unwrap()[0].state, swap);
+    }
+
+    #[tokio::test]
+    async fn failure_is_recorded_until_the_next_transition() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        let swap_id = Uuid::new_v4();
+        let started = Swap::Bob(
+            BobState::Started {
+                btc_amount: ::bitcoin::Amount::from_sat(100_000),
+            }
+            .into(),
+        );
+        db.insert_latest_state(swap_id, started).await.unwrap();
+
+        let error = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .context("Failed to dial Alice");
+        db.insert_failure(swap_id, Failure::new(&error))
+            .await
+            .unwrap();
+
+        let failure = db.get_failure(swap_id).unwrap().unwrap();
+        assert_eq!(failure.category, "network");
+        assert_eq!(
+            failure.to_string(),
+            "network error: Failed to dial Alice: connection refused"
+        );
+        assert_eq!(db.get_failure(Uuid::new_v4()).unwrap(), None);
+
+        db.insert_latest_state(swap_id, Swap::Bob(Bob::Done(BobEndState::SafelyAborted)))
+            .await
+            .unwrap();
+        assert_eq!(db.get_failure(swap_id).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn state_of_a_different_swap_is_not_overwritten() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+        let swap_id = db.new_swap_id();
+
+        let stored = Swap::Bob(
+            BobState::XmrRedeemed {
+                tx_lock_id: ::bitcoin::Txid::from_inner([1u8; 32]),
+            }
+            .into(),
+        );
+        db.insert_latest_state(swap_id, stored.clone())
+            .await
+            .unwrap();
+
+        let other_lineage = Swap::Bob(
+            BobState::XmrRedeemed {
+                tx_lock_id: ::bitcoin::Txid::from_inner([2u8; 32]),
+            }
+            .into(),
+        );
+        let error = db
+            .insert_latest_state(swap_id, other_lineage)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ConflictingSwap>());
+
+        let other_role = Swap::Alice(Alice::Done(AliceEndState::SafelyAborted));
+        let error = db
+            .insert_latest_state(swap_id, other_role)
+            .await
+            .unwrap_err();
+        assert!(error.is::<ConflictingSwap>());
+
+        assert_eq!(db.get_state(swap_id).unwrap(), stored);
+        assert_ne!(db.new_swap_id(), swap_id);
+    }
+
+    #[tokio::test]
+    async fn transactions_of_earlier_states_are_kept() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let swap_id = Uuid::new_v4();
+        let tx_lock_id = ::bitcoin::Txid::from_inner([1u8; 32]);
+
+        {
+            let db = Database::open(db_dir.path()).unwrap();
+            db.insert_latest_state(
+                swap_id,
+                Swap::Bob(BobState::XmrRedeemed { tx_lock_id }.into()),
+            )
+            .await
+            .unwrap();
+            db.insert_latest_state(swap_id, Swap::Bob(Bob::Done(BobEndState::SafelyAborted)))
+                .await
+                .unwrap();
+        }
+
+        let db = Database::open(db_dir.path()).unwrap();
+        let transactions = db.bitcoin_transactions(swap_id).unwrap();
+
+        assert_eq!(transactions.lock, Some(tx_lock_id));
+        assert_eq!(transactions.labeled(), vec![(
+            TransactionKind::Lock,
+            tx_lock_id
+        )]);
+    }
+
+    #[derive(Default)]
+    struct InMemoryStore {
+        swaps: Mutex<HashMap<Uuid, Swap>>,
+        counterparties: Mutex<HashMap<Uuid, Counterparty>>,
+        peers: Mutex<HashMap<Uuid, PeerId>>,
+        monero_subaddresses: Mutex<HashMap<Uuid, u32>>,
+    }
+
+    #[async_trait]
+    impl SwapStore for InMemoryStore {
+        async fn insert_latest_state(&self, swap_id: Uuid, state: Swap) -> Result<()> {
+            self.swaps.lock().unwrap().insert(swap_id, state);
+
+            Ok(())
+        }
+
+        fn get_state(&self, swap_id: Uuid) -> Result<Swap> {
+            self.swaps
+                .lock()
+                .unwrap()
+                .get(&swap_id)
+                .cloned()
+                .ok_or_else(|| anyhow!("Swap with id {} not found in database", swap_id))
+        }
+
+        fn all(&self) -> Result<Vec<(Uuid, Swap)>> {
+            Ok(self
+                .swaps
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(swap_id, swap)| (*swap_id, swap.clone()))
+                .collect())
+        }
+
+        async fn insert_counterparty(
+            &self,
+            swap_id: Uuid,
+            counterparty: Counterparty,
+        ) -> Result<()> {
+            self.counterparties
+                .lock()
+                .unwrap()
+                .insert(swap_id, counterparty);
+
+            Ok(())
+        }
+
+        fn get_counterparty(&self, swap_id: Uuid) -> Result<Option<Counterparty>> {
+            Ok(self.counterparties.lock().unwrap().get(&swap_id).cloned())
+        }
+
+        async fn insert_peer_id(&self, swap_id: Uuid, peer_id: PeerId) -> Result<()> {
+            self.peers.lock().unwrap().insert(swap_id, peer_id);
+
+            Ok(())
+        }
+
+        fn get_peer_id(&self, swap_id: Uuid) -> Result<Option<PeerId>> {
+            Ok(self.peers.lock().unwrap().get(&swap_id).cloned())
+        }
+
+        async fn insert_monero_subaddress(&self, swap_id: Uuid, index: u32) -> Result<()> {
+            self.monero_subaddresses
+                .lock()
+                .unwrap()
+                .insert(swap_id, index);
+
+            Ok(())
+        }
+
+        fn get_monero_subaddress(&self, swap_id: Uuid) -> Result<Option<u32>> {
+            Ok(self
+                .monero_subaddresses
+                .lock()
+                .unwrap()
+                .get(&swap_id)
+                .cloned())
+        }
+    }
+
+    #[tokio::test]
+    async fn can_write_and_read_peer_id() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        let swap_id = Uuid::new_v4();
+        let peer_id = PeerId::random();
+
+        assert_eq!(db.get_peer_id(swap_id).unwrap(), None);
+
+        db.insert_peer_id(swap_id, peer_id).await.unwrap();
+
+        assert_eq!(db.get_peer_id(swap_id).unwrap(), Some(peer_id));
+    }
+
+    #[tokio::test]
+    async fn can_write_and_read_monero_subaddress() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        let swap_id = Uuid::new_v4();
+
+        assert_eq!(db.get_monero_subaddress(swap_id).unwrap(), None);
+
+        db.insert_monero_subaddress(swap_id, 3).await.unwrap();
+
+        assert_eq!(db.get_monero_subaddress(swap_id).unwrap(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn can_write_and_read_counterparty() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        let swap_id = Uuid::new_v4();
+        let counterparty = Counterparty {
+            peer_id: PeerId::random(),
+            address: "/ip4/127.0.0.1/tcp/9939".parse().unwrap(),
+        };
+
+        assert_eq!(db.get_counterparty(swap_id).unwrap(), None);
+
+        db.insert_counterparty(swap_id, counterparty.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(db.get_counterparty(swap_id).unwrap(), Some(counterparty));
+        assert!(db.all().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn transitions_are_recorded_in_order() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        let swap_id = Uuid::new_v4();
+        let other_swap_id = Uuid::new_v4();
+        let started = Swap::Bob(
+            BobState::Started {
+                btc_amount: ::bitcoin::Amount::from_sat(100_000),
+            }
+            .into(),
+        );
+        let aborted = Swap::Bob(Bob::Done(BobEndState::SafelyAborted));
+
+        db.insert_latest_state(swap_id, started.clone())
+            .await
+            .unwrap();
+        db.insert_latest_state(other_swap_id, started.clone())
+            .await
+            .unwrap();
+        db.insert_latest_state(swap_id, aborted.clone())
+            .await
+            .unwrap();
+
+        let states = db
+            .state_history(swap_id)
+            .unwrap()
+            .into_iter()
+            .map(|transition| transition.state)
+            .collect::<Vec<_>>();
+
+        assert_eq!(states, vec![started, aborted]);
+        assert_eq!(db.state_history(other_swap_id).unwrap().len(), 1);
+        assert!(db.state_history(Uuid::new_v4()).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn swap_can_be_driven_to_completion_with_custom_store() {
+        let db = Database::new(InMemoryStore::default());
+        let swap_id = Uuid::new_v4();
+        let bitcoin_wallet = Arc::new(MockWallet::default());
+        let monero_wallet = Arc::new(monero::mock::MockWallet::default());
+
+        let (_, state2) = execution_setup(bitcoin_wallet.as_ref()).await;
+        let (state3, _) = state2.lock_btc().await.unwrap();
+        let state6 = state3.cancel();
+        bitcoin_wallet.set_status(
+            state6.tx_lock_id(),
+            ScriptStatus::from_confirmations(Regtest::CANCEL_TIMELOCK),
+        );
+        bitcoin_wallet.set_status(state6.tx_cancel_id(), ScriptStatus::from_confirmations(1));
+        let tx_refund_id = state6.tx_refund_id();
+        db.insert_latest_state(swap_id, Swap::Bob(BobState::BtcCancelled(state6).into()))
+            .await
+            .unwrap();
+
+        let resumed = BobState::from(db.get_state(swap_id).unwrap().try_into_bob().unwrap());
+        let receive_monero_address = monero_wallet.get_main_address();
+        let state = swap::run_until_with_wallets(
+            resumed,
+            swap::is_complete,
+            EventLoopHandle::connected(),
+            db.clone(),
+            bitcoin_wallet.clone(),
+            monero_wallet,
+            swap_id,
+            Regtest::get_config(),
+            receive_monero_address,
+            Arc::new(NoopNotifier),
+            Arc::new(AutoAccept),
+            None,
+            RefundAddressPolicy::Fresh,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(state, BobState::BtcRefunded(_)));
+        assert_eq!(
+            bitcoin_wallet.published().last(),
+            Some(&("refund".to_owned(), tx_refund_id))
+        );
+        let swaps = db.all().unwrap();
+        assert_eq!(swaps.len(), 1);
+        assert!(matches!(
+            BobState::from(swaps[0].1.clone().try_into_bob().unwrap()),
+            BobState::BtcRefunded(_)
+        ));
+    }
 }
@@ -54,6 +54,7 @@ pub enum AliceEndState {
     BtcRedeemed,
     XmrRefunded,
     BtcPunished,
+    BtcPunishDeclined,
 }
 
 impl From<&AliceState> for Alice {
@@ -116,6 +117,7 @@ impl From<&AliceState> for Alice {
                 state3: state3.as_ref().clone(),
             },
             AliceState::BtcPunished => Alice::Done(AliceEndState::BtcPunished),
+            AliceState::BtcPunishDeclined => Alice::Done(AliceEndState::BtcPunishDeclined),
             AliceState::SafelyAborted => Alice::Done(AliceEndState::SafelyAborted),
         }
     }
@@ -182,6 +184,7 @@ impl From<Alice> for AliceState {
                 AliceEndState::BtcRedeemed => AliceState::BtcRedeemed,
                 AliceEndState::XmrRefunded => AliceState::XmrRefunded,
                 AliceEndState::BtcPunished => AliceState::BtcPunished,
+                AliceEndState::BtcPunishDeclined => AliceState::BtcPunishDeclined,
             },
         }
     }
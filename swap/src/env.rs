@@ -1,19 +1,80 @@
 use crate::bitcoin::{CancelTimelock, PunishTimelock};
+use crate::monero::{Amount, PICONERO_OFFSET};
 use std::cmp::max;
 use std::time::Duration;
 use time::NumericalStdDurationShort;
 
+/// The plausible exchange rate band is the same on every network, quotes on
+/// regtest follow the same ASB pricing as on mainnet.
+const BOB_MIN_XMR_PER_BTC: Amount = Amount::from_piconero(10 * PICONERO_OFFSET);
+const BOB_MAX_XMR_PER_BTC: Amount = Amount::from_piconero(10_000 * PICONERO_OFFSET);
+
 #[derive(Debug, Copy, Clone)]
 pub struct Config {
     pub bob_time_to_act: Duration,
+    pub bob_transfer_proof_grace_period: Duration,
+    /// How long Alice waits for Bob to publish the Bitcoin lock transaction of
+    /// a new swap before safely aborting it and releasing its Monero.
+    pub bitcoin_lock_deadline: Duration,
+    /// The lowest and highest quote per BTC Bob considers plausible. Quotes
+    /// outside of this band are rejected before setting up the swap.
+    pub bob_min_xmr_per_btc: Amount,
+    pub bob_max_xmr_per_btc: Amount,
     pub bitcoin_finality_confirmations: u32,
     pub bitcoin_avg_block_time: Duration,
     pub bitcoin_cancel_timelock: CancelTimelock,
     pub bitcoin_punish_timelock: PunishTimelock,
     pub bitcoin_network: bitcoin::Network,
+    /// How the Bitcoin wallet talks to the electrum server.
+    pub bitcoin_electrum: ElectrumConfig,
+    /// How often the counterparty is pinged to keep an idle connection alive.
+    pub network_keepalive_interval: Duration,
+    /// How long to wait for a ping to be answered before the connection is
+    /// considered dead.
+    pub network_keepalive_timeout: Duration,
+    /// How long a connection to a peer without a swap in flight is kept open
+    /// after the peer last sent a request.
+    pub network_idle_timeout: Duration,
     pub monero_avg_block_time: Duration,
     pub monero_finality_confirmations: u32,
     pub monero_network: monero::Network,
+    /// How long to wait for the monero-wallet-rpc to answer while watching for
+    /// a transfer before it is considered stalled.
+    pub monero_rpc_timeout: Duration,
+}
+
+/// How the Bitcoin wallet talks to the electrum server.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ElectrumConfig {
+    pub max_requests_per_second: u32,
+    /// How often a batch of script histories is requested again if the
+    /// electrum server fails or answers incompletely.
+    pub history_retries: u32,
+    /// How long to wait before requesting a batch of script histories again.
+    pub history_retry_delay: Duration,
+    /// How many consecutive updates have to report an empty history for a
+    /// script with a confirmed transaction before the confirmation is
+    /// dropped, a single server out of sync must not reset the status.
+    pub empty_history_polls: u32,
+    /// The maximum number of scripts whose histories are requested in a single
+    /// batch, some electrum servers reject larger ones.
+    pub max_batch_size: usize,
+    /// How many script histories are kept at most. Beyond that, the least
+    /// recently requested ones that nobody is waiting on are evicted.
+    pub max_watched_scripts: usize,
+    /// How long to wait for an electrum server to answer before giving up on
+    /// the request, some servers accept connections but never respond.
+    pub timeout: Duration,
+    /// The number of connections to the electrum server, concurrent status
+    /// checks are spread across them.
+    pub pool_size: usize,
+    /// How long the status of a transaction is answered from the cache, as
+    /// long as no new block arrived.
+    pub status_cache_window: Duration,
+    /// The interval at which the status of a transaction is polled is randomly
+    /// extended by up to this much, so that concurrent swaps spread their
+    /// requests to the electrum server over time.
+    pub status_poll_jitter: Duration,
 }
 
 impl Config {
@@ -36,21 +97,84 @@ pub struct Mainnet;
 #[derive(Clone, Copy)]
 pub struct Testnet;
 
+/// Short timelocks and single-confirmation finality for tests and local
+/// development against a regtest node.
 #[derive(Clone, Copy)]
 pub struct Regtest;
 
+impl Regtest {
+    pub const CANCEL_TIMELOCK: u32 = 100;
+    pub const PUNISH_TIMELOCK: u32 = 50;
+
+    /// The regtest config with custom timelocks, e.g. to quickly run into the
+    /// cancel or punish scenario.
+    pub fn with_timelocks(cancel_timelock: u32, punish_timelock: u32) -> Config {
+        Config {
+            bob_time_to_act: 30.seconds(),
+            bob_transfer_proof_grace_period: 5.seconds(),
+            bitcoin_lock_deadline: 30.seconds(),
+            bob_min_xmr_per_btc: BOB_MIN_XMR_PER_BTC,
+            bob_max_xmr_per_btc: BOB_MAX_XMR_PER_BTC,
+            bitcoin_finality_confirmations: 1,
+            bitcoin_avg_block_time: 5.seconds(),
+            bitcoin_cancel_timelock: CancelTimelock::new(cancel_timelock),
+            bitcoin_punish_timelock: PunishTimelock::new(punish_timelock),
+            bitcoin_network: bitcoin::Network::Regtest,
+            bitcoin_electrum: ElectrumConfig {
+                max_requests_per_second: 100,
+                history_retries: 3,
+                history_retry_delay: 100.milliseconds(),
+                empty_history_polls: 2,
+                max_batch_size: usize::MAX,
+                max_watched_scripts: 1_000,
+                timeout: 30.seconds(),
+                pool_size: 2,
+                status_cache_window: 1.seconds(),
+                status_poll_jitter: 1.seconds(),
+            },
+            network_keepalive_interval: 15.seconds(),
+            network_keepalive_timeout: 20.seconds(),
+            network_idle_timeout: 5.minutes(),
+            monero_avg_block_time: 1.seconds(),
+            monero_finality_confirmations: 10,
+            monero_network: monero::Network::Mainnet, // yes this is strange
+            monero_rpc_timeout: 30.seconds(),
+        }
+    }
+}
+
 impl GetConfig for Mainnet {
     fn get_config() -> Config {
         Config {
             bob_time_to_act: 10.minutes(),
+            bob_transfer_proof_grace_period: 30.seconds(),
+            bitcoin_lock_deadline: 10.minutes(),
+            bob_min_xmr_per_btc: BOB_MIN_XMR_PER_BTC,
+            bob_max_xmr_per_btc: BOB_MAX_XMR_PER_BTC,
             bitcoin_finality_confirmations: 3,
             bitcoin_avg_block_time: 10.minutes(),
             bitcoin_cancel_timelock: CancelTimelock::new(72),
             bitcoin_punish_timelock: PunishTimelock::new(72),
             bitcoin_network: bitcoin::Network::Bitcoin,
+            bitcoin_electrum: ElectrumConfig {
+                max_requests_per_second: 10,
+                history_retries: 3,
+                history_retry_delay: 1.seconds(),
+                empty_history_polls: 3,
+                max_batch_size: usize::MAX,
+                max_watched_scripts: 1_000,
+                timeout: 30.seconds(),
+                pool_size: 3,
+                status_cache_window: 10.seconds(),
+                status_poll_jitter: 2.seconds(),
+            },
+            network_keepalive_interval: 15.seconds(),
+            network_keepalive_timeout: 20.seconds(),
+            network_idle_timeout: 5.minutes(),
             monero_avg_block_time: 2.minutes(),
             monero_finality_confirmations: 15,
             monero_network: monero::Network::Mainnet,
+            monero_rpc_timeout: 2.minutes(),
         }
     }
 }
@@ -59,31 +183,41 @@ impl GetConfig for Testnet {
     fn get_config() -> Config {
         Config {
             bob_time_to_act: 60.minutes(),
+            bob_transfer_proof_grace_period: 30.seconds(),
+            bitcoin_lock_deadline: 60.minutes(),
+            bob_min_xmr_per_btc: BOB_MIN_XMR_PER_BTC,
+            bob_max_xmr_per_btc: BOB_MAX_XMR_PER_BTC,
             bitcoin_finality_confirmations: 1,
             bitcoin_avg_block_time: 5.minutes(),
             bitcoin_cancel_timelock: CancelTimelock::new(12),
             bitcoin_punish_timelock: PunishTimelock::new(6),
             bitcoin_network: bitcoin::Network::Testnet,
+            bitcoin_electrum: ElectrumConfig {
+                max_requests_per_second: 10,
+                history_retries: 3,
+                history_retry_delay: 1.seconds(),
+                empty_history_polls: 3,
+                max_batch_size: usize::MAX,
+                max_watched_scripts: 1_000,
+                timeout: 30.seconds(),
+                pool_size: 3,
+                status_cache_window: 10.seconds(),
+                status_poll_jitter: 2.seconds(),
+            },
+            network_keepalive_interval: 15.seconds(),
+            network_keepalive_timeout: 20.seconds(),
+            network_idle_timeout: 5.minutes(),
             monero_avg_block_time: 2.minutes(),
             monero_finality_confirmations: 10,
             monero_network: monero::Network::Stagenet,
+            monero_rpc_timeout: 2.minutes(),
         }
     }
 }
 
 impl GetConfig for Regtest {
     fn get_config() -> Config {
-        Config {
-            bob_time_to_act: 30.seconds(),
-            bitcoin_finality_confirmations: 1,
-            bitcoin_avg_block_time: 5.seconds(),
-            bitcoin_cancel_timelock: CancelTimelock::new(100),
-            bitcoin_punish_timelock: PunishTimelock::new(50),
-            bitcoin_network: bitcoin::Network::Regtest,
-            monero_avg_block_time: 1.seconds(),
-            monero_finality_confirmations: 10,
-            monero_network: monero::Network::Mainnet, // yes this is strange
-        }
+        Regtest::with_timelocks(Regtest::CANCEL_TIMELOCK, Regtest::PUNISH_TIMELOCK)
     }
 }
 
@@ -108,4 +242,23 @@ mod tests {
 
         assert_eq!(interval, Duration::from_secs(10))
     }
+
+    #[test]
+    fn regtest_config_has_short_timelocks_and_single_confirmation_finality() {
+        let config = Regtest::get_config();
+
+        assert_eq!(config.bitcoin_cancel_timelock, CancelTimelock::new(100));
+        assert_eq!(config.bitcoin_punish_timelock, PunishTimelock::new(50));
+        assert_eq!(config.bitcoin_finality_confirmations, 1);
+        assert_eq!(config.bitcoin_network, bitcoin::Network::Regtest);
+    }
+
+    #[test]
+    fn regtest_timelocks_can_be_tuned() {
+        let config = Regtest::with_timelocks(1, 2);
+
+        assert_eq!(config.bitcoin_cancel_timelock, CancelTimelock::new(1));
+        assert_eq!(config.bitcoin_punish_timelock, PunishTimelock::new(2));
+        assert_eq!(config.bitcoin_finality_confirmations, 1);
+    }
 }
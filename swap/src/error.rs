@@ -0,0 +1,184 @@
+use crate::bitcoin::wallet::{AmountBelowDustThreshold, ElectrumServerBusy};
+use crate::monero::{BalanceTooLow, InsufficientFunds, RpcTimeout};
+use crate::protocol::bob::DialTimeout;
+
+/// The error returned by the public entry points of this crate.
+///
+/// Internally, errors are handled through [`anyhow`]. At the boundary, they are
+/// classified to allow callers to react to different failure modes, e.g. by
+/// retrying network errors.
+#[derive(Debug, thiserror::Error)]
+pub enum SwapError {
+    /// Communicating with a remote service (electrum, monero-wallet-rpc, the
+    /// counterparty) failed. Retrying later might succeed.
+    #[error("network error: {0:#}")]
+    Network(anyhow::Error),
+    /// One of the wallets failed to perform the requested operation.
+    #[error("wallet error: {0:#}")]
+    Wallet(anyhow::Error),
+    /// The swap protocol could not proceed.
+    #[error("protocol error: {0:#}")]
+    Protocol(anyhow::Error),
+    /// There are not enough funds to perform the requested operation.
+    #[error("insufficient funds: {0:#}")]
+    Funds(anyhow::Error),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Network,
+    Wallet,
+    Protocol,
+    Funds,
+}
+
+impl SwapError {
+    /// Classify the given error, treating it as a wallet error unless it has a
+    /// more specific cause.
+    pub(crate) fn wallet(error: anyhow::Error) -> Self {
+        let kind = classify(&error).unwrap_or(Kind::Wallet);
+
+        Self::new(kind, error)
+    }
+
+    fn new(kind: Kind, error: anyhow::Error) -> Self {
+        match kind {
+            Kind::Network => SwapError::Network(error),
+            Kind::Wallet => SwapError::Wallet(error),
+            Kind::Protocol => SwapError::Protocol(error),
+            Kind::Funds => SwapError::Funds(error),
+        }
+    }
+
+    /// The name of the variant, e.g. `network`.
+    pub fn category(&self) -> &'static str {
+        self.kind().category()
+    }
+
+    fn kind(&self) -> Kind {
+        match self {
+            SwapError::Network(_) => Kind::Network,
+            SwapError::Wallet(_) => Kind::Wallet,
+            SwapError::Protocol(_) => Kind::Protocol,
+            SwapError::Funds(_) => Kind::Funds,
+        }
+    }
+}
+
+impl Kind {
+    fn category(self) -> &'static str {
+        match self {
+            Kind::Network => "network",
+            Kind::Wallet => "wallet",
+            Kind::Protocol => "protocol",
+            Kind::Funds => "funds",
+        }
+    }
+}
+
+/// The category of the [`SwapError`] the given error is classified as.
+pub(crate) fn category_of(error: &anyhow::Error) -> &'static str {
+    classify(error).unwrap_or(Kind::Protocol).category()
+}
+
+/// Classifies errors, treating them as protocol errors unless they have a more
+/// specific cause.
+impl From<anyhow::Error> for SwapError {
+    fn from(error: anyhow::Error) -> Self {
+        let kind = classify(&error).unwrap_or(Kind::Protocol);
+
+        Self::new(kind, error)
+    }
+}
+
+fn classify(error: &anyhow::Error) -> Option<Kind> {
+    error.chain().find_map(|cause| {
+        if let Some(swap_error) = cause.downcast_ref::<SwapError>() {
+            return Some(swap_error.kind());
+        }
+
+        if cause.is::<InsufficientFunds>()
+            || cause.is::<BalanceTooLow>()
+            || cause.is::<AmountBelowDustThreshold>()
+        {
+            return Some(Kind::Funds);
+        }
+
+        if cause.is::<reqwest::Error>()
+            || cause.is::<std::io::Error>()
+            || cause.is::<ElectrumServerBusy>()
+            || cause.is::<RpcTimeout>()
+            || cause.is::<DialTimeout>()
+        {
+            return Some(Kind::Network);
+        }
+
+        match cause.downcast_ref::<bdk::Error>() {
+            Some(bdk::Error::Electrum(_)) => return Some(Kind::Network),
+            Some(_) => return Some(Kind::Wallet),
+            None => {}
+        }
+
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monero;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn insufficient_funds_is_funds_error() {
+        let error = anyhow::Error::new(InsufficientFunds {
+            expected: monero::Amount::ONE_XMR,
+            actual: monero::Amount::ZERO,
+        })
+        .context("Failed to watch for transfer");
+
+        assert!(matches!(SwapError::from(error), SwapError::Funds(_)));
+    }
+
+    #[test]
+    fn io_error_is_network_error() {
+        let error = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .context("Failed to connect");
+
+        assert!(matches!(SwapError::from(error), SwapError::Network(_)));
+    }
+
+    #[test]
+    fn dial_timeout_is_network_error() {
+        let error = anyhow::Error::new(DialTimeout(std::time::Duration::from_secs(60)));
+
+        let error = SwapError::from(error);
+
+        assert!(matches!(error, SwapError::Network(_)));
+        assert_eq!(
+            error.to_string(),
+            "network error: Could not reach maker within 60s"
+        );
+    }
+
+    #[test]
+    fn nested_swap_error_keeps_its_variant() {
+        let error = Err::<(), _>(SwapError::Wallet(anyhow!("Failed to sign")))
+            .context("Failed to lock Bitcoin")
+            .unwrap_err();
+
+        assert!(matches!(SwapError::from(error), SwapError::Wallet(_)));
+    }
+
+    #[test]
+    fn unknown_error_defaults_to_protocol_or_wallet_error() {
+        assert!(matches!(
+            SwapError::from(anyhow!("Unexpected message")),
+            SwapError::Protocol(_)
+        ));
+        assert!(matches!(
+            SwapError::wallet(anyhow!("Failed to open database")),
+            SwapError::Wallet(_)
+        ));
+    }
+}
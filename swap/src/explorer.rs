@@ -0,0 +1,118 @@
+//! Links to transactions on block explorers, logged along with the ids of
+//! published transactions.
+
+use crate::{bitcoin, monero};
+use anyhow::{bail, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+const TXID_PLACEHOLDER: &str = "{txid}";
+
+/// The URL of a transaction on a block explorer, with `{txid}` in place of
+/// the id of the transaction, e.g. `https://mempool.space/testnet/tx/{txid}`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct ExplorerUrl(String);
+
+impl ExplorerUrl {
+    /// The default explorer for the given Bitcoin network, if there is a
+    /// public one.
+    pub fn bitcoin(network: bitcoin::Network) -> Option<Self> {
+        let template = match network {
+            bitcoin::Network::Bitcoin => "https://mempool.space/tx/{txid}",
+            bitcoin::Network::Testnet => "https://mempool.space/testnet/tx/{txid}",
+            _ => return None,
+        };
+
+        Some(Self(template.to_owned()))
+    }
+
+    /// The default explorer for the given Monero network.
+    pub fn monero(network: monero::Network) -> Self {
+        let template = match network {
+            monero::Network::Mainnet => "https://xmrchain.net/tx/{txid}",
+            monero::Network::Stagenet => "https://stagenet.xmrchain.net/tx/{txid}",
+            monero::Network::Testnet => "https://testnet.xmrchain.net/tx/{txid}",
+        };
+
+        Self(template.to_owned())
+    }
+
+    /// The link to the transaction with the given id.
+    pub fn transaction(&self, txid: impl fmt::Display) -> String {
+        self.0.replace(TXID_PLACEHOLDER, &txid.to_string())
+    }
+}
+
+impl FromStr for ExplorerUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if !s.contains(TXID_PLACEHOLDER) {
+            bail!(
+                "Explorer URL {} does not contain {} in place of the transaction id",
+                s,
+                TXID_PLACEHOLDER
+            );
+        }
+
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl TryFrom<String> for ExplorerUrl {
+    type Error = Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        template.parse()
+    }
+}
+
+impl From<ExplorerUrl> for String {
+    fn from(explorer: ExplorerUrl) -> Self {
+        explorer.0
+    }
+}
+
+impl fmt::Display for ExplorerUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_templates_link_to_the_transaction() {
+        let txid = "6f6b1bd39a0a2ec5e0b3f25e9c4d8e4b2f3a1c0d9e8f7a6b5c4d3e2f1a0b9c8d";
+
+        assert_eq!(
+            ExplorerUrl::bitcoin(bitcoin::Network::Testnet)
+                .unwrap()
+                .transaction(txid),
+            format!("https://mempool.space/testnet/tx/{}", txid)
+        );
+        assert_eq!(
+            ExplorerUrl::monero(monero::Network::Stagenet).transaction(txid),
+            format!("https://stagenet.xmrchain.net/tx/{}", txid)
+        );
+        assert_eq!(ExplorerUrl::bitcoin(bitcoin::Network::Regtest), None);
+    }
+
+    #[test]
+    fn template_without_placeholder_is_rejected() {
+        assert!("https://mempool.space/tx/".parse::<ExplorerUrl>().is_err());
+
+        let explorer = "https://explorer.example/{txid}?raw"
+            .parse::<ExplorerUrl>()
+            .unwrap();
+        assert_eq!(
+            explorer.transaction("abc"),
+            "https://explorer.example/abc?raw"
+        );
+    }
+}
@@ -1,16 +1,21 @@
 use crate::asb::Rate;
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use futures::{SinkExt, StreamExt, TryStreamExt};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::convert::{Infallible, TryFrom};
+use std::fmt;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::watch;
 
-/// Connect to Kraken websocket API for a constant stream of rate updates.
+/// Connect to Kraken websocket API for a constant stream of rate updates,
+/// derived from the given pairs.
 ///
 /// If the connection fails, it will automatically be re-established.
-pub fn connect() -> Result<RateUpdateStream> {
+pub fn connect(pairs: Pairs) -> Result<RateUpdateStream> {
     let (rate_update, rate_update_receiver) = watch::channel(Err(Error::NotYetAvailable));
     let rate_update = Arc::new(rate_update);
 
@@ -27,8 +32,9 @@ pub fn connect() -> Result<RateUpdateStream> {
             backoff,
             || {
                 let rate_update = rate_update.clone();
+                let pairs = pairs.clone();
                 async move {
-                    let mut stream = connection::new().await?;
+                    let mut stream = connection::new(pairs).await?;
 
                     while let Some(update) = stream.try_next().await.map_err(to_backoff)? {
                         let send_result = rate_update.send(Ok(update));
@@ -65,6 +71,110 @@ pub fn connect() -> Result<RateUpdateStream> {
     })
 }
 
+/// The Kraken pairs the XMR/BTC rate is derived from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pairs {
+    /// The XMR/XBT pair.
+    XmrBtc,
+    /// The pairs of XMR and XBT in the same currency, e.g. XMR/USD and XBT/USD.
+    Via(Currency),
+}
+
+impl Default for Pairs {
+    fn default() -> Self {
+        Pairs::XmrBtc
+    }
+}
+
+impl Pairs {
+    /// The names of the pairs as used by the Kraken websocket API.
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            Pairs::XmrBtc => vec!["XMR/XBT".to_owned()],
+            Pairs::Via(currency) => vec![format!("XMR/{}", currency), format!("XBT/{}", currency)],
+        }
+    }
+
+    /// Derive the XMR/BTC rate from the latest prices of the pairs, once all
+    /// of them are known.
+    ///
+    /// Via another currency, the ask of XMR is divided by the bid of XBT: the
+    /// XMR sold in a swap is bought back with the currency that selling the
+    /// received BTC yields, so the rate never undercuts what that costs.
+    fn rate(&self, tickers: &HashMap<String, wire::Ticker>) -> Result<Option<Rate>, wire::Error> {
+        let ask = match self {
+            Pairs::XmrBtc => match tickers.get("XMR/XBT") {
+                Some(xmr) => xmr.ask,
+                None => return Ok(None),
+            },
+            Pairs::Via(currency) => {
+                let xmr = tickers.get(&format!("XMR/{}", currency));
+                let btc = tickers.get(&format!("XBT/{}", currency));
+
+                match (xmr, btc) {
+                    (Some(xmr), Some(btc)) => xmr
+                        .ask
+                        .checked_div(btc.bid)
+                        .ok_or(wire::Error::DivisionByZero)?,
+                    _ => return Ok(None),
+                }
+            }
+        };
+
+        let ask = bitcoin::Amount::from_str_in(
+            &ask.round_dp(8).to_string(),
+            ::bitcoin::Denomination::Bitcoin,
+        )?;
+
+        Ok(Some(Rate { ask }))
+    }
+}
+
+/// The currency of a Kraken pair, e.g. `USD` or `EUR`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency(String);
+
+impl FromStr for Currency {
+    type Err = anyhow::Error;
+
+    fn from_str(currency: &str) -> Result<Self> {
+        if !(3..=4).contains(&currency.len())
+            || !currency.chars().all(|char| char.is_ascii_uppercase())
+        {
+            bail!(
+                "Expected a Kraken currency code of 3 or 4 uppercase letters, e.g. USD, got '{}'",
+                currency
+            )
+        }
+        if currency == "XBT" || currency == "XMR" {
+            bail!("Cannot derive the XMR/BTC rate via {}", currency)
+        }
+
+        Ok(Currency(currency.to_owned()))
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = anyhow::Error;
+
+    fn try_from(currency: String) -> Result<Self> {
+        currency.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self {
+        currency.0
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct RateUpdateStream {
     inner: watch::Receiver<RateUpdate>,
@@ -105,6 +215,9 @@ fn to_backoff(e: connection::Error) -> backoff::Error<anyhow::Error> {
         // Failures while parsing a message are permanent because they most likely present a
         // programmer error
         connection::Error::Parse(_) => Permanent(anyhow::Error::from(e)),
+
+        // Kraken rejects pairs that do not exist, retrying does not help
+        connection::Error::Subscription(_) => Permanent(anyhow::Error::from(e)),
     }
 }
 
@@ -117,29 +230,47 @@ fn to_backoff(e: connection::Error) -> backoff::Error<anyhow::Error> {
 mod connection {
     use super::*;
     use crate::kraken::wire;
-    use futures::stream::BoxStream;
+    use futures::future;
+    use futures::stream::{BoxStream, Stream};
     use tokio_tungstenite::tungstenite;
 
-    pub async fn new() -> Result<BoxStream<'static, Result<Rate, Error>>> {
+    pub async fn new(pairs: Pairs) -> Result<BoxStream<'static, Result<Rate, Error>>> {
         let (mut rate_stream, _) = tokio_tungstenite::connect_async("wss://ws.kraken.com")
             .await
             .context("Failed to connect to Kraken websocket API")?;
 
         rate_stream
-            .send(SUBSCRIBE_XMR_BTC_TICKER_PAYLOAD.into())
+            .send(subscribe_ticker_payload(&pairs).into())
             .await?;
 
-        let stream = rate_stream.err_into().try_filter_map(parse_message).boxed();
+        Ok(rates(pairs, rate_stream))
+    }
 
-        Ok(stream)
+    /// Transform the websocket messages into rate updates, one for every
+    /// ticker update once the prices of all pairs are known.
+    pub fn rates(
+        pairs: Pairs,
+        messages: impl Stream<Item = Result<tungstenite::Message, tungstenite::Error>> + Send + 'static,
+    ) -> BoxStream<'static, Result<Rate, Error>> {
+        let mut tickers = HashMap::new();
+
+        messages
+            .err_into()
+            .try_filter_map(parse_message)
+            .try_filter_map(move |ticker| {
+                tickers.insert(ticker.pair.clone(), ticker);
+
+                future::ready(pairs.rate(&tickers).map_err(Error::from))
+            })
+            .boxed()
     }
 
-    /// Parse a websocket message into a [`Rate`].
+    /// Parse a websocket message into the pair and prices of a ticker update.
     ///
     /// Messages which are not actually ticker updates are ignored and result in
     /// `None` being returned. In the context of a [`TryStream`], these will
     /// simply be filtered out.
-    async fn parse_message(msg: tungstenite::Message) -> Result<Option<Rate>, Error> {
+    async fn parse_message(msg: tungstenite::Message) -> Result<Option<wire::Ticker>, Error> {
         let msg = match msg {
             tungstenite::Message::Text(msg) => msg,
             tungstenite::Message::Close(close_frame) => {
@@ -171,8 +302,18 @@ mod connection {
 
                 return Ok(None);
             }
-            Ok(wire::Event::SubscriptionStatus) => {
-                tracing::debug!("Subscribed to updates for ticker");
+            Ok(wire::Event::SubscriptionStatus {
+                status,
+                error_message,
+                pair,
+            }) => {
+                if status == "error" {
+                    return Err(Error::Subscription(
+                        error_message.unwrap_or_else(|| format!("Cannot subscribe to {:?}", pair)),
+                    ));
+                }
+
+                tracing::debug!(?pair, "Subscribed to updates for ticker");
 
                 return Ok(None);
             }
@@ -192,7 +333,7 @@ mod connection {
             },
         };
 
-        let update = Rate::try_from(update)?;
+        let update = wire::Ticker::try_from(update)?;
 
         Ok(Some(update))
     }
@@ -205,15 +346,112 @@ mod connection {
         WebSocket(#[from] tungstenite::Error),
         #[error("Failed to parse rate from websocket message")]
         Parse(#[from] wire::Error),
+        #[error("Kraken rejected the ticker subscription: {0}")]
+        Subscription(String),
     }
 
-    const SUBSCRIBE_XMR_BTC_TICKER_PAYLOAD: &str = r#"
-    { "event": "subscribe",
-      "pair": [ "XMR/XBT" ],
-      "subscription": {
-        "name": "ticker"
-      }
-    }"#;
+    fn subscribe_ticker_payload(pairs: &Pairs) -> String {
+        serde_json::json!({
+            "event": "subscribe",
+            "pair": pairs.names(),
+            "subscription": {
+                "name": "ticker"
+            }
+        })
+        .to_string()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use futures::stream;
+
+        fn ticker(
+            pair: &str,
+            ask: &str,
+            bid: &str,
+        ) -> Result<tungstenite::Message, tungstenite::Error> {
+            Ok(tungstenite::Message::Text(format!(
+                r#"[980,{{"a":["{}",7,"7.35318535"],"b":["{}",7,"7.57416678"]}},"ticker","{}"]"#,
+                ask, bid, pair
+            )))
+        }
+
+        fn subscription_status(
+            pair: &str,
+            status: &str,
+        ) -> Result<tungstenite::Message, tungstenite::Error> {
+            Ok(tungstenite::Message::Text(format!(
+                r#"{{"channelID":980,"channelName":"ticker","event":"subscriptionStatus","pair":"{}","status":"{}","subscription":{{"name":"ticker"}}}}"#,
+                pair, status
+            )))
+        }
+
+        fn rate(btc: f64) -> Rate {
+            Rate {
+                ask: bitcoin::Amount::from_btc(btc).unwrap(),
+            }
+        }
+
+        #[tokio::test]
+        async fn derives_rate_from_xmr_btc_pair() {
+            let feed = stream::iter(vec![
+                subscription_status("XMR/XBT", "subscribed"),
+                ticker("XMR/XBT", "0.00440700", "0.00440200"),
+            ]);
+
+            let rates = rates(Pairs::XmrBtc, feed)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+
+            assert_eq!(rates, vec![rate(0.004_407)]);
+        }
+
+        #[tokio::test]
+        async fn derives_rate_via_configured_currency() {
+            let feed = stream::iter(vec![
+                subscription_status("XMR/EUR", "subscribed"),
+                subscription_status("XBT/EUR", "subscribed"),
+                ticker("XMR/EUR", "200.00000", "199.00000"),
+                ticker("XBT/EUR", "50010.00000", "50000.00000"),
+                ticker("XMR/EUR", "250.00000", "249.00000"),
+                ticker("XMR/XBT", "0.00440700", "0.00440200"),
+            ]);
+
+            let rates = rates(Pairs::Via("EUR".parse().unwrap()), feed)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap();
+
+            // No rate until both pairs are known, other pairs do not matter. The ask of XMR
+            // is divided by the bid of XBT.
+            assert_eq!(rates, vec![rate(0.004), rate(0.005), rate(0.005)]);
+        }
+
+        #[tokio::test]
+        async fn rejected_pair_fails_permanently() {
+            let feed = stream::iter(vec![Ok(tungstenite::Message::Text(
+                r#"{"errorMessage":"Currency pair not supported XMR/ABC","event":"subscriptionStatus","pair":"XMR/ABC","status":"error","subscription":{"name":"ticker"}}"#.to_owned(),
+            ))]);
+
+            let error = rates(Pairs::Via("ABC".parse().unwrap()), feed)
+                .try_next()
+                .await
+                .unwrap_err();
+
+            assert!(matches!(error, Error::Subscription(_)));
+            assert!(matches!(to_backoff(error), backoff::Error::Permanent(_)));
+        }
+
+        #[test]
+        fn currency_must_be_a_currency_code() {
+            assert!("USD".parse::<Currency>().is_ok());
+            assert!("usd".parse::<Currency>().is_err());
+            assert!("XBT".parse::<Currency>().is_err());
+            assert!("US DOLLAR".parse::<Currency>().is_err());
+        }
+    }
 }
 
 /// Kraken websocket API wire module.
@@ -232,7 +470,12 @@ mod wire {
         #[serde(rename = "heartbeat")]
         Heartbeat,
         #[serde(rename = "subscriptionStatus")]
-        SubscriptionStatus,
+        SubscriptionStatus {
+            status: String,
+            #[serde(rename = "errorMessage")]
+            error_message: Option<String>,
+            pair: Option<String>,
+        },
     }
 
     #[derive(Clone, Debug, thiserror::Error)]
@@ -243,8 +486,26 @@ mod wire {
         UnexpectedAskRateElementType,
         #[error("Ask Rate Element is missing")]
         MissingAskRateElementType,
+        #[error("Bid Rate Element is of unexpected type")]
+        UnexpectedBidRateElementType,
+        #[error("Bid Rate Element is missing")]
+        MissingBidRateElementType,
         #[error("Failed to parse Bitcoin amount")]
         BitcoinParseAmount(#[from] ParseAmountError),
+        #[error("Failed to parse price")]
+        ParsePrice(#[from] rust_decimal::Error),
+        #[error("Pair is missing")]
+        PairMissing,
+        #[error("Bid price of the pair to divide by is zero")]
+        DivisionByZero,
+    }
+
+    /// The ask and bid price of a pair, in the currency of the pair.
+    #[derive(Debug, PartialEq)]
+    pub struct Ticker {
+        pub pair: String,
+        pub ask: Decimal,
+        pub bid: Decimal,
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -273,10 +534,15 @@ mod wire {
         Number(u64),
     }
 
-    impl TryFrom<TickerUpdate> for Rate {
+    impl TryFrom<TickerUpdate> for Ticker {
         type Error = Error;
 
         fn try_from(value: TickerUpdate) -> Result<Self, Error> {
+            // The pair is the last field of a ticker update
+            let pair = match value.0.last() {
+                Some(TickerField::Metadata(Value::String(pair))) => pair.clone(),
+                _ => return Err(Error::PairMissing),
+            };
             let data = value
                 .0
                 .iter()
@@ -287,13 +553,16 @@ mod wire {
                 .ok_or(Error::DataFieldMissing)?;
             let ask = data.ask.first().ok_or(Error::MissingAskRateElementType)?;
             let ask = match ask {
-                RateElement::Text(ask) => {
-                    bitcoin::Amount::from_str_in(ask, ::bitcoin::Denomination::Bitcoin)?
-                }
+                RateElement::Text(ask) => Decimal::from_str(ask)?,
                 _ => return Err(Error::UnexpectedAskRateElementType),
             };
+            let bid = data.bid.first().ok_or(Error::MissingBidRateElementType)?;
+            let bid = match bid {
+                RateElement::Text(bid) => Decimal::from_str(bid)?,
+                _ => return Err(Error::UnexpectedBidRateElementType),
+            };
 
-            Ok(Self { ask })
+            Ok(Self { pair, ask, bid })
         }
     }
 
@@ -316,7 +585,11 @@ mod wire {
 
             let event = serde_json::from_str::<Event>(event).unwrap();
 
-            assert_eq!(event, Event::SubscriptionStatus)
+            assert_eq!(event, Event::SubscriptionStatus {
+                status: "subscribed".to_owned(),
+                error_message: None,
+                pair: Some("XMR/XBT".to_owned()),
+            })
         }
 
         #[test]
@@ -21,6 +21,8 @@ pub mod bitcoin;
 pub mod cli;
 pub mod database;
 pub mod env;
+pub mod error;
+pub mod explorer;
 pub mod fs;
 pub mod kraken;
 pub mod monero;
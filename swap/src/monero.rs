@@ -1,13 +1,15 @@
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod wallet;
 mod wallet_rpc;
 
 pub use ::monero::{Address, Network, PrivateKey, PublicKey};
 pub use curve25519_dalek::scalar::Scalar;
-pub use wallet::Wallet;
-pub use wallet_rpc::{WalletRpc, WalletRpcProcess};
+pub use wallet::{Subaddress, Wallet};
+pub use wallet_rpc::{daemon_url, WalletRpc, WalletRpcProcess};
 
 use crate::bitcoin;
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use rand::{CryptoRng, RngCore};
 use rust_decimal::prelude::*;
 use rust_decimal::Decimal;
@@ -16,6 +18,7 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::ops::{Add, Mul, Sub};
 use std::str::FromStr;
+use std::time::Duration;
 
 pub const PICONERO_OFFSET: u64 = 1_000_000_000_000;
 
@@ -45,6 +48,14 @@ impl PrivateViewKey {
     }
 }
 
+impl FromStr for PrivateViewKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Self(PrivateKey::from_str(s)?))
+    }
+}
+
 impl Add for PrivateViewKey {
     type Output = Self;
 
@@ -78,7 +89,7 @@ impl Amount {
     /// piconeros.
     ///
     /// A piconero (a.k.a atomic unit) is equal to 1e-12 XMR.
-    pub fn from_piconero(amount: u64) -> Self {
+    pub const fn from_piconero(amount: u64) -> Self {
         Amount(amount)
     }
 
@@ -86,6 +97,11 @@ impl Amount {
         self.0
     }
 
+    /// Add `rhs`, returning `None` instead of panicking on overflow.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
     pub fn from_monero(amount: f64) -> Result<Self> {
         let decimal = Decimal::try_from(amount)?;
         Self::from_decimal(decimal)
@@ -96,6 +112,40 @@ impl Amount {
         Self::from_decimal(decimal)
     }
 
+    /// Parse an amount of monero given in XMR.
+    ///
+    /// The denomination can optionally be given as a suffix, i.e. `1.5` and
+    /// `1.5 XMR` are parsed to the same amount. Amounts with more than 12
+    /// decimal places are rejected instead of being rounded.
+    pub fn parse_xmr(s: &str) -> Result<Self> {
+        let mut parts = s.split_whitespace();
+
+        let amount = match (parts.next(), parts.next(), parts.next()) {
+            (Some(amount), None, None) => amount,
+            (Some(amount), Some(denomination), None)
+                if denomination.eq_ignore_ascii_case("XMR") =>
+            {
+                amount
+            }
+            (Some(_), Some(denomination), None) => {
+                bail!("Expected an amount in XMR, got {}", denomination)
+            }
+            _ => bail!("Invalid Monero amount {:?}", s),
+        };
+
+        let decimal = Decimal::from_str(amount)
+            .map_err(|e| anyhow!("Invalid Monero amount {:?}: {}", s, e))?;
+
+        if decimal.is_sign_negative() {
+            bail!("Invalid Monero amount {:?}: amount is negative", s)
+        }
+        if decimal.scale() > 12 {
+            bail!("Invalid Monero amount {:?}: more than 12 decimal places", s)
+        }
+
+        Self::from_decimal(decimal)
+    }
+
     fn from_decimal(amount: Decimal) -> Result<Self> {
         let piconeros_dec =
             amount.mul(Decimal::from_u64(PICONERO_OFFSET).expect("constant to fit into u64"));
@@ -188,6 +238,27 @@ pub struct InsufficientFunds {
     pub actual: Amount,
 }
 
+/// The monero-wallet-rpc did not answer in time, retrying later might
+/// succeed.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("the monero-wallet-rpc did not respond within {}s", timeout.as_secs())]
+pub struct RpcTimeout {
+    pub timeout: Duration,
+}
+
+/// The wallet has not yet scanned the blockchain up to the tip of the daemon,
+/// its balance and incoming transfers are not reliable yet.
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Monero wallet still syncing ({wallet}/{daemon} blocks)")]
+pub struct WalletNotSynced {
+    pub wallet: u32,
+    pub daemon: u32,
+}
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("cannot spend funds from a view-only wallet")]
+pub struct ViewOnly;
+
 #[derive(Debug, Clone, Copy, thiserror::Error)]
 #[error("The balance is too low, current balance: {balance}")]
 pub struct BalanceTooLow {
@@ -198,6 +269,26 @@ pub struct BalanceTooLow {
 #[error("Overflow, cannot convert {0} to u64")]
 pub struct OverflowError(pub String);
 
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error("The given Monero address is on network {actual:?}, expected an address on network {expected:?}")]
+pub struct AddressNetworkMismatch {
+    pub expected: Network,
+    pub actual: Network,
+}
+
+/// Check that the given address is on the network we are transacting on,
+/// Monero sent to an address of another network is lost.
+pub fn validate_address(address: &Address, network: Network) -> Result<(), AddressNetworkMismatch> {
+    if address.network != network {
+        return Err(AddressNetworkMismatch {
+            expected: network,
+            actual: address.network,
+        });
+    }
+
+    Ok(())
+}
+
 pub mod monero_private_key {
     use monero::consensus::{Decodable, Encodable};
     use monero::PrivateKey;
@@ -330,6 +421,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_xmr_with_and_without_denomination() {
+        let expected = Amount::from_piconero(1_500_000_000_000);
+
+        assert_eq!(Amount::parse_xmr("1.5").unwrap(), expected);
+        assert_eq!(Amount::parse_xmr("1.5 XMR").unwrap(), expected);
+        assert_eq!(Amount::parse_xmr(" 1.5 xmr ").unwrap(), expected);
+    }
+
+    #[test]
+    fn parse_xmr_rejects_malformed_input() {
+        assert!(Amount::parse_xmr("").is_err());
+        assert!(Amount::parse_xmr("abc").is_err());
+        assert!(Amount::parse_xmr("-1.5").is_err());
+        assert!(Amount::parse_xmr("1.5 BTC").is_err());
+        assert!(Amount::parse_xmr("1.5 XMR XMR").is_err());
+    }
+
+    #[test]
+    fn parse_xmr_rejects_amounts_more_precise_than_a_piconero() {
+        let error = Amount::parse_xmr("0.0000000000001").unwrap_err();
+
+        assert!(error.to_string().contains("decimal places"));
+    }
+
+    #[test]
+    fn display_xmr_min_and_max_roundtrips() {
+        let min = Amount::from_piconero(1);
+        let max = Amount::from_piconero(u64::MAX);
+
+        assert_eq!(Amount::parse_xmr(&min.to_string()).unwrap(), min);
+        assert_eq!(Amount::parse_xmr(&max.to_string()).unwrap(), max);
+    }
+
     use rand::rngs::OsRng;
     use serde::{Deserialize, Serialize};
 
@@ -356,4 +481,31 @@ mod tests {
         let decoded: MoneroAmount = serde_cbor::from_slice(&encoded).unwrap();
         assert_eq!(amount, decoded);
     }
+
+    fn random_public_key() -> PublicKey {
+        PublicKey::from_private_key(&PrivateKey::from_scalar(Scalar::random(&mut OsRng)))
+    }
+
+    #[test]
+    fn address_on_other_network_is_rejected() {
+        let address = Address::standard(Network::Mainnet, random_public_key(), random_public_key());
+
+        let error = validate_address(&address, Network::Stagenet).unwrap_err();
+
+        assert_eq!(error, AddressNetworkMismatch {
+            expected: Network::Stagenet,
+            actual: Network::Mainnet
+        });
+    }
+
+    #[test]
+    fn addresses_on_the_expected_network_are_accepted() {
+        let standard =
+            Address::standard(Network::Stagenet, random_public_key(), random_public_key());
+        let subaddress =
+            Address::subaddress(Network::Stagenet, random_public_key(), random_public_key());
+
+        validate_address(&standard, Network::Stagenet).unwrap();
+        validate_address(&subaddress, Network::Stagenet).unwrap();
+    }
 }
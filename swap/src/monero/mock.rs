@@ -0,0 +1,180 @@
+use crate::monero::wallet::{MoneroWallet, WatchRequest};
+use crate::monero::{Amount, InsufficientFunds, PrivateKey, PrivateViewKey, Scalar, TxHash};
+use ::monero::{Address, Network, PublicKey};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use monero_rpc::wallet::{BlockHeight, Refreshed};
+use rand::thread_rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// An in-memory [`MoneroWallet`] whose view of the blockchain is set by the
+/// test.
+///
+/// Watching for a transfer waits until the test lets it arrive with
+/// [`MockWallet::receive_transfer`]. Sweeping transfers the whole balance set
+/// with [`MockWallet::set_balance`] unless the test makes it fail.
+#[derive(Debug)]
+pub struct MockWallet {
+    address: Address,
+    block_height: Mutex<u32>,
+    transfers: Mutex<HashMap<String, Amount>>,
+    balance: Mutex<Amount>,
+    failing_sweeps: Mutex<u32>,
+    failing_watches: Mutex<u32>,
+    swept: Mutex<Vec<(Address, Amount)>>,
+    restored_from: Mutex<Option<BlockHeight>>,
+}
+
+impl Default for MockWallet {
+    fn default() -> Self {
+        let spend_key = PrivateKey::from_scalar(Scalar::random(&mut thread_rng()));
+        let view_key = PrivateKey::from_scalar(Scalar::random(&mut thread_rng()));
+
+        Self {
+            address: Address::standard(
+                Network::Stagenet,
+                PublicKey::from_private_key(&spend_key),
+                PublicKey::from_private_key(&view_key),
+            ),
+            block_height: Mutex::new(1),
+            transfers: Default::default(),
+            balance: Mutex::new(Amount::ZERO),
+            failing_sweeps: Default::default(),
+            failing_watches: Default::default(),
+            swept: Default::default(),
+            restored_from: Default::default(),
+        }
+    }
+}
+
+impl MockWallet {
+    /// Let the transfer with the given hash arrive with the given amount, it is
+    /// considered confirmed right away.
+    pub fn receive_transfer(&self, tx_hash: TxHash, amount: Amount) {
+        self.transfers.lock().unwrap().insert(tx_hash.0, amount);
+    }
+
+    pub fn set_block_height(&self, height: u32) {
+        *self.block_height.lock().unwrap() = height;
+    }
+
+    pub fn set_balance(&self, amount: Amount) {
+        *self.balance.lock().unwrap() = amount;
+    }
+
+    /// Fail the next `n` sweeps without moving any funds.
+    pub fn fail_next_sweeps(&self, n: u32) {
+        *self.failing_sweeps.lock().unwrap() = n;
+    }
+
+    /// Fail the next `n` attempts to watch for a transfer, as if the
+    /// monero-wallet-rpc could not be reached.
+    pub fn fail_next_watches(&self, n: u32) {
+        *self.failing_watches.lock().unwrap() = n;
+    }
+
+    /// The destination and amount of every sweep so far, in order.
+    pub fn swept(&self) -> Vec<(Address, Amount)> {
+        self.swept.lock().unwrap().clone()
+    }
+
+    /// The restore height of the wallet generated last, if any.
+    pub fn restored_from(&self) -> Option<BlockHeight> {
+        *self.restored_from.lock().unwrap()
+    }
+
+    fn transfer(&self, tx_hash: &TxHash) -> Option<Amount> {
+        self.transfers.lock().unwrap().get(&tx_hash.0).copied()
+    }
+}
+
+#[async_trait]
+impl MoneroWallet for MockWallet {
+    async fn watch_for_transfer(&self, request: WatchRequest) -> Result<()> {
+        let tx_hash = request.transfer_proof.tx_hash();
+
+        {
+            let mut failing_watches = self.failing_watches.lock().unwrap();
+            if *failing_watches > 0 {
+                *failing_watches -= 1;
+
+                bail!("Failed to check transfer {}", tx_hash.0);
+            }
+        }
+
+        let received = loop {
+            match self.transfer(&tx_hash) {
+                Some(amount) => break amount,
+                None => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        if received != request.expected {
+            bail!(InsufficientFunds {
+                expected: request.expected,
+                actual: received,
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn block_height(&self) -> Result<BlockHeight> {
+        Ok(BlockHeight {
+            height: *self.block_height.lock().unwrap(),
+        })
+    }
+
+    async fn refresh(&self) -> Result<Refreshed> {
+        Ok(Refreshed {
+            blocks_fetched: 0,
+            received_money: false,
+        })
+    }
+
+    async fn wait_until_synced(&self, _: Duration) -> Result<()> {
+        Ok(())
+    }
+
+    async fn wait_for_incoming_transfers(&self, _: u32, _: Option<Amount>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_from_and_load(
+        &self,
+        _: PrivateKey,
+        _: PrivateViewKey,
+        restore_height: BlockHeight,
+    ) -> Result<()> {
+        *self.restored_from.lock().unwrap() = Some(restore_height);
+
+        Ok(())
+    }
+
+    async fn sweep_all(&self, address: Address) -> Result<Vec<TxHash>> {
+        {
+            let mut failing_sweeps = self.failing_sweeps.lock().unwrap();
+            if *failing_sweeps > 0 {
+                *failing_sweeps -= 1;
+
+                return Err(anyhow!("Failed to sweep Monero to {}", address));
+            }
+        }
+
+        let amount = std::mem::replace(&mut *self.balance.lock().unwrap(), Amount::ZERO);
+        let mut swept = self.swept.lock().unwrap();
+        swept.push((address, amount));
+
+        Ok(vec![TxHash(format!("sweep {}", swept.len()))])
+    }
+
+    async fn get_balance(&self) -> Result<Amount> {
+        Ok(*self.balance.lock().unwrap())
+    }
+
+    fn get_main_address(&self) -> Address {
+        self.address
+    }
+}
@@ -1,18 +1,35 @@
 use crate::env::Config;
+use crate::explorer::ExplorerUrl;
 use crate::monero::{
-    Amount, InsufficientFunds, PrivateViewKey, PublicViewKey, TransferProof, TxHash,
+    Amount, BalanceTooLow, InsufficientFunds, PrivateViewKey, PublicViewKey, RpcTimeout,
+    TransferProof, TxHash, ViewOnly, WalletNotSynced,
 };
 use ::monero::{Address, Network, PrivateKey, PublicKey};
-use anyhow::{Context, Result};
-use monero_rpc::wallet;
-use monero_rpc::wallet::{BlockHeight, CheckTxKey, Refreshed};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use monero_rpc::wallet::{BlockHeight, CheckTxKey, Refreshed, TransferEntry, TransferPriority};
+use monero_rpc::{monerod, wallet};
 use std::future::Future;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 use tokio::time::Interval;
 use tracing::{debug, info};
 use url::Url;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subaddress {
+    pub index: u32,
+    pub address: Address,
+}
+
+/// The transactions and total fee of a withdrawal from the wallet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Withdrawal {
+    pub tx_hashes: Vec<TxHash>,
+    pub fee: Amount,
+}
 
 #[derive(Debug)]
 pub struct Wallet {
@@ -21,6 +38,11 @@ pub struct Wallet {
     name: String,
     main_address: monero::Address,
     sync_interval: Duration,
+    rpc_timeout: Duration,
+    view_only: bool,
+    priority: TransferPriority,
+    daemon: Option<monerod::Client>,
+    explorer: ExplorerUrl,
 }
 
 impl Wallet {
@@ -42,6 +64,63 @@ impl Wallet {
         Self::connect(client, name, env_config).await
     }
 
+    /// Like [`Wallet::open_or_create`] but tries up to `attempts` times,
+    /// doubling the delay between attempts, e.g. to wait for a
+    /// monero-wallet-rpc that was started at the same time.
+    pub async fn open_or_create_with_retries(
+        url: Url,
+        name: String,
+        env_config: Config,
+        attempts: u32,
+        initial_delay: Duration,
+    ) -> Result<Self> {
+        retry_open(attempts, initial_delay, || {
+            Self::open_or_create(url.clone(), name.clone(), env_config)
+        })
+        .await
+    }
+
+    /// Connect to a wallet RPC and load a view-only wallet for the given
+    /// address, generating it from the view key if it does not exist yet.
+    ///
+    /// A view-only wallet can observe the balance and incoming transfers but
+    /// refuses to spend any funds.
+    pub async fn open_or_create_view_only(
+        url: Url,
+        address: Address,
+        private_view_key: PrivateViewKey,
+        restore_height: BlockHeight,
+        env_config: Config,
+    ) -> Result<Self> {
+        let client = wallet::Client::new(url);
+        let name = view_only_wallet_name(&address);
+
+        let open_wallet_response = client.open_wallet(name.as_str()).await;
+        if open_wallet_response.is_err() {
+            client
+                .generate_from_keys(
+                    &name,
+                    &address.to_string(),
+                    "",
+                    &PrivateKey::from(private_view_key).to_string(),
+                    restore_height.height,
+                )
+                .await
+                .context("Unable to create view-only Monero wallet")?;
+
+            debug!("Created view-only Monero wallet for {}", address);
+        } else {
+            debug!("Opened view-only Monero wallet for {}", address);
+        }
+
+        let wallet = Self::connect(client, name, env_config).await?;
+
+        Ok(Self {
+            view_only: true,
+            ..wallet
+        })
+    }
+
     /// Connects to a wallet RPC where a wallet is already loaded.
     pub async fn connect(client: wallet::Client, name: String, env_config: Config) -> Result<Self> {
         let main_address =
@@ -52,9 +131,72 @@ impl Wallet {
             name,
             main_address,
             sync_interval: env_config.monero_sync_interval(),
+            rpc_timeout: env_config.monero_rpc_timeout,
+            view_only: false,
+            priority: TransferPriority::Default,
+            daemon: None,
+            explorer: ExplorerUrl::monero(env_config.monero_network),
         })
     }
 
+    /// Use the given priority for all transfers instead of the wallet's
+    /// default.
+    pub fn with_transfer_priority(self, priority: TransferPriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    /// Log links to published transactions on the given explorer instead of
+    /// the default one of the network.
+    pub fn with_explorer(self, explorer: ExplorerUrl) -> Self {
+        Self { explorer, ..self }
+    }
+
+    /// Compare the height of the wallet against the given daemon before
+    /// critical operations, see [`Wallet::wait_until_synced`].
+    pub fn with_daemon(self, daemon: monerod::Client) -> Self {
+        Self {
+            daemon: Some(daemon),
+            ..self
+        }
+    }
+
+    /// Wait until the wallet has scanned the blockchain up to the tip of the
+    /// daemon, logging the progress.
+    ///
+    /// Fails with [`WalletNotSynced`] if the wallet does not catch up within
+    /// `max_wait`. Without a daemon to compare against, the wallet is assumed
+    /// to be synced.
+    pub async fn wait_until_synced(&self, max_wait: Duration) -> Result<()> {
+        let daemon = match &self.daemon {
+            Some(daemon) => daemon,
+            None => return Ok(()),
+        };
+        let started = Instant::now();
+
+        loop {
+            let wallet_height = self.block_height().await?.height;
+            let daemon_height = daemon
+                .get_block_count()
+                .await
+                .context("Failed to get the block height of the Monero daemon")?;
+
+            if wallet_height + SYNC_TOLERANCE_BLOCKS >= daemon_height {
+                return Ok(());
+            }
+
+            let not_synced = WalletNotSynced {
+                wallet: wallet_height,
+                daemon: daemon_height,
+            };
+            if started.elapsed() >= max_wait {
+                bail!(not_synced)
+            }
+
+            info!("{}, waiting before continuing", not_synced);
+            tokio::time::sleep(self.sync_interval).await;
+        }
+    }
+
     /// Re-open the wallet using the internally stored name.
     pub async fn re_open(&self) -> Result<()> {
         self.inner
@@ -81,17 +223,76 @@ impl Wallet {
         let wallet = self.inner.lock().await;
 
         // Properly close the wallet before generating the other wallet to ensure that
-        // it saves its state correctly
-        let _ = wallet.close_wallet().await?;
+        // it saves its state correctly. Closing fails if no wallet is loaded, i.e. when
+        // retrying after generating the wallet failed, hence we only log the error.
+        if let Err(error) = wallet.close_wallet().await {
+            debug!("Failed to close wallet: {:#}", error);
+        }
 
-        let _ = wallet
+        let view_key = PrivateKey::from(private_view_key).to_string();
+
+        let generated = wallet
             .generate_from_keys(
+                &view_key,
                 &address.to_string(),
                 &private_spend_key.to_string(),
-                &PrivateKey::from(private_view_key).to_string(),
+                &view_key,
                 restore_height.height,
             )
-            .await?;
+            .await;
+
+        // If a previous attempt already generated the wallet, it is named after the
+        // view key and we can simply open it
+        if let Err(error) = generated {
+            wallet
+                .open_wallet(&view_key)
+                .await
+                .with_context(|| format!("Failed to generate wallet from keys: {:#}", error))?;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`Wallet::create_from_and_load`] but generates the wallet under a
+    /// new file name, e.g. because the file of the previously generated wallet
+    /// was lost or corrupted. The blockchain is scanned again from the restore
+    /// height.
+    pub async fn recreate_from_and_load(
+        &self,
+        private_spend_key: PrivateKey,
+        private_view_key: PrivateViewKey,
+        restore_height: BlockHeight,
+    ) -> Result<()> {
+        let public_spend_key = PublicKey::from_private_key(&private_spend_key);
+        let public_view_key = PublicKey::from_private_key(&private_view_key.into());
+
+        let address = Address::standard(self.network, public_spend_key, public_view_key);
+
+        let wallet = self.inner.lock().await;
+
+        if let Err(error) = wallet.close_wallet().await {
+            debug!("Failed to close wallet: {:#}", error);
+        }
+
+        let view_key = PrivateKey::from(private_view_key).to_string();
+        let filename = format!(
+            "{}-recovered-{}",
+            view_key,
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()
+        );
+
+        wallet
+            .generate_from_keys(
+                &filename,
+                &address.to_string(),
+                &private_spend_key.to_string(),
+                &view_key,
+                restore_height.height,
+            )
+            .await
+            .context("Failed to recreate wallet from keys")?;
+
+        info!("Recreated Monero wallet {}", filename);
 
         Ok(())
     }
@@ -100,11 +301,15 @@ impl Wallet {
     /// keys. The generated wallet will be opened, all funds sweeped to the
     /// main_address and then the wallet will be re-loaded using the internally
     /// stored name.
+    ///
+    /// The funds are swept to the given address of the default wallet, e.g. a
+    /// subaddress created for the swap.
     pub async fn create_from(
         &self,
         private_spend_key: PrivateKey,
         private_view_key: PrivateViewKey,
         restore_height: BlockHeight,
+        destination: Address,
     ) -> Result<()> {
         let public_spend_key = PublicKey::from_private_key(&private_spend_key);
         let public_view_key = PublicKey::from_private_key(&private_view_key.into());
@@ -118,11 +323,14 @@ impl Wallet {
         // it saves its state correctly
         let _ = wallet.close_wallet().await?;
 
+        let view_key = PrivateKey::from(private_view_key).to_string();
+
         let _ = wallet
             .generate_from_keys(
+                &view_key,
                 &temp_wallet_address.to_string(),
                 &private_spend_key.to_string(),
-                &PrivateKey::from(private_view_key).to_string(),
+                &view_key,
                 restore_height.height,
             )
             .await?;
@@ -130,18 +338,18 @@ impl Wallet {
         // Try to send all the funds from the generated wallet to the default wallet
         match wallet.refresh().await {
             Ok(_) => match wallet
-                .sweep_all(self.main_address.to_string().as_str())
+                .sweep_all(destination.to_string().as_str(), self.priority)
                 .await
             {
                 Ok(sweep_all) => {
                     for tx in sweep_all.tx_hash_list {
-                        tracing::info!(%tx, "Monero transferred back to default wallet {}", self.main_address);
+                        tracing::info!(%tx, "Monero transferred back to default wallet {}", destination);
                     }
                 }
                 Err(e) => {
                     tracing::warn!(
                         "Transferring Monero back to default wallet {} failed with {:#}",
-                        self.main_address,
+                        destination,
                         e
                     );
                 }
@@ -157,6 +365,10 @@ impl Wallet {
     }
 
     pub async fn transfer(&self, request: TransferRequest) -> Result<TransferProof> {
+        if self.view_only {
+            bail!(ViewOnly)
+        }
+
         let TransferRequest {
             public_spend_key,
             public_view_key,
@@ -170,7 +382,12 @@ impl Wallet {
             .inner
             .lock()
             .await
-            .transfer(0, amount.as_piconero(), &destination_address.to_string())
+            .transfer(
+                0,
+                amount.as_piconero(),
+                &destination_address.to_string(),
+                self.priority,
+            )
             .await?;
 
         tracing::debug!(
@@ -179,6 +396,7 @@ impl Wallet {
             public_spend_key,
             res.tx_hash
         );
+        tracing::info!(txid = %res.tx_hash, url = %self.explorer.transaction(&res.tx_hash), "Published Monero transaction");
 
         Ok(TransferProof::new(
             TxHash(res.tx_hash),
@@ -186,6 +404,13 @@ impl Wallet {
         ))
     }
 
+    /// Wait until the given transfer has reached the requested number of
+    /// confirmations.
+    ///
+    /// Fails with [`RpcTimeout`] if the monero-wallet-rpc stalls instead of
+    /// answering, the caller may simply try again. Fails with
+    /// [`InsufficientFunds`] only once the transfer is confirmed with another
+    /// amount than expected, an unconfirmed one is waited for.
     pub async fn watch_for_transfer(&self, request: WatchRequest) -> Result<()> {
         let WatchRequest {
             conf_target,
@@ -214,6 +439,7 @@ impl Wallet {
                     .await
             },
             check_interval,
+            self.rpc_timeout,
             expected,
             conf_target,
         )
@@ -222,19 +448,184 @@ impl Wallet {
         Ok(())
     }
 
+    /// Wait until the incoming transfers of the currently loaded wallet have
+    /// reached the given number of confirmations.
+    ///
+    /// If the expected amount is given, the transfers with enough
+    /// confirmations have to add up to it. Transfers the wallet does not list
+    /// yet, e.g. because they are still in the mempool, are waited for then.
+    /// Otherwise all listed transfers have to reach the confirmations.
+    ///
+    /// This protects against spending funds that could still be affected by a
+    /// reorg.
+    pub async fn wait_for_incoming_transfers(
+        &self,
+        conf_target: u32,
+        expected: Option<Amount>,
+    ) -> Result<()> {
+        tracing::info!(
+            "Waiting for {} confirmation{} of incoming Monero transfers",
+            conf_target,
+            if conf_target > 1 { "s" } else { "" }
+        );
+
+        let check_interval = tokio::time::interval(self.sync_interval);
+
+        wait_for_incoming_confirmations(
+            || async move {
+                let wallet = self.inner.lock().await;
+                wallet.refresh().await?;
+                wallet.get_incoming_transfers().await
+            },
+            check_interval,
+            conf_target,
+            expected,
+        )
+        .await;
+
+        Ok(())
+    }
+
     pub async fn sweep_all(&self, address: Address) -> Result<Vec<TxHash>> {
+        if self.view_only {
+            bail!(ViewOnly)
+        }
+
         let sweep_all = self
             .inner
             .lock()
             .await
-            .sweep_all(address.to_string().as_str())
+            .sweep_all(address.to_string().as_str(), self.priority)
             .await?;
 
         let tx_hashes = sweep_all.tx_hash_list.into_iter().map(TxHash).collect();
         Ok(tx_hashes)
     }
 
-    /// Get the balance of the primary account.
+    /// Send Monero to an external address, e.g. to move funds to cold storage.
+    ///
+    /// The `reserved` Monero stays in the wallet because it is needed to lock
+    /// the Monero of active swaps. Without an amount, everything that is not
+    /// reserved is withdrawn.
+    pub async fn withdraw(
+        &self,
+        address: Address,
+        amount: Option<Amount>,
+        reserved: Amount,
+    ) -> Result<Withdrawal> {
+        if self.view_only {
+            bail!(ViewOnly)
+        }
+
+        let balance = self.get_balance().await?;
+        if balance < reserved + self.static_tx_fee_estimate() {
+            bail!(BalanceTooLow { balance })
+        }
+        let available = balance - reserved - self.static_tx_fee_estimate();
+
+        if amount.is_none() && reserved == Amount::ZERO {
+            let sweep_all = self
+                .inner
+                .lock()
+                .await
+                .sweep_all(address.to_string().as_str(), self.priority)
+                .await?;
+
+            return Ok(Withdrawal {
+                tx_hashes: sweep_all.tx_hash_list.into_iter().map(TxHash).collect(),
+                fee: Amount::from_piconero(sweep_all.fee_list.iter().sum()),
+            });
+        }
+
+        let amount = amount.unwrap_or(available);
+        if amount > available {
+            bail!(
+                "Cannot withdraw {}, only {} are not reserved for active swaps",
+                amount,
+                available
+            )
+        }
+
+        let transfer = self
+            .inner
+            .lock()
+            .await
+            .transfer(0, amount.as_piconero(), &address.to_string(), self.priority)
+            .await?;
+
+        Ok(Withdrawal {
+            tx_hashes: vec![TxHash(transfer.tx_hash)],
+            fee: Amount::from_piconero(transfer.fee),
+        })
+    }
+
+    /// Create a new subaddress of the primary account, e.g. to attribute
+    /// incoming funds to a swap.
+    pub async fn new_subaddress(&self, label: &str) -> Result<Subaddress> {
+        let created = self.inner.lock().await.create_address(0, label).await?;
+
+        Ok(Subaddress {
+            index: created.address_index,
+            address: Address::from_str(&created.address)?,
+        })
+    }
+
+    /// Get the subaddress of the primary account that belongs to the given
+    /// swap, creating it on first use.
+    ///
+    /// The index of the subaddress is derived from the swap id, see
+    /// [`swap_subaddress_index`], so the same swap always yields the same
+    /// address, even if the index stored for it got lost.
+    pub async fn get_address_for_swap(&self, swap_id: Uuid) -> Result<Subaddress> {
+        let index = swap_subaddress_index(swap_id);
+
+        let addresses = self.inner.lock().await.get_address(0).await?.addresses;
+        if let Some(subaddress) = find_subaddress(&addresses, index)? {
+            return Ok(subaddress);
+        }
+
+        // The wallet only scans the subaddresses it created, hence all subaddresses up
+        // to the index are created
+        let label = swap_label(swap_id);
+        let mut next_index = addresses
+            .iter()
+            .map(|subaddress| subaddress.address_index + 1)
+            .max()
+            .unwrap_or(0);
+
+        while next_index <= index {
+            let label = if next_index == index {
+                label.as_str()
+            } else {
+                ""
+            };
+            let created = self.inner.lock().await.create_address(0, label).await?;
+
+            if created.address_index == index {
+                return Ok(Subaddress {
+                    index,
+                    address: Address::from_str(&created.address)?,
+                });
+            }
+            next_index = created.address_index + 1;
+        }
+
+        // Another subaddress was created concurrently at the index
+        let addresses = self.inner.lock().await.get_address(0).await?.addresses;
+        find_subaddress(&addresses, index)?
+            .with_context(|| format!("Subaddress {} of swap {} was not created", index, swap_id))
+    }
+
+    /// Get the existing subaddress of the primary account at the given index.
+    pub async fn get_subaddress(&self, index: u32) -> Result<Subaddress> {
+        let addresses = self.inner.lock().await.get_address(0).await?.addresses;
+
+        find_subaddress(&addresses, index)?
+            .with_context(|| format!("Subaddress {} does not exist", index))
+    }
+
+    /// Get the balance of the primary account, including all its
+    /// subaddresses.
     pub async fn get_balance(&self) -> Result<Amount> {
         let amount = self.inner.lock().await.get_balance(0).await?;
 
@@ -259,6 +650,125 @@ impl Wallet {
     }
 }
 
+/// The operations of the Monero wallet that the swap protocols rely on.
+///
+/// [`Wallet`] is used outside of tests. Tests drive the swap state machines
+/// against a wallet whose view of the blockchain they control instead, see
+/// `MockWallet` in the `mock` module, which is only compiled for tests.
+#[async_trait]
+pub trait MoneroWallet: Send + Sync {
+    /// See [`Wallet::watch_for_transfer`].
+    async fn watch_for_transfer(&self, request: WatchRequest) -> Result<()>;
+    async fn block_height(&self) -> Result<BlockHeight>;
+    async fn refresh(&self) -> Result<Refreshed>;
+    async fn wait_until_synced(&self, max_wait: Duration) -> Result<()>;
+    async fn wait_for_incoming_transfers(
+        &self,
+        conf_target: u32,
+        expected: Option<Amount>,
+    ) -> Result<()>;
+    async fn create_from_and_load(
+        &self,
+        private_spend_key: PrivateKey,
+        private_view_key: PrivateViewKey,
+        restore_height: BlockHeight,
+    ) -> Result<()>;
+    async fn sweep_all(&self, address: Address) -> Result<Vec<TxHash>>;
+    async fn get_balance(&self) -> Result<Amount>;
+    fn get_main_address(&self) -> Address;
+}
+
+#[async_trait]
+impl MoneroWallet for Wallet {
+    async fn watch_for_transfer(&self, request: WatchRequest) -> Result<()> {
+        Wallet::watch_for_transfer(self, request).await
+    }
+
+    async fn block_height(&self) -> Result<BlockHeight> {
+        Wallet::block_height(self).await
+    }
+
+    async fn refresh(&self) -> Result<Refreshed> {
+        Wallet::refresh(self).await
+    }
+
+    async fn wait_until_synced(&self, max_wait: Duration) -> Result<()> {
+        Wallet::wait_until_synced(self, max_wait).await
+    }
+
+    async fn wait_for_incoming_transfers(
+        &self,
+        conf_target: u32,
+        expected: Option<Amount>,
+    ) -> Result<()> {
+        Wallet::wait_for_incoming_transfers(self, conf_target, expected).await
+    }
+
+    async fn create_from_and_load(
+        &self,
+        private_spend_key: PrivateKey,
+        private_view_key: PrivateViewKey,
+        restore_height: BlockHeight,
+    ) -> Result<()> {
+        Wallet::create_from_and_load(self, private_spend_key, private_view_key, restore_height)
+            .await
+    }
+
+    async fn sweep_all(&self, address: Address) -> Result<Vec<TxHash>> {
+        Wallet::sweep_all(self, address).await
+    }
+
+    async fn get_balance(&self) -> Result<Amount> {
+        Wallet::get_balance(self).await
+    }
+
+    fn get_main_address(&self) -> Address {
+        Wallet::get_main_address(self)
+    }
+}
+
+/// The name of the wallet file of the view-only wallet for the given address,
+/// which must not reveal the view key.
+fn view_only_wallet_name(address: &Address) -> String {
+    format!("view-only-{}", address)
+}
+
+/// The label of the subaddress that belongs to the given swap.
+fn swap_label(swap_id: Uuid) -> String {
+    format!("swap {}", swap_id)
+}
+
+fn find_subaddress(addresses: &[wallet::SubaddressInfo], index: u32) -> Result<Option<Subaddress>> {
+    addresses
+        .iter()
+        .find(|subaddress| subaddress.address_index == index)
+        .map(|subaddress| {
+            Ok(Subaddress {
+                index,
+                address: Address::from_str(&subaddress.address)?,
+            })
+        })
+        .transpose()
+}
+
+/// The number of subaddress indices of the primary account the swaps are
+/// spread over.
+const SWAP_SUBADDRESS_INDICES: u32 = 1 << 16;
+
+/// The index of the subaddress of the primary account that belongs to the
+/// swap, derived from the random bytes of the swap id. The main address at
+/// index 0 is never used, distinct swaps may share an index.
+pub fn swap_subaddress_index(swap_id: Uuid) -> u32 {
+    let bytes = swap_id.as_bytes();
+    let random = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    1 + random % (SWAP_SUBADDRESS_INDICES - 1)
+}
+
+/// How many blocks the wallet may lag behind the daemon and still be
+/// considered synced, a new block may arrive between the two requests.
+const SYNC_TOLERANCE_BLOCKS: u32 = 1;
+
 #[derive(Debug)]
 pub struct TransferRequest {
     pub public_spend_key: PublicKey,
@@ -266,7 +776,7 @@ pub struct TransferRequest {
     pub amount: Amount,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WatchRequest {
     pub public_spend_key: PublicKey,
     pub public_view_key: PublicViewKey,
@@ -279,16 +789,23 @@ async fn wait_for_confirmations<Fut>(
     txid: String,
     fetch_tx: impl Fn(String) -> Fut,
     mut check_interval: Interval,
+    rpc_timeout: Duration,
     expected: Amount,
     conf_target: u32,
-) -> Result<(), InsufficientFunds>
+) -> Result<()>
 where
     Fut: Future<Output = Result<CheckTxKey>>,
 {
     let mut seen_confirmations = 0u32;
 
     while seen_confirmations < conf_target {
-        let tx = match fetch_tx(txid.clone()).await {
+        let fetched = tokio::time::timeout(rpc_timeout, fetch_tx(txid.clone()))
+            .await
+            .map_err(|_| RpcTimeout {
+                timeout: rpc_timeout,
+            })?;
+
+        let tx = match fetched {
             Ok(proof) => proof,
             Err(error) => {
                 tracing::debug!(%txid, "Failed to retrieve tx from blockchain: {:#}", error);
@@ -301,8 +818,15 @@ where
 
         let received = Amount::from_piconero(tx.received);
 
+        // Until the transfer is in a block, the wallet may not report the full
+        // amount yet. Only a confirmed transfer is final.
+        if received != expected && tx.confirmations == 0 {
+            tracing::debug!(%txid, "Unconfirmed Monero lock tx has {} instead of {}, waiting for it to be confirmed", received, expected);
+            check_interval.tick().await;
+            continue;
+        }
         if received != expected {
-            return Err(InsufficientFunds {
+            bail!(InsufficientFunds {
                 expected,
                 actual: received,
             });
@@ -319,13 +843,635 @@ where
     Ok(())
 }
 
+async fn wait_for_incoming_confirmations<Fut>(
+    fetch_transfers: impl Fn() -> Fut,
+    mut check_interval: Interval,
+    conf_target: u32,
+    expected: Option<Amount>,
+) where
+    Fut: Future<Output = Result<Vec<TransferEntry>>>,
+{
+    let mut seen_confirmations = 0u32;
+
+    loop {
+        check_interval.tick().await;
+
+        let transfers = match fetch_transfers().await {
+            Ok(transfers) => transfers,
+            Err(error) => {
+                tracing::debug!("Failed to retrieve incoming transfers: {:#}", error);
+                continue;
+            }
+        };
+
+        let confirmations = match transfers.iter().map(|t| t.confirmations).min() {
+            Some(confirmations) => confirmations,
+            None => continue, // the wallet has not yet seen the transfer
+        };
+
+        if confirmations > seen_confirmations {
+            seen_confirmations = confirmations;
+            info!(
+                "Incoming Monero transfers have {} out of {} confirmations",
+                confirmations, conf_target
+            );
+        }
+
+        let is_final = match expected {
+            Some(expected) => {
+                let final_amount = transfers
+                    .iter()
+                    .filter(|t| t.confirmations >= conf_target)
+                    .map(|t| t.amount)
+                    .sum::<u64>();
+
+                final_amount >= expected.as_piconero()
+            }
+            None => confirmations >= conf_target,
+        };
+
+        if is_final {
+            return;
+        }
+    }
+}
+
+async fn retry_open<T, F, Fut>(attempts: u32, initial_delay: Duration, mut open: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = initial_delay;
+    let mut attempt = 1;
+
+    loop {
+        match open().await {
+            Ok(wallet) => return Ok(wallet),
+            Err(error) if attempt < attempts => {
+                tracing::warn!(
+                    "Failed to open Monero wallet (attempt {} of {}), retrying in {}s: {:#}",
+                    attempt,
+                    attempts,
+                    delay.as_secs_f64(),
+                    error
+                );
+
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(error) => {
+                return Err(error).with_context(|| {
+                    format!("Failed to open Monero wallet after {} attempts", attempt)
+                })
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::env::GetConfig;
+    use crate::monero::Scalar;
     use monero_rpc::wallet::CheckTxKey;
+    use rand::rngs::OsRng;
     use std::sync::atomic::{AtomicU32, Ordering};
     use std::sync::Arc;
 
+    #[tokio::test]
+    async fn opening_is_retried_until_wallet_rpc_is_available() {
+        let attempts = AtomicU32::new(0);
+
+        let opened = retry_open(3, Duration::from_millis(10), || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                bail!("Connection refused")
+            }
+
+            Ok("wallet")
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(opened, "wallet");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn opening_gives_up_after_the_configured_attempts() {
+        let attempts = AtomicU32::new(0);
+
+        let result = retry_open(3, Duration::from_millis(10), || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(anyhow::anyhow!("Connection refused"))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn view_only_wallet_refuses_to_spend() {
+        let wallet = Wallet {
+            view_only: true,
+            ..test_wallet(wallet::Client::localhost(0))
+        };
+        let address = wallet.get_main_address();
+
+        let sweep_error = wallet.sweep_all(address).await.unwrap_err();
+        let transfer_error = wallet
+            .transfer(TransferRequest {
+                public_spend_key: address.public_spend,
+                public_view_key: PublicViewKey(address.public_view),
+                amount: Amount::ONE_XMR,
+            })
+            .await
+            .unwrap_err();
+
+        assert!(sweep_error.downcast_ref::<ViewOnly>().is_some());
+        assert!(transfer_error.downcast_ref::<ViewOnly>().is_some());
+    }
+
+    #[tokio::test]
+    async fn view_only_wallet_is_generated_under_name_of_address() {
+        let address = random_address();
+        let (port, requests) = serve_rpc(vec![
+            r#"{"id": "0", "jsonrpc": "2.0", "error": {"code": -1, "message": "Failed to open wallet"}}"#.to_owned(),
+            r#"{"id": "0", "jsonrpc": "2.0", "result": {"address": "", "info": "Wallet has been generated successfully."}}"#.to_owned(),
+            format!(
+                r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{0}", "addresses": [{{"address": "{0}", "address_index": 0, "label": "Primary account", "used": true}}]}}}}"#,
+                address
+            ),
+        ]);
+        let view_key = PrivateViewKey::new_random(&mut OsRng);
+
+        let wallet = Wallet::open_or_create_view_only(
+            Url::parse(&format!("http://127.0.0.1:{}/json_rpc", port)).unwrap(),
+            address,
+            view_key,
+            BlockHeight { height: 100 },
+            crate::env::Regtest::get_config(),
+        )
+        .await
+        .unwrap();
+
+        let open = requests.recv().unwrap();
+        let generate = requests.recv().unwrap();
+        let view_key = PrivateKey::from(view_key).to_string();
+        assert_eq!(open["params"]["filename"], view_only_wallet_name(&address));
+        assert_eq!(
+            generate["params"]["filename"],
+            view_only_wallet_name(&address)
+        );
+        assert_eq!(generate["params"]["viewkey"], view_key.as_str());
+        assert_eq!(generate["params"]["spendkey"], "");
+        assert!(!generate["params"]["filename"]
+            .as_str()
+            .unwrap()
+            .contains(&view_key));
+        assert_eq!(wallet.get_main_address(), address);
+        assert!(wallet.view_only);
+    }
+
+    #[tokio::test]
+    async fn priority_is_passed_to_sweep_all_and_transfer() {
+        let (client, requests) = mock_rpc(vec![
+            r#"{
+              "id": "0",
+              "jsonrpc": "2.0",
+              "result": {
+                "amount_list": [29921410000],
+                "fee_list": [78590000],
+                "multisig_txset": "",
+                "tx_hash_list": ["c1d8cfa87d445c1915a59d67be3e93ba8a29018640cf69b465f07b1840a8f8c8"],
+                "unsigned_txset": "",
+                "weight_list": [1448]
+              }
+            }"#,
+            r#"{
+              "id": "0",
+              "jsonrpc": "2.0",
+              "result": {
+                "amount": 100,
+                "fee": 1,
+                "multisig_txset": "",
+                "tx_blob": "",
+                "tx_hash": "c1d8cfa87d445c1915a59d67be3e93ba8a29018640cf69b465f07b1840a8f8c8",
+                "tx_key": "0100000000000000000000000000000000000000000000000000000000000000",
+                "tx_metadata": "",
+                "unsigned_txset": ""
+              }
+            }"#,
+        ]);
+        let wallet = test_wallet(client).with_transfer_priority(TransferPriority::Elevated);
+        let address = wallet.get_main_address();
+
+        wallet.sweep_all(address).await.unwrap();
+        wallet
+            .transfer(TransferRequest {
+                public_spend_key: address.public_spend,
+                public_view_key: PublicViewKey(address.public_view),
+                amount: Amount::ONE_XMR,
+            })
+            .await
+            .unwrap();
+
+        let sweep_all = requests.recv().unwrap();
+        let transfer = requests.recv().unwrap();
+        assert_eq!(sweep_all["method"], "sweep_all");
+        assert_eq!(sweep_all["params"]["priority"], 3);
+        assert_eq!(transfer["method"], "transfer");
+        assert_eq!(transfer["params"]["priority"], 3);
+    }
+
+    #[tokio::test]
+    async fn subaddresses_are_distinct_and_balance_covers_them() {
+        let address = |index: u32| {
+            let address = random_address();
+            let response = format!(
+                r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{}", "address_index": {}}}}}"#,
+                address, index
+            );
+
+            (address, response)
+        };
+        let (first_address, first_response) = address(1);
+        let (second_address, second_response) = address(2);
+        let (client, requests) = mock_rpc(vec![
+            first_response,
+            second_response,
+            r#"{
+              "id": "0",
+              "jsonrpc": "2.0",
+              "result": {
+                "balance": 300,
+                "blocks_to_unlock": 0,
+                "multisig_import_needed": false,
+                "time_to_unlock": 0,
+                "unlocked_balance": 300
+              }
+            }"#
+            .to_owned(),
+        ]);
+        let wallet = Wallet {
+            main_address: first_address,
+            ..test_wallet(client)
+        };
+
+        let first = wallet.new_subaddress("swap 1").await.unwrap();
+        let second = wallet.new_subaddress("swap 2").await.unwrap();
+        let balance = wallet.get_balance().await.unwrap();
+
+        assert_eq!(first, Subaddress {
+            index: 1,
+            address: first_address
+        });
+        assert_eq!(second, Subaddress {
+            index: 2,
+            address: second_address
+        });
+        assert_eq!(balance, Amount::from_piconero(300));
+
+        let first_request = requests.recv().unwrap();
+        let second_request = requests.recv().unwrap();
+        let balance_request = requests.recv().unwrap();
+        assert_eq!(first_request["method"], "create_address");
+        assert_eq!(first_request["params"]["account_index"], 0);
+        assert_eq!(first_request["params"]["label"], "swap 1");
+        assert_eq!(second_request["params"]["label"], "swap 2");
+        assert_eq!(balance_request["method"], "get_balance");
+        assert_eq!(balance_request["params"]["account_index"], 0);
+    }
+
+    #[tokio::test]
+    async fn lost_wallet_is_generated_again_under_new_name() {
+        let (client, requests) = mock_rpc(vec![
+            r#"{"id": "0", "jsonrpc": "2.0", "result": {}}"#,
+            r#"{
+              "id": "0",
+              "jsonrpc": "2.0",
+              "result": {
+                "address": "",
+                "info": "Wallet has been generated successfully."
+              }
+            }"#,
+        ]);
+        let wallet = test_wallet(client);
+        let spend_key = PrivateKey::from_scalar(Scalar::random(&mut OsRng));
+        let view_key = PrivateViewKey::new_random(&mut OsRng);
+
+        wallet
+            .recreate_from_and_load(spend_key, view_key, BlockHeight { height: 100 })
+            .await
+            .unwrap();
+
+        let close = requests.recv().unwrap();
+        let generate = requests.recv().unwrap();
+        let filename = generate["params"]["filename"].as_str().unwrap();
+        let view_key = PrivateKey::from(view_key).to_string();
+        assert_eq!(close["method"], "close_wallet");
+        assert_eq!(generate["method"], "generate_from_keys");
+        assert_eq!(generate["params"]["restore_height"], 100);
+        assert_eq!(generate["params"]["viewkey"], view_key.as_str());
+        assert!(filename.starts_with(&view_key));
+        assert_ne!(filename, view_key, "previous wallet file is not reused");
+    }
+
+    #[tokio::test]
+    async fn withdrawal_respects_reserved_monero_and_reports_fee() {
+        let balance = r#"{
+              "id": "0",
+              "jsonrpc": "2.0",
+              "result": {
+                "balance": 3000000000000,
+                "blocks_to_unlock": 0,
+                "multisig_import_needed": false,
+                "time_to_unlock": 0,
+                "unlocked_balance": 3000000000000
+              }
+            }"#;
+        let (client, requests) = mock_rpc(vec![
+            balance,
+            balance,
+            r#"{
+              "id": "0",
+              "jsonrpc": "2.0",
+              "result": {
+                "amount": 1000000000000,
+                "fee": 78590000,
+                "multisig_txset": "",
+                "tx_blob": "",
+                "tx_hash": "c1d8cfa87d445c1915a59d67be3e93ba8a29018640cf69b465f07b1840a8f8c8",
+                "tx_key": "0100000000000000000000000000000000000000000000000000000000000000",
+                "tx_metadata": "",
+                "unsigned_txset": ""
+              }
+            }"#,
+        ]);
+        let wallet = test_wallet(client);
+        let address = wallet.get_main_address();
+        let reserved = Amount::ONE_XMR * 2;
+
+        let too_much = wallet
+            .withdraw(address, Some(Amount::ONE_XMR * 2), reserved)
+            .await;
+        let withdrawal = wallet
+            .withdraw(address, Some(Amount::ONE_XMR), reserved)
+            .await
+            .unwrap();
+
+        assert!(too_much.is_err(), "reserved Monero must not be withdrawn");
+        assert_eq!(withdrawal, Withdrawal {
+            tx_hashes: vec![TxHash(String::from(
+                "c1d8cfa87d445c1915a59d67be3e93ba8a29018640cf69b465f07b1840a8f8c8"
+            ))],
+            fee: Amount::from_piconero(78_590_000),
+        });
+
+        assert_eq!(requests.recv().unwrap()["method"], "get_balance");
+        assert_eq!(requests.recv().unwrap()["method"], "get_balance");
+        let transfer = requests.recv().unwrap();
+        assert_eq!(transfer["method"], "transfer");
+        assert_eq!(
+            transfer["params"]["destinations"][0]["amount"],
+            1_000_000_000_000u64
+        );
+    }
+
+    #[tokio::test]
+    async fn swap_address_is_the_same_after_reopening_the_wallet() {
+        let mut swap_id = *Uuid::new_v4().as_bytes();
+        swap_id[..4].copy_from_slice(&1u32.to_be_bytes());
+        let swap_id = Uuid::from_bytes(swap_id);
+        assert_eq!(swap_subaddress_index(swap_id), 2);
+
+        let main_address = random_address();
+        let swap_address = random_address();
+
+        let only_main_address = format!(
+            r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{0}", "addresses": [{{"address": "{0}", "address_index": 0, "label": "Primary account", "used": true}}]}}}}"#,
+            main_address
+        );
+        let created_filler = format!(
+            r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{}", "address_index": 1}}}}"#,
+            main_address
+        );
+        let created = format!(
+            r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{}", "address_index": 2}}}}"#,
+            swap_address
+        );
+        let with_swap_address = format!(
+            r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{0}", "addresses": [{{"address": "{0}", "address_index": 0, "label": "Primary account", "used": true}}, {{"address": "{0}", "address_index": 1, "label": "", "used": false}}, {{"address": "{1}", "address_index": 2, "label": "swap {2}", "used": false}}]}}}}"#,
+            main_address, swap_address, swap_id
+        );
+        let wallet = |client| Wallet {
+            main_address,
+            ..test_wallet(client)
+        };
+
+        let (client, requests) = mock_rpc(vec![only_main_address, created_filler, created]);
+        let first = wallet(client).get_address_for_swap(swap_id).await.unwrap();
+        assert_eq!(requests.recv().unwrap()["method"], "get_address");
+        // Every subaddress up to the derived index is created, only the one of the swap
+        // is labelled
+        for label in &[String::new(), format!("swap {}", swap_id)] {
+            let create_request = requests.recv().unwrap();
+            assert_eq!(create_request["method"], "create_address");
+            assert_eq!(create_request["params"]["label"], *label);
+        }
+
+        // After reopening, the subaddress is found again instead of creating another
+        // one.
+        let (client, requests) = mock_rpc(vec![with_swap_address]);
+        let reopened = wallet(client).get_address_for_swap(swap_id).await.unwrap();
+        assert_eq!(requests.recv().unwrap()["method"], "get_address");
+        assert!(requests.try_recv().is_err());
+
+        assert_eq!(first, Subaddress {
+            index: 2,
+            address: swap_address
+        });
+        assert_eq!(reopened, first);
+    }
+
+    #[tokio::test]
+    async fn stored_subaddress_is_looked_up_without_creating_one() {
+        let main_address = random_address();
+        let swap_address = random_address();
+        let addresses = format!(
+            r#"{{"id": "0", "jsonrpc": "2.0", "result": {{"address": "{0}", "addresses": [{{"address": "{0}", "address_index": 0, "label": "Primary account", "used": true}}, {{"address": "{1}", "address_index": 5, "label": "", "used": false}}]}}}}"#,
+            main_address, swap_address
+        );
+
+        let (client, requests) = mock_rpc(vec![addresses.clone()]);
+        let subaddress = test_wallet(client).get_subaddress(5).await.unwrap();
+        assert_eq!(requests.recv().unwrap()["method"], "get_address");
+        assert!(requests.try_recv().is_err());
+        assert_eq!(subaddress, Subaddress {
+            index: 5,
+            address: swap_address
+        });
+
+        let (client, _) = mock_rpc(vec![addresses]);
+        assert!(test_wallet(client).get_subaddress(3).await.is_err());
+    }
+
+    /// A wallet talking to the given client, with a random main address.
+    fn test_wallet(client: wallet::Client) -> Wallet {
+        Wallet {
+            inner: Mutex::new(client),
+            network: Network::Mainnet,
+            name: String::from("wallet"),
+            main_address: random_address(),
+            sync_interval: Duration::from_secs(1),
+            rpc_timeout: Duration::from_secs(30),
+            view_only: false,
+            priority: TransferPriority::Default,
+            daemon: None,
+            explorer: ExplorerUrl::monero(Network::Mainnet),
+        }
+    }
+
+    fn random_address() -> Address {
+        let public_key =
+            PublicKey::from_private_key(&PrivateKey::from_scalar(Scalar::random(&mut OsRng)));
+
+        Address::standard(Network::Mainnet, public_key, public_key)
+    }
+
+    fn mock_rpc(
+        responses: Vec<impl Into<String>>,
+    ) -> (wallet::Client, std::sync::mpsc::Receiver<serde_json::Value>) {
+        let (port, requests) = serve_rpc(responses);
+
+        (wallet::Client::localhost(port), requests)
+    }
+
+    /// Serves the given JSON-RPC responses, one per connection, and hands out
+    /// the received requests.
+    fn serve_rpc(
+        responses: Vec<impl Into<String>>,
+    ) -> (u16, std::sync::mpsc::Receiver<serde_json::Value>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let responses = responses
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<String>>();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, receiver) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end().to_lowercase();
+
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("content-length:") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                sender.send(serde_json::from_slice(&body).unwrap()).unwrap();
+
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+
+        (port, receiver)
+    }
+
+    #[tokio::test]
+    async fn wallet_behind_daemon_is_reported_as_syncing() {
+        let (wallet_client, _) = mock_rpc(vec![
+            r#"{"id": "0", "jsonrpc": "2.0", "result": {"height": 100}}"#,
+        ]);
+        let (daemon_port, daemon_requests) = serve_rpc(vec![
+            r#"{"id": "0", "jsonrpc": "2.0", "result": {"count": 250, "status": "OK"}}"#,
+        ]);
+        let wallet =
+            test_wallet(wallet_client).with_daemon(monerod::Client::localhost(daemon_port));
+
+        let error = wallet
+            .wait_until_synced(Duration::from_secs(0))
+            .await
+            .unwrap_err();
+
+        let not_synced = error.downcast_ref::<WalletNotSynced>().unwrap();
+        assert_eq!(not_synced.wallet, 100);
+        assert_eq!(not_synced.daemon, 250);
+        assert_eq!(
+            error.to_string(),
+            "Monero wallet still syncing (100/250 blocks)"
+        );
+        assert_eq!(daemon_requests.recv().unwrap()["method"], "get_block_count");
+    }
+
+    #[tokio::test]
+    async fn wallet_without_daemon_is_assumed_synced() {
+        let wallet = test_wallet(wallet::Client::localhost(0));
+
+        wallet
+            .wait_until_synced(Duration::from_secs(0))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stalling_rpc_fails_watch_with_retriable_timeout() {
+        // Accepts connections but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let public_key =
+            PublicKey::from_private_key(&PrivateKey::from_scalar(Scalar::random(&mut OsRng)));
+        let wallet = Wallet {
+            rpc_timeout: Duration::from_millis(200),
+            ..test_wallet(wallet::Client::localhost(port))
+        };
+
+        let error = tokio::time::timeout(
+            Duration::from_secs(10),
+            wallet.watch_for_transfer(WatchRequest {
+                public_spend_key: public_key,
+                public_view_key: PublicViewKey(public_key),
+                transfer_proof: TransferProof::new(
+                    TxHash(String::from("TXID")),
+                    PrivateKey::from_scalar(Scalar::random(&mut OsRng)),
+                ),
+                conf_target: 1,
+                expected: Amount::ONE_XMR,
+            }),
+        )
+        .await
+        .expect("stall to be detected by the RPC timeout")
+        .unwrap_err();
+        drop(listener);
+
+        assert!(error.is::<RpcTimeout>());
+        assert!(matches!(
+            crate::error::SwapError::from(error),
+            crate::error::SwapError::Network(_)
+        ));
+    }
+
     #[tokio::test]
     async fn given_exact_confirmations_does_not_fetch_tx_again() {
         let requests = Arc::new(AtomicU32::new(0));
@@ -346,6 +1492,7 @@ mod tests {
                 }
             },
             tokio::time::interval(Duration::from_millis(10)),
+            Duration::from_secs(30),
             Amount::from_piconero(100),
             10,
         )
@@ -354,6 +1501,52 @@ mod tests {
         assert!(result.is_ok())
     }
 
+    #[tokio::test]
+    async fn stalled_confirmations_keep_waiting_instead_of_failing() {
+        let result = tokio::time::timeout(
+            Duration::from_millis(200),
+            wait_for_confirmations(
+                String::from("TXID"),
+                |_| async {
+                    Ok(CheckTxKey {
+                        confirmations: 0,
+                        received: 0,
+                    })
+                },
+                tokio::time::interval(Duration::from_millis(10)),
+                Duration::from_secs(30),
+                Amount::from_piconero(100),
+                10,
+            ),
+        )
+        .await;
+
+        assert!(result.is_err(), "watch should still be waiting");
+    }
+
+    #[tokio::test]
+    async fn confirmed_transfer_with_too_little_fails_with_insufficient_funds() {
+        let error = wait_for_confirmations(
+            String::from("TXID"),
+            |_| async {
+                Ok(CheckTxKey {
+                    confirmations: 1,
+                    received: 50,
+                })
+            },
+            tokio::time::interval(Duration::from_millis(10)),
+            Duration::from_secs(30),
+            Amount::from_piconero(100),
+            10,
+        )
+        .await
+        .unwrap_err();
+
+        let insufficient = error.downcast_ref::<InsufficientFunds>().unwrap();
+        assert_eq!(insufficient.expected, Amount::from_piconero(100));
+        assert_eq!(insufficient.actual, Amount::from_piconero(50));
+    }
+
     /// A test that allows us to easily, visually verify if the log output is as
     /// we desire.
     ///
@@ -387,6 +1580,7 @@ mod tests {
                 }
             },
             tokio::time::interval(Duration::from_millis(10)),
+            Duration::from_secs(30),
             Amount::from_piconero(100),
             10,
         )
@@ -394,4 +1588,69 @@ mod tests {
 
         assert!(result.is_ok())
     }
+
+    #[tokio::test]
+    async fn waits_until_incoming_transfers_reach_confirmation_target() {
+        let requests = Arc::new(AtomicU32::new(0));
+
+        wait_for_incoming_confirmations(
+            {
+                let requests = requests.clone();
+                move || {
+                    let requests = requests.clone();
+
+                    async move {
+                        let confirmations = requests.fetch_add(1, Ordering::SeqCst);
+
+                        Ok(vec![TransferEntry {
+                            txid: String::from("TXID"),
+                            amount: 100,
+                            confirmations,
+                            height: 1,
+                        }])
+                    }
+                }
+            },
+            tokio::time::interval(Duration::from_millis(10)),
+            5,
+            None,
+        )
+        .await;
+
+        assert_eq!(requests.load(Ordering::SeqCst), 6)
+    }
+
+    #[tokio::test]
+    async fn waits_until_transfers_of_expected_amount_reach_confirmation_target() {
+        let requests = Arc::new(AtomicU32::new(0));
+        let transfer = |txid: &str, confirmations| TransferEntry {
+            txid: txid.to_owned(),
+            amount: 50,
+            confirmations,
+            height: 1,
+        };
+
+        wait_for_incoming_confirmations(
+            {
+                let requests = requests.clone();
+                move || {
+                    let requests = requests.clone();
+
+                    async move {
+                        // The second half only shows up once it is in a block
+                        Ok(match requests.fetch_add(1, Ordering::SeqCst) {
+                            0 => vec![transfer("FIRST", 10)],
+                            request => vec![transfer("FIRST", 10), transfer("SECOND", request)],
+                        })
+                    }
+                }
+            },
+            tokio::time::interval(Duration::from_millis(10)),
+            5,
+            Some(Amount::from_piconero(100)),
+        )
+        .await;
+
+        assert_eq!(requests.load(Ordering::SeqCst), 6)
+    }
 }
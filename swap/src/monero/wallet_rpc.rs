@@ -51,6 +51,26 @@ pub struct WalletRpc {
     working_dir: PathBuf,
 }
 
+/// The JSON-RPC endpoint of the given daemon host.
+///
+/// Like the monero-wallet-rpc, the default port of the network is assumed if
+/// the host does not include one.
+pub fn daemon_url(daemon_host: &str, network: Network) -> Result<Url> {
+    let default_port = match network {
+        Network::Mainnet => 18081,
+        Network::Stagenet => 38081,
+        Network::Testnet => 28081,
+    };
+    let host = if daemon_host.contains(':') {
+        daemon_host.to_owned()
+    } else {
+        format!("{}:{}", daemon_host, default_port)
+    };
+
+    Url::parse(&format!("http://{}/json_rpc", host))
+        .with_context(|| format!("Invalid monero daemon host {}", daemon_host))
+}
+
 impl WalletRpc {
     pub async fn new(working_dir: impl AsRef<Path>) -> Result<WalletRpc> {
         let working_dir = working_dir.as_ref();
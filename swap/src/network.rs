@@ -1,4 +1,6 @@
+pub mod keepalive;
 pub mod peer_tracker;
+pub mod protocol_version;
 pub mod quote;
 pub mod request_response;
 pub mod spot_price;
@@ -0,0 +1,176 @@
+use libp2p::ping::{Ping, PingConfig, PingEvent};
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub type OutEvent = PingEvent;
+
+/// A behaviour that regularly pings the counterparty.
+///
+/// Without it, libp2p closes connections once no request is pending and NATs
+/// along the way silently drop mappings of idle connections, e.g. while
+/// waiting for the Monero to be locked. A connection whose pings are not
+/// answered within `timeout` is closed, so that it is re-established on the
+/// next dial.
+///
+/// Pings alone keep a connection open forever, see [`IdlePeers`] for closing
+/// connections that are no longer needed.
+pub fn behaviour(interval: Duration, timeout: Duration) -> Ping {
+    Ping::new(
+        PingConfig::new()
+            .with_interval(interval)
+            .with_timeout(timeout)
+            .with_keep_alive(true),
+    )
+}
+
+/// Remembers when each peer was last active, i.e. did anything but answer
+/// pings, so that connections kept open only by [`behaviour`] can be closed.
+#[derive(Debug, Default)]
+pub struct IdlePeers {
+    last_active: HashMap<PeerId, Instant>,
+}
+
+impl IdlePeers {
+    pub fn active(&mut self, peer: PeerId) {
+        self.active_at(peer, Instant::now());
+    }
+
+    fn active_at(&mut self, peer: PeerId, at: Instant) {
+        self.last_active.insert(peer, at);
+    }
+
+    /// Forgets and returns the peers that were not active within `timeout`.
+    pub fn take_idle(&mut self, timeout: Duration) -> Vec<PeerId> {
+        self.take_idle_at(Instant::now(), timeout)
+    }
+
+    fn take_idle_at(&mut self, now: Instant, timeout: Duration) -> Vec<PeerId> {
+        let idle = self
+            .last_active
+            .iter()
+            .filter(|(_, last_active)| now.saturating_duration_since(**last_active) >= timeout)
+            .map(|(peer, _)| *peer)
+            .collect::<Vec<_>>();
+
+        for peer in &idle {
+            self.last_active.remove(peer);
+        }
+
+        idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{transport, TokioExecutor};
+    use libp2p::core::identity;
+    use libp2p::swarm::{SwarmBuilder, SwarmEvent};
+    use libp2p::{PeerId, Swarm};
+
+    fn swarm(keep_alive: bool) -> (Swarm<Ping>, PeerId) {
+        let identity = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(identity.public());
+        let behaviour = Ping::new(
+            PingConfig::new()
+                .with_interval(Duration::from_millis(100))
+                .with_keep_alive(keep_alive),
+        );
+
+        let swarm = SwarmBuilder::new(transport::build(&identity).unwrap(), behaviour, peer_id)
+            .executor(Box::new(TokioExecutor {
+                handle: tokio::runtime::Handle::current(),
+            }))
+            .build();
+
+        (swarm, peer_id)
+    }
+
+    /// Connects two swarms and drives them for a period without any requests,
+    /// returning whether the connection was closed in the meantime.
+    async fn connection_closed_while_idle(keep_alive: bool, idle: Duration) -> bool {
+        let (mut alice, alice_peer_id) = swarm(keep_alive);
+        let (mut bob, _) = swarm(keep_alive);
+
+        Swarm::listen_on(&mut alice, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let address = loop {
+            if let SwarmEvent::NewListenAddr(address) = alice.next_event().await {
+                break address;
+            }
+        };
+        Swarm::dial_addr(&mut bob, address).unwrap();
+
+        loop {
+            if let SwarmEvent::ConnectionEstablished { .. } = bob.next_event().await {
+                break;
+            }
+        }
+
+        let idle = tokio::time::sleep(idle);
+        tokio::pin!(idle);
+
+        loop {
+            tokio::select! {
+                _ = &mut idle => break,
+                event = alice.next_event() => if let SwarmEvent::ConnectionClosed { .. } = event {
+                    return true;
+                },
+                event = bob.next_event() => if let SwarmEvent::ConnectionClosed { .. } = event {
+                    return true;
+                },
+            }
+        }
+
+        !Swarm::is_connected(&bob, &alice_peer_id)
+    }
+
+    #[tokio::test]
+    async fn idle_connection_survives_with_keepalive() {
+        let closed = connection_closed_while_idle(true, Duration::from_secs(2)).await;
+
+        assert!(!closed);
+    }
+
+    #[tokio::test]
+    async fn idle_connection_is_closed_without_keepalive() {
+        let closed = connection_closed_while_idle(false, Duration::from_secs(2)).await;
+
+        assert!(closed);
+    }
+
+    #[test]
+    fn only_peers_inactive_for_the_timeout_are_idle() {
+        let start = Instant::now();
+        let stale = PeerId::random();
+        let recent = PeerId::random();
+        let mut peers = IdlePeers::default();
+
+        peers.active_at(stale, start);
+        peers.active_at(recent, start + Duration::from_secs(60));
+        let idle = peers.take_idle_at(start + Duration::from_secs(300), Duration::from_secs(300));
+
+        assert_eq!(idle, vec![stale]);
+        assert!(peers
+            .take_idle_at(start + Duration::from_secs(300), Duration::from_secs(300))
+            .is_empty());
+    }
+
+    #[test]
+    fn activity_postpones_idleness() {
+        let start = Instant::now();
+        let peer = PeerId::random();
+        let mut peers = IdlePeers::default();
+
+        peers.active_at(peer, start);
+        peers.active_at(peer, start + Duration::from_secs(200));
+
+        assert!(peers
+            .take_idle_at(start + Duration::from_secs(300), Duration::from_secs(300))
+            .is_empty());
+        assert_eq!(
+            peers.take_idle_at(start + Duration::from_secs(500), Duration::from_secs(300)),
+            vec![peer]
+        );
+    }
+}
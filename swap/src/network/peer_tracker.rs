@@ -19,7 +19,7 @@ pub enum OutEvent {
 #[derive(Default, Debug)]
 pub struct Behaviour {
     connected: Option<(PeerId, Multiaddr)>,
-    address_of_peer: HashMap<PeerId, Multiaddr>,
+    addresses_of_peer: HashMap<PeerId, Vec<Multiaddr>>,
     events: VecDeque<OutEvent>,
 }
 
@@ -50,9 +50,14 @@ impl Behaviour {
         None
     }
 
-    /// Add an address for a given peer. We only store one address per peer.
+    /// Add an address for a given peer. Addresses are dialed in the order they
+    /// were added, later ones serve as fallback.
     pub fn add_address(&mut self, peer_id: PeerId, address: Multiaddr) {
-        self.address_of_peer.insert(peer_id, address);
+        let addresses = self.addresses_of_peer.entry(peer_id).or_default();
+
+        if !addresses.contains(&address) {
+            addresses.push(address);
+        }
     }
 }
 
@@ -73,8 +78,8 @@ impl NetworkBehaviour for Behaviour {
             }
         }
 
-        if let Some(addr) = self.address_of_peer.get(peer_id) {
-            addresses.push(addr.clone());
+        if let Some(known_addresses) = self.addresses_of_peer.get(peer_id) {
+            addresses.extend(known_addresses.iter().cloned());
         }
 
         addresses
@@ -124,3 +129,25 @@ impl NetworkBehaviour for Behaviour {
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_returned_in_the_order_they_were_added() {
+        let peer_id = PeerId::random();
+        let stored = "/ip4/127.0.0.1/tcp/9939".parse::<Multiaddr>().unwrap();
+        let fallback = "/ip4/127.0.0.1/tcp/9940".parse::<Multiaddr>().unwrap();
+
+        let mut behaviour = Behaviour::default();
+        behaviour.add_address(peer_id, stored.clone());
+        behaviour.add_address(peer_id, fallback.clone());
+        behaviour.add_address(peer_id, stored.clone());
+
+        assert_eq!(behaviour.addresses_of_peer(&peer_id), vec![
+            stored, fallback
+        ]);
+        assert!(behaviour.addresses_of_peer(&PeerId::random()).is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::network::request_response::CborCodec;
+use libp2p::core::ProtocolName;
+use libp2p::request_response::{
+    ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
+};
+use serde::{Deserialize, Serialize};
+
+/// The version of the swap protocol spoken by this node.
+///
+/// Bump this whenever a change to one of the protocols makes it incompatible
+/// with previous releases.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional features of the swap protocol supported by this node.
+pub const FEATURES: &[&str] = &["liquidity-proof"];
+
+pub type OutEvent = RequestResponseEvent<Handshake, Handshake>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtocolVersionProtocol;
+
+impl ProtocolName for ProtocolVersionProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/comit/xmr/btc/protocol-version/1.0.0"
+    }
+}
+
+/// The protocol version and features a node supports, exchanged with every
+/// peer upon connecting.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub version: u32,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl Handshake {
+    /// The handshake describing this node.
+    pub fn ours() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+        }
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.iter().any(|supported| supported == feature)
+    }
+
+    /// Check that the other peer speaks the same version of the protocol.
+    pub fn check(&self, theirs: &Handshake) -> Result<(), VersionMismatch> {
+        if self.version != theirs.version {
+            return Err(VersionMismatch {
+                ours: self.version,
+                theirs: theirs.version,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+#[error("Protocol version mismatch: we speak version {ours} but the peer speaks version {theirs}, one of the two has to upgrade")]
+pub struct VersionMismatch {
+    pub ours: u32,
+    pub theirs: u32,
+}
+
+pub type Behaviour = RequestResponse<CborCodec<ProtocolVersionProtocol, Handshake, Handshake>>;
+
+/// Constructs a new instance of the `protocol_version` behaviour to be used by
+/// Alice.
+///
+/// Alice only supports inbound connections, i.e. answering handshakes.
+pub fn alice() -> Behaviour {
+    Behaviour::new(
+        CborCodec::default(),
+        vec![(ProtocolVersionProtocol, ProtocolSupport::Inbound)],
+        RequestResponseConfig::default(),
+    )
+}
+
+/// Constructs a new instance of the `protocol_version` behaviour to be used by
+/// Bob.
+///
+/// Bob only supports outbound connections, i.e. initiating handshakes.
+pub fn bob() -> Behaviour {
+    Behaviour::new(
+        CborCodec::default(),
+        vec![(ProtocolVersionProtocol, ProtocolSupport::Outbound)],
+        RequestResponseConfig::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{transport, TokioExecutor};
+    use libp2p::core::identity;
+    use libp2p::request_response::RequestResponseMessage;
+    use libp2p::swarm::{SwarmBuilder, SwarmEvent};
+    use libp2p::{PeerId, Swarm};
+
+    fn swarm(behaviour: Behaviour) -> (Swarm<Behaviour>, PeerId) {
+        let identity = identity::Keypair::generate_ed25519();
+        let peer_id = PeerId::from(identity.public());
+
+        let swarm = SwarmBuilder::new(transport::build(&identity).unwrap(), behaviour, peer_id)
+            .executor(Box::new(TokioExecutor {
+                handle: tokio::runtime::Handle::current(),
+            }))
+            .build();
+
+        (swarm, peer_id)
+    }
+
+    /// Connects Bob to Alice, exchanges the given handshakes and returns the
+    /// result of the check on both sides.
+    async fn negotiate(
+        alice_handshake: Handshake,
+        bob_handshake: Handshake,
+    ) -> (Result<(), VersionMismatch>, Result<(), VersionMismatch>) {
+        let (mut alice, alice_peer_id) = swarm(super::alice());
+        let (mut bob, _) = swarm(super::bob());
+
+        Swarm::listen_on(&mut alice, "/ip4/127.0.0.1/tcp/0".parse().unwrap()).unwrap();
+        let address = loop {
+            if let SwarmEvent::NewListenAddr(address) = alice.next_event().await {
+                break address;
+            }
+        };
+        bob.add_address(&alice_peer_id, address);
+        bob.send_request(&alice_peer_id, bob_handshake.clone());
+
+        let mut alice_result = None;
+        loop {
+            tokio::select! {
+                event = alice.next_event() => if let SwarmEvent::Behaviour(RequestResponseEvent::Message {
+                    message: RequestResponseMessage::Request { request, channel, .. },
+                    ..
+                }) = event {
+                    alice_result = Some(alice_handshake.check(&request));
+                    alice.send_response(channel, alice_handshake.clone()).unwrap();
+                },
+                event = bob.next_event() => match event {
+                    SwarmEvent::Behaviour(RequestResponseEvent::Message {
+                        message: RequestResponseMessage::Response { response, .. },
+                        ..
+                    }) => {
+                        let bob_result = bob_handshake.check(&response);
+                        return (alice_result.expect("Alice to answer the handshake"), bob_result);
+                    }
+                    SwarmEvent::Behaviour(RequestResponseEvent::OutboundFailure { error, .. }) => {
+                        panic!("Handshake failed: {:?}", error)
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_of_same_version_agree() {
+        let (alice, bob) = negotiate(Handshake::ours(), Handshake::ours()).await;
+
+        assert!(alice.is_ok());
+        assert!(bob.is_ok());
+    }
+
+    #[tokio::test]
+    async fn peers_of_differing_versions_report_mismatch() {
+        let newer = Handshake {
+            version: PROTOCOL_VERSION + 1,
+            features: vec![],
+        };
+
+        let (alice, bob) = negotiate(Handshake::ours(), newer).await;
+
+        assert_eq!(alice.unwrap_err(), VersionMismatch {
+            ours: PROTOCOL_VERSION,
+            theirs: PROTOCOL_VERSION + 1
+        });
+        let error = bob.unwrap_err();
+        assert_eq!(error, VersionMismatch {
+            ours: PROTOCOL_VERSION + 1,
+            theirs: PROTOCOL_VERSION
+        });
+        assert!(error.to_string().contains("Protocol version mismatch"));
+    }
+
+    #[test]
+    fn handshake_lists_supported_features() {
+        let handshake = Handshake::ours();
+
+        assert!(handshake.supports("liquidity-proof"));
+        assert!(!handshake.supports("unknown"));
+    }
+}
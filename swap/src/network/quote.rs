@@ -1,10 +1,18 @@
-use crate::bitcoin;
 use crate::network::request_response::CborCodec;
-use libp2p::core::ProtocolName;
+use crate::{bitcoin, monero};
+use anyhow::Result;
+use libp2p::core::{identity, ProtocolName};
 use libp2p::request_response::{
     ProtocolSupport, RequestResponse, RequestResponseConfig, RequestResponseEvent,
 };
+use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a liquidity advertisement is valid after it was signed. Quotes are
+/// requested right before they are used, this only has to cover clock skew
+/// between maker and taker.
+pub const LIQUIDITY_PROOF_VALIDITY: Duration = Duration::from_secs(10 * 60);
 
 pub type OutEvent = RequestResponseEvent<(), BidQuote>;
 
@@ -26,6 +34,100 @@ pub struct BidQuote {
     /// The maximum quantity the maker is willing to buy.
     #[serde(with = "::bitcoin::util::amount::serde::as_sat")]
     pub max_quantity: bitcoin::Amount,
+    /// The liquidity the maker advertises, if any.
+    #[serde(default)]
+    pub liquidity_proof: Option<LiquidityProof>,
+}
+
+impl BidQuote {
+    /// The liquidity advertised with this quote, if the maker advertises
+    /// any.
+    ///
+    /// Fails if the advertisement was not signed by the given maker or
+    /// expired.
+    pub fn verified_liquidity(&self, maker: &PeerId) -> Result<Option<Liquidity>> {
+        match &self.liquidity_proof {
+            Some(proof) => Ok(Some(proof.verify(maker, SystemTime::now())?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The Monero a maker claims to be able to sell and optionally a Bitcoin
+/// transaction locking funds as fidelity bond.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Liquidity {
+    pub max_sell: monero::Amount,
+    pub fidelity_bond: Option<bitcoin::Txid>,
+}
+
+/// A [`Liquidity`] statement signed with the identity of the maker, valid
+/// until the signed expiry.
+///
+/// This only proves that the maker stands behind the statement, not that the
+/// funds actually exist.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiquidityProof {
+    liquidity: Liquidity,
+    /// Seconds since the unix epoch after which the statement must not be
+    /// trusted anymore. Statements of makers that don't sign an expiry are
+    /// treated as expired.
+    #[serde(default)]
+    expires_at: u64,
+    /// The protobuf encoded public key of the maker.
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl LiquidityProof {
+    pub fn sign(
+        liquidity: Liquidity,
+        expires_at: SystemTime,
+        identity: &identity::Keypair,
+    ) -> Result<Self> {
+        let expires_at = expires_at.duration_since(UNIX_EPOCH)?.as_secs();
+        let signature = identity.sign(&serde_cbor::to_vec(&(liquidity, expires_at))?)?;
+
+        Ok(Self {
+            liquidity,
+            expires_at,
+            public_key: identity.public().into_protobuf_encoding(),
+            signature,
+        })
+    }
+
+    /// Check that the statement was signed by the given maker and has not
+    /// expired by `now`.
+    pub fn verify(
+        &self,
+        maker: &PeerId,
+        now: SystemTime,
+    ) -> Result<Liquidity, InvalidLiquidityProof> {
+        let public_key = identity::PublicKey::from_protobuf_encoding(&self.public_key)
+            .map_err(|_| InvalidLiquidityProof::Signature)?;
+        let message = serde_cbor::to_vec(&(self.liquidity, self.expires_at))
+            .map_err(|_| InvalidLiquidityProof::Signature)?;
+
+        if &PeerId::from(public_key.clone()) != maker
+            || !public_key.verify(&message, &self.signature)
+        {
+            return Err(InvalidLiquidityProof::Signature);
+        }
+
+        if now > UNIX_EPOCH + Duration::from_secs(self.expires_at) {
+            return Err(InvalidLiquidityProof::Expired);
+        }
+
+        Ok(self.liquidity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum InvalidLiquidityProof {
+    #[error("The liquidity advertisement was not signed by the seller")]
+    Signature,
+    #[error("The liquidity advertisement expired")]
+    Expired,
 }
 
 pub type Behaviour = RequestResponse<CborCodec<BidQuoteProtocol, (), BidQuote>>;
@@ -51,3 +153,92 @@ pub fn bob() -> Behaviour {
         RequestResponseConfig::default(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn liquidity() -> Liquidity {
+        Liquidity {
+            max_sell: monero::Amount::ONE_XMR,
+            fidelity_bond: None,
+        }
+    }
+
+    fn expires_at() -> SystemTime {
+        SystemTime::now() + LIQUIDITY_PROOF_VALIDITY
+    }
+
+    #[test]
+    fn valid_liquidity_proof_is_accepted() {
+        let identity = identity::Keypair::generate_ed25519();
+        let maker = PeerId::from(identity.public());
+
+        let proof = LiquidityProof::sign(liquidity(), expires_at(), &identity).unwrap();
+
+        assert_eq!(
+            proof.verify(&maker, SystemTime::now()).unwrap(),
+            liquidity()
+        );
+    }
+
+    #[test]
+    fn liquidity_proof_of_other_peer_is_rejected() {
+        let identity = identity::Keypair::generate_ed25519();
+        let other = PeerId::from(identity::Keypair::generate_ed25519().public());
+
+        let proof = LiquidityProof::sign(liquidity(), expires_at(), &identity).unwrap();
+
+        assert_eq!(
+            proof.verify(&other, SystemTime::now()).unwrap_err(),
+            InvalidLiquidityProof::Signature
+        );
+    }
+
+    #[test]
+    fn tampered_liquidity_proof_is_rejected() {
+        let identity = identity::Keypair::generate_ed25519();
+        let maker = PeerId::from(identity.public());
+
+        let mut proof = LiquidityProof::sign(liquidity(), expires_at(), &identity).unwrap();
+        proof.liquidity.max_sell = monero::Amount::ONE_XMR * 100;
+
+        assert_eq!(
+            proof.verify(&maker, SystemTime::now()).unwrap_err(),
+            InvalidLiquidityProof::Signature
+        );
+    }
+
+    #[test]
+    fn replayed_expired_liquidity_proof_is_rejected() {
+        let identity = identity::Keypair::generate_ed25519();
+        let maker = PeerId::from(identity.public());
+        let proof = LiquidityProof::sign(liquidity(), expires_at(), &identity).unwrap();
+
+        let replayed =
+            serde_cbor::from_slice::<LiquidityProof>(&serde_cbor::to_vec(&proof).unwrap()).unwrap();
+
+        assert_eq!(
+            replayed
+                .verify(&maker, SystemTime::now() + LIQUIDITY_PROOF_VALIDITY * 2)
+                .unwrap_err(),
+            InvalidLiquidityProof::Expired
+        );
+    }
+
+    #[test]
+    fn extended_expiry_of_liquidity_proof_is_rejected() {
+        let identity = identity::Keypair::generate_ed25519();
+        let maker = PeerId::from(identity.public());
+
+        let mut proof = LiquidityProof::sign(liquidity(), expires_at(), &identity).unwrap();
+        proof.expires_at += LIQUIDITY_PROOF_VALIDITY.as_secs() * 2;
+
+        assert_eq!(
+            proof
+                .verify(&maker, SystemTime::now() + LIQUIDITY_PROOF_VALIDITY * 2)
+                .unwrap_err(),
+            InvalidLiquidityProof::Signature
+        );
+    }
+}
@@ -33,8 +33,18 @@ pub struct Request {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Response {
-    pub xmr: monero::Amount,
+pub enum Response {
+    Xmr(monero::Amount),
+    Error(Error),
+}
+
+/// Why Alice refuses to provide a spot price.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("The maker does not accept swaps from this peer")]
+    PeerNotAllowed,
+    #[error("The maker is temporarily unavailable, try again later")]
+    MakerUnavailable,
 }
 
 pub type Behaviour = RequestResponse<CborCodec<SpotPriceProtocol, Request, Response>>;
@@ -1,11 +1,14 @@
+use crate::database::{Database, Failure};
 use conquer_once::Lazy;
 use ecdsa_fun::fun::marker::Mark;
 use sha2::Sha256;
 use sigma_fun::ext::dl_secp256k1_ed25519_eq::CrossCurveDLEQ;
 use sigma_fun::HashTranscript;
+use uuid::Uuid;
 
 pub mod alice;
 pub mod bob;
+pub mod urgency;
 
 pub static CROSS_CURVE_PROOF_SYSTEM: Lazy<
     CrossCurveDLEQ<HashTranscript<Sha256, rand_chacha::ChaCha20Rng>>,
@@ -16,6 +19,71 @@ pub static CROSS_CURVE_PROOF_SYSTEM: Lazy<
     )
 });
 
+/// The next action that safely moves an unfinished swap towards completion
+/// if it is not progressing on its own.
+///
+/// Actions that depend on a timelock can only be taken once it expired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    PublishCancel,
+    PublishRefund,
+    PublishPunish,
+    /// Alice redeems the Bitcoin with the encrypted signature she learned.
+    PublishRedeem,
+    ClaimXmr,
+    /// The cancel timelock has to expire before the swap can be cancelled.
+    WaitForCancelTimelock,
+    /// Nothing of value is locked by the party yet, the swap can be abandoned.
+    Nothing,
+}
+
+/// Run the execution setup between Alice and Bob in-process and return their
+/// states once it is done.
+#[cfg(test)]
+pub(crate) async fn execution_setup(
+    bitcoin_wallet: &crate::bitcoin::mock::MockWallet,
+) -> (alice::State3, bob::State2) {
+    use crate::env::{GetConfig, Regtest};
+    use rand::rngs::OsRng;
+
+    let btc = crate::bitcoin::Amount::from_sat(1_000_000);
+    let xmr = crate::monero::Amount::ONE_XMR;
+    let env_config = Regtest::get_config();
+
+    let alice = alice::State0::new(btc, xmr, env_config, bitcoin_wallet, &mut OsRng)
+        .await
+        .unwrap();
+    let bob = bob::State0::new(
+        &mut OsRng,
+        btc,
+        xmr,
+        env_config.bitcoin_cancel_timelock,
+        env_config.bitcoin_punish_timelock,
+        bitcoin_wallet.new_address().await.unwrap(),
+        env_config.monero_finality_confirmations,
+    );
+
+    let alice = alice.receive(bob.next_message()).unwrap();
+    let bob = bob
+        .receive(bitcoin_wallet, alice.next_message())
+        .await
+        .unwrap();
+    let alice = alice.receive(bob.next_message());
+    let bob = bob.receive(alice.next_message()).unwrap();
+    let alice = alice.receive(bob.next_message()).unwrap();
+
+    (alice, bob)
+}
+
+/// Persist why the swap failed so it can be looked up later.
+///
+/// Failing to do so is only logged to not shadow the error of the swap.
+async fn record_failure(db: &Database, swap_id: Uuid, error: &anyhow::Error) {
+    if let Err(e) = db.insert_failure(swap_id, Failure::new(error)).await {
+        tracing::warn!(%swap_id, "Failed to record why the swap failed: {:#}", e);
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct StartingBalances {
     pub xmr: crate::monero::Amount,
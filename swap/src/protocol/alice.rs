@@ -3,10 +3,14 @@
 use crate::database::Database;
 use crate::env::Config;
 use crate::{bitcoin, monero};
+use std::path::PathBuf;
 use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use uuid::Uuid;
 
+pub use self::abort::{force_abort, request_abort};
 pub use self::behaviour::{Behaviour, OutEvent};
+pub use self::cold_sign::{sign_redeem, SealedKey, SwapKey};
 pub use self::event_loop::{EventLoop, EventLoopHandle};
 pub use self::execution_setup::Message1;
 pub use self::state::*;
@@ -14,7 +18,9 @@ pub use self::swap::{run, run_until};
 pub use self::transfer_proof::TransferProof;
 pub use execution_setup::Message3;
 
+mod abort;
 mod behaviour;
+mod cold_sign;
 mod encrypted_signature;
 pub mod event_loop;
 mod execution_setup;
@@ -30,4 +36,17 @@ pub struct Swap {
     pub env_config: Config,
     pub swap_id: Uuid,
     pub db: Arc<Database>,
+    /// Whether to punish if the counterparty neither redeems nor refunds.
+    pub punish: bool,
+    /// The directory the redeem transaction is exchanged through if the key of
+    /// the swap is sealed for cold signing.
+    pub cold_sign_dir: Option<PathBuf>,
+    /// The directory the operator requests to abort the swap through while it
+    /// waits for the Bitcoin lock, see [`request_abort`].
+    pub abort_dir: Option<PathBuf>,
+    /// Held while a resumed swap drives its first transition.
+    pub resume_permit: Option<OwnedSemaphorePermit>,
+    /// Bounds how many resumed swaps drive a transition at the same time, a
+    /// swap releases its permit while it waits for the blockchain or Bob.
+    pub resume_limit: Option<Arc<Semaphore>>,
 }
@@ -0,0 +1,125 @@
+use crate::bitcoin::wallet::BitcoinWallet;
+use crate::database::{Database, Swap};
+use crate::protocol::alice::AliceState;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often a swap waiting for the Bitcoin lock checks whether the operator
+/// requested to abort it.
+const ABORT_REQUEST_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Abort a swap in which the Bitcoin has not been locked yet, e.g. because it
+/// got stuck while waiting for Bob.
+///
+/// Refuses to abort a swap once the Bitcoin lock transaction has been seen,
+/// the Bitcoin of Bob is at stake then and the swap has to be resumed instead.
+pub async fn force_abort(
+    swap_id: Uuid,
+    state: AliceState,
+    bitcoin_wallet: &dyn BitcoinWallet,
+    db: &Database,
+) -> Result<AliceState> {
+    let state3 = match state {
+        AliceState::Started { state3 } => state3,
+        _ => bail!(
+            "Cannot abort swap {} because it is in state {}. Only swaps in which the Bitcoin has not been locked can be aborted.",
+            swap_id,
+            state
+        ),
+    };
+
+    // The known script histories report the lock as unseen until its script was
+    // fetched, e.g. right after the ASB started
+    let lock_status = bitcoin_wallet
+        .fetch_status_of_script(&state3.tx_lock)
+        .await?;
+    if lock_status.has_been_seen() {
+        bail!(
+            "Cannot abort swap {} because the Bitcoin lock transaction {} has been published, resume the swap instead.",
+            swap_id,
+            state3.tx_lock.txid()
+        )
+    }
+
+    let state = AliceState::SafelyAborted;
+    db.insert_latest_state(swap_id, Swap::Alice((&state).into()))
+        .await?;
+
+    Ok(state)
+}
+
+/// Ask the running ASB to abort the given swap by placing a request in the
+/// given directory.
+///
+/// The swap picks the request up while it waits for Bob to lock the Bitcoin.
+/// It is only aborted if the lock transaction has not been seen by then.
+pub fn request_abort(dir: &Path, swap_id: Uuid) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+    let path = dir.join(swap_id.to_string());
+    fs::write(&path, [])
+        .with_context(|| format!("Failed to write abort request {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Resolves once an abort of the given swap was requested through the given
+/// directory, never if there is none.
+pub(crate) async fn abort_requested(dir: Option<&Path>, swap_id: Uuid) {
+    let path = match dir {
+        Some(dir) => dir.join(swap_id.to_string()),
+        None => return futures::future::pending().await,
+    };
+
+    while !path.exists() {
+        tokio::time::sleep(ABORT_REQUEST_POLL_INTERVAL).await;
+    }
+}
+
+/// Remove a handled abort request, it is not acted on again after a restart.
+pub(crate) fn clear_abort_request(dir: Option<&Path>, swap_id: Uuid) {
+    let path = match dir {
+        Some(dir) => dir.join(swap_id.to_string()),
+        None => return,
+    };
+
+    if let Err(error) = fs::remove_file(&path) {
+        tracing::warn!(%swap_id, "Failed to remove abort request {}: {:#}", path.display(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::mock::MockWallet;
+    use crate::bitcoin::wallet::ScriptStatus;
+    use crate::protocol::execution_setup;
+
+    #[tokio::test]
+    async fn lock_not_fetched_yet_prevents_abort() {
+        let bitcoin_wallet = MockWallet::default();
+        let (state3, _) = execution_setup(&bitcoin_wallet).await;
+        let tx_lock_id = state3.tx_lock.txid();
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        bitcoin_wallet.set_status(tx_lock_id, ScriptStatus::from_confirmations(1));
+        bitcoin_wallet.set_stale_status(tx_lock_id, ScriptStatus::Unseen);
+
+        let result = force_abort(
+            Uuid::new_v4(),
+            AliceState::Started {
+                state3: Box::new(state3),
+            },
+            &bitcoin_wallet,
+            &db,
+        )
+        .await;
+
+        assert!(result.is_err(), "a seen lock must prevent the abort");
+    }
+}
@@ -1,12 +1,13 @@
 use crate::env::Config;
 use crate::network::quote::BidQuote;
-use crate::network::{peer_tracker, quote, spot_price};
+use crate::network::{keepalive, peer_tracker, protocol_version, quote, spot_price};
 use crate::protocol::alice::{
     encrypted_signature, execution_setup, transfer_proof, State0, State3, TransferProof,
 };
 use crate::protocol::bob::EncryptedSignature;
 use crate::{bitcoin, monero};
 use anyhow::{anyhow, Error, Result};
+use libp2p::ping::{Ping, PingFailure, PingSuccess};
 use libp2p::request_response::{RequestResponseMessage, ResponseChannel};
 use libp2p::{NetworkBehaviour, PeerId};
 use rand::{CryptoRng, RngCore};
@@ -15,6 +16,12 @@ use tracing::debug;
 #[derive(Debug)]
 pub enum OutEvent {
     ConnectionEstablished(PeerId),
+    ProtocolVersionReceived {
+        handshake: protocol_version::Handshake,
+        channel: ResponseChannel<protocol_version::Handshake>,
+        peer: PeerId,
+    },
+    ProtocolVersionAnswered(PeerId),
     SpotPriceRequested {
         msg: spot_price::Request,
         channel: ResponseChannel<spot_price::Response>,
@@ -35,12 +42,36 @@ pub enum OutEvent {
         peer: PeerId,
     },
     ResponseSent, // Same variant is used for all messages as no processing is done
+    Pinged {
+        peer: PeerId,
+        result: Result<PingSuccess, PingFailure>,
+    },
     Failure {
         peer: PeerId,
         error: Error,
     },
 }
 
+impl OutEvent {
+    /// The peer that did something other than answering a ping, if any.
+    pub fn active_peer(&self) -> Option<PeerId> {
+        match self {
+            OutEvent::ConnectionEstablished(peer)
+            | OutEvent::ProtocolVersionReceived { peer, .. }
+            | OutEvent::ProtocolVersionAnswered(peer)
+            | OutEvent::SpotPriceRequested { peer, .. }
+            | OutEvent::QuoteRequested { peer, .. }
+            | OutEvent::ExecutionSetupDone {
+                bob_peer_id: peer, ..
+            }
+            | OutEvent::TransferProofAcknowledged(peer)
+            | OutEvent::EncryptedSignature { peer, .. }
+            | OutEvent::Failure { peer, .. } => Some(*peer),
+            OutEvent::ResponseSent | OutEvent::Pinged { .. } => None,
+        }
+    }
+}
+
 impl From<peer_tracker::OutEvent> for OutEvent {
     fn from(event: peer_tracker::OutEvent) -> Self {
         match event {
@@ -51,6 +82,46 @@ impl From<peer_tracker::OutEvent> for OutEvent {
     }
 }
 
+impl From<protocol_version::OutEvent> for OutEvent {
+    fn from(event: protocol_version::OutEvent) -> Self {
+        match event {
+            protocol_version::OutEvent::Message {
+                peer,
+                message:
+                    RequestResponseMessage::Request {
+                        channel,
+                        request: handshake,
+                        ..
+                    },
+            } => OutEvent::ProtocolVersionReceived {
+                handshake,
+                channel,
+                peer,
+            },
+            protocol_version::OutEvent::Message {
+                message: RequestResponseMessage::Response { .. },
+                peer,
+            } => OutEvent::Failure {
+                error: anyhow!(
+                    "Alice is only meant to answer the protocol version handshake, not initiate it"
+                ),
+                peer,
+            },
+            protocol_version::OutEvent::ResponseSent { peer, .. } => {
+                OutEvent::ProtocolVersionAnswered(peer)
+            }
+            protocol_version::OutEvent::InboundFailure { peer, error, .. } => OutEvent::Failure {
+                error: anyhow!("protocol_version protocol failed due to {:?}", error),
+                peer,
+            },
+            protocol_version::OutEvent::OutboundFailure { peer, error, .. } => OutEvent::Failure {
+                error: anyhow!("protocol_version protocol failed due to {:?}", error),
+                peer,
+            },
+        }
+    }
+}
+
 impl From<spot_price::OutEvent> for OutEvent {
     fn from(event: spot_price::OutEvent) -> Self {
         match event {
@@ -157,33 +228,59 @@ impl From<encrypted_signature::OutEvent> for OutEvent {
     }
 }
 
+impl From<keepalive::OutEvent> for OutEvent {
+    fn from(event: keepalive::OutEvent) -> Self {
+        OutEvent::Pinged {
+            peer: event.peer,
+            result: event.result,
+        }
+    }
+}
+
 /// A `NetworkBehaviour` that represents an XMR/BTC swap node as Alice.
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "OutEvent", event_process = false)]
 #[allow(missing_debug_implementations)]
 pub struct Behaviour {
     pt: peer_tracker::Behaviour,
+    protocol_version: protocol_version::Behaviour,
     quote: quote::Behaviour,
     spot_price: spot_price::Behaviour,
     execution_setup: execution_setup::Behaviour,
     transfer_proof: transfer_proof::Behaviour,
     encrypted_signature: encrypted_signature::Behaviour,
+    keepalive: Ping,
 }
 
-impl Default for Behaviour {
-    fn default() -> Self {
+impl Behaviour {
+    pub fn new(env_config: Config) -> Self {
         Self {
             pt: Default::default(),
+            protocol_version: protocol_version::alice(),
             quote: quote::alice(),
             spot_price: spot_price::alice(),
             execution_setup: Default::default(),
             transfer_proof: Default::default(),
             encrypted_signature: Default::default(),
+            keepalive: keepalive::behaviour(
+                env_config.network_keepalive_interval,
+                env_config.network_keepalive_timeout,
+            ),
         }
     }
-}
 
-impl Behaviour {
+    /// Answer the protocol version handshake of a peer with our own.
+    pub fn send_protocol_version(
+        &mut self,
+        channel: ResponseChannel<protocol_version::Handshake>,
+    ) -> Result<()> {
+        self.protocol_version
+            .send_response(channel, protocol_version::Handshake::ours())
+            .map_err(|_| anyhow!("Failed to respond with protocol version"))?;
+
+        Ok(())
+    }
+
     pub fn send_quote(
         &mut self,
         channel: ResponseChannel<BidQuote>,
@@ -0,0 +1,421 @@
+//! Cold signing of the redeem transaction.
+//!
+//! If a cold signing key is configured, the key `a` of a new swap is encrypted
+//! to it once the execution setup is done, before the swap is stored. The
+//! signatures on the cancel and punish transactions and the encrypted
+//! signature on the refund transaction are computed beforehand and kept
+//! instead, the ASB can therefore run the swap up to the redeem without ever
+//! storing `a`.
+//!
+//! Once the encrypted signature of Bob is learned, the running ASB writes the
+//! redeem transaction as PSBT, carrying the encrypted key, to
+//! `<swap_id>.psbt` in the cold signing directory. The PSBT is signed with
+//! [`sign_redeem`] on the machine holding the cold signing key and the result
+//! is put next to it as `<swap_id>.signed.psbt`, which the ASB picks up,
+//! finalizes and publishes.
+
+use crate::bitcoin::{
+    self, sign_redeem_psbt, verify_encsig, verify_sig, EncryptedSignature, PublicKey, SecretKey,
+    Signature, Transaction, TxRedeem,
+};
+use crate::monero_ext::ScalarExt;
+use crate::protocol::alice::State3;
+use ::bitcoin::consensus::{deserialize, serialize};
+use ::bitcoin::secp256k1::ecdh::SharedSecret;
+use ::bitcoin::util::psbt::{raw, PartiallySignedTransaction};
+use ::bitcoin::{secp256k1, SigHash};
+use anyhow::{bail, ensure, Context, Result};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::ChaCha20Poly1305;
+use ecdsa_fun::fun::marker::{Mark, NonZero};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often the ASB looks for a signed redeem transaction.
+const SIGNED_REDEEM_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The type of proprietary PSBT fields as defined in BIP 174.
+const PSBT_PROPRIETARY: u8 = 0xFC;
+
+/// The identifier of the proprietary PSBT field carrying the sealed key.
+const PSBT_PROPRIETARY_PREFIX: &[u8] = b"xmr-btc-swap";
+
+/// The subtype of the proprietary PSBT field carrying the sealed key.
+const PSBT_SEALED_KEY_SUBTYPE: u8 = 0x00;
+
+const NONCE_LENGTH: usize = 12;
+
+/// The key `a` of a swap, either held by the ASB or sealed for cold signing.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum SwapKey {
+    Hot(SecretKey),
+    Sealed(Box<SealedKey>),
+}
+
+/// The key `a` encrypted to the cold signing key, together with every
+/// signature of it the swap needs apart from the one on the redeem
+/// transaction.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SealedKey {
+    A: PublicKey,
+    secret: SealedSecret,
+    signatures: Vec<Signature>,
+    tx_refund_encsig: EncryptedSignature,
+}
+
+/// A secret key encrypted with the key agreed between an ephemeral key and
+/// the cold signing key.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+struct SealedSecret {
+    ephemeral: PublicKey,
+    nonce: [u8; NONCE_LENGTH],
+    ciphertext: Vec<u8>,
+}
+
+impl SwapKey {
+    pub fn public(&self) -> PublicKey {
+        match self {
+            SwapKey::Hot(a) => a.public(),
+            SwapKey::Sealed(sealed) => sealed.A,
+        }
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        matches!(self, SwapKey::Sealed(_))
+    }
+
+    /// The key itself, fails if it is sealed.
+    pub fn hot(&self) -> Result<&SecretKey> {
+        match self {
+            SwapKey::Hot(a) => Ok(a),
+            SwapKey::Sealed(_) => bail!("The key of the swap is sealed for cold signing"),
+        }
+    }
+
+    /// Sign the digest, a sealed key only returns the signatures computed
+    /// before it was sealed.
+    pub fn sign(&self, digest: SigHash) -> Result<Signature> {
+        match self {
+            SwapKey::Hot(a) => Ok(a.sign(digest)),
+            SwapKey::Sealed(sealed) => sealed
+                .signatures
+                .iter()
+                .find(|sig| verify_sig(&sealed.A, &digest, sig).is_ok())
+                .cloned()
+                .context("Transaction was not signed before the key of the swap was sealed"),
+        }
+    }
+
+    /// Encrypt a signature of the digest with `Y`, a sealed key only returns
+    /// the encrypted signature of the refund transaction.
+    pub fn encsign(&self, Y: PublicKey, digest: SigHash) -> Result<EncryptedSignature> {
+        match self {
+            SwapKey::Hot(a) => Ok(a.encsign(Y, digest)),
+            SwapKey::Sealed(sealed) => {
+                verify_encsig(sealed.A, Y, &digest, &sealed.tx_refund_encsig)
+                    .context("Transaction was not signed before the key of the swap was sealed")?;
+
+                Ok(sealed.tx_refund_encsig.clone())
+            }
+        }
+    }
+
+    /// Encrypt the key to the cold signing key, after signing the given
+    /// digests and encrypting a signature of the refund digest with `S_b`.
+    ///
+    /// A sealed key is returned as is.
+    pub fn seal(
+        self,
+        cold_signing_key: ::bitcoin::PublicKey,
+        digests: &[SigHash],
+        (S_b, tx_refund_digest): (PublicKey, SigHash),
+    ) -> Self {
+        let a = match self {
+            SwapKey::Hot(a) => a,
+            sealed @ SwapKey::Sealed(_) => return sealed,
+        };
+
+        SwapKey::Sealed(Box::new(SealedKey {
+            A: a.public(),
+            secret: SealedSecret::seal(&a, cold_signing_key),
+            signatures: digests.iter().map(|digest| a.sign(*digest)).collect(),
+            tx_refund_encsig: a.encsign(S_b, tx_refund_digest),
+        }))
+    }
+}
+
+impl SealedSecret {
+    fn seal(secret: &SecretKey, cold_signing_key: ::bitcoin::PublicKey) -> Self {
+        let ephemeral = SecretKey::new_random(&mut rand::thread_rng());
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = cipher(&cold_signing_key.key, &to_secp256k1(&ephemeral))
+            .encrypt(GenericArray::from_slice(&nonce), secret.to_bytes().as_ref())
+            .expect("encrypting 32 bytes never fails");
+
+        Self {
+            ephemeral: ephemeral.public(),
+            nonce,
+            ciphertext,
+        }
+    }
+
+    fn unseal(&self, cold_signing_key: &::bitcoin::PrivateKey) -> Result<SecretKey> {
+        let ephemeral = ::bitcoin::PublicKey::from(self.ephemeral).key;
+
+        let bytes = cipher(&ephemeral, &cold_signing_key.key)
+            .decrypt(
+                GenericArray::from_slice(&self.nonce),
+                self.ciphertext.as_ref(),
+            )
+            .ok()
+            .context(
+                "Failed to decrypt the key of the swap, it is sealed for another cold signing key",
+            )?;
+
+        let mut secret = [0u8; 32];
+        ensure!(bytes.len() == secret.len(), "Sealed key has wrong length");
+        secret.copy_from_slice(&bytes);
+
+        let scalar = bitcoin::Scalar::from_bytes(secret)
+            .and_then(|scalar| scalar.mark::<NonZero>())
+            .context("Sealed key is not a valid secret key")?;
+
+        Ok(SecretKey::from(scalar))
+    }
+}
+
+/// The cipher keyed by the shared secret of the given keys.
+fn cipher(public: &secp256k1::PublicKey, secret: &secp256k1::SecretKey) -> ChaCha20Poly1305 {
+    let shared_secret = SharedSecret::new(public, secret);
+
+    ChaCha20Poly1305::new(GenericArray::from_slice(&shared_secret[..]))
+}
+
+fn to_secp256k1(secret: &SecretKey) -> secp256k1::SecretKey {
+    secp256k1::SecretKey::from_slice(&secret.to_bytes()).expect("valid secret key")
+}
+
+/// Write the redeem transaction of the swap as PSBT carrying the sealed key
+/// into the cold signing directory, returns the path of the file.
+pub fn export_redeem(
+    dir: &Path,
+    swap_id: Uuid,
+    state3: &State3,
+    encrypted_signature: EncryptedSignature,
+) -> Result<PathBuf> {
+    let sealed = match &state3.a {
+        SwapKey::Sealed(sealed) => sealed,
+        SwapKey::Hot(_) => bail!("The key of swap {} is not sealed for cold signing", swap_id),
+    };
+
+    let mut psbt = TxRedeem::new(&state3.tx_lock, &state3.redeem_address).export_psbt(
+        encrypted_signature,
+        state3.s_a.to_secpfun_scalar(),
+        state3.B,
+    )?;
+    psbt.inputs[0]
+        .unknown
+        .insert(sealed_key_field(), serde_cbor::to_vec(&sealed.secret)?);
+
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    let path = dir.join(format!("{}.psbt", swap_id));
+    fs::write(&path, base64::encode(serialize(&psbt)))
+        .with_context(|| format!("Failed to write redeem transaction to {}", path.display()))?;
+
+    Ok(path)
+}
+
+/// Wait for the signed redeem transaction of the swap to show up in the cold
+/// signing directory and finalize it.
+///
+/// A file that cannot be finalized is renamed, so that it can be replaced.
+pub async fn signed_redeem(dir: &Path, swap_id: Uuid, state3: &State3) -> Result<Transaction> {
+    let path = dir.join(format!("{}.signed.psbt", swap_id));
+
+    loop {
+        if path.exists() {
+            match finalize_redeem(&path, state3) {
+                Ok(transaction) => return Ok(transaction),
+                Err(error) => {
+                    let rejected = path.with_extension("psbt.rejected");
+                    tracing::warn!(
+                        "Signed redeem transaction {} is invalid, moved it to {}: {:#}",
+                        path.display(),
+                        rejected.display(),
+                        error
+                    );
+                    fs::rename(&path, &rejected)?;
+                }
+            }
+        }
+
+        tokio::time::sleep(SIGNED_REDEEM_POLL_INTERVAL).await;
+    }
+}
+
+fn finalize_redeem(path: &Path, state3: &State3) -> Result<Transaction> {
+    let psbt = deserialize(&base64::decode(fs::read_to_string(path)?.trim())?)?;
+
+    TxRedeem::new(&state3.tx_lock, &state3.redeem_address).finalize_psbt(
+        psbt,
+        state3.a.public(),
+        state3.B,
+    )
+}
+
+/// Sign a redeem PSBT written by the ASB, on the machine holding the cold
+/// signing key.
+///
+/// The key of the swap is recovered from the PSBT and has to be one of the
+/// keys the spent lock output is locked to.
+pub fn sign_redeem(
+    mut psbt: PartiallySignedTransaction,
+    cold_signing_key: &::bitcoin::PrivateKey,
+) -> Result<PartiallySignedTransaction> {
+    let input = psbt.inputs.first_mut().context("PSBT has no inputs")?;
+    let sealed = input
+        .unknown
+        .remove(&sealed_key_field())
+        .context("PSBT does not carry a sealed key")?;
+    let sealed: SealedSecret = serde_cbor::from_slice(&sealed)?;
+
+    let a = sealed.unseal(cold_signing_key)?;
+
+    let A = ::bitcoin::PublicKey::from(a.public()).to_bytes();
+    let witness_script = input
+        .witness_script
+        .as_ref()
+        .context("PSBT is missing the witness script of the lock output")?;
+    ensure!(
+        witness_script
+            .as_bytes()
+            .windows(A.len())
+            .any(|key| key == A.as_slice()),
+        "PSBT does not spend an output locked to the sealed key"
+    );
+
+    sign_redeem_psbt(psbt, &a)
+}
+
+fn sealed_key_field() -> raw::Key {
+    let mut key = vec![PSBT_PROPRIETARY_PREFIX.len() as u8];
+    key.extend_from_slice(PSBT_PROPRIETARY_PREFIX);
+    key.push(PSBT_SEALED_KEY_SUBTYPE);
+
+    raw::Key {
+        type_value: PSBT_PROPRIETARY,
+        key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::{Address, Amount, Network, TxLock};
+    use ::bitcoin::hashes::Hash;
+    use ecdsa_fun::fun::Scalar;
+    use rand::thread_rng;
+
+    fn cold_signing_key() -> ::bitcoin::PrivateKey {
+        ::bitcoin::PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: to_secp256k1(&SecretKey::new_random(&mut thread_rng())),
+        }
+    }
+
+    fn public(key: &::bitcoin::PrivateKey) -> ::bitcoin::PublicKey {
+        key.public_key(&secp256k1::Secp256k1::signing_only())
+    }
+
+    fn digest(byte: u8) -> SigHash {
+        SigHash::from_inner([byte; 32])
+    }
+
+    #[test]
+    fn sealed_key_is_only_recovered_with_the_cold_signing_key() {
+        let a = SecretKey::new_random(&mut thread_rng());
+        let cold_signing_key = cold_signing_key();
+
+        let sealed = SealedSecret::seal(&a, public(&cold_signing_key));
+
+        assert_eq!(sealed.unseal(&cold_signing_key).unwrap(), a);
+        assert!(sealed.unseal(&self::cold_signing_key()).is_err());
+    }
+
+    #[test]
+    fn sealed_key_only_signs_what_was_signed_before_sealing() {
+        let a = SecretKey::new_random(&mut thread_rng());
+        let S_b = SecretKey::new_random(&mut thread_rng()).public();
+        let hot = SwapKey::Hot(a.clone());
+
+        let sealed = hot.clone().seal(
+            public(&cold_signing_key()),
+            &[digest(1), digest(2)],
+            (S_b, digest(3)),
+        );
+
+        assert_eq!(sealed.public(), a.public());
+        assert!(sealed.hot().is_err());
+        for digest in &[digest(1), digest(2)] {
+            assert_eq!(sealed.sign(*digest).unwrap(), hot.sign(*digest).unwrap());
+        }
+        assert!(sealed.sign(digest(4)).is_err());
+        assert_eq!(
+            sealed.encsign(S_b, digest(3)).unwrap(),
+            hot.encsign(S_b, digest(3)).unwrap()
+        );
+        assert!(sealed.encsign(S_b, digest(4)).is_err());
+    }
+
+    #[test]
+    fn hot_key_deserializes_from_a_plain_secret_key() {
+        let a = SecretKey::new_random(&mut thread_rng());
+
+        let stored = serde_cbor::to_vec(&a).unwrap();
+
+        assert_eq!(
+            serde_cbor::from_slice::<SwapKey>(&stored).unwrap(),
+            SwapKey::Hot(a)
+        );
+    }
+
+    #[test]
+    fn redeem_psbt_is_signed_with_the_cold_signing_key_only() {
+        let a = SecretKey::new_random(&mut thread_rng());
+        let b = SecretKey::new_random(&mut thread_rng());
+        let s_a = Scalar::random(&mut thread_rng());
+        let cold_signing_key = cold_signing_key();
+
+        let tx_lock = TxLock::unfunded(Amount::from_sat(100_000), a.public(), b.public());
+        let redeem_address =
+            Address::p2wpkh(&::bitcoin::PublicKey::from(a.public()), Network::Regtest).unwrap();
+        let tx_redeem = TxRedeem::new(&tx_lock, &redeem_address);
+        let encsig = b.encsign(PublicKey::from(s_a.clone()), tx_redeem.digest());
+
+        let mut psbt = TxRedeem::new(&tx_lock, &redeem_address)
+            .export_psbt(encsig, s_a, b.public())
+            .unwrap();
+        psbt.inputs[0].unknown.insert(
+            sealed_key_field(),
+            serde_cbor::to_vec(&SealedSecret::seal(&a, public(&cold_signing_key))).unwrap(),
+        );
+
+        assert!(sign_redeem(psbt.clone(), &self::cold_signing_key()).is_err());
+        let signed = sign_redeem(psbt, &cold_signing_key).unwrap();
+        assert!(signed.inputs[0].unknown.is_empty());
+
+        tx_redeem
+            .finalize_psbt(signed, a.public(), b.public())
+            .unwrap();
+    }
+}
@@ -1,37 +1,68 @@
-use crate::asb::{FixedRate, Rate};
-use crate::database::Database;
+use crate::asb::{FixedRate, MakerPaused, PauseSwitch, PeerAllowlist, PeerNotAllowed, Rate};
+use crate::database::{Database, Quote, QuoteRetention};
 use crate::env::Config;
 use crate::monero::BalanceTooLow;
-use crate::network::quote::BidQuote;
-use crate::network::{spot_price, transport, TokioExecutor};
+use crate::network::keepalive::IdlePeers;
+use crate::network::quote::{BidQuote, Liquidity, LiquidityProof, LIQUIDITY_PROOF_VALIDITY};
+use crate::network::{protocol_version, spot_price, transport, TokioExecutor};
 use crate::protocol::alice::{AliceState, Behaviour, OutEvent, State3, Swap, TransferProof};
 use crate::protocol::bob::EncryptedSignature;
+use crate::protocol::urgency::{self, Urgency};
 use crate::seed::Seed;
 use crate::{bitcoin, kraken, monero};
 use anyhow::{bail, Context, Result};
 use futures::future;
 use futures::future::{BoxFuture, FutureExt};
 use futures::stream::{FuturesUnordered, StreamExt};
-use libp2p::core::Multiaddr;
+use libp2p::core::{identity, Multiaddr};
 use libp2p::{PeerId, Swarm};
 use rand::rngs::OsRng;
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot};
+use std::time::{Duration, SystemTime};
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
 use tracing::{debug, error, trace};
 use uuid::Uuid;
 
+/// How long to wait between spawning resumed swaps, so that they don't all hit
+/// the wallets and the electrum server at once.
+const RESUME_STAGGER: Duration = Duration::from_secs(1);
+
 #[allow(missing_debug_implementations)]
 pub struct EventLoop<RS> {
     swarm: libp2p::Swarm<Behaviour>,
+    identity: identity::Keypair,
     peer_id: PeerId,
     env_config: Config,
     bitcoin_wallet: Arc<bitcoin::Wallet>,
     monero_wallet: Arc<monero::Wallet>,
     db: Arc<Database>,
     latest_rate: RS,
+    min_buy: bitcoin::Amount,
     max_buy: bitcoin::Amount,
+    allowlist: PeerAllowlist,
+    /// Rejects spot price requests, and thereby new swaps, while paused.
+    pause: PauseSwitch,
+    /// Whether to sign the available Monero into quotes.
+    advertise_liquidity: bool,
+    fidelity_bond: Option<bitcoin::Txid>,
+    punish: bool,
+    /// Seal the key of new swaps for cold signing if set.
+    cold_signing_key: Option<::bitcoin::PublicKey>,
+    cold_sign_dir: Option<PathBuf>,
+    /// Swaps waiting for the Bitcoin lock are aborted on request through this
+    /// directory if set.
+    abort_dir: Option<PathBuf>,
+    /// Record spot prices quoted to peers if set.
+    quote_retention: Option<QuoteRetention>,
+    /// Bounds how many resumed swaps run at the same time if set.
+    resume_limit: Option<Arc<Semaphore>>,
+    /// Connections to peers without a swap in flight are closed once idle.
+    idle_peers: IdlePeers,
 
     /// Stores a sender per peer for incoming [`EncryptedSignature`]s.
     recv_encrypted_signature: HashMap<PeerId, oneshot::Sender<EncryptedSignature>>,
@@ -55,10 +86,12 @@ where
         monero_wallet: Arc<monero::Wallet>,
         db: Arc<Database>,
         latest_rate: LR,
+        min_buy: bitcoin::Amount,
         max_buy: bitcoin::Amount,
+        allowlist: PeerAllowlist,
     ) -> Result<(Self, mpsc::Receiver<Swap>)> {
         let identity = seed.derive_libp2p_identity();
-        let behaviour = Behaviour::default();
+        let behaviour = Behaviour::new(env_config);
         let transport = transport::build(&identity)?;
         let peer_id = PeerId::from(identity.public());
 
@@ -75,6 +108,7 @@ where
 
         let event_loop = EventLoop {
             swarm,
+            identity,
             peer_id,
             env_config,
             bitcoin_wallet,
@@ -82,13 +116,82 @@ where
             db,
             latest_rate,
             swap_sender: swap_channel.sender,
+            min_buy,
             max_buy,
+            allowlist,
+            pause: PauseSwitch::default(),
+            advertise_liquidity: false,
+            fidelity_bond: None,
+            punish: true,
+            cold_signing_key: None,
+            cold_sign_dir: None,
+            abort_dir: None,
+            quote_retention: None,
+            resume_limit: None,
+            idle_peers: IdlePeers::default(),
             recv_encrypted_signature: Default::default(),
             send_transfer_proof: Default::default(),
         };
         Ok((event_loop, swap_channel.receiver))
     }
 
+    /// Advertise the available Monero as signed statement in every quote,
+    /// optionally pointing to a Bitcoin transaction that serves as fidelity
+    /// bond.
+    pub fn with_liquidity_proof(mut self, fidelity_bond: Option<bitcoin::Txid>) -> Self {
+        self.advertise_liquidity = true;
+        self.fidelity_bond = fidelity_bond;
+        self
+    }
+
+    /// Whether swaps punish a counterparty that neither redeems nor refunds,
+    /// enabled by default.
+    pub fn with_punish(mut self, punish: bool) -> Self {
+        self.punish = punish;
+        self
+    }
+
+    /// Seal the key of new swaps for the given cold signing key if set, the
+    /// redeem transactions of such swaps are exchanged through the given
+    /// directory, see the `sign-redeem` command of the ASB.
+    pub fn with_cold_signing(
+        mut self,
+        cold_signing_key: Option<::bitcoin::PublicKey>,
+        cold_sign_dir: PathBuf,
+    ) -> Self {
+        self.cold_signing_key = cold_signing_key;
+        self.cold_sign_dir = Some(cold_sign_dir);
+        self
+    }
+
+    /// Let the operator abort swaps that wait for the Bitcoin lock through the
+    /// given directory, see the `abort` command of the ASB.
+    pub fn with_abort_requests(mut self, abort_dir: PathBuf) -> Self {
+        self.abort_dir = Some(abort_dir);
+        self
+    }
+
+    /// Record every spot price quoted to a peer, keeping as many as the given
+    /// retention allows.
+    pub fn with_quote_history(mut self, retention: QuoteRetention) -> Self {
+        self.quote_retention = Some(retention);
+        self
+    }
+
+    /// Let at most this many resumed swaps drive a transition at the same
+    /// time, at least one. The others are queued until one of them waits for
+    /// the blockchain or Bob.
+    pub fn with_resume_concurrency(mut self, max_concurrent: usize) -> Self {
+        self.resume_limit = Some(Arc::new(Semaphore::new(max_concurrent.max(1))));
+        self
+    }
+
+    /// The switch to stop accepting new swaps at runtime while letting the
+    /// ones in flight finish.
+    pub fn pause_switch(&self) -> PauseSwitch {
+        self.pause.clone()
+    }
+
     pub fn peer_id(&self) -> PeerId {
         self.peer_id
     }
@@ -98,24 +201,53 @@ where
         // terminate forever.
         self.send_transfer_proof.push(future::pending().boxed());
 
+        if let Err(error) = self.resume_unfinished_swaps().await {
+            error!("Failed to resume unfinished swaps: {:#}", error);
+        }
+
+        let mut idle_check = tokio::time::interval(self.env_config.network_keepalive_interval);
+
         loop {
             tokio::select! {
                 swarm_event = self.swarm.next() => {
+                    if let Some(peer) = swarm_event.active_peer() {
+                        self.idle_peers.active(peer);
+                    }
+
                     match swarm_event {
                         OutEvent::ConnectionEstablished(alice) => {
                             debug!("Connection Established with {}", alice);
                         }
+                        OutEvent::ProtocolVersionReceived { handshake, channel, peer } => {
+                            // Answer regardless so Bob can tell his user why the swap cannot happen
+                            if let Err(e) = protocol_version::Handshake::ours().check(&handshake) {
+                                tracing::warn!(%peer, "Bob cannot swap with us: {}", e);
+                            }
+
+                            if let Err(e) = self.swarm.send_protocol_version(channel) {
+                                debug!(%peer, "failed to respond with protocol version: {:#}", e);
+                            }
+                        }
+                        OutEvent::ProtocolVersionAnswered(peer) => {
+                            trace!(%peer, "Answered protocol version handshake");
+                        }
                         OutEvent::SpotPriceRequested { msg, channel, peer } => {
                             let btc = msg.btc;
-                            let xmr = match self.handle_spot_price_request(btc, self.monero_wallet.clone()).await {
+                            let xmr = match self.handle_spot_price_request(peer, btc, self.monero_wallet.clone()).await {
                                 Ok(xmr) => xmr,
                                 Err(e) => {
                                     tracing::warn!(%peer, "failed to produce spot price for {}: {:#}", btc, e);
+
+                                    if let Some(refusal) = refusal(&e) {
+                                        if let Err(e) = self.swarm.send_spot_price(channel, spot_price::Response::Error(refusal)) {
+                                            debug!(%peer, "failed to respond with refusal: {:#}", e);
+                                        }
+                                    }
                                     continue;
                                 }
                             };
 
-                            match self.swarm.send_spot_price(channel, spot_price::Response { xmr }) {
+                            match self.swarm.send_spot_price(channel, spot_price::Response::Xmr(xmr)) {
                                 Ok(_) => {},
                                 Err(e) => {
                                     // if we can't respond, the peer probably just disconnected so it is not a huge deal, only log this on debug
@@ -124,6 +256,11 @@ where
                                 }
                             }
 
+                            if let Err(e) = self.pause.ensure_not_paused() {
+                                tracing::warn!(%peer, "not starting execution setup: {}", e);
+                                continue;
+                            }
+
                             match self.swarm.start_execution_setup(peer, btc, xmr, self.env_config, self.bitcoin_wallet.as_ref(), &mut OsRng).await {
                                 Ok(_) => {},
                                 Err(e) => {
@@ -171,6 +308,11 @@ where
                             }
                         }
                         OutEvent::ResponseSent => {}
+                        OutEvent::Pinged { peer, result } => {
+                            if let Err(error) = result {
+                                debug!(%peer, "Keepalive ping failed: {}", error);
+                            }
+                        }
                         OutEvent::Failure {peer, error} => {
                             error!(%peer, "Communication error: {:#}", error);
                         }
@@ -189,37 +331,76 @@ where
                         }
                     }
                 }
+                _ = idle_check.tick() => {
+                    self.close_idle_connections();
+                }
+            }
+        }
+    }
+
+    /// Closes the connections to peers that neither sent a request within the
+    /// idle timeout nor have a swap waiting for their encrypted signature, the
+    /// keepalive pings would otherwise keep them open forever.
+    fn close_idle_connections(&mut self) {
+        self.recv_encrypted_signature
+            .retain(|_, sender| !sender.is_closed());
+        for peer in self.recv_encrypted_signature.keys() {
+            self.idle_peers.active(*peer);
+        }
+
+        for peer in self
+            .idle_peers
+            .take_idle(self.env_config.network_idle_timeout)
+        {
+            if Swarm::disconnect_peer_id(&mut self.swarm, peer).is_ok() {
+                debug!(%peer, "Closed idle connection");
             }
         }
     }
 
     async fn handle_spot_price_request(
         &mut self,
+        peer: PeerId,
         btc: bitcoin::Amount,
         monero_wallet: Arc<monero::Wallet>,
     ) -> Result<monero::Amount> {
+        self.allowlist.ensure_allowed(&peer)?;
+        self.pause.ensure_not_paused()?;
+
         let rate = self
             .latest_rate
             .latest_rate()
             .context("Failed to get latest rate")?;
 
-        if btc > self.max_buy {
-            bail!(MaximumBuyAmountExceeded {
-                actual: btc,
-                max: self.max_buy
-            })
-        }
+        ensure_within_buy_limits(btc, self.min_buy, self.max_buy)?;
 
         let xmr_balance = monero_wallet.get_balance().await?;
         let xmr_lock_fees = monero_wallet.static_tx_fee_estimate();
         let xmr = rate.sell_quote(btc)?;
 
-        if xmr_balance < xmr + xmr_lock_fees {
+        let xmr_required = xmr
+            .checked_add(xmr_lock_fees)
+            .context("Quoted Monero amount is too large")?;
+        if xmr_balance < xmr_required {
             bail!(BalanceTooLow {
                 balance: xmr_balance
             })
         }
 
+        if let Some(retention) = self.quote_retention {
+            let quote = Quote {
+                timestamp: OffsetDateTime::now_utc(),
+                peer_id: peer,
+                btc,
+                xmr,
+                ask: rate.ask,
+            };
+
+            if let Err(error) = self.db.insert_quote(quote, retention).await {
+                tracing::warn!(%peer, "Failed to record quote: {:#}", error);
+            }
+        }
+
         Ok(xmr)
     }
 
@@ -229,16 +410,43 @@ where
             .latest_rate()
             .context("Failed to get latest rate")?;
 
+        let liquidity_proof = if self.advertise_liquidity {
+            let liquidity = Liquidity {
+                max_sell: self.monero_wallet.get_balance().await?,
+                fidelity_bond: self.fidelity_bond,
+            };
+
+            Some(LiquidityProof::sign(
+                liquidity,
+                SystemTime::now() + LIQUIDITY_PROOF_VALIDITY,
+                &self.identity,
+            )?)
+        } else {
+            None
+        };
+
         Ok(BidQuote {
             price: rate.ask,
             max_quantity: max_buy,
+            liquidity_proof,
         })
     }
 
     async fn handle_execution_setup_done(&mut self, bob_peer_id: PeerId, state3: State3) {
-        let swap_id = Uuid::new_v4();
+        // Bob has not locked any bitcoin yet, dropping the swap is safe
+        if let Err(error) = self.pause.ensure_not_paused() {
+            tracing::warn!(peer = %bob_peer_id, "Not starting swap: {}", error);
+            return;
+        }
+
+        let swap_id = self.db.new_swap_id();
         let handle = self.new_handle(bob_peer_id);
 
+        // The key must be sealed before the swap is stored
+        let state3 = match self.cold_signing_key {
+            Some(cold_signing_key) => state3.seal_key(cold_signing_key),
+            None => state3,
+        };
         let initial_state = AliceState::Started {
             state3: Box::new(state3),
         };
@@ -251,19 +459,106 @@ where
             db: self.db.clone(),
             state: initial_state,
             swap_id,
+            punish: self.punish,
+            cold_sign_dir: self.cold_sign_dir.clone(),
+            abort_dir: self.abort_dir.clone(),
+            resume_permit: None,
+            resume_limit: None,
         };
 
+        if let Err(error) = self.db.insert_peer_id(swap_id, bob_peer_id).await {
+            tracing::warn!(%swap_id, "Failed to store peer id, the swap cannot be resumed after a restart: {:#}", error);
+        }
+
         if let Err(error) = self.swap_sender.send(swap).await {
             tracing::warn!(%swap_id, "Swap cannot be spawned: {}", error);
         }
     }
 
+    /// Spawn the unfinished swaps stored in the database, the most urgent
+    /// ones first.
+    async fn resume_unfinished_swaps(&mut self) -> Result<()> {
+        let mut unfinished = Vec::new();
+
+        for (swap_id, swap) in self.db.all()? {
+            let state = match swap.clone() {
+                crate::database::Swap::Alice(state) => AliceState::from(state),
+                crate::database::Swap::Bob(_) => continue,
+            };
+            if state.is_recoverable().is_none() {
+                continue;
+            }
+
+            let peer_id = match self.db.get_peer_id(swap_id)? {
+                Some(peer_id) => peer_id,
+                None => {
+                    tracing::warn!(%swap_id, "Cannot resume swap because the peer id of Bob is unknown");
+                    continue;
+                }
+            };
+
+            let urgency = match urgency::classify(swap, &self.bitcoin_wallet).await {
+                Ok(urgency) => urgency.unwrap_or(Urgency::Ok),
+                Err(error) => {
+                    tracing::warn!(%swap_id, "Failed to classify urgency of swap: {:#}", error);
+                    Urgency::ActionRequired
+                }
+            };
+
+            unfinished.push(((swap_id, peer_id, state), urgency));
+        }
+
+        let swaps = most_urgent_first(unfinished)
+            .into_iter()
+            .map(|(swap_id, peer_id, state)| Swap {
+                event_loop_handle: self.new_handle(peer_id),
+                bitcoin_wallet: self.bitcoin_wallet.clone(),
+                monero_wallet: self.monero_wallet.clone(),
+                env_config: self.env_config,
+                db: self.db.clone(),
+                state,
+                swap_id,
+                punish: self.punish,
+                cold_sign_dir: self.cold_sign_dir.clone(),
+                abort_dir: self.abort_dir.clone(),
+                resume_permit: None,
+                resume_limit: self.resume_limit.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let swap_sender = self.swap_sender.clone();
+        let resume = move |mut swap: Swap, resume_permit| {
+            let swap_sender = swap_sender.clone();
+            async move {
+                let swap_id = swap.swap_id;
+                tracing::info!(%swap_id, "Resuming swap");
+
+                swap.resume_permit = resume_permit;
+                if let Err(error) = swap_sender.send(swap).await {
+                    tracing::warn!(%swap_id, "Swap cannot be spawned: {}", error);
+                }
+            }
+        };
+        tokio::spawn(resume_in_order(
+            swaps,
+            self.resume_limit.clone(),
+            RESUME_STAGGER,
+            resume,
+        ));
+
+        Ok(())
+    }
+
     /// Create a new [`EventLoopHandle`] that is scoped for communication with
     /// the given peer.
     fn new_handle(&mut self, peer: PeerId) -> EventLoopHandle {
         let (send_transfer_proof_sender, send_transfer_proof_receiver) = oneshot::channel();
         let (recv_enc_sig_sender, recv_enc_sig_receiver) = oneshot::channel();
 
+        // Swaps that ended early, e.g. because they were aborted, never receive
+        // the encrypted signature they were waiting for.
+        self.recv_encrypted_signature
+            .retain(|_, sender| !sender.is_closed());
         self.recv_encrypted_signature
             .insert(peer, recv_enc_sig_sender);
         self.send_transfer_proof.push(
@@ -344,6 +639,81 @@ pub struct MaximumBuyAmountExceeded {
     pub actual: bitcoin::Amount,
 }
 
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Refusing to buy {actual} because the minimum configured limit is {min}")]
+pub struct MinimumBuyAmountNotReached {
+    pub min: bitcoin::Amount,
+    pub actual: bitcoin::Amount,
+}
+
+fn ensure_within_buy_limits(
+    btc: bitcoin::Amount,
+    min_buy: bitcoin::Amount,
+    max_buy: bitcoin::Amount,
+) -> Result<()> {
+    if btc < min_buy {
+        bail!(MinimumBuyAmountNotReached {
+            actual: btc,
+            min: min_buy
+        })
+    }
+
+    if btc > max_buy {
+        bail!(MaximumBuyAmountExceeded {
+            actual: btc,
+            max: max_buy
+        })
+    }
+
+    // The cancel transaction and the refund or punish transaction pay their
+    // fees from the locked amount, what is left has to be above dust.
+    let min = bitcoin::Amount::from_sat(2 * bitcoin::TX_FEE + bitcoin::DUST_AMOUNT);
+    if btc < min {
+        bail!(MinimumBuyAmountNotReached { actual: btc, min })
+    }
+
+    Ok(())
+}
+
+/// Order the swaps by their urgency, keeping the order of equally urgent
+/// ones.
+fn most_urgent_first<T>(mut swaps: Vec<(T, Urgency)>) -> Vec<T> {
+    swaps.sort_by_key(|(_, urgency)| std::cmp::Reverse(*urgency));
+
+    swaps.into_iter().map(|(swap, _)| swap).collect()
+}
+
+/// Hand the swaps over for resuming one after another, each with a permit of
+/// the limit for its first transition if there is one. Waits for a resumed
+/// swap to release its permit before handing over another one once the limit
+/// is reached.
+async fn resume_in_order<T, F, Fut>(
+    swaps: Vec<T>,
+    limit: Option<Arc<Semaphore>>,
+    stagger: Duration,
+    mut resume: F,
+) where
+    F: FnMut(T, Option<OwnedSemaphorePermit>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    for swap in swaps {
+        let permit = match &limit {
+            Some(limit) => Some(
+                limit
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("resume limit is never closed"),
+            ),
+            None => None,
+        };
+
+        resume(swap, permit).await;
+
+        tokio::time::sleep(stagger).await;
+    }
+}
+
 #[allow(missing_debug_implementations)]
 struct MpscChannels<T> {
     sender: mpsc::Sender<T>,
@@ -356,3 +726,142 @@ impl<T> Default for MpscChannels<T> {
         MpscChannels { sender, receiver }
     }
 }
+
+/// The reason told to Bob if a spot price request fails because of the given
+/// error, `None` if he is not told why.
+fn refusal(error: &anyhow::Error) -> Option<spot_price::Error> {
+    if error.is::<PeerNotAllowed>() {
+        return Some(spot_price::Error::PeerNotAllowed);
+    }
+    if error.is::<MakerPaused>() {
+        return Some(spot_price::Error::MakerUnavailable);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn peers_not_on_the_allowlist_are_told_why() {
+        let allowlist = PeerAllowlist::new(vec![PeerId::random()]);
+        let error = anyhow::Error::from(allowlist.ensure_allowed(&PeerId::random()).unwrap_err());
+
+        assert_eq!(refusal(&error), Some(spot_price::Error::PeerNotAllowed));
+        assert_eq!(refusal(&anyhow::anyhow!("No rate available")), None);
+    }
+
+    #[test]
+    fn paused_maker_tells_peers_it_is_unavailable() {
+        let pause = PauseSwitch::default();
+        pause.pause();
+        let error = anyhow::Error::from(pause.ensure_not_paused().unwrap_err());
+
+        assert_eq!(refusal(&error), Some(spot_price::Error::MakerUnavailable));
+    }
+
+    #[test]
+    fn most_urgent_swaps_are_resumed_first() {
+        let ok = Uuid::new_v4();
+        let action_required = Uuid::new_v4();
+        let at_risk = Uuid::new_v4();
+        let also_ok = Uuid::new_v4();
+
+        let order = most_urgent_first(vec![
+            (ok, Urgency::Ok),
+            (action_required, Urgency::ActionRequired),
+            (at_risk, Urgency::AtRiskOfPunish),
+            (also_ok, Urgency::Ok),
+        ]);
+
+        assert_eq!(order, vec![at_risk, action_required, ok, also_ok]);
+    }
+
+    #[tokio::test]
+    async fn no_more_resumed_swaps_than_the_limit_run_at_once() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let (finished_sender, mut finished_receiver) = mpsc::unbounded_channel();
+
+        let resume = |_: u32, permit: Option<OwnedSemaphorePermit>| {
+            let active = active.clone();
+            let max_active = max_active.clone();
+            let finished_sender = finished_sender.clone();
+            async move {
+                tokio::spawn(async move {
+                    let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(now_active, Ordering::SeqCst);
+
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    drop(permit);
+                    finished_sender.send(()).unwrap();
+                });
+            }
+        };
+        resume_in_order(
+            (0..5).collect(),
+            Some(Arc::new(Semaphore::new(2))),
+            Duration::from_secs(0),
+            resume,
+        )
+        .await;
+
+        for _ in 0..5 {
+            finished_receiver.recv().await.unwrap();
+        }
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn requests_below_min_buy_are_rejected() {
+        let min_buy = bitcoin::Amount::from_sat(100_000);
+        let max_buy = bitcoin::Amount::ONE_BTC;
+
+        let error = ensure_within_buy_limits(bitcoin::Amount::from_sat(99_999), min_buy, max_buy)
+            .unwrap_err();
+
+        assert!(error.is::<MinimumBuyAmountNotReached>());
+    }
+
+    #[test]
+    fn requests_not_covering_the_fees_are_rejected() {
+        let min_buy = bitcoin::Amount::ZERO;
+        let max_buy = bitcoin::Amount::ONE_BTC;
+
+        for btc in vec![
+            bitcoin::Amount::ZERO,
+            bitcoin::Amount::from_sat(bitcoin::TX_FEE),
+            bitcoin::Amount::from_sat(2 * bitcoin::TX_FEE),
+            bitcoin::Amount::from_sat(2 * bitcoin::TX_FEE + bitcoin::DUST_AMOUNT - 1),
+        ] {
+            assert!(ensure_within_buy_limits(btc, min_buy, max_buy)
+                .unwrap_err()
+                .is::<MinimumBuyAmountNotReached>());
+        }
+        assert!(ensure_within_buy_limits(
+            bitcoin::Amount::from_sat(2 * bitcoin::TX_FEE + bitcoin::DUST_AMOUNT),
+            min_buy,
+            max_buy
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn requests_within_limits_are_accepted() {
+        let min_buy = bitcoin::Amount::from_sat(100_000);
+        let max_buy = bitcoin::Amount::ONE_BTC;
+
+        assert!(ensure_within_buy_limits(min_buy, min_buy, max_buy).is_ok());
+        assert!(ensure_within_buy_limits(max_buy, min_buy, max_buy).is_ok());
+        assert!(
+            ensure_within_buy_limits(max_buy + bitcoin::Amount::from_sat(1), min_buy, max_buy)
+                .unwrap_err()
+                .is::<MaximumBuyAmountExceeded>()
+        );
+    }
+}
@@ -1,12 +1,14 @@
+use crate::bitcoin::wallet::BitcoinWallet;
 use crate::bitcoin::{
-    current_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock, TxCancel, TxPunish, TxRefund,
+    timelock_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock, TimelockEpoch,
+    TransactionKind, TxCancel, TxPunish, TxRefund,
 };
 use crate::env::Config;
 use crate::monero::wallet::{TransferRequest, WatchRequest};
 use crate::monero::TransferProof;
-use crate::protocol::alice::{Message1, Message3};
+use crate::protocol::alice::{Message1, Message3, SwapKey};
 use crate::protocol::bob::{Message0, Message2, Message4};
-use crate::protocol::CROSS_CURVE_PROOF_SYSTEM;
+use crate::protocol::{RecoveryAction, CROSS_CURVE_PROOF_SYSTEM};
 use crate::{bitcoin, monero};
 use anyhow::{anyhow, bail, Context, Result};
 use monero_rpc::wallet::BlockHeight;
@@ -52,6 +54,9 @@ pub enum AliceState {
         state3: Box<State3>,
     },
     BtcPunished,
+    /// Bob could have been punished but punishing is disabled, the swap is
+    /// abandoned.
+    BtcPunishDeclined,
     SafelyAborted,
 }
 
@@ -66,6 +71,7 @@ impl fmt::Display for AliceState {
             AliceState::BtcCancelled { .. } => write!(f, "btc is cancelled"),
             AliceState::BtcRefunded { .. } => write!(f, "btc is refunded"),
             AliceState::BtcPunished => write!(f, "btc is punished"),
+            AliceState::BtcPunishDeclined => write!(f, "punish is declined"),
             AliceState::SafelyAborted => write!(f, "safely aborted"),
             AliceState::BtcPunishable { .. } => write!(f, "btc is punishable"),
             AliceState::XmrRefunded => write!(f, "xmr is refunded"),
@@ -74,6 +80,84 @@ impl fmt::Display for AliceState {
     }
 }
 
+impl AliceState {
+    /// The Bitcoin transactions of this swap that are published or can be
+    /// published in the current state, labeled by their kind.
+    ///
+    /// Transactions that can only be published later, e.g. once a timelock
+    /// expired, are not included until the swap reaches the state they are
+    /// published in.
+    pub fn bitcoin_transactions(&self) -> Vec<(TransactionKind, bitcoin::Txid)> {
+        match self {
+            AliceState::Started { state3 }
+            | AliceState::BtcLocked { state3 }
+            | AliceState::XmrLocked { state3, .. } => {
+                vec![(TransactionKind::Lock, state3.tx_lock.txid())]
+            }
+            AliceState::EncSigLearned { state3, .. } => vec![
+                (TransactionKind::Lock, state3.tx_lock.txid()),
+                (
+                    TransactionKind::Redeem,
+                    bitcoin::TxRedeem::new(&state3.tx_lock, &state3.redeem_address).txid(),
+                ),
+            ],
+            AliceState::CancelTimelockExpired { state3, .. } => vec![
+                (TransactionKind::Lock, state3.tx_lock.txid()),
+                (TransactionKind::Cancel, state3.tx_cancel().txid()),
+            ],
+            AliceState::BtcCancelled { state3, .. } | AliceState::BtcRefunded { state3, .. } => {
+                vec![
+                    (TransactionKind::Lock, state3.tx_lock.txid()),
+                    (TransactionKind::Cancel, state3.tx_cancel().txid()),
+                    (TransactionKind::Refund, state3.tx_refund().txid()),
+                ]
+            }
+            AliceState::BtcPunishable { state3, .. } => vec![
+                (TransactionKind::Lock, state3.tx_lock.txid()),
+                (TransactionKind::Cancel, state3.tx_cancel().txid()),
+                (TransactionKind::Refund, state3.tx_refund().txid()),
+                (TransactionKind::Punish, state3.tx_punish().txid()),
+            ],
+            AliceState::BtcRedeemed
+            | AliceState::XmrRefunded
+            | AliceState::BtcPunished
+            | AliceState::BtcPunishDeclined
+            | AliceState::SafelyAborted => vec![],
+        }
+    }
+
+    /// The action to take to recover this swap, `None` if it is complete.
+    pub fn is_recoverable(&self) -> Option<RecoveryAction> {
+        match self {
+            AliceState::Started { .. } | AliceState::BtcLocked { .. } => {
+                Some(RecoveryAction::Nothing)
+            }
+            AliceState::XmrLocked { .. } | AliceState::CancelTimelockExpired { .. } => {
+                Some(RecoveryAction::PublishCancel)
+            }
+            AliceState::EncSigLearned { .. } => Some(RecoveryAction::PublishRedeem),
+            AliceState::BtcCancelled { .. } | AliceState::BtcPunishable { .. } => {
+                Some(RecoveryAction::PublishPunish)
+            }
+            AliceState::BtcRefunded { .. } => Some(RecoveryAction::ClaimXmr),
+            AliceState::BtcRedeemed
+            | AliceState::XmrRefunded
+            | AliceState::BtcPunished
+            | AliceState::BtcPunishDeclined
+            | AliceState::SafelyAborted => None,
+        }
+    }
+
+    /// The Monero this swap still has to lock and that must therefore not be
+    /// spent otherwise.
+    pub fn reserved_xmr(&self) -> monero::Amount {
+        match self {
+            AliceState::Started { state3 } | AliceState::BtcLocked { state3 } => state3.xmr,
+            _ => monero::Amount::ZERO,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct State0 {
     pub a: bitcoin::SecretKey,
@@ -96,7 +180,7 @@ impl State0 {
         btc: bitcoin::Amount,
         xmr: monero::Amount,
         env_config: Config,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
         rng: &mut R,
     ) -> Result<Self>
     where
@@ -273,7 +357,7 @@ impl State2 {
             .context("Failed to verify punish transaction")?;
 
         Ok(State3 {
-            a: self.a,
+            a: SwapKey::Hot(self.a),
             B: self.B,
             s_a: self.s_a,
             S_b_monero: self.S_b_monero,
@@ -295,7 +379,7 @@ impl State2 {
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct State3 {
-    pub a: bitcoin::SecretKey,
+    pub a: SwapKey,
     pub B: bitcoin::PublicKey,
     pub s_a: monero::Scalar,
     pub S_b_monero: monero::PublicKey,
@@ -332,12 +416,16 @@ impl State3 {
         &self,
         bitcoin_wallet: &bitcoin::Wallet,
     ) -> Result<ExpiredTimelocks> {
+        Ok(self.timelock_epoch(bitcoin_wallet).await?.epoch)
+    }
+
+    pub async fn timelock_epoch(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<TimelockEpoch> {
         let tx_cancel = self.tx_cancel();
 
         let tx_lock_status = bitcoin_wallet.status_of_script(&self.tx_lock).await?;
         let tx_cancel_status = bitcoin_wallet.status_of_script(&tx_cancel).await?;
 
-        Ok(current_epoch(
+        Ok(timelock_epoch(
             self.cancel_timelock,
             self.punish_timelock,
             tx_lock_status,
@@ -391,4 +479,112 @@ impl State3 {
     pub fn tx_refund(&self) -> TxRefund {
         bitcoin::TxRefund::new(&self.tx_cancel(), &self.refund_address)
     }
+
+    /// Seal the key of the swap for cold signing, signing the cancel and punish
+    /// transactions and encrypting a signature of the refund transaction
+    /// beforehand.
+    pub fn seal_key(self, cold_signing_key: ::bitcoin::PublicKey) -> Self {
+        let digests = [self.tx_cancel().digest(), self.tx_punish().digest()];
+        let tx_refund_digest = self.tx_refund().digest();
+
+        Self {
+            a: self.a.seal(
+                cold_signing_key,
+                &digests,
+                (self.S_b_bitcoin, tx_refund_digest),
+            ),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::execution_setup;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn complete_swaps_are_not_recoverable() {
+        assert_eq!(AliceState::BtcRedeemed.is_recoverable(), None);
+        assert_eq!(AliceState::XmrRefunded.is_recoverable(), None);
+        assert_eq!(AliceState::BtcPunished.is_recoverable(), None);
+        assert_eq!(AliceState::BtcPunishDeclined.is_recoverable(), None);
+        assert_eq!(AliceState::SafelyAborted.is_recoverable(), None);
+    }
+
+    #[tokio::test]
+    async fn unfinished_swaps_are_recovered_by_their_next_action() {
+        let bitcoin_wallet = bitcoin::mock::MockWallet::default();
+        let (state3, bob_state2) = execution_setup(&bitcoin_wallet).await;
+        let (bob_state3, _) = bob_state2.lock_btc().await.unwrap();
+        let encrypted_signature = bob_state3
+            .xmr_locked(BlockHeight { height: 0 })
+            .tx_redeem_encsig();
+        let state3 = Box::new(state3);
+        let monero_wallet_restore_blockheight = BlockHeight { height: 0 };
+
+        let expected = vec![
+            (
+                AliceState::Started {
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::Nothing),
+            ),
+            (
+                AliceState::BtcLocked {
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::Nothing),
+            ),
+            (
+                AliceState::XmrLocked {
+                    monero_wallet_restore_blockheight,
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                AliceState::EncSigLearned {
+                    monero_wallet_restore_blockheight,
+                    encrypted_signature: Box::new(encrypted_signature),
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::PublishRedeem),
+            ),
+            (
+                AliceState::CancelTimelockExpired {
+                    monero_wallet_restore_blockheight,
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                AliceState::BtcCancelled {
+                    monero_wallet_restore_blockheight,
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::PublishPunish),
+            ),
+            (
+                AliceState::BtcPunishable {
+                    monero_wallet_restore_blockheight,
+                    state3: state3.clone(),
+                },
+                Some(RecoveryAction::PublishPunish),
+            ),
+            (
+                AliceState::BtcRefunded {
+                    monero_wallet_restore_blockheight,
+                    spend_key: monero::PrivateKey::from_scalar(monero::Scalar::random(&mut OsRng)),
+                    state3,
+                },
+                Some(RecoveryAction::ClaimXmr),
+            ),
+        ];
+
+        for (state, action) in expected {
+            assert_eq!(state.is_recoverable(), action, "{}", state);
+        }
+    }
 }
@@ -4,17 +4,21 @@ use crate::bitcoin::{ExpiredTimelocks, TxRedeem};
 use crate::database::Database;
 use crate::env::Config;
 use crate::monero_ext::ScalarExt;
-use crate::protocol::alice;
 use crate::protocol::alice::event_loop::EventLoopHandle;
-use crate::protocol::alice::AliceState;
+use crate::protocol::alice::{abort, cold_sign, AliceState};
+use crate::protocol::{alice, record_failure};
 use crate::{bitcoin, database, monero};
 use anyhow::{bail, Context, Result};
 use async_recursion::async_recursion;
 use rand::{CryptoRng, RngCore};
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::select;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio::time::timeout;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 trait Rng: RngCore + CryptoRng + Send {}
@@ -27,12 +31,21 @@ pub fn is_complete(state: &AliceState) -> bool {
         AliceState::XmrRefunded
             | AliceState::BtcRedeemed
             | AliceState::BtcPunished
+            | AliceState::BtcPunishDeclined
             | AliceState::SafelyAborted
     )
 }
 
 pub async fn run(swap: alice::Swap) -> Result<AliceState> {
-    run_until(swap, is_complete).await
+    let swap_id = swap.swap_id;
+    let db = swap.db.clone();
+
+    let result = run_until(swap, is_complete).await;
+    if let Err(error) = &result {
+        record_failure(&db, swap_id, error).await;
+    }
+
+    result
 }
 
 #[tracing::instrument(name = "swap", skip(swap,is_target_state), fields(id = %swap.swap_id))]
@@ -49,6 +62,11 @@ pub async fn run_until(
         swap.env_config,
         swap.swap_id,
         swap.db,
+        swap.punish,
+        swap.cold_sign_dir,
+        swap.abort_dir,
+        swap.resume_permit,
+        swap.resume_limit,
     )
     .await
 }
@@ -65,6 +83,11 @@ async fn run_until_internal(
     env_config: Config,
     swap_id: Uuid,
     db: Arc<Database>,
+    punish: bool,
+    cold_sign_dir: Option<PathBuf>,
+    abort_dir: Option<PathBuf>,
+    mut transition_permit: Option<OwnedSemaphorePermit>,
+    resume_limit: Option<Arc<Semaphore>>,
 ) -> Result<AliceState> {
     info!("Current state: {}", state);
     if is_target_state(&state) {
@@ -73,29 +96,65 @@ async fn run_until_internal(
 
     let new_state = match state {
         AliceState::Started { state3 } => {
-            timeout(
-                env_config.bob_time_to_act,
-                bitcoin_wallet.watch_until_status(&state3.tx_lock, |status| status.has_been_seen()),
-            )
-            .await
-            .context("Failed to find lock Bitcoin tx")??;
+            drop(transition_permit.take());
+            let lock_seen = select! {
+                lock_seen = wait_for_lock_until_deadline(
+                    env_config.bitcoin_lock_deadline,
+                    bitcoin_wallet.watch_until_status(&state3.tx_lock, |status| status.has_been_seen()),
+                ) => {
+                    let lock_seen = lock_seen?;
+                    if !lock_seen {
+                        info!(
+                            "Bob did not lock Bitcoin within {}s, aborting swap",
+                            env_config.bitcoin_lock_deadline.as_secs()
+                        );
+                    }
 
-            bitcoin_wallet
-                .watch_until_status(&state3.tx_lock, |status| {
-                    status.is_confirmed_with(env_config.bitcoin_finality_confirmations)
-                })
-                .await?;
+                    lock_seen
+                }
+                _ = abort::abort_requested(abort_dir.as_deref(), swap_id) => {
+                    abort::clear_abort_request(abort_dir.as_deref(), swap_id);
+
+                    let lock_seen = bitcoin_wallet
+                        .status_of_script(&state3.tx_lock)
+                        .await?
+                        .has_been_seen();
+                    if lock_seen {
+                        warn!("Not aborting swap as requested, Bob already published the Bitcoin lock transaction");
+                    } else {
+                        info!("Aborting swap as requested");
+                    }
 
-            AliceState::BtcLocked { state3 }
+                    lock_seen
+                }
+            };
+
+            if lock_seen {
+                bitcoin_wallet
+                    .watch_until_status(&state3.tx_lock, |status| {
+                        status.is_confirmed_with(bitcoin_wallet.finality_confirmations(state3.btc))
+                    })
+                    .await?;
+
+                AliceState::BtcLocked { state3 }
+            } else {
+                AliceState::SafelyAborted
+            }
         }
         AliceState::BtcLocked { state3 } => {
             // Record the current monero wallet block height so we don't have to scan from
             // block 0 for scenarios where we create a refund wallet.
             let monero_wallet_restore_blockheight = monero_wallet.block_height().await?;
 
+            // A wallet that is still syncing may not know about outputs it already spent
+            monero_wallet
+                .wait_until_synced(MONERO_SYNC_MAX_WAIT)
+                .await?;
+
             let transfer_proof = monero_wallet
                 .transfer(state3.lock_xmr_transfer_request())
                 .await?;
+            drop(transition_permit.take());
 
             monero_wallet
                 .watch_for_transfer(state3.lock_xmr_watch_request(transfer_proof.clone(), 1))
@@ -123,6 +182,7 @@ async fn run_until_internal(
             monero_wallet_restore_blockheight,
         } => match state3.expired_timelocks(bitcoin_wallet.as_ref()).await? {
             ExpiredTimelocks::None => {
+                drop(transition_permit.take());
                 select! {
                     _ = state3.wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref()) => {
                         AliceState::CancelTimelockExpired {
@@ -151,22 +211,64 @@ async fn run_until_internal(
             encrypted_signature,
             monero_wallet_restore_blockheight,
         } => match state3.expired_timelocks(bitcoin_wallet.as_ref()).await? {
+            ExpiredTimelocks::None if state3.a.is_sealed() => {
+                let dir = cold_sign_dir.as_deref().context(
+                    "The key of the swap is sealed for cold signing, but there is no directory to exchange the redeem transaction through",
+                )?;
+                let tx_redeem = TxRedeem::new(&state3.tx_lock, &state3.redeem_address);
+                let exported =
+                    cold_sign::export_redeem(dir, swap_id, &state3, *encrypted_signature)?;
+                info!(
+                    "Redeem transaction has to be signed externally, sign {} with `asb sign-redeem` and put the result next to it as {}.signed.psbt",
+                    exported.display(),
+                    swap_id
+                );
+                drop(transition_permit.take());
+
+                select! {
+                    signed = cold_sign::signed_redeem(dir, swap_id, &state3) => {
+                        let (_, finality) = bitcoin_wallet.broadcast(signed?, "redeem").await?;
+                        finality.await?;
+
+                        AliceState::BtcRedeemed
+                    }
+                    redeemed = bitcoin_wallet.watch_until_status(&tx_redeem, |status| {
+                        status.is_confirmed_with(bitcoin_wallet.finality_confirmations(state3.btc))
+                    }) => {
+                        redeemed?;
+
+                        AliceState::BtcRedeemed
+                    }
+                    _ = state3.wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref()) => {
+                        AliceState::CancelTimelockExpired {
+                            state3,
+                            monero_wallet_restore_blockheight,
+                        }
+                    }
+                }
+            }
             ExpiredTimelocks::None => {
-                match TxRedeem::new(&state3.tx_lock, &state3.redeem_address).complete(
-                    *encrypted_signature,
-                    state3.a.clone(),
-                    state3.s_a.to_secpfun_scalar(),
-                    state3.B,
-                ) {
+                match state3.a.hot().and_then(|a| {
+                    TxRedeem::new(&state3.tx_lock, &state3.redeem_address).complete(
+                        *encrypted_signature,
+                        a.clone(),
+                        state3.s_a.to_secpfun_scalar(),
+                        state3.B,
+                    )
+                }) {
                     Ok(tx) => match bitcoin_wallet.broadcast(tx, "redeem").await {
-                        Ok((_, finality)) => match finality.await {
-                            Ok(_) => AliceState::BtcRedeemed,
-                            Err(e) => {
-                                bail!("Waiting for Bitcoin transaction finality failed with {}! The redeem transaction was published, but it is not ensured that the transaction was included! You're screwed.", e)
+                        Ok((_, finality)) => {
+                            drop(transition_permit.take());
+                            match finality.await {
+                                Ok(_) => AliceState::BtcRedeemed,
+                                Err(e) => {
+                                    bail!("Waiting for Bitcoin transaction finality failed with {}! The redeem transaction was published, but it is not ensured that the transaction was included! You're screwed.", e)
+                                }
                             }
-                        },
+                        }
                         Err(e) => {
                             error!("Publishing the redeem transaction failed with {}, attempting to wait for cancellation now. If you restart the application before the timelock is expired publishing the redeem transaction will be retried.", e);
+                            drop(transition_permit.take());
                             state3
                                 .wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref())
                                 .await?;
@@ -179,6 +281,7 @@ async fn run_until_internal(
                     },
                     Err(e) => {
                         error!("Constructing the redeem transaction failed with {}, attempting to wait for cancellation now.", e);
+                        drop(transition_permit.take());
                         state3
                             .wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref())
                             .await?;
@@ -207,8 +310,13 @@ async fn run_until_internal(
                 .await
                 .is_err()
             {
+                let sig_a = state3.a.sign(tx_cancel.digest())?;
                 let transaction = tx_cancel
-                    .complete_as_alice(state3.a.clone(), state3.B, state3.tx_cancel_sig_bob.clone())
+                    .complete_as_alice(
+                        (state3.a.public(), sig_a),
+                        state3.B,
+                        state3.tx_cancel_sig_bob.clone(),
+                    )
                     .context("Failed to complete Bitcoin cancel transaction")?;
 
                 if let Err(e) = bitcoin_wallet.broadcast(transaction, "cancel").await {
@@ -241,15 +349,18 @@ async fn run_until_internal(
                 status.is_confirmed_with(state3.punish_timelock)
             });
 
+            drop(transition_permit.take());
+
             select! {
                 seen_refund = seen_refund_tx => {
                     seen_refund.context("Failed to monitor refund transaction")?;
                     let published_refund_tx = bitcoin_wallet.get_raw_transaction(tx_refund.txid()).await?;
 
+                    let tx_refund_encsig = state3.a.encsign(state3.S_b_bitcoin, tx_refund.digest())?;
                     let spend_key = tx_refund.extract_monero_private_key(
                         published_refund_tx,
                         state3.s_a,
-                        state3.a.clone(),
+                        (state3.a.public(), tx_refund_encsig),
                         state3.S_b_bitcoin,
                     )?;
 
@@ -260,9 +371,14 @@ async fn run_until_internal(
                     }
                 }
                 _ = punish_timelock_expired => {
-                    AliceState::BtcPunishable {
-                        state3,
-                        monero_wallet_restore_blockheight,
+                    if punish {
+                        AliceState::BtcPunishable {
+                            state3,
+                            monero_wallet_restore_blockheight,
+                        }
+                    } else {
+                        info!("Punish timelock expired, not punishing because punishing is disabled");
+                        AliceState::BtcPunishDeclined
                     }
                 }
             }
@@ -274,24 +390,45 @@ async fn run_until_internal(
         } => {
             let view_key = state3.v;
 
+            // Reuse the subaddress of an earlier run, it may have been derived differently
+            let subaddress = match db.get_monero_subaddress(swap_id)? {
+                Some(index) => monero_wallet.get_subaddress(index).await?,
+                None => {
+                    let subaddress = monero_wallet.get_address_for_swap(swap_id).await?;
+                    db.insert_monero_subaddress(swap_id, subaddress.index)
+                        .await?;
+
+                    subaddress
+                }
+            };
+
             monero_wallet
-                .create_from(spend_key, view_key, monero_wallet_restore_blockheight)
+                .create_from(
+                    spend_key,
+                    view_key,
+                    monero_wallet_restore_blockheight,
+                    subaddress.address,
+                )
                 .await?;
 
             AliceState::XmrRefunded
         }
+        AliceState::BtcPunishable { .. } if !punish => AliceState::BtcPunishDeclined,
         AliceState::BtcPunishable {
             state3,
             monero_wallet_restore_blockheight,
         } => {
-            let signed_tx_punish = state3.tx_punish().complete(
+            let tx_punish = state3.tx_punish();
+            let sig_a = state3.a.sign(tx_punish.digest())?;
+            let signed_tx_punish = tx_punish.complete(
                 state3.tx_punish_sig_bob.clone(),
-                state3.a.clone(),
+                (state3.a.public(), sig_a),
                 state3.B,
             )?;
 
             let punish = async {
                 let (txid, finality) = bitcoin_wallet.broadcast(signed_tx_punish, "punish").await?;
+                drop(transition_permit.take());
                 finality.await?;
 
                 Result::<_, anyhow::Error>::Ok(txid)
@@ -317,10 +454,12 @@ async fn run_until_internal(
                     let published_refund_tx =
                         bitcoin_wallet.get_raw_transaction(tx_refund.txid()).await?;
 
+                    let tx_refund_encsig =
+                        state3.a.encsign(state3.S_b_bitcoin, tx_refund.digest())?;
                     let spend_key = tx_refund.extract_monero_private_key(
                         published_refund_tx,
                         state3.s_a,
-                        state3.a.clone(),
+                        (state3.a.public(), tx_refund_encsig),
                         state3.S_b_bitcoin,
                     )?;
 
@@ -335,12 +474,28 @@ async fn run_until_internal(
         AliceState::XmrRefunded => AliceState::XmrRefunded,
         AliceState::BtcRedeemed => AliceState::BtcRedeemed,
         AliceState::BtcPunished => AliceState::BtcPunished,
+        AliceState::BtcPunishDeclined => AliceState::BtcPunishDeclined,
         AliceState::SafelyAborted => AliceState::SafelyAborted,
     };
 
     let db_state = (&new_state).into();
     db.insert_latest_state(swap_id, database::Swap::Alice(db_state))
         .await?;
+
+    // Resumed swaps only hold a permit while driving a transition, not while
+    // waiting for the blockchain or Bob
+    drop(transition_permit);
+    let transition_permit = match &resume_limit {
+        Some(resume_limit) => Some(
+            resume_limit
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("resume limit is never closed"),
+        ),
+        None => None,
+    };
+
     run_until_internal(
         new_state,
         is_target_state,
@@ -350,6 +505,59 @@ async fn run_until_internal(
         env_config,
         swap_id,
         db,
+        punish,
+        cold_sign_dir,
+        abort_dir,
+        transition_permit,
+        resume_limit,
     )
     .await
 }
+
+/// How long we wait for the Monero wallet to catch up with the daemon before
+/// locking the XMR. The swap can be resumed to try again.
+const MONERO_SYNC_MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+
+/// Wait for Bob's lock transaction to be seen, giving up once the deadline has
+/// passed.
+///
+/// Returns whether the lock transaction was seen before the deadline. At this
+/// point no funds have been committed by either party, hence it is safe to
+/// abort the swap if Bob does not act in time.
+async fn wait_for_lock_until_deadline(
+    deadline: Duration,
+    lock_seen: impl Future<Output = Result<()>>,
+) -> Result<bool> {
+    match timeout(deadline, lock_seen).await {
+        Ok(result) => {
+            result.context("Failed to watch for lock Bitcoin tx")?;
+
+            Ok(true)
+        }
+        Err(_) => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn given_lock_not_seen_before_deadline_aborts() {
+        let lock_seen =
+            wait_for_lock_until_deadline(Duration::from_millis(10), futures::future::pending())
+                .await
+                .unwrap();
+
+        assert!(!lock_seen)
+    }
+
+    #[tokio::test]
+    async fn given_lock_seen_before_deadline_continues() {
+        let lock_seen = wait_for_lock_until_deadline(Duration::from_secs(10), async { Ok(()) })
+            .await
+            .unwrap();
+
+        assert!(lock_seen)
+    }
+}
@@ -1,35 +1,47 @@
 use crate::database::Database;
 use crate::env::Config;
-use crate::network::{peer_tracker, spot_price};
+use crate::network::{keepalive, peer_tracker, protocol_version, spot_price};
 use crate::protocol::alice::TransferProof;
 use crate::protocol::bob;
 use crate::{bitcoin, monero};
 use anyhow::{anyhow, Error, Result};
 pub use execution_setup::{Message0, Message2, Message4};
 use libp2p::core::Multiaddr;
-use libp2p::request_response::{RequestResponseMessage, ResponseChannel};
+use libp2p::ping::{Ping, PingFailure, PingSuccess};
+use libp2p::request_response::{OutboundFailure, RequestResponseMessage, ResponseChannel};
 use libp2p::{NetworkBehaviour, PeerId};
 use std::sync::Arc;
+use time::OffsetDateTime;
 use tracing::debug;
 use uuid::Uuid;
 
+pub use self::abandon::abandon;
 pub use self::cancel::cancel;
 pub use self::encrypted_signature::EncryptedSignature;
-pub use self::event_loop::{EventLoop, EventLoopHandle};
+pub use self::event_loop::{DialTimeout, EventLoop, EventLoopHandle};
+pub use self::notification::{DesktopNotifier, NoopNotifier, Notifier};
+pub use self::recover_xmr::recover_xmr;
 pub use self::refund::refund;
+pub use self::refund_address::RefundAddressPolicy;
 pub use self::state::*;
 pub use self::swap::{run, run_until};
+pub use self::verification::{AutoAccept, ConsolePrompt, SwapAmounts, VerifyAmounts};
 use crate::network::quote;
 use crate::network::quote::BidQuote;
 
+pub mod abandon;
 pub mod cancel;
 mod encrypted_signature;
 pub mod event_loop;
 mod execution_setup;
+pub mod notification;
+pub mod recover_xmr;
 pub mod refund;
+mod refund_address;
 pub mod state;
 pub mod swap;
 mod transfer_proof;
+pub mod verification;
 
 pub struct Swap {
     pub state: BobState,
@@ -40,6 +52,10 @@ pub struct Swap {
     pub env_config: Config,
     pub swap_id: Uuid,
     pub receive_monero_address: ::monero::Address,
+    pub notifier: Arc<dyn Notifier>,
+    pub verifier: Arc<dyn VerifyAmounts>,
+    pub lock_deadline: Option<OffsetDateTime>,
+    pub refund_address_policy: RefundAddressPolicy,
 }
 
 pub struct Builder {
@@ -55,6 +71,11 @@ pub struct Builder {
     event_loop_handle: EventLoopHandle,
 
     receive_monero_address: ::monero::Address,
+
+    notifier: Arc<dyn Notifier>,
+    verifier: Arc<dyn VerifyAmounts>,
+    lock_deadline: Option<OffsetDateTime>,
+    refund_address_policy: RefundAddressPolicy,
 }
 
 enum InitParams {
@@ -82,6 +103,10 @@ impl Builder {
             env_config,
             event_loop_handle,
             receive_monero_address,
+            notifier: Arc::new(NoopNotifier),
+            verifier: Arc::new(AutoAccept),
+            lock_deadline: None,
+            refund_address_policy: RefundAddressPolicy::default(),
         }
     }
 
@@ -92,7 +117,33 @@ impl Builder {
         }
     }
 
+    pub fn with_notifier(self, notifier: Arc<dyn Notifier>) -> Self {
+        Self { notifier, ..self }
+    }
+
+    pub fn with_verifier(self, verifier: Arc<dyn VerifyAmounts>) -> Self {
+        Self { verifier, ..self }
+    }
+
+    /// Abort the swap instead of locking the Bitcoin once the given point in
+    /// time has passed.
+    pub fn with_lock_deadline(self, lock_deadline: Option<OffsetDateTime>) -> Self {
+        Self {
+            lock_deadline,
+            ..self
+        }
+    }
+
+    pub fn with_refund_address_policy(self, refund_address_policy: RefundAddressPolicy) -> Self {
+        Self {
+            refund_address_policy,
+            ..self
+        }
+    }
+
     pub fn build(self) -> Result<bob::Swap> {
+        monero::validate_address(&self.receive_monero_address, self.env_config.monero_network)?;
+
         let state = match self.init_params {
             InitParams::New { btc_amount } => BobState::Started { btc_amount },
             InitParams::None => self.db.get_state(self.swap_id)?.try_into_bob()?.into(),
@@ -107,6 +158,10 @@ impl Builder {
             swap_id: self.swap_id,
             env_config: self.env_config,
             receive_monero_address: self.receive_monero_address,
+            notifier: self.notifier,
+            verifier: self.verifier,
+            lock_deadline: self.lock_deadline,
+            refund_address_policy: self.refund_address_policy,
         })
     }
 }
@@ -114,6 +169,13 @@ impl Builder {
 #[derive(Debug)]
 pub enum OutEvent {
     ConnectionEstablished(PeerId),
+    ProtocolVersionAgreed {
+        peer: PeerId,
+        handshake: protocol_version::Handshake,
+    },
+    /// The peer does not support the protocol version handshake because it
+    /// predates it.
+    ProtocolVersionUnsupported(PeerId),
     QuoteReceived(BidQuote),
     SpotPriceReceived(spot_price::Response),
     ExecutionSetupDone(Result<Box<State2>>),
@@ -123,6 +185,10 @@ pub enum OutEvent {
     },
     EncryptedSignatureAcknowledged,
     ResponseSent, // Same variant is used for all messages as no processing is done
+    Pinged {
+        peer: PeerId,
+        result: Result<PingSuccess, PingFailure>,
+    },
     CommunicationError(Error),
 }
 
@@ -200,6 +266,53 @@ impl From<quote::OutEvent> for OutEvent {
     }
 }
 
+impl From<protocol_version::OutEvent> for OutEvent {
+    fn from(event: protocol_version::OutEvent) -> Self {
+        match event {
+            protocol_version::OutEvent::Message {
+                peer,
+                message: RequestResponseMessage::Response { response, .. },
+            } => match protocol_version::Handshake::ours().check(&response) {
+                Ok(()) => OutEvent::ProtocolVersionAgreed {
+                    peer,
+                    handshake: response,
+                },
+                Err(mismatch) => OutEvent::CommunicationError(
+                    Error::new(mismatch).context(format!("Cannot swap with peer {}", peer)),
+                ),
+            },
+            protocol_version::OutEvent::Message {
+                message: RequestResponseMessage::Request { .. },
+                ..
+            }
+            | protocol_version::OutEvent::ResponseSent { .. } => {
+                OutEvent::CommunicationError(anyhow!(
+                    "Bob is only meant to initiate the protocol version handshake, not answer it"
+                ))
+            }
+            protocol_version::OutEvent::InboundFailure { peer, error, .. } => {
+                OutEvent::CommunicationError(anyhow!(
+                    "protocol_version protocol with peer {} failed due to {:?}",
+                    peer,
+                    error
+                ))
+            }
+            protocol_version::OutEvent::OutboundFailure {
+                peer,
+                error: OutboundFailure::UnsupportedProtocols,
+                ..
+            } => OutEvent::ProtocolVersionUnsupported(peer),
+            protocol_version::OutEvent::OutboundFailure { peer, error, .. } => {
+                OutEvent::CommunicationError(anyhow!(
+                    "protocol_version protocol with peer {} failed due to {:?}",
+                    peer,
+                    error
+                ))
+            }
+        }
+    }
+}
+
 impl From<execution_setup::OutEvent> for OutEvent {
     fn from(event: execution_setup::OutEvent) -> Self {
         match event {
@@ -236,33 +349,55 @@ impl From<encrypted_signature::OutEvent> for OutEvent {
     }
 }
 
+impl From<keepalive::OutEvent> for OutEvent {
+    fn from(event: keepalive::OutEvent) -> Self {
+        OutEvent::Pinged {
+            peer: event.peer,
+            result: event.result,
+        }
+    }
+}
+
 /// A `NetworkBehaviour` that represents an XMR/BTC swap node as Bob.
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "OutEvent", event_process = false)]
 #[allow(missing_debug_implementations)]
 pub struct Behaviour {
     pt: peer_tracker::Behaviour,
+    protocol_version: protocol_version::Behaviour,
     quote: quote::Behaviour,
     spot_price: spot_price::Behaviour,
     execution_setup: execution_setup::Behaviour,
     transfer_proof: transfer_proof::Behaviour,
     encrypted_signature: encrypted_signature::Behaviour,
+    keepalive: Ping,
 }
 
-impl Default for Behaviour {
-    fn default() -> Self {
+impl Behaviour {
+    pub fn new(env_config: Config) -> Self {
         Self {
             pt: Default::default(),
+            protocol_version: protocol_version::bob(),
             quote: quote::bob(),
             spot_price: spot_price::bob(),
             execution_setup: Default::default(),
             transfer_proof: Default::default(),
             encrypted_signature: Default::default(),
+            keepalive: keepalive::behaviour(
+                env_config.network_keepalive_interval,
+                env_config.network_keepalive_timeout,
+            ),
         }
     }
-}
 
-impl Behaviour {
+    /// Tell Alice which protocol version we speak, her answer is checked
+    /// against it.
+    pub fn negotiate_protocol_version(&mut self, alice: PeerId) {
+        let _ = self
+            .protocol_version
+            .send_request(&alice, protocol_version::Handshake::ours());
+    }
+
     pub fn request_quote(&mut self, alice: PeerId) {
         let _ = self.quote.send_request(&alice, ());
     }
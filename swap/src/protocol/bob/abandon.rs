@@ -0,0 +1,66 @@
+use crate::bitcoin::{ExpiredTimelocks, Wallet};
+use crate::database::{Database, Swap};
+use crate::protocol::bob::BobState;
+use anyhow::{bail, Result};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Abandon a swap in which the Monero has not been locked yet and get the
+/// Bitcoin back as fast as the timelocks allow.
+///
+/// The Bitcoin can only be reclaimed through the cancel and refund
+/// transactions. This drives through both, waiting for the cancel timelock to
+/// expire if necessary and persisting the progress on the way.
+pub async fn abandon(
+    swap_id: Uuid,
+    state: BobState,
+    bitcoin_wallet: Arc<Wallet>,
+    db: Database,
+) -> Result<BobState> {
+    let state6 = match state {
+        BobState::BtcLocked(state3) => state3.cancel(),
+        BobState::CancelTimelockExpired(state6) => state6,
+        BobState::BtcCancelled(state6) => state6,
+        _ => bail!(
+            "Cannot abandon swap {} because it is in state {}. Only swaps in which the Monero has not been locked can be abandoned, use the cancel and refund commands instead.",
+            swap_id,
+            state
+        ),
+    };
+
+    let is_cancelled = state6.check_for_tx_cancel(&*bitcoin_wallet).await.is_ok();
+
+    if !is_cancelled {
+        if let ExpiredTimelocks::None = state6.expired_timelock(&*bitcoin_wallet).await? {
+            tracing::info!(
+                "The Bitcoin can only be reclaimed once the cancel timelock of {} expired. The timelock starts counting once the lock transaction {} is confirmed, waiting ...",
+                state6.cancel_timelock(),
+                state6.tx_lock_id()
+            );
+
+            state6
+                .wait_for_cancel_timelock_to_expire(&*bitcoin_wallet)
+                .await?;
+        }
+
+        let state = BobState::CancelTimelockExpired(state6.clone());
+        db.insert_latest_state(swap_id, Swap::Bob(state.into()))
+            .await?;
+
+        let txid = state6.submit_tx_cancel(&*bitcoin_wallet).await?;
+        tracing::info!(%txid, "Published cancel transaction");
+    }
+
+    let state = BobState::BtcCancelled(state6.clone());
+    db.insert_latest_state(swap_id, Swap::Bob(state.into()))
+        .await?;
+
+    tracing::info!("Refunding the Bitcoin, waiting for the refund transaction to be final ...");
+    state6.refund_btc(&*bitcoin_wallet).await?;
+
+    let state = BobState::BtcRefunded(state6);
+    db.insert_latest_state(swap_id, Swap::Bob(state.clone().into()))
+        .await?;
+
+    Ok(state)
+}
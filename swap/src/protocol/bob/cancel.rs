@@ -34,15 +34,11 @@ pub async fn cancel(
     };
 
     if !force {
-        if let ExpiredTimelocks::None = state6.expired_timelock(bitcoin_wallet.as_ref()).await? {
+        if let ExpiredTimelocks::None = state6.expired_timelock(&*bitcoin_wallet).await? {
             return Ok(Err(Error::CancelTimelockNotExpiredYet));
         }
 
-        if state6
-            .check_for_tx_cancel(bitcoin_wallet.as_ref())
-            .await
-            .is_ok()
-        {
+        if state6.check_for_tx_cancel(&*bitcoin_wallet).await.is_ok() {
             let state = BobState::BtcCancelled(state6);
             let db_state = state.into();
             db.insert_latest_state(swap_id, Swap::Bob(db_state)).await?;
@@ -51,7 +47,7 @@ pub async fn cancel(
         }
     }
 
-    let txid = state6.submit_tx_cancel(bitcoin_wallet.as_ref()).await?;
+    let txid = state6.submit_tx_cancel(&*bitcoin_wallet).await?;
 
     let state = BobState::BtcCancelled(state6);
     let db_state = state.clone().into();
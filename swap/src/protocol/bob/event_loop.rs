@@ -1,4 +1,6 @@
 use crate::bitcoin::EncryptedSignature;
+use crate::database::{Counterparty, Database};
+use crate::env::Config;
 use crate::network::quote::BidQuote;
 use crate::network::{spot_price, transport, TokioExecutor};
 use crate::protocol::alice::TransferProof;
@@ -10,8 +12,17 @@ use libp2p::core::Multiaddr;
 use libp2p::PeerId;
 use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tracing::{debug, error, trace};
+use tracing::{debug, error, trace, warn};
+use uuid::Uuid;
+
+/// How long to wait for the connection to Alice unless configured otherwise.
+pub const DEFAULT_DIAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Could not reach maker within {}s", .0.as_secs())]
+pub struct DialTimeout(pub Duration);
 
 #[derive(Debug)]
 pub struct Channels<T> {
@@ -39,6 +50,7 @@ pub struct EventLoopHandle {
     recv_transfer_proof: Receiver<TransferProof>,
     conn_established: Receiver<PeerId>,
     dial_alice: Sender<()>,
+    dial_timeout: Duration,
     send_encrypted_signature: Sender<EncryptedSignature>,
     request_spot_price: Sender<spot_price::Request>,
     recv_spot_price: Receiver<spot_price::Response>,
@@ -47,6 +59,48 @@ pub struct EventLoopHandle {
 }
 
 impl EventLoopHandle {
+    /// Fail dialing Alice if the connection is not established within the
+    /// given time.
+    pub fn with_dial_timeout(self, dial_timeout: Duration) -> Self {
+        Self {
+            dial_timeout,
+            ..self
+        }
+    }
+
+    /// A handle that is not backed by an event loop. Dialing Alice always
+    /// succeeds, everything else fails. Used to drive the swap state machine
+    /// in tests.
+    #[cfg(test)]
+    pub(crate) fn connected() -> Self {
+        let dial_alice = Channels::new();
+        let conn_established = Channels::new();
+
+        let mut dials = dial_alice.receiver;
+        let established = conn_established.sender;
+        tokio::spawn(async move {
+            while dials.recv().await.is_some() {
+                if established.send(PeerId::random()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            start_execution_setup: Channels::new().sender,
+            done_execution_setup: Channels::new().receiver,
+            recv_transfer_proof: Channels::new().receiver,
+            conn_established: conn_established.receiver,
+            dial_alice: dial_alice.sender,
+            dial_timeout: DEFAULT_DIAL_TIMEOUT,
+            send_encrypted_signature: Channels::new().sender,
+            request_spot_price: Channels::new().sender,
+            recv_spot_price: Channels::new().receiver,
+            request_quote: Channels::new().sender,
+            recv_quote: Channels::new().receiver,
+        }
+    }
+
     pub async fn execution_setup(&mut self, state0: State0) -> Result<State2> {
         let _ = self.start_execution_setup.send(state0).await?;
 
@@ -65,12 +119,14 @@ impl EventLoopHandle {
 
     /// Dials other party and wait for the connection to be established.
     /// Do nothing if we are already connected
+    ///
+    /// Fails with [`DialTimeout`] if Alice cannot be reached in time.
     pub async fn dial(&mut self) -> Result<()> {
         let _ = self.dial_alice.send(()).await?;
 
-        self.conn_established
-            .recv()
+        tokio::time::timeout(self.dial_timeout, self.conn_established.recv())
             .await
+            .map_err(|_| DialTimeout(self.dial_timeout))?
             .ok_or_else(|| anyhow!("Failed to receive connection established from Alice"))?;
 
         Ok(())
@@ -88,7 +144,10 @@ impl EventLoopHandle {
             .await
             .ok_or_else(|| anyhow!("Failed to receive spot price from Alice"))?;
 
-        Ok(response.xmr)
+        match response {
+            spot_price::Response::Xmr(xmr) => Ok(xmr),
+            spot_price::Response::Error(error) => Err(error.into()),
+        }
     }
 
     pub async fn request_quote(&mut self) -> Result<BidQuote> {
@@ -128,6 +187,7 @@ pub struct EventLoop {
     send_encrypted_signature: Receiver<EncryptedSignature>,
     request_quote: Receiver<()>,
     recv_quote: Sender<BidQuote>,
+    counterparty_db: Option<(Database, Uuid)>,
 }
 
 impl EventLoop {
@@ -136,8 +196,9 @@ impl EventLoop {
         alice_peer_id: PeerId,
         alice_addr: Multiaddr,
         bitcoin_wallet: Arc<bitcoin::Wallet>,
+        env_config: Config,
     ) -> Result<(Self, EventLoopHandle)> {
-        let behaviour = Behaviour::default();
+        let behaviour = Behaviour::new(env_config);
         let transport = transport::build(identity)?;
 
         let mut swarm = libp2p::swarm::SwarmBuilder::new(
@@ -177,6 +238,7 @@ impl EventLoop {
             recv_spot_price: recv_spot_price.sender,
             request_quote: request_quote.receiver,
             recv_quote: recv_quote.sender,
+            counterparty_db: None,
         };
 
         let handle = EventLoopHandle {
@@ -185,6 +247,7 @@ impl EventLoop {
             recv_transfer_proof: recv_transfer_proof.receiver,
             conn_established: conn_established.receiver,
             dial_alice: dial_alice.sender,
+            dial_timeout: DEFAULT_DIAL_TIMEOUT,
             send_encrypted_signature: send_encrypted_signature.sender,
             request_spot_price: request_spot_price.sender,
             recv_spot_price: recv_spot_price.receiver,
@@ -195,14 +258,36 @@ impl EventLoop {
         Ok((event_loop, handle))
     }
 
+    /// Add another address of Alice, dialed if the known ones fail.
+    pub fn add_alice_address(&mut self, address: Multiaddr) {
+        self.swarm.add_address(self.alice_peer_id, address);
+    }
+
+    /// Store the address Alice was reached at as the counterparty of the
+    /// given swap, so the next resume dials the address that worked last.
+    pub fn with_counterparty_updates(self, db: Database, swap_id: Uuid) -> Self {
+        Self {
+            counterparty_db: Some((db, swap_id)),
+            ..self
+        }
+    }
+
     pub async fn run(mut self) -> Result<Infallible> {
         loop {
             tokio::select! {
                 swarm_event = self.swarm.next().fuse() => {
                     match swarm_event {
                         OutEvent::ConnectionEstablished(peer_id) => {
+                            self.swarm.negotiate_protocol_version(peer_id);
+                            self.update_counterparty().await;
                             let _ = self.conn_established.send(peer_id).await;
                         }
+                        OutEvent::ProtocolVersionAgreed { peer, handshake } => {
+                            debug!(%peer, features = ?handshake.features, "Agreed on protocol version {}", handshake.version);
+                        }
+                        OutEvent::ProtocolVersionUnsupported(peer) => {
+                            debug!(%peer, "Peer predates the protocol version handshake");
+                        }
                         OutEvent::SpotPriceReceived(msg) => {
                             let _ = self.recv_spot_price.send(msg).await;
                         },
@@ -223,6 +308,11 @@ impl EventLoop {
                             debug!("Alice acknowledged encrypted signature");
                         }
                         OutEvent::ResponseSent => {}
+                        OutEvent::Pinged { peer, result } => {
+                            if let Err(error) = result {
+                                debug!(%peer, "Keepalive ping failed: {}", error);
+                            }
+                        }
                         OutEvent::CommunicationError(err) => {
                             bail!("Communication error: {:#}", err)
                         }
@@ -265,4 +355,22 @@ impl EventLoop {
             }
         }
     }
+
+    async fn update_counterparty(&self) {
+        let (db, swap_id) = match &self.counterparty_db {
+            Some(counterparty_db) => counterparty_db,
+            None => return,
+        };
+        let (peer_id, address) = match self.swarm.pt.counterparty() {
+            Some(counterparty) if counterparty.0 == self.alice_peer_id => counterparty,
+            _ => return,
+        };
+
+        if let Err(error) = db
+            .insert_counterparty(*swap_id, Counterparty { peer_id, address })
+            .await
+        {
+            warn!("Failed to store the address of Alice: {:#}", error);
+        }
+    }
 }
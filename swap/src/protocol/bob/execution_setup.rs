@@ -80,7 +80,7 @@ impl Behaviour {
                 let message1 =
                     serde_cbor::from_slice::<Message1>(&substream.read_message(BUF_SIZE).await?)
                         .context("Failed to deserialize message1")?;
-                let state1 = state0.receive(bitcoin_wallet.as_ref(), message1).await?;
+                let state1 = state0.receive(&*bitcoin_wallet, message1).await?;
 
                 substream
                     .write_message(
@@ -0,0 +1,151 @@
+use crate::protocol::bob::BobState;
+use uuid::Uuid;
+
+/// Informs the user about important events of a swap outside of the logs.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, title: &str, message: &str);
+}
+
+/// A [`Notifier`] that does nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopNotifier;
+
+impl Notifier for NoopNotifier {
+    fn notify(&self, _: &str, _: &str) {}
+}
+
+/// A [`Notifier`] that shows a desktop notification using the tooling provided
+/// by the operating system.
+///
+/// On platforms without notification support, this is a no-op.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    #[cfg(target_os = "linux")]
+    fn notify(&self, title: &str, message: &str) {
+        let result = tokio::process::Command::new("notify-send")
+            .arg(title)
+            .arg(message)
+            .spawn();
+
+        if let Err(error) = result {
+            tracing::debug!("Failed to show desktop notification: {:#}", error);
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn notify(&self, title: &str, message: &str) {
+        let script = format!(
+            "display notification {} with title {}",
+            applescript_string(message),
+            applescript_string(title)
+        );
+
+        let result = tokio::process::Command::new("osascript")
+            .arg("-e")
+            .arg(script)
+            .spawn();
+
+        if let Err(error) = result {
+            tracing::debug!("Failed to show desktop notification: {:#}", error);
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn notify(&self, _: &str, _: &str) {}
+}
+
+/// Quote the given text as an AppleScript string literal.
+#[cfg(any(target_os = "macos", test))]
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Notify the user if the swap transitioned into a state that is either
+/// terminal or requires their attention.
+pub fn notify_state_transition(notifier: &dyn Notifier, swap_id: Uuid, state: &BobState) {
+    let message = match state {
+        BobState::XmrRedeemed { .. } => "Swap completed, the Monero has been redeemed.",
+        BobState::BtcRefunded(..) => "Swap refunded, the Bitcoin has been returned to your wallet.",
+        BobState::BtcPunished { .. } => "Swap failed, the Bitcoin has been punished.",
+        BobState::CancelTimelockExpired(..) => {
+            "The cancel timelock expired, the swap is being cancelled."
+        }
+        _ => return,
+    };
+
+    notifier.notify(&format!("Swap {}", swap_id), message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::execution_setup;
+    use ::bitcoin::hashes::Hash;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockNotifier {
+        notifications: Mutex<Vec<String>>,
+    }
+
+    impl Notifier for MockNotifier {
+        fn notify(&self, _: &str, message: &str) {
+            self.notifications.lock().unwrap().push(message.to_owned());
+        }
+    }
+
+    #[test]
+    fn notifies_on_terminal_states() {
+        let notifier = MockNotifier::default();
+        let tx_lock_id = crate::bitcoin::Txid::from_inner([0u8; 32]);
+
+        notify_state_transition(&notifier, Uuid::new_v4(), &BobState::XmrRedeemed {
+            tx_lock_id,
+        });
+        notify_state_transition(&notifier, Uuid::new_v4(), &BobState::BtcPunished {
+            tx_lock_id,
+        });
+
+        assert_eq!(notifier.notifications.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn notifies_on_refund_and_expired_cancel_timelock() {
+        let notifier = MockNotifier::default();
+        let bitcoin_wallet = crate::bitcoin::mock::MockWallet::default();
+        let (_, state2) = execution_setup(&bitcoin_wallet).await;
+        let (state3, _) = state2.lock_btc().await.unwrap();
+        let state6 = state3.cancel();
+
+        notify_state_transition(
+            &notifier,
+            Uuid::new_v4(),
+            &BobState::CancelTimelockExpired(state6.clone()),
+        );
+        notify_state_transition(&notifier, Uuid::new_v4(), &BobState::BtcRefunded(state6));
+
+        assert_eq!(*notifier.notifications.lock().unwrap(), vec![
+            "The cancel timelock expired, the swap is being cancelled.",
+            "Swap refunded, the Bitcoin has been returned to your wallet.",
+        ]);
+    }
+
+    #[test]
+    fn escapes_quotes_and_backslashes_for_applescript() {
+        assert_eq!(applescript_string(r#"Swap "a\b""#), r#""Swap \"a\\b\"""#);
+    }
+
+    #[test]
+    fn does_not_notify_on_intermediate_states() {
+        let notifier = MockNotifier::default();
+
+        notify_state_transition(&notifier, Uuid::new_v4(), &BobState::Started {
+            btc_amount: crate::bitcoin::Amount::ONE_BTC,
+        });
+        notify_state_transition(&notifier, Uuid::new_v4(), &BobState::SafelyAborted);
+
+        assert!(notifier.notifications.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,52 @@
+use crate::database::{Database, Swap};
+use crate::env::Config;
+use crate::monero;
+use crate::protocol::bob::swap::sweep_xmr;
+use crate::protocol::bob::BobState;
+use anyhow::{bail, Result};
+use monero_rpc::wallet::BlockHeight;
+use uuid::Uuid;
+
+/// Generate the wallet holding the Monero of a swap again and sweep it to the
+/// given address.
+///
+/// Covers the loss of the wallet generated when claiming the Monero, it is
+/// recreated from the keys persisted with the swap. The wallet scans from the
+/// given height, or the restore height persisted with the swap if there is
+/// none.
+pub async fn recover_xmr(
+    swap_id: Uuid,
+    state: BobState,
+    monero_wallet: &monero::Wallet,
+    env_config: Config,
+    receive_monero_address: monero::Address,
+    from_height: Option<BlockHeight>,
+    db: Database,
+) -> Result<BobState> {
+    let state5 = match state {
+        BobState::BtcRedeemed(state5) => state5,
+        _ => bail!(
+            "Cannot recover the Monero of swap {} because it is in state {}. Only swaps whose Bitcoin was redeemed by the seller can be recovered.",
+            swap_id,
+            state
+        ),
+    };
+
+    state5.recover_xmr(monero_wallet, from_height).await?;
+    sweep_xmr(
+        monero_wallet,
+        env_config.monero_finality_confirmations,
+        state5.xmr(),
+        receive_monero_address,
+    )
+    .await?;
+
+    let state = BobState::XmrRedeemed {
+        tx_lock_id: state5.tx_lock_id(),
+    };
+    let db_state = state.clone().into();
+
+    db.insert_latest_state(swap_id, Swap::Bob(db_state)).await?;
+
+    Ok(state)
+}
@@ -0,0 +1,109 @@
+use crate::bitcoin;
+use crate::bitcoin::wallet::BitcoinWallet;
+use anyhow::{bail, Result};
+
+/// Where the Bitcoin is refunded to in case the swap is cancelled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefundAddressPolicy {
+    /// Derive a new address of the internal wallet for every swap. Refunds of
+    /// different swaps cannot be linked to each other on-chain.
+    Fresh,
+    /// Refund every swap to the same, configured address.
+    Fixed(bitcoin::Address),
+}
+
+impl Default for RefundAddressPolicy {
+    fn default() -> Self {
+        RefundAddressPolicy::Fresh
+    }
+}
+
+impl RefundAddressPolicy {
+    /// Refund to the given address if there is one, otherwise to a fresh
+    /// address per swap.
+    ///
+    /// Fails if the address is not valid on the given network.
+    pub fn new(
+        refund_address: Option<bitcoin::Address>,
+        network: bitcoin::Network,
+    ) -> Result<Self> {
+        let refund_address = match refund_address {
+            Some(refund_address) => refund_address,
+            None => return Ok(RefundAddressPolicy::Fresh),
+        };
+
+        if refund_address.network != network {
+            bail!(
+                "Refund address {} is a {} address but the swap runs on {}",
+                refund_address,
+                refund_address.network,
+                network
+            )
+        }
+
+        Ok(RefundAddressPolicy::Fixed(refund_address))
+    }
+
+    pub async fn refund_address(
+        &self,
+        bitcoin_wallet: &dyn BitcoinWallet,
+    ) -> Result<bitcoin::Address> {
+        let refund_address = match self {
+            RefundAddressPolicy::Fresh => return bitcoin_wallet.new_address().await,
+            RefundAddressPolicy::Fixed(refund_address) => refund_address,
+        };
+
+        if !bitcoin_wallet.is_our_address(refund_address).await? {
+            tracing::warn!(
+                "Refund address {} does not belong to the internal wallet, a refund will be sent to this external address",
+                refund_address
+            );
+        }
+
+        Ok(refund_address.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::mock::MockWallet;
+    use rand::thread_rng;
+
+    #[tokio::test]
+    async fn fresh_policy_derives_refund_address_from_wallet() {
+        let bitcoin_wallet = MockWallet::default();
+        let policy = RefundAddressPolicy::new(None, bitcoin::Network::Regtest).unwrap();
+
+        let refund_address = policy.refund_address(&bitcoin_wallet).await.unwrap();
+
+        assert_eq!(policy, RefundAddressPolicy::Fresh);
+        assert_eq!(refund_address, bitcoin_wallet.new_address().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn fixed_policy_refunds_to_configured_address() {
+        let bitcoin_wallet = MockWallet::default();
+        let configured = external_address();
+        let policy =
+            RefundAddressPolicy::new(Some(configured.clone()), bitcoin::Network::Regtest).unwrap();
+
+        let refund_address = policy.refund_address(&bitcoin_wallet).await.unwrap();
+
+        assert_eq!(refund_address, configured);
+        assert_ne!(refund_address, bitcoin_wallet.new_address().await.unwrap());
+    }
+
+    #[test]
+    fn refund_address_of_other_network_is_rejected() {
+        let configured = external_address();
+
+        assert!(RefundAddressPolicy::new(Some(configured), bitcoin::Network::Bitcoin).is_err());
+    }
+
+    fn external_address() -> bitcoin::Address {
+        let key = bitcoin::SecretKey::new_random(&mut thread_rng()).public();
+
+        bitcoin::Address::p2wpkh(&key.into(), bitcoin::Network::Regtest).unwrap()
+    }
+}
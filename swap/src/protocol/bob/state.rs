@@ -1,14 +1,16 @@
+use crate::bitcoin::wallet::{BitcoinWallet, ScriptStatus};
 use crate::bitcoin::{
-    self, current_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock, Transaction, TxCancel,
-    TxLock, Txid,
+    self, timelock_epoch, CancelTimelock, ExpiredTimelocks, PunishTimelock, TimelockEpoch,
+    Transaction, TransactionKind, TxCancel, TxLock, Txid,
 };
 use crate::monero;
-use crate::monero::wallet::WatchRequest;
+use crate::monero::wallet::{MoneroWallet, WatchRequest};
 use crate::monero::{monero_private_key, TransferProof};
 use crate::monero_ext::ScalarExt;
 use crate::protocol::alice::{Message1, Message3};
-use crate::protocol::bob::{EncryptedSignature, Message0, Message2, Message4};
-use crate::protocol::CROSS_CURVE_PROOF_SYSTEM;
+use crate::protocol::bob::{EncryptedSignature, Message0, Message2, Message4, SwapAmounts};
+use crate::protocol::{RecoveryAction, CROSS_CURVE_PROOF_SYSTEM};
+use ::bitcoin::util::psbt::PartiallySignedTransaction;
 use anyhow::{anyhow, bail, Context, Result};
 use ecdsa_fun::adaptor::{Adaptor, HashTranscript};
 use ecdsa_fun::nonce::Deterministic;
@@ -69,6 +71,131 @@ impl fmt::Display for BobState {
     }
 }
 
+impl BobState {
+    /// The Bitcoin transactions of this swap that are published or can be
+    /// published in the current state, labeled by their kind.
+    ///
+    /// Transactions that can only be published later, e.g. once a timelock
+    /// expired, are not included until the swap reaches the state they are
+    /// published in.
+    pub fn bitcoin_transactions(&self) -> Vec<(TransactionKind, bitcoin::Txid)> {
+        match self {
+            BobState::Started { .. }
+            | BobState::ExecutionSetupDone(..)
+            | BobState::SafelyAborted => {
+                vec![]
+            }
+            BobState::BtcLocked(state) | BobState::XmrLockProofReceived { state, .. } => {
+                vec![(TransactionKind::Lock, state.tx_lock_id())]
+            }
+            BobState::XmrLocked(state) => vec![(TransactionKind::Lock, state.tx_lock_id())],
+            BobState::EncSigSent(state) => vec![
+                (TransactionKind::Lock, state.tx_lock_id()),
+                (TransactionKind::Redeem, state.tx_redeem_id()),
+            ],
+            BobState::BtcRedeemed(state) => vec![(TransactionKind::Lock, state.tx_lock_id())],
+            BobState::CancelTimelockExpired(state) => vec![
+                (TransactionKind::Lock, state.tx_lock_id()),
+                (TransactionKind::Cancel, state.tx_cancel_id()),
+            ],
+            BobState::BtcCancelled(state) | BobState::BtcRefunded(state) => vec![
+                (TransactionKind::Lock, state.tx_lock_id()),
+                (TransactionKind::Cancel, state.tx_cancel_id()),
+                (TransactionKind::Refund, state.tx_refund_id()),
+            ],
+            BobState::XmrRedeemed { tx_lock_id } | BobState::BtcPunished { tx_lock_id } => {
+                vec![(TransactionKind::Lock, *tx_lock_id)]
+            }
+        }
+    }
+
+    /// The id of the cancel transaction of this swap, if it is known in the
+    /// current state.
+    pub fn tx_cancel_id(&self) -> Option<Txid> {
+        match self {
+            BobState::BtcLocked(state) | BobState::XmrLockProofReceived { state, .. } => {
+                Some(state.cancel().tx_cancel_id())
+            }
+            BobState::XmrLocked(state) | BobState::EncSigSent(state) => Some(state.tx_cancel_id()),
+            BobState::CancelTimelockExpired(state)
+            | BobState::BtcCancelled(state)
+            | BobState::BtcRefunded(state) => Some(state.tx_cancel_id()),
+            BobState::Started { .. }
+            | BobState::ExecutionSetupDone(..)
+            | BobState::BtcRedeemed(..)
+            | BobState::XmrRedeemed { .. }
+            | BobState::BtcPunished { .. }
+            | BobState::SafelyAborted => None,
+        }
+    }
+
+    /// The id of the refund transaction of this swap, if it is known in the
+    /// current state.
+    pub fn tx_refund_id(&self) -> Option<Txid> {
+        match self {
+            BobState::BtcLocked(state) | BobState::XmrLockProofReceived { state, .. } => {
+                Some(state.cancel().tx_refund_id())
+            }
+            BobState::XmrLocked(state) | BobState::EncSigSent(state) => {
+                Some(state.clone().cancel().tx_refund_id())
+            }
+            BobState::CancelTimelockExpired(state)
+            | BobState::BtcCancelled(state)
+            | BobState::BtcRefunded(state) => Some(state.tx_refund_id()),
+            BobState::Started { .. }
+            | BobState::ExecutionSetupDone(..)
+            | BobState::BtcRedeemed(..)
+            | BobState::XmrRedeemed { .. }
+            | BobState::BtcPunished { .. }
+            | BobState::SafelyAborted => None,
+        }
+    }
+
+    /// The cancel and punish timelocks of this swap, if they are known in the
+    /// current state.
+    pub fn timelocks(&self) -> Option<(CancelTimelock, PunishTimelock)> {
+        match self {
+            BobState::ExecutionSetupDone(state) => {
+                Some((state.cancel_timelock, state.punish_timelock))
+            }
+            BobState::BtcLocked(state) | BobState::XmrLockProofReceived { state, .. } => {
+                Some((state.cancel_timelock, state.punish_timelock))
+            }
+            BobState::XmrLocked(state) | BobState::EncSigSent(state) => {
+                Some((state.cancel_timelock, state.punish_timelock))
+            }
+            BobState::CancelTimelockExpired(state)
+            | BobState::BtcCancelled(state)
+            | BobState::BtcRefunded(state) => Some((state.cancel_timelock, state.punish_timelock)),
+            BobState::Started { .. }
+            | BobState::BtcRedeemed(..)
+            | BobState::XmrRedeemed { .. }
+            | BobState::BtcPunished { .. }
+            | BobState::SafelyAborted => None,
+        }
+    }
+
+    /// The action to take to recover this swap, `None` if it is complete.
+    pub fn is_recoverable(&self) -> Option<RecoveryAction> {
+        match self {
+            BobState::Started { .. } | BobState::ExecutionSetupDone(..) => {
+                Some(RecoveryAction::Nothing)
+            }
+            BobState::BtcLocked(..)
+            | BobState::XmrLockProofReceived { .. }
+            | BobState::XmrLocked(..)
+            | BobState::EncSigSent(..)
+            | BobState::CancelTimelockExpired(..) => Some(RecoveryAction::PublishCancel),
+            BobState::BtcCancelled(..) => Some(RecoveryAction::PublishRefund),
+            BobState::BtcRedeemed(..) => Some(RecoveryAction::ClaimXmr),
+            BobState::BtcRefunded(..)
+            | BobState::XmrRedeemed { .. }
+            | BobState::BtcPunished { .. }
+            | BobState::SafelyAborted => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct State0 {
     b: bitcoin::SecretKey,
@@ -132,7 +259,7 @@ impl State0 {
         }
     }
 
-    pub async fn receive(self, wallet: &bitcoin::Wallet, msg: Message1) -> Result<State1> {
+    pub async fn receive(self, wallet: &dyn BitcoinWallet, msg: Message1) -> Result<State1> {
         let valid = CROSS_CURVE_PROOF_SYSTEM.verify(
             &msg.dleq_proof_s_a,
             (
@@ -262,6 +389,25 @@ impl State2 {
         }
     }
 
+    /// The unsigned Bitcoin lock transaction of this swap.
+    pub fn unsigned_tx_lock(&self) -> Transaction {
+        PartiallySignedTransaction::from(self.tx_lock.clone())
+            .global
+            .unsigned_tx
+    }
+
+    /// The amounts that Bob commits to by locking the Bitcoin.
+    pub async fn amounts(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<SwapAmounts> {
+        Ok(SwapAmounts {
+            btc: self.tx_lock.lock_amount(),
+            xmr: self.xmr,
+            btc_lock_fee: bitcoin_wallet
+                .unpublished_fee(&self.unsigned_tx_lock())
+                .await?,
+            btc_refund_fee: bitcoin::Amount::from_sat(bitcoin::TX_FEE),
+        })
+    }
+
     pub async fn lock_btc(self) -> Result<(State3, TxLock)> {
         Ok((
             State3 {
@@ -335,17 +481,19 @@ impl State3 {
             tx_cancel_sig_a: self.tx_cancel_sig_a,
             tx_refund_encsig: self.tx_refund_encsig,
             monero_wallet_restore_blockheight,
+            xmr: Some(self.xmr),
         }
     }
 
     pub async fn wait_for_cancel_timelock_to_expire(
         &self,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
     ) -> Result<()> {
         bitcoin_wallet
-            .watch_until_status(&self.tx_lock, |status| {
-                status.is_confirmed_with(self.cancel_timelock)
-            })
+            .watch_until_status(
+                &self.tx_lock,
+                Box::new(|status: ScriptStatus| status.is_confirmed_with(self.cancel_timelock)),
+            )
             .await?;
         Ok(())
     }
@@ -368,16 +516,33 @@ impl State3 {
         self.tx_lock.txid()
     }
 
+    /// The unsigned Bitcoin lock transaction, e.g. to publish it again.
+    pub fn tx_lock(&self) -> &bitcoin::TxLock {
+        &self.tx_lock
+    }
+
+    /// Fetched from electrum, see [`BitcoinWallet::fetch_status_of_script`].
+    pub async fn tx_lock_status(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<ScriptStatus> {
+        bitcoin_wallet.fetch_status_of_script(&self.tx_lock).await
+    }
+
     pub async fn current_epoch(
         &self,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
     ) -> Result<ExpiredTimelocks> {
+        Ok(self.timelock_epoch(bitcoin_wallet).await?.epoch)
+    }
+
+    pub async fn timelock_epoch(
+        &self,
+        bitcoin_wallet: &dyn BitcoinWallet,
+    ) -> Result<TimelockEpoch> {
         let tx_cancel = TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
 
         let tx_lock_status = bitcoin_wallet.status_of_script(&self.tx_lock).await?;
         let tx_cancel_status = bitcoin_wallet.status_of_script(&tx_cancel).await?;
 
-        Ok(current_epoch(
+        Ok(timelock_epoch(
             self.cancel_timelock,
             self.punish_timelock,
             tx_lock_status,
@@ -401,6 +566,9 @@ pub struct State4 {
     tx_cancel_sig_a: Signature,
     tx_refund_encsig: bitcoin::EncryptedSignature,
     monero_wallet_restore_blockheight: BlockHeight,
+    /// The Monero Alice locked, unknown for swaps persisted by older versions.
+    #[serde(default)]
+    xmr: Option<monero::Amount>,
 }
 
 impl State4 {
@@ -415,12 +583,32 @@ impl State4 {
         self.b.encsign(self.S_a_bitcoin, tx_redeem.digest())
     }
 
-    pub async fn watch_for_redeem_btc(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<State5> {
+    pub fn tx_lock_id(&self) -> bitcoin::Txid {
+        self.tx_lock.txid()
+    }
+
+    /// The unsigned Bitcoin lock transaction, e.g. to publish it again.
+    pub fn tx_lock(&self) -> &bitcoin::TxLock {
+        &self.tx_lock
+    }
+
+    pub fn tx_redeem_id(&self) -> bitcoin::Txid {
+        bitcoin::TxRedeem::new(&self.tx_lock, &self.redeem_address).txid()
+    }
+
+    pub fn tx_cancel_id(&self) -> bitcoin::Txid {
+        TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public()).txid()
+    }
+
+    pub async fn watch_for_redeem_btc(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<State5> {
         let tx_redeem = bitcoin::TxRedeem::new(&self.tx_lock, &self.redeem_address);
         let tx_redeem_encsig = self.b.encsign(self.S_a_bitcoin, tx_redeem.digest());
 
         bitcoin_wallet
-            .watch_until_status(&tx_redeem, |status| status.has_been_seen())
+            .watch_until_status(
+                &tx_redeem,
+                Box::new(|status: ScriptStatus| status.has_been_seen()),
+            )
             .await?;
 
         let tx_redeem_candidate = bitcoin_wallet.get_raw_transaction(tx_redeem.txid()).await?;
@@ -436,32 +624,46 @@ impl State4 {
             v: self.v,
             tx_lock: self.tx_lock.clone(),
             monero_wallet_restore_blockheight: self.monero_wallet_restore_blockheight,
+            xmr: self.xmr,
         })
     }
 
     pub async fn wait_for_cancel_timelock_to_expire(
         &self,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
     ) -> Result<()> {
         bitcoin_wallet
-            .watch_until_status(&self.tx_lock, |status| {
-                status.is_confirmed_with(self.cancel_timelock)
-            })
+            .watch_until_status(
+                &self.tx_lock,
+                Box::new(|status: ScriptStatus| status.is_confirmed_with(self.cancel_timelock)),
+            )
             .await?;
 
         Ok(())
     }
 
+    /// Fetched from electrum, see [`BitcoinWallet::fetch_status_of_script`].
+    pub async fn tx_lock_status(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<ScriptStatus> {
+        bitcoin_wallet.fetch_status_of_script(&self.tx_lock).await
+    }
+
     pub async fn expired_timelock(
         &self,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
     ) -> Result<ExpiredTimelocks> {
+        Ok(self.timelock_epoch(bitcoin_wallet).await?.epoch)
+    }
+
+    pub async fn timelock_epoch(
+        &self,
+        bitcoin_wallet: &dyn BitcoinWallet,
+    ) -> Result<TimelockEpoch> {
         let tx_cancel = TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
 
         let tx_lock_status = bitcoin_wallet.status_of_script(&self.tx_lock).await?;
         let tx_cancel_status = bitcoin_wallet.status_of_script(&tx_cancel).await?;
 
-        Ok(current_epoch(
+        Ok(timelock_epoch(
             self.cancel_timelock,
             self.punish_timelock,
             tx_lock_status,
@@ -492,22 +694,108 @@ pub struct State5 {
     v: monero::PrivateViewKey,
     tx_lock: bitcoin::TxLock,
     monero_wallet_restore_blockheight: BlockHeight,
+    /// The Monero Alice locked, unknown for swaps persisted by older versions.
+    #[serde(default)]
+    xmr: Option<monero::Amount>,
 }
 
 impl State5 {
-    pub async fn claim_xmr(&self, monero_wallet: &monero::Wallet) -> Result<()> {
-        let s_b = monero::PrivateKey { scalar: self.s_b };
+    /// The claimed Monero, unknown for swaps persisted by older versions.
+    pub fn xmr(&self) -> Option<monero::Amount> {
+        self.xmr
+    }
 
-        let s = self.s_a + s_b;
+    /// Generate the wallet holding the claimed Monero, scanning from the given
+    /// height instead of the restore height recorded with the swap if any.
+    pub async fn claim_xmr(
+        &self,
+        monero_wallet: &dyn MoneroWallet,
+        from_height: Option<BlockHeight>,
+    ) -> Result<()> {
+        let restore_height = self.restore_height(from_height)?;
 
         // NOTE: This actually generates and opens a new wallet, closing the currently
         // open one.
         monero_wallet
-            .create_from_and_load(s, self.v, self.monero_wallet_restore_blockheight)
+            .create_from_and_load(self.spend_key(), self.v, restore_height)
             .await?;
+        self.ensure_monero_found(monero_wallet, from_height).await?;
 
         Ok(())
     }
+
+    /// Generate the wallet holding the claimed Monero again, in case the one
+    /// generated by [`State5::claim_xmr`] was lost.
+    pub async fn recover_xmr(
+        &self,
+        monero_wallet: &monero::Wallet,
+        from_height: Option<BlockHeight>,
+    ) -> Result<()> {
+        let restore_height = self.restore_height(from_height)?;
+
+        monero_wallet
+            .recreate_from_and_load(self.spend_key(), self.v, restore_height)
+            .await?;
+        self.ensure_monero_found(monero_wallet, from_height).await?;
+
+        Ok(())
+    }
+
+    /// An explicit height cannot be checked against the Monero lock if the swap
+    /// recorded no restore height. A wallet scanning from it must find the
+    /// Monero then, otherwise the height was past the lock.
+    async fn ensure_monero_found(
+        &self,
+        monero_wallet: &dyn MoneroWallet,
+        from_height: Option<BlockHeight>,
+    ) -> Result<()> {
+        let from_height = match from_height {
+            Some(from_height) if self.monero_wallet_restore_blockheight.height == 0 => from_height,
+            _ => return Ok(()),
+        };
+
+        monero_wallet.refresh().await?;
+        if monero_wallet.get_balance().await? == monero::Amount::ZERO {
+            bail!(
+                "No Monero found scanning from height {}, it may have been locked before. Try an earlier height",
+                from_height.height
+            )
+        }
+
+        Ok(())
+    }
+
+    /// The height to scan for the locked Monero from.
+    ///
+    /// The restore height recorded with the swap precedes the Monero lock, an
+    /// explicit height must not be later than it. Swaps that recorded none
+    /// scan from the explicit height, which [`State5::ensure_monero_found`]
+    /// checks afterwards, or the genesis block otherwise.
+    fn restore_height(&self, from_height: Option<BlockHeight>) -> Result<BlockHeight> {
+        let recorded = self.monero_wallet_restore_blockheight;
+
+        let from_height = match from_height {
+            Some(from_height) => from_height,
+            None => return Ok(recorded),
+        };
+
+        if recorded.height != 0 && from_height.height > recorded.height {
+            bail!(
+                "Cannot scan from height {} because the Monero may have been locked from height {} onwards",
+                from_height.height,
+                recorded.height
+            )
+        }
+
+        Ok(from_height)
+    }
+
+    fn spend_key(&self) -> monero::PrivateKey {
+        let s_b = monero::PrivateKey { scalar: self.s_b };
+
+        self.s_a + s_b
+    }
+
     pub fn tx_lock_id(&self) -> bitcoin::Txid {
         self.tx_lock.txid()
     }
@@ -527,16 +815,41 @@ pub struct State6 {
 }
 
 impl State6 {
+    pub async fn wait_for_cancel_timelock_to_expire(
+        &self,
+        bitcoin_wallet: &dyn BitcoinWallet,
+    ) -> Result<()> {
+        bitcoin_wallet
+            .watch_until_status(
+                &self.tx_lock,
+                Box::new(|status: ScriptStatus| status.is_confirmed_with(self.cancel_timelock)),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn cancel_timelock(&self) -> CancelTimelock {
+        self.cancel_timelock
+    }
+
     pub async fn expired_timelock(
         &self,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
     ) -> Result<ExpiredTimelocks> {
+        Ok(self.timelock_epoch(bitcoin_wallet).await?.epoch)
+    }
+
+    pub async fn timelock_epoch(
+        &self,
+        bitcoin_wallet: &dyn BitcoinWallet,
+    ) -> Result<TimelockEpoch> {
         let tx_cancel = TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
 
         let tx_lock_status = bitcoin_wallet.status_of_script(&self.tx_lock).await?;
         let tx_cancel_status = bitcoin_wallet.status_of_script(&tx_cancel).await?;
 
-        Ok(current_epoch(
+        Ok(timelock_epoch(
             self.cancel_timelock,
             self.punish_timelock,
             tx_lock_status,
@@ -546,7 +859,7 @@ impl State6 {
 
     pub async fn check_for_tx_cancel(
         &self,
-        bitcoin_wallet: &bitcoin::Wallet,
+        bitcoin_wallet: &dyn BitcoinWallet,
     ) -> Result<Transaction> {
         let tx_cancel =
             bitcoin::TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
@@ -556,7 +869,11 @@ impl State6 {
         Ok(tx)
     }
 
-    pub async fn submit_tx_cancel(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<Txid> {
+    /// The cancel transaction is signed by both parties in advance and cannot
+    /// be replaced by one paying a higher fee. If it gets stuck, fee bumping
+    /// speeds it up through the refund transaction spending it, as long as the
+    /// refund goes to an address of the internal wallet.
+    pub async fn submit_tx_cancel(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<Txid> {
         let transaction =
             bitcoin::TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public())
                 .complete_as_bob(self.A, self.b.clone(), self.tx_cancel_sig_a.clone())
@@ -567,7 +884,7 @@ impl State6 {
         Ok(tx_id)
     }
 
-    pub async fn refund_btc(&self, bitcoin_wallet: &bitcoin::Wallet) -> Result<()> {
+    pub async fn refund_btc(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<()> {
         let tx_cancel =
             bitcoin::TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
         let tx_refund = bitcoin::TxRefund::new(&tx_cancel, &self.refund_address);
@@ -581,7 +898,9 @@ impl State6 {
         let signed_tx_refund =
             tx_refund.add_signatures((self.A, sig_a), (self.b.public(), sig_b))?;
 
-        let (_, finality) = bitcoin_wallet.broadcast(signed_tx_refund, "refund").await?;
+        let (_, finality) = bitcoin_wallet
+            .ensure_broadcast(signed_tx_refund, "refund")
+            .await?;
 
         finality.await?;
 
@@ -591,4 +910,181 @@ impl State6 {
     pub fn tx_lock_id(&self) -> bitcoin::Txid {
         self.tx_lock.txid()
     }
+
+    /// The unsigned Bitcoin lock transaction, e.g. to publish it again.
+    pub fn tx_lock(&self) -> &bitcoin::TxLock {
+        &self.tx_lock
+    }
+
+    pub fn tx_cancel_id(&self) -> bitcoin::Txid {
+        TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public()).txid()
+    }
+
+    /// Fetched from electrum, see [`BitcoinWallet::fetch_status_of_script`].
+    pub async fn tx_lock_status(&self, bitcoin_wallet: &dyn BitcoinWallet) -> Result<ScriptStatus> {
+        bitcoin_wallet.fetch_status_of_script(&self.tx_lock).await
+    }
+
+    /// Fetched from electrum, see [`BitcoinWallet::fetch_status_of_script`].
+    pub async fn tx_cancel_status(
+        &self,
+        bitcoin_wallet: &dyn BitcoinWallet,
+    ) -> Result<ScriptStatus> {
+        let tx_cancel = TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
+
+        bitcoin_wallet.fetch_status_of_script(&tx_cancel).await
+    }
+
+    pub fn tx_refund_id(&self) -> bitcoin::Txid {
+        let tx_cancel = TxCancel::new(&self.tx_lock, self.cancel_timelock, self.A, self.b.public());
+
+        bitcoin::TxRefund::new(&tx_cancel, &self.refund_address).txid()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::execution_setup;
+    use ::bitcoin::hashes::Hash;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn swap_without_locked_bitcoin_needs_no_recovery() {
+        let state = BobState::Started {
+            btc_amount: bitcoin::Amount::ONE_BTC,
+        };
+
+        assert_eq!(state.is_recoverable(), Some(RecoveryAction::Nothing));
+    }
+
+    #[test]
+    fn complete_swaps_are_not_recoverable() {
+        let tx_lock_id = bitcoin::Txid::from_inner([0u8; 32]);
+
+        assert_eq!(BobState::XmrRedeemed { tx_lock_id }.is_recoverable(), None);
+        assert_eq!(BobState::BtcPunished { tx_lock_id }.is_recoverable(), None);
+        assert_eq!(BobState::SafelyAborted.is_recoverable(), None);
+    }
+
+    #[tokio::test]
+    async fn unfinished_swaps_are_recovered_by_their_next_action() {
+        let bitcoin_wallet = bitcoin::mock::MockWallet::default();
+        let (_, state2) = execution_setup(&bitcoin_wallet).await;
+        let (state3, _) = state2.clone().lock_btc().await.unwrap();
+        let state4 = state3.clone().xmr_locked(BlockHeight { height: 0 });
+        let state5 = btc_redeemed(BlockHeight { height: 0 }).await;
+        let state6 = state3.cancel();
+        let lock_transfer_proof = TransferProof::new(
+            monero::TxHash("lock".to_owned()),
+            monero::PrivateKey::from_scalar(monero::Scalar::random(&mut OsRng)),
+        );
+
+        let expected = vec![
+            (
+                BobState::ExecutionSetupDone(state2),
+                Some(RecoveryAction::Nothing),
+            ),
+            (
+                BobState::BtcLocked(state3.clone()),
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                BobState::XmrLockProofReceived {
+                    state: state3,
+                    lock_transfer_proof,
+                    monero_wallet_restore_blockheight: BlockHeight { height: 0 },
+                },
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                BobState::XmrLocked(state4.clone()),
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                BobState::EncSigSent(state4),
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                BobState::BtcRedeemed(state5),
+                Some(RecoveryAction::ClaimXmr),
+            ),
+            (
+                BobState::CancelTimelockExpired(state6.clone()),
+                Some(RecoveryAction::PublishCancel),
+            ),
+            (
+                BobState::BtcCancelled(state6.clone()),
+                Some(RecoveryAction::PublishRefund),
+            ),
+            (BobState::BtcRefunded(state6), None),
+        ];
+
+        for (state, action) in expected {
+            assert_eq!(state.is_recoverable(), action, "{}", state);
+        }
+    }
+
+    #[tokio::test]
+    async fn explicit_restore_height_is_used_to_claim_xmr() {
+        let monero_wallet = monero::mock::MockWallet::default();
+        monero_wallet.set_balance(monero::Amount::ONE_XMR);
+        let state5 = btc_redeemed(BlockHeight { height: 0 }).await;
+
+        state5
+            .claim_xmr(&monero_wallet, Some(BlockHeight { height: 2_000_000 }))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            monero_wallet.restored_from(),
+            Some(BlockHeight { height: 2_000_000 })
+        );
+    }
+
+    #[tokio::test]
+    async fn restore_height_past_the_monero_lock_is_rejected() {
+        let monero_wallet = monero::mock::MockWallet::default();
+        let state5 = btc_redeemed(BlockHeight { height: 2_000_000 }).await;
+
+        let result = state5
+            .claim_xmr(&monero_wallet, Some(BlockHeight { height: 2_000_001 }))
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(monero_wallet.restored_from(), None);
+    }
+
+    #[tokio::test]
+    async fn explicit_restore_height_without_monero_is_rejected_if_none_was_recorded() {
+        let monero_wallet = monero::mock::MockWallet::default();
+        let state5 = btc_redeemed(BlockHeight { height: 0 }).await;
+
+        let result = state5
+            .claim_xmr(&monero_wallet, Some(BlockHeight { height: 2_000_000 }))
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    async fn btc_redeemed(monero_wallet_restore_blockheight: BlockHeight) -> State5 {
+        let bitcoin_wallet = bitcoin::mock::MockWallet::default();
+        let tx_lock = TxLock::new(
+            &bitcoin_wallet,
+            bitcoin::Amount::from_sat(1_000_000),
+            bitcoin::SecretKey::new_random(&mut OsRng).public(),
+            bitcoin::SecretKey::new_random(&mut OsRng).public(),
+        )
+        .await
+        .unwrap();
+
+        State5 {
+            s_a: monero::PrivateKey::from_scalar(monero::Scalar::random(&mut OsRng)),
+            s_b: monero::Scalar::random(&mut OsRng),
+            v: monero::PrivateViewKey::new_random(&mut OsRng),
+            tx_lock,
+            monero_wallet_restore_blockheight,
+            xmr: Some(monero::Amount::ONE_XMR),
+        }
+    }
 }
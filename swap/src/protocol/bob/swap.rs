@@ -1,14 +1,23 @@
+use crate::bitcoin::wallet::{BitcoinWallet, ScriptStatus};
 use crate::bitcoin::ExpiredTimelocks;
 use crate::database::{Database, Swap};
 use crate::env::Config;
-use crate::protocol::bob;
+use crate::error::SwapError;
+use crate::monero::wallet::{MoneroWallet, WatchRequest};
 use crate::protocol::bob::event_loop::EventLoopHandle;
+use crate::protocol::bob::notification::{notify_state_transition, Notifier};
+use crate::protocol::bob::refund_address::RefundAddressPolicy;
 use crate::protocol::bob::state::*;
+use crate::protocol::bob::verification::VerifyAmounts;
+use crate::protocol::{bob, record_failure};
 use crate::{bitcoin, monero};
 use anyhow::{bail, Context, Result};
 use async_recursion::async_recursion;
 use rand::rngs::OsRng;
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
 use tokio::select;
 use tracing::trace;
 use uuid::Uuid;
@@ -24,15 +33,23 @@ pub fn is_complete(state: &BobState) -> bool {
 }
 
 #[allow(clippy::too_many_arguments)]
-pub async fn run(swap: bob::Swap) -> Result<BobState> {
-    run_until(swap, is_complete).await
+pub async fn run(swap: bob::Swap) -> Result<BobState, SwapError> {
+    let swap_id = swap.swap_id;
+    let db = swap.db.clone();
+
+    let result = run_until(swap, is_complete).await;
+    if let Err(error) = &result {
+        record_failure(&db, swap_id, error).await;
+    }
+
+    result.map_err(SwapError::from)
 }
 
 pub async fn run_until(
     swap: bob::Swap,
     is_target_state: fn(&BobState) -> bool,
 ) -> Result<BobState> {
-    run_until_internal(
+    run_until_with_wallets(
         swap.state,
         is_target_state,
         swap.event_loop_handle,
@@ -42,6 +59,47 @@ pub async fn run_until(
         swap.swap_id,
         swap.env_config,
         swap.receive_monero_address,
+        swap.notifier,
+        swap.verifier,
+        swap.lock_deadline,
+        swap.refund_address_policy,
+    )
+    .await
+}
+
+/// [`run_until`] with any wallets, e.g. to drive a swap against test doubles.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn run_until_with_wallets(
+    state: BobState,
+    is_target_state: fn(&BobState) -> bool,
+    event_loop_handle: EventLoopHandle,
+    db: Database,
+    bitcoin_wallet: Arc<dyn BitcoinWallet>,
+    monero_wallet: Arc<dyn MoneroWallet>,
+    swap_id: Uuid,
+    env_config: Config,
+    receive_monero_address: monero::Address,
+    notifier: Arc<dyn Notifier>,
+    verifier: Arc<dyn VerifyAmounts>,
+    lock_deadline: Option<OffsetDateTime>,
+    refund_address_policy: RefundAddressPolicy,
+) -> Result<BobState> {
+    let state = reconcile(state, swap_id, &db, bitcoin_wallet.as_ref()).await?;
+
+    run_until_internal(
+        state,
+        is_target_state,
+        event_loop_handle,
+        db,
+        bitcoin_wallet,
+        monero_wallet,
+        swap_id,
+        env_config,
+        receive_monero_address,
+        notifier,
+        verifier,
+        lock_deadline,
+        refund_address_policy,
     )
     .await
 }
@@ -54,11 +112,15 @@ async fn run_until_internal(
     is_target_state: fn(&BobState) -> bool,
     mut event_loop_handle: EventLoopHandle,
     db: Database,
-    bitcoin_wallet: Arc<bitcoin::Wallet>,
-    monero_wallet: Arc<monero::Wallet>,
+    bitcoin_wallet: Arc<dyn BitcoinWallet>,
+    monero_wallet: Arc<dyn MoneroWallet>,
     swap_id: Uuid,
     env_config: Config,
     receive_monero_address: monero::Address,
+    notifier: Arc<dyn Notifier>,
+    verifier: Arc<dyn VerifyAmounts>,
+    lock_deadline: Option<OffsetDateTime>,
+    refund_address_policy: RefundAddressPolicy,
 ) -> Result<BobState> {
     trace!("Current state: {}", state);
     if is_target_state(&state) {
@@ -67,7 +129,9 @@ async fn run_until_internal(
 
     let new_state = match state {
         BobState::Started { btc_amount } => {
-            let bitcoin_refund_address = bitcoin_wallet.new_address().await?;
+            let bitcoin_refund_address = refund_address_policy
+                .refund_address(bitcoin_wallet.as_ref())
+                .await?;
 
             event_loop_handle.dial().await?;
 
@@ -79,59 +143,127 @@ async fn run_until_internal(
             )
             .await?;
 
-            BobState::ExecutionSetupDone(state2)
+            let amounts = state2.amounts(bitcoin_wallet.as_ref()).await?;
+            tracing::info!("Alice offers {}", amounts);
+
+            if verifier.accept(amounts).await {
+                BobState::ExecutionSetupDone(state2)
+            } else {
+                tracing::info!("Swap amounts rejected, aborting swap");
+                bitcoin_wallet
+                    .release_utxos(&state2.unsigned_tx_lock())
+                    .await;
+
+                BobState::SafelyAborted
+            }
         }
         BobState::ExecutionSetupDone(state2) => {
             // Do not lock Bitcoin if not connected to Alice.
             event_loop_handle.dial().await?;
             // Alice and Bob have exchanged info
+            let unsigned_tx_lock = state2.unsigned_tx_lock();
             let (state3, tx_lock) = state2.lock_btc().await?;
-            let signed_tx = bitcoin_wallet
+            let signed_tx = match bitcoin_wallet
                 .sign_and_finalize(tx_lock.clone().into())
                 .await
-                .context("Failed to sign Bitcoin lock transaction")?;
-            let (..) = bitcoin_wallet.broadcast(signed_tx, "lock").await?;
+            {
+                Ok(signed_tx) => signed_tx,
+                Err(error) => {
+                    bitcoin_wallet.release_utxos(&unsigned_tx_lock).await;
+                    return Err(error.context("Failed to sign Bitcoin lock transaction"));
+                }
+            };
+
+            // Dialing may take a while, hence the deadline is checked right before
+            // publishing the lock transaction
+            match lock_deadline {
+                Some(deadline) if OffsetDateTime::now_utc() > deadline => {
+                    tracing::info!(
+                        "Not locking Bitcoin after the deadline of {}, aborting swap",
+                        deadline.format("%F %T %z")
+                    );
+                    bitcoin_wallet.release_utxos(&signed_tx).await;
+
+                    BobState::SafelyAborted
+                }
+                _ => {
+                    let (..) = bitcoin_wallet.broadcast(signed_tx, "lock").await?;
 
-            BobState::BtcLocked(state3)
+                    BobState::BtcLocked(state3)
+                }
+            }
         }
         // Bob has locked Btc
         // Watch for Alice to Lock Xmr or for cancel timelock to elapse
         BobState::BtcLocked(state3) => {
-            if let ExpiredTimelocks::None = state3.current_epoch(bitcoin_wallet.as_ref()).await? {
-                event_loop_handle.dial().await?;
+            match state3.current_epoch(bitcoin_wallet.as_ref()).await? {
+                ExpiredTimelocks::None => {
+                    event_loop_handle.dial().await?;
 
-                let transfer_proof_watcher = event_loop_handle.recv_transfer_proof();
-                let cancel_timelock_expires =
-                    state3.wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref());
+                    let transfer_proof_watcher = event_loop_handle.recv_transfer_proof();
+                    let cancel_timelock_expires =
+                        state3.wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref());
 
-                // Record the current monero wallet block height so we don't have to scan from
-                // block 0 once we create the redeem wallet.
-                let monero_wallet_restore_blockheight = monero_wallet.block_height().await?;
+                    // Record the current monero wallet block height so we don't have to scan from
+                    // block 0 once we create the redeem wallet.
+                    let monero_wallet_restore_blockheight = monero_wallet.block_height().await?;
 
-                tracing::info!("Waiting for Alice to lock Monero");
+                    tracing::info!("Waiting for Alice to lock Monero");
 
-                select! {
-                    transfer_proof = transfer_proof_watcher => {
-                        let transfer_proof = transfer_proof?.tx_lock_proof;
+                    select! {
+                        transfer_proof = transfer_proof_watcher => {
+                            let transfer_proof = transfer_proof?.tx_lock_proof;
+
+                            tracing::info!(txid = %transfer_proof.tx_hash(), "Alice locked Monero");
 
-                        tracing::info!(txid = %transfer_proof.tx_hash(), "Alice locked Monero");
+                            BobState::XmrLockProofReceived {
+                                state: state3,
+                                lock_transfer_proof: transfer_proof,
+                                monero_wallet_restore_blockheight
+                            }
+                        },
+                        _ = cancel_timelock_expires => {
+                            tracing::info!("Alice took too long to lock Monero, cancelling the swap");
 
-                        BobState::XmrLockProofReceived {
-                            state: state3,
-                            lock_transfer_proof: transfer_proof,
-                            monero_wallet_restore_blockheight
+                            let state4 = state3.cancel();
+                            BobState::CancelTimelockExpired(state4)
                         }
-                    },
-                    _ = cancel_timelock_expires => {
-                        tracing::info!("Alice took too long to lock Monero, cancelling the swap");
+                    }
+                }
+                // When resuming right at the boundary, the transfer proof may still be on
+                // its way. Give Alice a short grace period before cancelling.
+                ExpiredTimelocks::Cancel => {
+                    let monero_wallet_restore_blockheight = monero_wallet.block_height().await?;
+
+                    let transfer_proof =
+                        transfer_proof_within(env_config.bob_transfer_proof_grace_period, async {
+                            event_loop_handle.dial().await?;
+                            let transfer_proof = event_loop_handle.recv_transfer_proof().await?;
 
-                        let state4 = state3.cancel();
-                        BobState::CancelTimelockExpired(state4)
+                            Ok(transfer_proof.tx_lock_proof)
+                        })
+                        .await;
+
+                    match transfer_proof {
+                        Some(transfer_proof) => {
+                            tracing::info!(txid = %transfer_proof.tx_hash(), "Alice locked Monero just as the cancel timelock expired");
+
+                            BobState::XmrLockProofReceived {
+                                state: state3,
+                                lock_transfer_proof: transfer_proof,
+                                monero_wallet_restore_blockheight,
+                            }
+                        }
+                        None => {
+                            let state4 = state3.cancel();
+                            BobState::CancelTimelockExpired(state4)
+                        }
                     }
                 }
-            } else {
-                let state4 = state3.cancel();
-                BobState::CancelTimelockExpired(state4)
+                ExpiredTimelocks::Punish => {
+                    let state4 = state3.cancel();
+                    BobState::CancelTimelockExpired(state4)
+                }
             }
         }
         BobState::XmrLockProofReceived {
@@ -142,18 +274,19 @@ async fn run_until_internal(
             if let ExpiredTimelocks::None = state.current_epoch(bitcoin_wallet.as_ref()).await? {
                 event_loop_handle.dial().await?;
 
-                let watch_request = state.lock_xmr_watch_request(lock_transfer_proof);
+                let watch_request = state.lock_xmr_watch_request(lock_transfer_proof.clone());
 
                 select! {
-                    received_xmr = monero_wallet.watch_for_transfer(watch_request) => {
+                    received_xmr = watch_for_lock_xmr(monero_wallet.as_ref(), watch_request) => {
                         match received_xmr {
                             Ok(()) => BobState::XmrLocked(state.xmr_locked(monero_wallet_restore_blockheight)),
-                            Err(e) => {
+                            Err(e) if e.is::<monero::InsufficientFunds>() => {
                                  tracing::warn!("Waiting for refund because insufficient Monero have been locked! {}", e);
                                  state.wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref()).await?;
 
                                  BobState::CancelTimelockExpired(state.cancel())
                             },
+                            Err(e) => return Err(e),
                         }
                     }
                     _ = state.wait_for_cancel_timelock_to_expire(bitcoin_wallet.as_ref()) => {
@@ -197,17 +330,20 @@ async fn run_until_internal(
             }
         }
         BobState::BtcRedeemed(state) => {
-            // Bob redeems XMR using revealed s_a
-            state.claim_xmr(monero_wallet.as_ref()).await?;
-
-            // Ensure that the generated wallet is synced so we have a proper balance
-            monero_wallet.refresh().await?;
-            // Sweep (transfer all funds) to the given address
-            let tx_hashes = monero_wallet.sweep_all(receive_monero_address).await?;
+            // Bob redeems XMR using revealed s_a. The secret is persisted as part of this
+            // state, hence claiming can safely be retried, also across restarts.
+            retry("claim XMR", CLAIM_XMR_MAX_ELAPSED_TIME, || {
+                state.claim_xmr(monero_wallet.as_ref(), None)
+            })
+            .await?;
 
-            for tx_hash in tx_hashes {
-                tracing::info!("Sent XMR to {} in tx {}", receive_monero_address, tx_hash.0);
-            }
+            sweep_xmr(
+                monero_wallet.as_ref(),
+                env_config.monero_finality_confirmations,
+                state.xmr(),
+                receive_monero_address,
+            )
+            .await?;
 
             BobState::XmrRedeemed {
                 tx_lock_id: state.tx_lock_id(),
@@ -233,7 +369,13 @@ async fn run_until_internal(
                     );
                 }
                 ExpiredTimelocks::Cancel => {
-                    state.refund_btc(bitcoin_wallet.as_ref()).await?;
+                    // The refund transaction is not published again if it already was, hence
+                    // refunding can safely be retried, also across restarts.
+                    retry("refund BTC", REFUND_BTC_MAX_ELAPSED_TIME, || {
+                        state.refund_btc(bitcoin_wallet.as_ref())
+                    })
+                    .await?;
+
                     BobState::BtcRefunded(state)
                 }
                 ExpiredTimelocks::Punish => BobState::BtcPunished {
@@ -249,6 +391,8 @@ async fn run_until_internal(
 
     let db_state = new_state.clone().into();
     db.insert_latest_state(swap_id, Swap::Bob(db_state)).await?;
+    notify_state_transition(notifier.as_ref(), swap_id, &new_state);
+
     run_until_internal(
         new_state,
         is_target_state,
@@ -259,8 +403,273 @@ async fn run_until_internal(
         swap_id,
         env_config,
         receive_monero_address,
+        notifier,
+        verifier,
+        lock_deadline,
+        refund_address_policy,
+    )
+    .await
+}
+
+/// Sweep the claimed Monero from the generated wallet to the given address.
+///
+/// Only the transfers adding up to the `expected` amount have to be final, all
+/// incoming ones if it is unknown.
+pub(crate) async fn sweep_xmr(
+    monero_wallet: &dyn MoneroWallet,
+    conf_target: u32,
+    expected: Option<monero::Amount>,
+    receive_monero_address: monero::Address,
+) -> Result<()> {
+    // Ensure that the generated wallet is synced so we have a proper balance
+    monero_wallet.refresh().await?;
+    monero_wallet
+        .wait_until_synced(MONERO_SYNC_MAX_WAIT)
+        .await?;
+    // Only sweep once the funds are final so a reorg cannot affect them
+    monero_wallet
+        .wait_for_incoming_transfers(conf_target, expected)
+        .await?;
+    // Sweep (transfer all funds) to the given address
+    let tx_hashes = monero_wallet.sweep_all(receive_monero_address).await?;
+
+    for tx_hash in tx_hashes {
+        tracing::info!("Sent XMR to {} in tx {}", receive_monero_address, tx_hash.0);
+    }
+
+    Ok(())
+}
+
+/// What a persisted state assumes to be visible on the blockchain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assumption {
+    Nothing,
+    LockPublished,
+    CancelPublished,
+}
+
+/// A persisted assumption that does not hold on the blockchain (anymore).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Divergence {
+    LockMissing,
+    CancelMissing,
+}
+
+fn assumption(state: &BobState) -> Assumption {
+    match state {
+        BobState::BtcLocked(..)
+        | BobState::XmrLockProofReceived { .. }
+        | BobState::XmrLocked(..)
+        | BobState::EncSigSent(..) => Assumption::LockPublished,
+        BobState::BtcCancelled(..) => Assumption::CancelPublished,
+        _ => Assumption::Nothing,
+    }
+}
+
+fn divergence(
+    assumption: Assumption,
+    tx_lock_status: ScriptStatus,
+    tx_cancel_status: ScriptStatus,
+) -> Option<Divergence> {
+    match assumption {
+        Assumption::Nothing => None,
+        Assumption::LockPublished | Assumption::CancelPublished
+            if tx_lock_status == ScriptStatus::Unseen =>
+        {
+            Some(Divergence::LockMissing)
+        }
+        Assumption::CancelPublished if tx_cancel_status == ScriptStatus::Unseen => {
+            Some(Divergence::CancelMissing)
+        }
+        Assumption::LockPublished | Assumption::CancelPublished => None,
+    }
+}
+
+/// Verify the assumptions of a persisted state against the blockchain before
+/// resuming from it.
+///
+/// Transactions the state relies on may have been reorged out or replaced
+/// while the swap was not running. A missing cancel transaction downgrades the
+/// state so that it is published again. A missing lock transaction is
+/// published again and the swap resumes waiting for its confirmations, if its
+/// inputs have been spent in the meantime the swap cannot be resumed.
+///
+/// The statuses are fetched from electrum because the wallet has not looked at
+/// the scripts of a resumed swap yet and would report them as unseen.
+async fn reconcile(
+    state: BobState,
+    swap_id: Uuid,
+    db: &Database,
+    bitcoin_wallet: &dyn BitcoinWallet,
+) -> Result<BobState> {
+    let (tx_lock, tx_lock_status, tx_cancel_status) = match &state {
+        BobState::BtcLocked(state3) | BobState::XmrLockProofReceived { state: state3, .. } => (
+            state3.tx_lock().clone(),
+            state3.tx_lock_status(bitcoin_wallet).await?,
+            ScriptStatus::Unseen,
+        ),
+        BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => (
+            state4.tx_lock().clone(),
+            state4.tx_lock_status(bitcoin_wallet).await?,
+            ScriptStatus::Unseen,
+        ),
+        BobState::BtcCancelled(state6) => (
+            state6.tx_lock().clone(),
+            state6.tx_lock_status(bitcoin_wallet).await?,
+            state6.tx_cancel_status(bitcoin_wallet).await?,
+        ),
+        _ => return Ok(state),
+    };
+
+    match (
+        divergence(assumption(&state), tx_lock_status, tx_cancel_status),
+        state,
+    ) {
+        (None, state) => Ok(state),
+        (Some(Divergence::LockMissing), state) => {
+            tracing::warn!(
+                "The Bitcoin lock transaction of swap {} in state {} is not visible on the blockchain, it may have been reorged out. Publishing it again",
+                swap_id,
+                state
+            );
+
+            let txid = tx_lock.txid();
+            let signed_tx_lock = bitcoin_wallet
+                .sign_and_finalize(tx_lock.into())
+                .await
+                .context("Failed to sign Bitcoin lock transaction")?;
+            bitcoin_wallet
+                .broadcast(signed_tx_lock, "lock")
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to publish the Bitcoin lock transaction {} of swap {} again. If its inputs have been spent by another transaction, no Bitcoin are locked in this swap and it cannot be resumed",
+                        txid, swap_id
+                    )
+                })?;
+
+            Ok(state)
+        }
+        (Some(Divergence::CancelMissing), BobState::BtcCancelled(state6)) => {
+            tracing::warn!(
+                "The Bitcoin cancel transaction of swap {} is not visible on the blockchain, publishing it again",
+                swap_id
+            );
+
+            let state = BobState::CancelTimelockExpired(state6);
+            db.insert_latest_state(swap_id, Swap::Bob(state.clone().into()))
+                .await?;
+
+            Ok(state)
+        }
+        (Some(Divergence::CancelMissing), state) => Ok(state),
+    }
+}
+
+/// Wait at most `grace_period` for a transfer proof that might be about to
+/// arrive. Failing to receive it is not an error, the caller proceeds as if it
+/// never arrived.
+async fn transfer_proof_within<F>(
+    grace_period: Duration,
+    transfer_proof: F,
+) -> Option<monero::TransferProof>
+where
+    F: Future<Output = Result<monero::TransferProof>>,
+{
+    match tokio::time::timeout(grace_period, transfer_proof).await {
+        Ok(Ok(transfer_proof)) => Some(transfer_proof),
+        Ok(Err(error)) => {
+            tracing::debug!(
+                "Failed to receive transfer proof during grace period: {:#}",
+                error
+            );
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Watch for the Monero lock transaction, retrying with exponential backoff
+/// while it cannot be checked, e.g. because the monero-wallet-rpc timed out.
+///
+/// Only an underfunded lock is returned as an error, the caller bounds the
+/// wait by the cancel timelock.
+async fn watch_for_lock_xmr(monero_wallet: &dyn MoneroWallet, request: WatchRequest) -> Result<()> {
+    let backoff = backoff::ExponentialBackoff {
+        max_elapsed_time: None,
+        ..backoff::ExponentialBackoff::default()
+    };
+
+    backoff::future::retry_notify(
+        backoff,
+        || {
+            let request = request.clone();
+
+            async move {
+                monero_wallet
+                    .watch_for_transfer(request)
+                    .await
+                    .map_err(|error| {
+                        if error.is::<monero::InsufficientFunds>() {
+                            backoff::Error::Permanent(error)
+                        } else {
+                            backoff::Error::Transient(error)
+                        }
+                    })
+            }
+        },
+        |error, next: Duration| {
+            tracing::warn!(
+                "Failed to check the Monero lock transaction, retrying in {}s: {:#}",
+                next.as_secs(),
+                error
+            );
+        },
+    )
+    .await
+}
+
+/// How long we wait for the Monero wallet to catch up with the daemon before
+/// sweeping the XMR. The swap can be resumed to try again.
+const MONERO_SYNC_MAX_WAIT: Duration = Duration::from_secs(30 * 60);
+
+/// How long we keep retrying to claim the XMR before giving up. The swap can
+/// be resumed to try again.
+const CLAIM_XMR_MAX_ELAPSED_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// How long we keep retrying to refund the BTC before giving up. The swap can
+/// be resumed to try again.
+const REFUND_BTC_MAX_ELAPSED_TIME: Duration = Duration::from_secs(5 * 60);
+
+/// Retry the given operation with exponential backoff until it either
+/// succeeds or `max_elapsed_time` has passed.
+async fn retry<F, Fut>(action: &str, max_elapsed_time: Duration, mut operation: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let backoff = backoff::ExponentialBackoff {
+        max_elapsed_time: Some(max_elapsed_time),
+        ..backoff::ExponentialBackoff::default()
+    };
+
+    backoff::future::retry_notify(
+        backoff,
+        || {
+            let operation = operation();
+            async move { operation.await.map_err(backoff::Error::Transient) }
+        },
+        |error, next: Duration| {
+            tracing::warn!(
+                "Failed to {}, retrying in {}s: {:#}",
+                action,
+                next.as_secs(),
+                error
+            );
+        },
     )
     .await
+    .with_context(|| format!("Failed to {}, please resume the swap to try again", action))
 }
 
 pub async fn request_price_and_setup(
@@ -273,6 +682,13 @@ pub async fn request_price_and_setup(
 
     tracing::info!("Spot price for {} is {}", btc, xmr);
 
+    ensure_plausible_quote(
+        btc,
+        xmr,
+        env_config.bob_min_xmr_per_btc,
+        env_config.bob_max_xmr_per_btc,
+    )?;
+
     let state0 = State0::new(
         &mut OsRng,
         btc,
@@ -287,3 +703,508 @@ pub async fn request_price_and_setup(
 
     Ok(state2)
 }
+
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("Seller quoted {xmr} for {btc}, expected between {min_xmr_per_btc} and {max_xmr_per_btc} per BTC")]
+pub struct ImplausibleQuote {
+    pub btc: bitcoin::Amount,
+    pub xmr: monero::Amount,
+    pub min_xmr_per_btc: monero::Amount,
+    pub max_xmr_per_btc: monero::Amount,
+}
+
+/// Guard against a seller returning a malformed quote, e.g. zero Monero for
+/// the requested Bitcoin.
+fn ensure_plausible_quote(
+    btc: bitcoin::Amount,
+    xmr: monero::Amount,
+    min_xmr_per_btc: monero::Amount,
+    max_xmr_per_btc: monero::Amount,
+) -> Result<(), ImplausibleQuote> {
+    // Compare xmr / btc against the band without losing precision:
+    // xmr * 1 BTC must lie within [min * btc, max * btc].
+    let xmr_times_one_btc = xmr.as_piconero() as u128 * bitcoin::Amount::ONE_BTC.as_sat() as u128;
+    let lower = min_xmr_per_btc.as_piconero() as u128 * btc.as_sat() as u128;
+    let upper = max_xmr_per_btc.as_piconero() as u128 * btc.as_sat() as u128;
+
+    if xmr == monero::Amount::ZERO || xmr_times_one_btc < lower || xmr_times_one_btc > upper {
+        return Err(ImplausibleQuote {
+            btc,
+            xmr,
+            min_xmr_per_btc,
+            max_xmr_per_btc,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin::mock::MockWallet;
+    use crate::bitcoin::wallet::Confirmed;
+    use crate::env::{GetConfig, Regtest};
+    use crate::protocol::bob::{AutoAccept, NoopNotifier};
+    use crate::protocol::execution_setup;
+    use anyhow::anyhow;
+    use monero_rpc::wallet::BlockHeight;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn given_rpc_failure_claim_is_retried() {
+        let attempts = Arc::new(AtomicU32::new(0));
+
+        let result = retry("claim XMR", Duration::from_secs(10), || {
+            let attempts = attempts.clone();
+
+            async move {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 => Err(anyhow!("monero-wallet-rpc is not available")),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn given_failed_refund_broadcast_refund_is_retried() {
+        let bitcoin_wallet = MockWallet::default();
+        let state6 = btc_locked(&bitcoin_wallet).await.cancel();
+        state6.submit_tx_cancel(&bitcoin_wallet).await.unwrap();
+        bitcoin_wallet.fail_next_broadcasts(1);
+
+        let result = retry("refund BTC", Duration::from_secs(10), || {
+            state6.refund_btc(&bitcoin_wallet)
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(
+            bitcoin_wallet.published().last(),
+            Some(&("refund".to_owned(), state6.tx_refund_id()))
+        );
+    }
+
+    #[tokio::test]
+    async fn given_refund_published_before_restart_it_is_not_published_again() {
+        let bitcoin_wallet = MockWallet::default();
+        let state6 = btc_locked(&bitcoin_wallet).await.cancel();
+        state6.submit_tx_cancel(&bitcoin_wallet).await.unwrap();
+        bitcoin_wallet.set_status(state6.tx_refund_id(), ScriptStatus::InMempool);
+        bitcoin_wallet.set_stale_status(state6.tx_refund_id(), ScriptStatus::Unseen);
+
+        state6.refund_btc(&bitcoin_wallet).await.unwrap();
+
+        assert_eq!(bitcoin_wallet.published(), vec![(
+            "cancel".to_owned(),
+            state6.tx_cancel_id()
+        )]);
+    }
+
+    #[test]
+    fn lock_confirmed_state_with_unseen_lock_diverges() {
+        assert_eq!(
+            divergence(
+                Assumption::LockPublished,
+                ScriptStatus::Unseen,
+                ScriptStatus::Unseen
+            ),
+            Some(Divergence::LockMissing)
+        );
+        assert_eq!(
+            divergence(
+                Assumption::CancelPublished,
+                ScriptStatus::Unseen,
+                ScriptStatus::Unseen
+            ),
+            Some(Divergence::LockMissing)
+        );
+    }
+
+    #[test]
+    fn cancelled_state_with_unseen_cancel_diverges() {
+        assert_eq!(
+            divergence(
+                Assumption::CancelPublished,
+                ScriptStatus::from_confirmations(10),
+                ScriptStatus::Unseen
+            ),
+            Some(Divergence::CancelMissing)
+        );
+    }
+
+    #[test]
+    fn published_transactions_are_consistent() {
+        assert_eq!(
+            divergence(
+                Assumption::LockPublished,
+                ScriptStatus::InMempool,
+                ScriptStatus::Unseen
+            ),
+            None
+        );
+        assert_eq!(
+            divergence(
+                Assumption::CancelPublished,
+                ScriptStatus::from_confirmations(10),
+                ScriptStatus::from_confirmations(1)
+            ),
+            None
+        );
+        assert_eq!(
+            assumption(&BobState::Started {
+                btc_amount: bitcoin::Amount::ONE_BTC
+            }),
+            Assumption::Nothing
+        );
+    }
+
+    fn transfer_proof() -> monero::TransferProof {
+        monero::TransferProof::new(
+            monero::TxHash("0".repeat(64)),
+            monero::PrivateKey::from_scalar(monero::Scalar::one()),
+        )
+    }
+
+    #[tokio::test]
+    async fn transfer_proof_arriving_at_epoch_boundary_is_honored() {
+        let received = transfer_proof_within(Duration::from_secs(5), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(transfer_proof())
+        })
+        .await;
+
+        assert_eq!(received, Some(transfer_proof()));
+    }
+
+    #[tokio::test]
+    async fn given_no_transfer_proof_within_grace_period_returns_none() {
+        let pending = transfer_proof_within(
+            Duration::from_millis(50),
+            std::future::pending::<Result<monero::TransferProof>>(),
+        )
+        .await;
+        let failed = transfer_proof_within(Duration::from_secs(5), async {
+            Err(anyhow!("Failed to dial Alice"))
+        })
+        .await;
+
+        assert_eq!(pending, None);
+        assert_eq!(failed, None);
+    }
+
+    fn check_quote(btc: bitcoin::Amount, xmr: monero::Amount) -> Result<(), ImplausibleQuote> {
+        ensure_plausible_quote(
+            btc,
+            xmr,
+            monero::Amount::ONE_XMR * 10,
+            monero::Amount::ONE_XMR * 10_000,
+        )
+    }
+
+    #[test]
+    fn zero_quote_is_rejected() {
+        let result = check_quote(bitcoin::Amount::from_sat(1_000_000), monero::Amount::ZERO);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn absurd_quotes_are_rejected() {
+        let btc = bitcoin::Amount::ONE_BTC;
+
+        assert!(check_quote(btc, monero::Amount::from_piconero(1)).is_err());
+        assert!(check_quote(btc, monero::Amount::ONE_XMR * 1_000_000).is_err());
+    }
+
+    #[test]
+    fn plausible_quote_is_accepted() {
+        let btc = bitcoin::Amount::from_sat(1_000_000);
+
+        assert!(check_quote(btc, monero::Amount::ONE_XMR).is_ok());
+        assert!(check_quote(btc, monero::Amount::from_piconero(100_000_000_000)).is_ok());
+        assert!(check_quote(btc, monero::Amount::ONE_XMR * 100).is_ok());
+    }
+
+    #[tokio::test]
+    async fn given_persistent_failure_claim_eventually_fails() {
+        let result = retry("claim XMR", Duration::from_millis(100), || async {
+            Err(anyhow!("monero-wallet-rpc is not available"))
+        })
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn given_reorged_cancel_transaction_state_is_reverted_to_cancel() {
+        let bitcoin_wallet = MockWallet::default();
+        let state6 = btc_locked(&bitcoin_wallet).await.cancel();
+        let swap_id = Uuid::new_v4();
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        bitcoin_wallet.set_status(
+            state6.tx_lock_id(),
+            ScriptStatus::Confirmed(Confirmed::new(10)),
+        );
+        bitcoin_wallet.set_status(state6.tx_cancel_id(), ScriptStatus::Unseen);
+
+        let state = reconcile(
+            BobState::BtcCancelled(state6),
+            swap_id,
+            &db,
+            &bitcoin_wallet,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(state, BobState::CancelTimelockExpired(_)));
+        assert!(matches!(
+            BobState::from(db.get_state(swap_id).unwrap().try_into_bob().unwrap()),
+            BobState::CancelTimelockExpired(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn given_reorged_lock_transaction_it_is_published_again() {
+        let bitcoin_wallet = MockWallet::default();
+        let state3 = btc_locked(&bitcoin_wallet).await;
+        let tx_lock_id = state3.tx_lock_id();
+        let swap_id = Uuid::new_v4();
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        let state = reconcile(BobState::BtcLocked(state3), swap_id, &db, &bitcoin_wallet)
+            .await
+            .unwrap();
+
+        assert!(matches!(state, BobState::BtcLocked(_)));
+        assert_eq!(bitcoin_wallet.published(), vec![(
+            "lock".to_owned(),
+            tx_lock_id
+        )]);
+    }
+
+    #[tokio::test]
+    async fn given_lock_transaction_cannot_be_published_again_resuming_fails() {
+        let bitcoin_wallet = MockWallet::default();
+        let state3 = btc_locked(&bitcoin_wallet).await;
+        let swap_id = Uuid::new_v4();
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        bitcoin_wallet.fail_next_broadcasts(1);
+        let result = reconcile(BobState::BtcLocked(state3), swap_id, &db, &bitcoin_wallet).await;
+
+        assert!(result.is_err());
+        assert!(bitcoin_wallet.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn given_cancel_transaction_not_fetched_yet_state_is_kept() {
+        let bitcoin_wallet = MockWallet::default();
+        let state6 = btc_locked(&bitcoin_wallet).await.cancel();
+        let swap_id = Uuid::new_v4();
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+
+        bitcoin_wallet.set_status(
+            state6.tx_lock_id(),
+            ScriptStatus::Confirmed(Confirmed::new(10)),
+        );
+        bitcoin_wallet.set_status(
+            state6.tx_cancel_id(),
+            ScriptStatus::Confirmed(Confirmed::new(2)),
+        );
+        bitcoin_wallet.set_stale_status(state6.tx_lock_id(), ScriptStatus::Unseen);
+        bitcoin_wallet.set_stale_status(state6.tx_cancel_id(), ScriptStatus::Unseen);
+
+        let state = reconcile(
+            BobState::BtcCancelled(state6),
+            swap_id,
+            &db,
+            &bitcoin_wallet,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(state, BobState::BtcCancelled(_)));
+        assert!(db.get_state(swap_id).is_err());
+    }
+
+    #[tokio::test]
+    async fn given_failed_broadcast_cancel_and_refund_are_published_once() {
+        let bitcoin_wallet = MockWallet::default();
+        let state6 = btc_locked(&bitcoin_wallet).await.cancel();
+
+        bitcoin_wallet.fail_next_broadcasts(1);
+        assert!(state6.submit_tx_cancel(&bitcoin_wallet).await.is_err());
+        state6.submit_tx_cancel(&bitcoin_wallet).await.unwrap();
+
+        bitcoin_wallet.fail_next_broadcasts(1);
+        retry("refund BTC", Duration::from_secs(10), || {
+            state6.refund_btc(&bitcoin_wallet)
+        })
+        .await
+        .unwrap();
+        state6.refund_btc(&bitcoin_wallet).await.unwrap();
+
+        assert_eq!(bitcoin_wallet.published(), vec![
+            ("cancel".to_owned(), state6.tx_cancel_id()),
+            ("refund".to_owned(), state6.tx_refund_id())
+        ]);
+    }
+
+    #[tokio::test]
+    async fn given_monero_lock_arrives_xmr_is_locked() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let bitcoin_wallet = Arc::new(MockWallet::default());
+        let monero_wallet = Arc::new(monero::mock::MockWallet::default());
+        let state3 = btc_locked(&bitcoin_wallet).await;
+        let lock_transfer_proof = transfer_proof();
+
+        monero_wallet.receive_transfer(lock_transfer_proof.tx_hash(), monero::Amount::ONE_XMR);
+
+        let state = run_until_xmr_lock_checked(
+            BobState::XmrLockProofReceived {
+                state: state3,
+                lock_transfer_proof,
+                monero_wallet_restore_blockheight: BlockHeight { height: 1 },
+            },
+            bitcoin_wallet,
+            monero_wallet,
+            Database::open(db_dir.path()).unwrap(),
+            Uuid::new_v4(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(state, BobState::XmrLocked(_)));
+    }
+
+    #[tokio::test]
+    async fn given_monero_wallet_failures_lock_is_watched_again_without_transitions() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let db = Database::open(db_dir.path()).unwrap();
+        let swap_id = Uuid::new_v4();
+        let bitcoin_wallet = Arc::new(MockWallet::default());
+        let monero_wallet = Arc::new(monero::mock::MockWallet::default());
+        let state3 = btc_locked(&bitcoin_wallet).await;
+        let lock_transfer_proof = transfer_proof();
+
+        monero_wallet.fail_next_watches(2);
+        monero_wallet.receive_transfer(lock_transfer_proof.tx_hash(), monero::Amount::ONE_XMR);
+
+        let state = run_until_xmr_lock_checked(
+            BobState::XmrLockProofReceived {
+                state: state3,
+                lock_transfer_proof,
+                monero_wallet_restore_blockheight: BlockHeight { height: 1 },
+            },
+            bitcoin_wallet,
+            monero_wallet,
+            db.clone(),
+            swap_id,
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(state, BobState::XmrLocked(_)));
+        assert_eq!(db.state_history(swap_id).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn given_underfunded_monero_lock_swap_is_cancelled_once_timelock_expires() {
+        let db_dir = tempfile::tempdir().unwrap();
+        let bitcoin_wallet = Arc::new(MockWallet::default());
+        let monero_wallet = Arc::new(monero::mock::MockWallet::default());
+        let state3 = btc_locked(&bitcoin_wallet).await;
+        let lock_transfer_proof = transfer_proof();
+
+        bitcoin_wallet.set_status(state3.tx_lock_id(), ScriptStatus::from_confirmations(1));
+        monero_wallet.receive_transfer(
+            lock_transfer_proof.tx_hash(),
+            monero::Amount::from_piconero(1),
+        );
+
+        let tx_lock_id = state3.tx_lock_id();
+        let wallet = bitcoin_wallet.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            wallet.set_status(
+                tx_lock_id,
+                ScriptStatus::from_confirmations(Regtest::CANCEL_TIMELOCK),
+            );
+        });
+
+        let state = run_until_xmr_lock_checked(
+            BobState::XmrLockProofReceived {
+                state: state3,
+                lock_transfer_proof,
+                monero_wallet_restore_blockheight: BlockHeight { height: 1 },
+            },
+            bitcoin_wallet,
+            monero_wallet,
+            Database::open(db_dir.path()).unwrap(),
+            Uuid::new_v4(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(state, BobState::CancelTimelockExpired(_)));
+    }
+
+    async fn run_until_xmr_lock_checked(
+        state: BobState,
+        bitcoin_wallet: Arc<MockWallet>,
+        monero_wallet: Arc<monero::mock::MockWallet>,
+        db: Database,
+        swap_id: Uuid,
+    ) -> Result<BobState> {
+        let receive_monero_address = monero_wallet.get_main_address();
+
+        run_until_internal(
+            state,
+            |state| {
+                matches!(
+                    state,
+                    BobState::XmrLocked(_) | BobState::CancelTimelockExpired(_)
+                )
+            },
+            EventLoopHandle::connected(),
+            db,
+            bitcoin_wallet,
+            monero_wallet,
+            swap_id,
+            Regtest::get_config(),
+            receive_monero_address,
+            Arc::new(NoopNotifier),
+            Arc::new(AutoAccept),
+            None,
+            RefundAddressPolicy::Fresh,
+        )
+        .await
+    }
+
+    fn transfer_proof() -> monero::TransferProof {
+        monero::TransferProof::new(
+            monero::TxHash("lock".to_owned()),
+            monero::PrivateKey::from_scalar(monero::Scalar::random(&mut OsRng)),
+        )
+    }
+
+    /// Run the execution setup between Alice and Bob in-process and return
+    /// Bob's state after locking the Bitcoin.
+    async fn btc_locked(bitcoin_wallet: &MockWallet) -> State3 {
+        let (_, state2) = execution_setup(bitcoin_wallet).await;
+        let (state3, _) = state2.lock_btc().await.unwrap();
+
+        state3
+    }
+}
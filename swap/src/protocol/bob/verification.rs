@@ -0,0 +1,109 @@
+use crate::{bitcoin, monero};
+use async_trait::async_trait;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// The exact amounts of a swap, known once the execution setup with Alice is
+/// done and before Bob locks any Bitcoin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SwapAmounts {
+    /// The amount of Bitcoin locked.
+    pub btc: bitcoin::Amount,
+    /// The amount of Monero received in return.
+    pub xmr: monero::Amount,
+    /// The fee paid for publishing the Bitcoin lock transaction.
+    pub btc_lock_fee: bitcoin::Amount,
+    /// The fee deducted from the locked amount if the swap is refunded.
+    pub btc_refund_fee: bitcoin::Amount,
+}
+
+impl fmt::Display for SwapAmounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (plus {} lock fee) for {}, a refund would cost another {}",
+            self.btc, self.btc_lock_fee, self.xmr, self.btc_refund_fee
+        )
+    }
+}
+
+/// Decides whether Bob goes ahead with a swap for the given amounts.
+#[async_trait]
+pub trait VerifyAmounts: Send + Sync {
+    async fn accept(&self, amounts: SwapAmounts) -> bool;
+}
+
+/// A [`VerifyAmounts`] that accepts any amounts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoAccept;
+
+#[async_trait]
+impl VerifyAmounts for AutoAccept {
+    async fn accept(&self, _: SwapAmounts) -> bool {
+        true
+    }
+}
+
+/// A [`VerifyAmounts`] that asks the user to confirm the amounts on the
+/// terminal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsolePrompt;
+
+#[async_trait]
+impl VerifyAmounts for ConsolePrompt {
+    async fn accept(&self, amounts: SwapAmounts) -> bool {
+        let answer = tokio::task::spawn_blocking(move || {
+            print!("Swap {}? [y/N] ", amounts);
+            io::stdout().flush()?;
+
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer)?;
+
+            Ok(answer)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|answer: anyhow::Result<String>| answer);
+
+        match answer {
+            Ok(answer) => is_confirmation(&answer),
+            Err(error) => {
+                tracing::warn!("Failed to read confirmation: {:#}", error);
+                false
+            }
+        }
+    }
+}
+
+fn is_confirmation(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_explicit_yes_confirms() {
+        assert!(is_confirmation("y\n"));
+        assert!(is_confirmation(" Yes "));
+        assert!(!is_confirmation("\n"));
+        assert!(!is_confirmation("n"));
+        assert!(!is_confirmation("yep"));
+    }
+
+    #[test]
+    fn amounts_are_displayed_with_fees() {
+        let amounts = SwapAmounts {
+            btc: bitcoin::Amount::from_sat(100_000),
+            xmr: monero::Amount::from_piconero(1_000_000_000_000),
+            btc_lock_fee: bitcoin::Amount::from_sat(1_000),
+            btc_refund_fee: bitcoin::Amount::from_sat(bitcoin::TX_FEE),
+        };
+
+        assert_eq!(
+            amounts.to_string(),
+            "0.00100000 BTC (plus 0.00001000 BTC lock fee) for 1.000000000000 XMR, a refund would cost another 0.00015000 BTC"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use crate::bitcoin;
+use crate::bitcoin::ExpiredTimelocks;
+use crate::database::Swap;
+use crate::protocol::alice::AliceState;
+use crate::protocol::bob::BobState;
+use anyhow::Result;
+use std::fmt;
+
+/// How urgently a swap needs the attention of its operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Urgency {
+    /// The swap progresses without manual intervention.
+    Ok,
+    /// The swap needs to be resumed, cancelled or refunded.
+    ActionRequired,
+    /// The punish timelock expired, the Bitcoin may be lost any moment.
+    AtRiskOfPunish,
+}
+
+impl fmt::Display for Urgency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Urgency::Ok => write!(f, "ok"),
+            Urgency::ActionRequired => write!(f, "action required"),
+            Urgency::AtRiskOfPunish => write!(f, "at risk of punish"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Alice,
+    Bob,
+}
+
+/// What a swap that is not yet complete is waiting for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Pending {
+    /// The counterparty or the blockchain.
+    Protocol,
+    /// The swap is stuck until it is resumed, cancelled or refunded.
+    Action,
+}
+
+/// Classify the urgency of the given swap based on its state and the
+/// timelocks that already expired on the blockchain.
+///
+/// Returns `None` for swaps that are complete.
+pub async fn classify(swap: Swap, bitcoin_wallet: &bitcoin::Wallet) -> Result<Option<Urgency>> {
+    let urgency = match swap {
+        Swap::Alice(state) => {
+            let state = AliceState::from(state);
+            let pending = match alice_pending(&state) {
+                Some(pending) => pending,
+                None => return Ok(None),
+            };
+            let expired_timelocks = alice_expired_timelocks(&state, bitcoin_wallet).await?;
+
+            urgency(Role::Alice, pending, expired_timelocks)
+        }
+        Swap::Bob(state) => {
+            let state = BobState::from(state);
+            let pending = match bob_pending(&state) {
+                Some(pending) => pending,
+                None => return Ok(None),
+            };
+            let expired_timelocks = bob_expired_timelocks(&state, bitcoin_wallet).await?;
+
+            urgency(Role::Bob, pending, expired_timelocks)
+        }
+    };
+
+    Ok(Some(urgency))
+}
+
+fn urgency(role: Role, pending: Pending, expired_timelocks: Option<ExpiredTimelocks>) -> Urgency {
+    match (role, pending, expired_timelocks) {
+        (Role::Bob, _, Some(ExpiredTimelocks::Punish)) => Urgency::AtRiskOfPunish,
+        (_, Pending::Action, _)
+        | (_, _, Some(ExpiredTimelocks::Cancel))
+        | (Role::Alice, _, Some(ExpiredTimelocks::Punish)) => Urgency::ActionRequired,
+        (_, Pending::Protocol, Some(ExpiredTimelocks::None)) | (_, Pending::Protocol, None) => {
+            Urgency::Ok
+        }
+    }
+}
+
+fn alice_pending(state: &AliceState) -> Option<Pending> {
+    match state {
+        AliceState::BtcRedeemed
+        | AliceState::XmrRefunded
+        | AliceState::BtcPunished
+        | AliceState::BtcPunishDeclined
+        | AliceState::SafelyAborted => None,
+        AliceState::Started { .. }
+        | AliceState::BtcLocked { .. }
+        | AliceState::XmrLocked { .. }
+        | AliceState::EncSigLearned { .. } => Some(Pending::Protocol),
+        AliceState::CancelTimelockExpired { .. }
+        | AliceState::BtcCancelled { .. }
+        | AliceState::BtcRefunded { .. }
+        | AliceState::BtcPunishable { .. } => Some(Pending::Action),
+    }
+}
+
+fn bob_pending(state: &BobState) -> Option<Pending> {
+    match state {
+        BobState::XmrRedeemed { .. }
+        | BobState::BtcRefunded(..)
+        | BobState::BtcPunished { .. }
+        | BobState::SafelyAborted => None,
+        BobState::Started { .. }
+        | BobState::ExecutionSetupDone(..)
+        | BobState::BtcLocked(..)
+        | BobState::XmrLockProofReceived { .. }
+        | BobState::XmrLocked(..)
+        | BobState::EncSigSent(..) => Some(Pending::Protocol),
+        BobState::BtcRedeemed(..)
+        | BobState::CancelTimelockExpired(..)
+        | BobState::BtcCancelled(..) => Some(Pending::Action),
+    }
+}
+
+async fn alice_expired_timelocks(
+    state: &AliceState,
+    bitcoin_wallet: &bitcoin::Wallet,
+) -> Result<Option<ExpiredTimelocks>> {
+    let state3 = match state {
+        AliceState::Started { state3 }
+        | AliceState::BtcLocked { state3 }
+        | AliceState::XmrLocked { state3, .. }
+        | AliceState::EncSigLearned { state3, .. }
+        | AliceState::BtcCancelled { state3, .. }
+        | AliceState::BtcRefunded { state3, .. }
+        | AliceState::BtcPunishable { state3, .. }
+        | AliceState::CancelTimelockExpired { state3, .. } => state3,
+        AliceState::BtcRedeemed
+        | AliceState::XmrRefunded
+        | AliceState::BtcPunished
+        | AliceState::BtcPunishDeclined
+        | AliceState::SafelyAborted => return Ok(None),
+    };
+
+    Ok(Some(state3.expired_timelocks(bitcoin_wallet).await?))
+}
+
+async fn bob_expired_timelocks(
+    state: &BobState,
+    bitcoin_wallet: &bitcoin::Wallet,
+) -> Result<Option<ExpiredTimelocks>> {
+    let expired_timelocks = match state {
+        BobState::BtcLocked(state3) | BobState::XmrLockProofReceived { state: state3, .. } => {
+            state3.current_epoch(bitcoin_wallet).await?
+        }
+        BobState::XmrLocked(state4) | BobState::EncSigSent(state4) => {
+            state4.expired_timelock(bitcoin_wallet).await?
+        }
+        BobState::CancelTimelockExpired(state6) | BobState::BtcCancelled(state6) => {
+            state6.expired_timelock(bitcoin_wallet).await?
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(expired_timelocks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::bitcoin::hashes::Hash;
+
+    #[test]
+    fn progressing_swaps_are_ok_until_cancel_timelock_expires() {
+        assert_eq!(urgency(Role::Bob, Pending::Protocol, None), Urgency::Ok);
+        assert_eq!(
+            urgency(Role::Alice, Pending::Protocol, Some(ExpiredTimelocks::None)),
+            Urgency::Ok
+        );
+        assert_eq!(
+            urgency(Role::Bob, Pending::Protocol, Some(ExpiredTimelocks::Cancel)),
+            Urgency::ActionRequired
+        );
+        assert_eq!(
+            urgency(
+                Role::Alice,
+                Pending::Protocol,
+                Some(ExpiredTimelocks::Cancel)
+            ),
+            Urgency::ActionRequired
+        );
+    }
+
+    #[test]
+    fn stuck_swaps_require_action() {
+        assert_eq!(
+            urgency(Role::Bob, Pending::Action, None),
+            Urgency::ActionRequired
+        );
+        assert_eq!(
+            urgency(Role::Alice, Pending::Action, Some(ExpiredTimelocks::None)),
+            Urgency::ActionRequired
+        );
+    }
+
+    #[test]
+    fn only_bob_is_at_risk_of_punish() {
+        assert_eq!(
+            urgency(Role::Bob, Pending::Protocol, Some(ExpiredTimelocks::Punish)),
+            Urgency::AtRiskOfPunish
+        );
+        assert_eq!(
+            urgency(Role::Bob, Pending::Action, Some(ExpiredTimelocks::Punish)),
+            Urgency::AtRiskOfPunish
+        );
+        assert_eq!(
+            urgency(Role::Alice, Pending::Action, Some(ExpiredTimelocks::Punish)),
+            Urgency::ActionRequired
+        );
+    }
+
+    #[test]
+    fn complete_swaps_are_not_classified() {
+        let tx_lock_id = bitcoin::Txid::from_inner([0u8; 32]);
+
+        assert_eq!(bob_pending(&BobState::XmrRedeemed { tx_lock_id }), None);
+        assert_eq!(bob_pending(&BobState::BtcPunished { tx_lock_id }), None);
+        assert_eq!(bob_pending(&BobState::SafelyAborted), None);
+        assert_eq!(alice_pending(&AliceState::BtcRedeemed), None);
+        assert_eq!(alice_pending(&AliceState::XmrRefunded), None);
+
+        assert_eq!(
+            bob_pending(&BobState::Started {
+                btc_amount: bitcoin::Amount::ONE_BTC
+            }),
+            Some(Pending::Protocol)
+        );
+    }
+}
@@ -4,9 +4,14 @@ use ::bitcoin::secp256k1::{self, SecretKey};
 use anyhow::{Context, Result};
 use bdk::bitcoin::util::bip32::ExtendedPrivKey;
 use bitcoin::hashes::{sha256, Hash, HashEngine};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac, NewMac};
 use libp2p::identity;
 use pem::{encode, Pem};
 use rand::prelude::*;
+use sha2::Sha256;
 use std::ffi::OsStr;
 use std::fmt;
 use std::fs::{self, File};
@@ -15,6 +20,20 @@ use std::path::{Path, PathBuf};
 
 pub const SEED_LENGTH: usize = 32;
 
+/// The environment variable holding the passphrase the seed file is encrypted
+/// with. The seed file is stored in plaintext if it is not set.
+pub const PASSPHRASE_ENV_VAR: &str = "SWAP_SEED_PASSPHRASE";
+
+const PEM_TAG: &str = "SEED";
+const ENCRYPTED_PEM_TAG: &str = "ENCRYPTED SEED";
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+/// The number of PBKDF2 rounds deriving the key that encrypts the seed file
+/// from the passphrase.
+const KDF_ROUNDS: u32 = 100_000;
+
 #[derive(Clone, Copy, Eq, PartialEq)]
 pub struct Seed([u8; SEED_LENGTH]);
 
@@ -47,18 +66,41 @@ impl Seed {
         identity::Keypair::Ed25519(key.into())
     }
 
+    /// Read the seed from the data directory, generating one if there is
+    /// none yet, with the passphrase set in [`PASSPHRASE_ENV_VAR`] if any.
     pub fn from_file_or_generate(data_dir: &Path) -> Result<Self, Error> {
+        let passphrase = std::env::var(PASSPHRASE_ENV_VAR)
+            .ok()
+            .filter(|passphrase| !passphrase.is_empty());
+
+        Self::from_file_or_generate_with_passphrase(data_dir, passphrase.as_deref())
+    }
+
+    /// Read the seed from the data directory, generating one if there is
+    /// none yet.
+    ///
+    /// A generated seed is encrypted with the given passphrase, an encrypted
+    /// seed file can only be read with it.
+    pub fn from_file_or_generate_with_passphrase(
+        data_dir: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<Self, Error> {
         let file_path_buf = data_dir.join("seed.pem");
         let file_path = Path::new(&file_path_buf);
 
         if file_path.exists() {
-            return Self::from_file(&file_path);
+            return Self::from_file_with_passphrase(&file_path, passphrase);
         }
 
         tracing::debug!("No seed file found, creating at: {}", file_path.display());
 
         let random_seed = Seed::random()?;
-        random_seed.write_to(file_path.to_path_buf())?;
+        match passphrase {
+            Some(passphrase) => {
+                random_seed.write_encrypted_to(file_path.to_path_buf(), passphrase)?
+            }
+            None => random_seed.write_to(file_path.to_path_buf())?,
+        }
 
         Ok(random_seed)
     }
@@ -83,7 +125,15 @@ impl Seed {
         self.0
     }
 
+    #[cfg(test)]
     fn from_file<D>(seed_file: D) -> Result<Self, Error>
+    where
+        D: AsRef<OsStr>,
+    {
+        Self::from_file_with_passphrase(seed_file, None)
+    }
+
+    fn from_file_with_passphrase<D>(seed_file: D, passphrase: Option<&str>) -> Result<Self, Error>
     where
         D: AsRef<OsStr>,
     {
@@ -93,6 +143,19 @@ impl Seed {
 
         tracing::debug!("Reading in seed from {}", file.display());
 
+        if pem.tag == ENCRYPTED_PEM_TAG {
+            let passphrase = passphrase.ok_or(Error::PassphraseRequired)?;
+
+            return Self::from_encrypted_pem(pem, passphrase);
+        }
+
+        if passphrase.is_some() {
+            tracing::warn!(
+                "Seed file {} is not encrypted, the passphrase is only used for new seed files",
+                file.display()
+            );
+        }
+
         Self::from_pem(pem)
     }
 
@@ -109,22 +172,97 @@ impl Seed {
         }
     }
 
+    /// Decrypt a seed encrypted with [`Seed::write_encrypted_to`], the PEM
+    /// contents are the salt, the nonce and the ciphertext.
+    fn from_encrypted_pem(pem: pem::Pem, passphrase: &str) -> Result<Self, Error> {
+        if pem.contents.len() < SALT_LENGTH + NONCE_LENGTH {
+            return Err(Error::IncorrectLength(pem.contents.len()));
+        }
+
+        let (salt, rest) = pem.contents.split_at(SALT_LENGTH);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+
+        let plaintext = cipher(passphrase, salt)
+            .decrypt(GenericArray::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::WrongPassphrase)?;
+
+        Self::from_pem(Pem {
+            tag: String::from(PEM_TAG),
+            contents: plaintext,
+        })
+    }
+
     fn write_to(&self, seed_file: PathBuf) -> Result<(), Error> {
-        ensure_directory_exists(&seed_file)?;
+        let pem = Pem {
+            tag: String::from(PEM_TAG),
+            contents: self.bytes().to_vec(),
+        };
+
+        write_pem(&pem, seed_file)
+    }
+
+    fn write_encrypted_to(&self, seed_file: PathBuf, passphrase: &str) -> Result<(), Error> {
+        let mut salt = [0u8; SALT_LENGTH];
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = cipher(passphrase, &salt)
+            .encrypt(GenericArray::from_slice(&nonce), self.bytes().as_ref())
+            .expect("encrypting 32 bytes never fails");
 
-        let data = self.bytes();
         let pem = Pem {
-            tag: String::from("SEED"),
-            contents: data.to_vec(),
+            tag: String::from(ENCRYPTED_PEM_TAG),
+            contents: [&salt[..], &nonce[..], &ciphertext[..]].concat(),
         };
 
-        let pem_string = encode(&pem);
+        write_pem(&pem, seed_file)
+    }
+}
+
+/// The cipher encrypting the seed file, keyed by the passphrase stretched
+/// with the given salt.
+fn cipher(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let key = pbkdf2_hmac_sha256(passphrase.as_bytes(), salt, KDF_ROUNDS);
+
+    ChaCha20Poly1305::new(GenericArray::from_slice(&key))
+}
+
+/// PBKDF2 with HMAC-SHA256 as defined in RFC 8018, deriving a single block of
+/// 32 bytes.
+fn pbkdf2_hmac_sha256(password: &[u8], salt: &[u8], rounds: u32) -> [u8; 32] {
+    let prf = Hmac::<Sha256>::new_varkey(password).expect("HMAC takes keys of any length");
+
+    let mut mac = prf.clone();
+    mac.update(salt);
+    mac.update(&1u32.to_be_bytes());
+    let mut block = mac.finalize().into_bytes();
 
-        let mut file = File::create(seed_file)?;
-        file.write_all(pem_string.as_bytes())?;
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&block);
 
-        Ok(())
+    for _ in 1..rounds {
+        let mut mac = prf.clone();
+        mac.update(&block);
+        block = mac.finalize().into_bytes();
+
+        for (key, block) in key.iter_mut().zip(block.iter()) {
+            *key ^= block;
+        }
     }
+
+    key
+}
+
+fn write_pem(pem: &Pem, seed_file: PathBuf) -> Result<(), Error> {
+    ensure_directory_exists(&seed_file)?;
+
+    let pem_string = encode(pem);
+
+    let mut file = File::create(seed_file)?;
+    file.write_all(pem_string.as_bytes())?;
+
+    Ok(())
 }
 
 impl fmt::Debug for Seed {
@@ -159,18 +297,46 @@ pub enum Error {
     Rand(#[from] rand::Error),
     #[error("no default path")]
     NoDefaultPath,
+    #[error("seed file is encrypted, set the passphrase in {}", PASSPHRASE_ENV_VAR)]
+    PassphraseRequired,
+    #[error("failed to decrypt seed file, the passphrase is wrong or the file is corrupted")]
+    WrongPassphrase,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoin::hashes::hex::ToHex;
     use std::env::temp_dir;
+    use tempfile::tempdir;
 
     #[test]
     fn generate_random_seed() {
         let _ = Seed::random().unwrap();
     }
 
+    #[test]
+    fn pbkdf2_matches_test_vectors() {
+        for (rounds, expected) in &[
+            (
+                1,
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            ),
+            (
+                2,
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+            ),
+            (
+                4096,
+                "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            ),
+        ] {
+            let key = pbkdf2_hmac_sha256(b"password", b"salt", *rounds);
+
+            assert_eq!(key.to_hex(), *expected);
+        }
+    }
+
     #[test]
     fn seed_byte_string_must_be_32_bytes_long() {
         let _seed = Seed::from(*b"this string is exactly 32 bytes!");
@@ -242,4 +408,37 @@ mbKANv2qKGmNVg1qtquj6Hx1pFPelpqOfE2JaJJAMEg1FlFhNRNlFlE=
         let rinsed = Seed::from_file(tmpfile).expect("Read from temp file");
         assert_eq!(seed.0, rinsed.0);
     }
+
+    #[test]
+    fn round_trip_through_encrypted_file() {
+        let tmpdir = tempdir().unwrap();
+        let tmpfile = tmpdir.path().join("seed.pem");
+
+        let seed = Seed::random().unwrap();
+        seed.write_encrypted_to(tmpfile.clone(), "correct horse battery staple")
+            .unwrap();
+
+        let decrypted =
+            Seed::from_file_with_passphrase(tmpfile, Some("correct horse battery staple")).unwrap();
+        assert_eq!(seed, decrypted);
+    }
+
+    #[test]
+    fn encrypted_file_cannot_be_read_with_wrong_or_missing_passphrase() {
+        let tmpdir = tempdir().unwrap();
+        let tmpfile = tmpdir.path().join("seed.pem");
+
+        let seed = Seed::random().unwrap();
+        seed.write_encrypted_to(tmpfile.clone(), "correct horse battery staple")
+            .unwrap();
+
+        assert!(matches!(
+            Seed::from_file_with_passphrase(tmpfile.clone(), Some("wrong passphrase")),
+            Err(Error::WrongPassphrase)
+        ));
+        assert!(matches!(
+            Seed::from_file(tmpfile),
+            Err(Error::PassphraseRequired)
+        ));
+    }
 }
@@ -1,9 +1,39 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::Subscriber;
 use tracing_log::LogTracer;
 use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::FmtSubscriber;
 
-pub fn init_tracing(level: LevelFilter) -> Result<()> {
+/// The format of the emitted log lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Human-readable log lines, meant for interactive use.
+    Human,
+    /// One JSON object per log line, meant for log aggregators.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            other => bail!("Unknown log format {}, expected human or json", other),
+        }
+    }
+}
+
+/// Install a global tracing subscriber emitting logs in the given format.
+///
+/// Logs are written to stderr unless a file is given.
+pub fn init_tracing(level: LevelFilter, format: Format, file: Option<&Path>) -> Result<()> {
     if level == LevelFilter::OFF {
         return Ok(());
     }
@@ -11,21 +41,144 @@ pub fn init_tracing(level: LevelFilter) -> Result<()> {
     // We want upstream library log messages, just only at Info level.
     LogTracer::init_with_filter(tracing_log::log::LevelFilter::Info)?;
 
-    let is_terminal = atty::is(atty::Stream::Stderr);
+    match (format, file) {
+        (Format::Human, None) => {
+            let is_terminal = atty::is(atty::Stream::Stderr);
+
+            let builder = FmtSubscriber::builder()
+                .with_env_filter(env_filter(level))
+                .with_writer(std::io::stderr)
+                .with_ansi(is_terminal)
+                .with_target(false);
 
-    let builder = FmtSubscriber::builder()
-        .with_env_filter(format!("asb={},swap={}", level, level))
-        .with_writer(std::io::stderr)
-        .with_ansi(is_terminal)
-        .with_target(false);
+            if !is_terminal {
+                tracing::subscriber::set_global_default(builder.without_time().finish())?;
+            } else {
+                tracing::subscriber::set_global_default(builder.finish())?;
+            }
+        }
+        (Format::Human, Some(path)) => {
+            let file = open_log_file(path)?;
+            let subscriber = FmtSubscriber::builder()
+                .with_env_filter(env_filter(level))
+                .with_writer(file)
+                .with_ansi(false)
+                .with_target(false)
+                .finish();
 
-    if !is_terminal {
-        builder.without_time().init();
-    } else {
-        builder.init();
+            tracing::subscriber::set_global_default(subscriber)?;
+        }
+        (Format::Json, None) => {
+            tracing::subscriber::set_global_default(json_subscriber(level, std::io::stderr))?;
+        }
+        (Format::Json, Some(path)) => {
+            let file = open_log_file(path)?;
+            let subscriber = json_subscriber(level, file);
+
+            tracing::subscriber::set_global_default(subscriber)?;
+        }
     }
 
     tracing::info!("Initialized tracing with level: {}", level);
 
     Ok(())
 }
+
+fn json_subscriber<W>(level: LevelFilter, make_writer: W) -> impl Subscriber + Send + Sync
+where
+    W: MakeWriter + Send + Sync + 'static,
+{
+    FmtSubscriber::builder()
+        .with_env_filter(env_filter(level))
+        .with_writer(make_writer)
+        .json()
+        .with_current_span(true)
+        .with_span_list(false)
+        .finish()
+}
+
+fn env_filter(level: LevelFilter) -> String {
+    format!("asb={},swap={}", level, level)
+}
+
+fn open_log_file(path: &Path) -> Result<LogFile> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path.display()))?;
+
+    Ok(LogFile(file))
+}
+
+/// Hands out a handle to the log file for every log line.
+///
+/// If the handle cannot be cloned, e.g. because the process ran out of file
+/// descriptors, the line is written to stderr instead of being lost or
+/// aborting the process.
+struct LogFile(File);
+
+impl MakeWriter for LogFile {
+    type Writer = Box<dyn io::Write>;
+
+    fn make_writer(&self) -> Self::Writer {
+        match self.0.try_clone() {
+            Ok(file) => Box::new(file),
+            Err(_) => Box::new(io::stderr()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_log_line_contains_message_and_swap_id() {
+        let buffer = Buffer::default();
+        let swap_id = Uuid::new_v4();
+
+        let subscriber = json_subscriber(LevelFilter::DEBUG, {
+            let buffer = buffer.clone();
+            move || buffer.clone()
+        });
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("swap", id = %swap_id);
+            let _enter = span.enter();
+
+            tracing::info!("Hello from the swap");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line = output.lines().next().expect("at least one log line");
+        let json = serde_json::from_str::<serde_json::Value>(line).unwrap();
+
+        assert_eq!(json["fields"]["message"], "Hello from the swap");
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["span"]["id"], swap_id.to_string());
+    }
+
+    #[test]
+    fn parses_log_format() {
+        assert_eq!(Format::from_str("human").unwrap(), Format::Human);
+        assert_eq!(Format::from_str("json").unwrap(), Format::Json);
+        assert!(Format::from_str("xml").is_err());
+    }
+}
@@ -0,0 +1,63 @@
+pub mod testutils;
+
+use swap::protocol::alice::AliceState;
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+use testutils::bob_run_until::is_btc_locked;
+use testutils::SlowPunishConfig;
+
+/// Bob locks Btc and goes offline. Alice locks Xmr and cancels but has
+/// punishing disabled, she ends the swap without publishing the punish
+/// transaction once the punish timelock expires. Bob can still refund.
+#[tokio::test]
+async fn alice_declines_to_punish_if_punishing_is_disabled() {
+    testutils::setup_test_with_asb(
+        SlowPunishConfig,
+        |event_loop| event_loop.with_punish(false),
+        |mut ctx| async move {
+            let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+            let bitcoin_wallet = bob_swap.bitcoin_wallet.clone();
+            let bob_state = bob::run_until(bob_swap, is_btc_locked).await?;
+            let state6 = if let BobState::BtcLocked(state3) = &bob_state {
+                state3.cancel()
+            } else {
+                panic!("Bob in unexpected state {}", bob_state);
+            };
+            bob_join_handle.abort();
+
+            let alice_swap = ctx.alice_next_swap().await;
+            let alice_swap_id = alice_swap.swap_id;
+            let alice_db = alice_swap.db.clone();
+            let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+            ctx.mine_bitcoin_blocks(SlowPunishConfig::CANCEL_TIMELOCK)
+                .await?;
+            testutils::wait_for_cancel_seen(&state6, &bitcoin_wallet).await?;
+            ctx.mine_bitcoin_blocks(SlowPunishConfig::PUNISH_TIMELOCK)
+                .await?;
+
+            let alice_state = alice_swap.await??;
+            ctx.assert_alice_declined_punish(alice_state).await;
+
+            let stored_state =
+                AliceState::from(alice_db.get_state(alice_swap_id)?.try_into_alice()?);
+            assert!(matches!(stored_state, AliceState::BtcPunishDeclined));
+
+            let (bob_swap, _) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+            assert!(matches!(bob_swap.state, BobState::BtcLocked { .. }));
+
+            let bob_state = bob::refund(
+                bob_swap.swap_id,
+                bob_swap.state,
+                bob_swap.bitcoin_wallet,
+                bob_swap.db,
+                true,
+            )
+            .await??;
+            ctx.assert_bob_refunded(bob_state).await;
+
+            Ok(())
+        },
+    )
+    .await;
+}
@@ -0,0 +1,122 @@
+pub mod testutils;
+
+use std::time::Duration;
+use swap::database::Swap;
+use swap::monero::Amount;
+use swap::protocol::alice::AliceState;
+use swap::protocol::{alice, bob};
+use testutils::bob_run_until::{is_btc_locked, is_execution_setup_done};
+use testutils::SlowCancelConfig;
+
+/// Bob never locks the Bitcoin and Alice's swap is stuck in setup, the
+/// operator aborts it.
+#[tokio::test]
+async fn given_btc_not_locked_alice_force_aborts() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_state = bob::run_until(bob_swap, is_execution_setup_done).await?;
+        assert!(is_execution_setup_done(&bob_state));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let swap_id = alice_swap.swap_id;
+        alice_swap
+            .db
+            .insert_latest_state(swap_id, Swap::Alice((&alice_swap.state).into()))
+            .await?;
+
+        let alice_state = alice::force_abort(
+            swap_id,
+            alice_swap.state,
+            alice_swap.bitcoin_wallet.as_ref(),
+            &alice_swap.db,
+        )
+        .await?;
+        assert!(matches!(alice_state, AliceState::SafelyAborted));
+
+        let stored_state = AliceState::from(alice_swap.db.get_state(swap_id)?.try_into_alice()?);
+        assert!(matches!(stored_state, AliceState::SafelyAborted));
+
+        Ok(())
+    })
+    .await
+}
+
+/// The ASB is running while the operator requests to abort the swap that
+/// waits for Bob to lock the Bitcoin.
+#[tokio::test]
+async fn given_btc_not_locked_alice_aborts_running_swap_on_request() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_state = bob::run_until(bob_swap, is_execution_setup_done).await?;
+        assert!(is_execution_setup_done(&bob_state));
+
+        let mut alice_swap = ctx.alice_next_swap().await;
+        let swap_id = alice_swap.swap_id;
+        let abort_dir = tempfile::tempdir()?;
+        alice_swap.abort_dir = Some(abort_dir.path().to_path_buf());
+        alice::request_abort(abort_dir.path(), swap_id)?;
+
+        let alice_state = alice::run(alice_swap).await?;
+        assert!(matches!(alice_state, AliceState::SafelyAborted));
+        assert!(!abort_dir.path().join(swap_id.to_string()).exists());
+
+        Ok(())
+    })
+    .await
+}
+
+/// Bob never locks the Bitcoin, Alice safely aborts the swap once the lock
+/// deadline passed and no longer reserves the Monero for it.
+#[tokio::test]
+async fn given_btc_not_locked_before_deadline_alice_aborts_and_releases_monero() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_state = bob::run_until(bob_swap, is_execution_setup_done).await?;
+        assert!(is_execution_setup_done(&bob_state));
+
+        let mut alice_swap = ctx.alice_next_swap().await;
+        let swap_id = alice_swap.swap_id;
+        let db = alice_swap.db.clone();
+        alice_swap.env_config.bitcoin_lock_deadline = Duration::from_secs(1);
+        db.insert_latest_state(swap_id, Swap::Alice((&alice_swap.state).into()))
+            .await?;
+        assert!(alice_swap.state.reserved_xmr() > Amount::ZERO);
+
+        let alice_state = alice::run(alice_swap).await?;
+        assert!(matches!(alice_state, AliceState::SafelyAborted));
+
+        let stored_state = AliceState::from(db.get_state(swap_id)?.try_into_alice()?);
+        assert!(matches!(stored_state, AliceState::SafelyAborted));
+        assert_eq!(stored_state.reserved_xmr(), Amount::ZERO);
+
+        Ok(())
+    })
+    .await
+}
+
+/// Bob locked the Bitcoin, aborting the swap would put his funds at risk.
+#[tokio::test]
+async fn given_btc_locked_alice_refuses_to_force_abort() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_state = bob::run_until(bob_swap, is_btc_locked).await?;
+        assert!(is_btc_locked(&bob_state));
+
+        let alice_swap = ctx.alice_next_swap().await;
+
+        let result = alice::force_abort(
+            alice_swap.swap_id,
+            alice_swap.state,
+            alice_swap.bitcoin_wallet.as_ref(),
+            &alice_swap.db,
+        )
+        .await;
+        assert!(
+            result.is_err(),
+            "swap with locked Bitcoin must not be aborted"
+        );
+
+        Ok(())
+    })
+    .await
+}
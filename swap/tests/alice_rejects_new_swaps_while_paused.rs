@@ -0,0 +1,47 @@
+pub mod testutils;
+
+use swap::bitcoin;
+use swap::protocol::{alice, bob};
+use testutils::SlowCancelConfig;
+use tokio::join;
+
+/// Alice is paused once a swap is set up, she rejects the spot price request
+/// of another Bob while the swap in flight completes.
+#[tokio::test]
+async fn given_alice_is_paused_new_swaps_are_rejected_while_in_flight_swap_completes() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+        ctx.pause_alice();
+
+        let (event_loop, mut other_bob) = ctx.new_bob_eventloop()?;
+        tokio::spawn(event_loop.run());
+        other_bob.dial().await?;
+        assert!(other_bob
+            .request_spot_price(bitcoin::Amount::from_sat(100_000))
+            .await
+            .is_err());
+
+        let (bob_state, alice_state) = join!(bob_swap, alice_swap);
+
+        ctx.assert_alice_redeemed(alice_state??).await;
+        ctx.assert_bob_redeemed(bob_state??).await;
+
+        ctx.resume_alice();
+
+        let (event_loop, mut other_bob) = ctx.new_bob_eventloop()?;
+        tokio::spawn(event_loop.run());
+        other_bob.dial().await?;
+        assert!(other_bob
+            .request_spot_price(bitcoin::Amount::from_sat(100_000))
+            .await
+            .is_ok());
+
+        Ok(())
+    })
+    .await;
+}
@@ -0,0 +1,46 @@
+pub mod testutils;
+
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+use testutils::bob_run_until::is_btc_locked;
+use testutils::FastCancelConfig;
+
+/// Bob locks Btc and abandons the swap right away. Abandoning waits for the
+/// cancel timelock, cancels and refunds in one go.
+#[tokio::test]
+async fn given_bob_abandons_after_btc_locked_bob_refunds() {
+    testutils::setup_test(FastCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run_until(bob_swap, is_btc_locked));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+        let bob_state = bob_swap.await??;
+        assert!(matches!(bob_state, BobState::BtcLocked { .. }));
+
+        let (bob_swap, bob_join_handle) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+        assert!(matches!(bob_swap.state, BobState::BtcLocked { .. }));
+
+        // Bob abandons without waiting for the cancel timelock to expire first
+        bob_join_handle.abort();
+        let bob_state = bob::abandon(
+            bob_swap.swap_id,
+            bob_swap.state,
+            bob_swap.bitcoin_wallet,
+            bob_swap.db,
+        )
+        .await?;
+
+        ctx.assert_bob_refunded(bob_state).await;
+
+        let (bob_swap, _) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+        assert!(matches!(bob_swap.state, BobState::BtcRefunded { .. }));
+
+        let alice_state = alice_swap.await??;
+        ctx.assert_alice_refunded(alice_state).await;
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,32 @@
+pub mod testutils;
+
+use swap::protocol::alice::AliceState;
+use swap::protocol::{alice, bob};
+use testutils::SlowCancelConfig;
+use time::OffsetDateTime;
+
+/// The deadline for locking the Bitcoin passes before Bob gets to lock it,
+/// both parties abort the swap without funds having moved.
+#[tokio::test]
+async fn given_lock_deadline_passed_bob_aborts_without_locking() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = bob::Swap {
+            lock_deadline: Some(OffsetDateTime::unix_epoch()),
+            ..bob_swap
+        };
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+        let bob_state = bob_swap.await??;
+        ctx.assert_bob_safely_aborted(bob_state).await;
+
+        let alice_state = alice_swap.await??;
+        assert!(matches!(alice_state, AliceState::SafelyAborted));
+
+        Ok(())
+    })
+    .await
+}
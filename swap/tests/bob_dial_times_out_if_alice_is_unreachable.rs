@@ -0,0 +1,45 @@
+pub mod testutils;
+
+use libp2p::core::identity::Keypair;
+use libp2p::PeerId;
+use std::time::{Duration, Instant};
+use swap::error::SwapError;
+use swap::protocol::bob;
+use testutils::SlowCancelConfig;
+
+const DIAL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bob is given an address nobody listens on, the swap fails once the dial
+/// timeout elapsed instead of waiting for Alice forever.
+#[tokio::test]
+async fn given_alice_is_unreachable_bob_fails_within_dial_timeout() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        bob_join_handle.abort();
+
+        let (event_loop, event_loop_handle) = bob::EventLoop::new(
+            &Keypair::generate_ed25519(),
+            PeerId::random(),
+            "/ip4/127.0.0.1/tcp/1".parse()?,
+            bob_swap.bitcoin_wallet.clone(),
+            bob_swap.env_config,
+        )?;
+        tokio::spawn(event_loop.run());
+        let bob_swap = bob::Swap {
+            event_loop_handle: event_loop_handle.with_dial_timeout(DIAL_TIMEOUT),
+            ..bob_swap
+        };
+
+        let started = Instant::now();
+        let error = bob::run(bob_swap).await.unwrap_err();
+
+        assert!(started.elapsed() < DIAL_TIMEOUT + Duration::from_secs(5));
+        assert!(matches!(error, SwapError::Network(_)));
+        assert!(error
+            .to_string()
+            .contains("Could not reach maker within 5s"));
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,40 @@
+pub mod testutils;
+
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+use testutils::SlowCancelConfig;
+use tokio::join;
+
+#[tokio::test]
+async fn given_happy_path_bob_records_only_the_published_transactions() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let swap_id = bob_swap.swap_id;
+        let db = bob_swap.db.clone();
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+        let (bob_state, alice_state) = join!(bob_swap, alice_swap);
+        let bob_state = bob_state??;
+
+        let tx_lock_id = match &bob_state {
+            BobState::XmrRedeemed { tx_lock_id } => *tx_lock_id,
+            state => panic!("Bob in unexpected state {}", state),
+        };
+
+        ctx.assert_alice_redeemed(alice_state??).await;
+        ctx.assert_bob_redeemed(bob_state).await;
+
+        let transactions = db.bitcoin_transactions(swap_id)?;
+        assert_eq!(transactions.lock, Some(tx_lock_id));
+        assert!(transactions.redeem.is_some());
+        assert_eq!(transactions.cancel, None);
+        assert_eq!(transactions.refund, None);
+        assert_eq!(transactions.punish, None);
+
+        Ok(())
+    })
+    .await;
+}
@@ -0,0 +1,61 @@
+pub mod testutils;
+
+use swap::bitcoin::TransactionKind;
+use swap::cli::recover::recover;
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob, RecoveryAction};
+use testutils::bob_run_until::is_btc_locked;
+use testutils::FastCancelConfig;
+
+#[tokio::test]
+async fn given_dry_run_bob_recover_only_describes_the_cancel() {
+    testutils::setup_test(FastCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run_until(bob_swap, is_btc_locked));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let _ = tokio::spawn(alice::run(alice_swap));
+
+        let bob_state = bob_swap.await??;
+        assert!(matches!(bob_state, BobState::BtcLocked { .. }));
+
+        let (bob_swap, bob_join_handle) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+        bob_join_handle.abort();
+
+        let state6 = if let BobState::BtcLocked(state3) = bob_swap.state.clone() {
+            state3
+                .wait_for_cancel_timelock_to_expire(&*bob_swap.bitcoin_wallet)
+                .await?;
+            state3.cancel()
+        } else {
+            panic!("Bob in unexpected state {}", bob_swap.state);
+        };
+
+        let plan = recover(
+            bob_swap.swap_id,
+            bob_swap.state,
+            bob_swap.bitcoin_wallet.clone(),
+            bob_swap.db,
+            true,
+        )
+        .await?;
+
+        assert_eq!(plan.action, Some(RecoveryAction::PublishCancel));
+        assert_eq!(plan.broadcasts, vec![(
+            TransactionKind::Cancel,
+            state6.tx_cancel_id()
+        )]);
+        assert!(plan
+            .to_string()
+            .contains("Action: publish the cancel transaction"));
+
+        let tx_cancel_status = state6.tx_cancel_status(&*bob_swap.bitcoin_wallet).await?;
+        assert!(!tx_cancel_status.has_been_seen());
+
+        let (bob_swap, _) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+        assert!(matches!(bob_swap.state, BobState::BtcLocked { .. }));
+
+        Ok(())
+    })
+    .await
+}
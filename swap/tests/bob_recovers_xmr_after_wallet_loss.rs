@@ -0,0 +1,51 @@
+pub mod testutils;
+
+use swap::protocol::bob::BobState;
+use swap::protocol::{alice, bob};
+use testutils::bob_run_until::is_btc_redeemed;
+use testutils::SlowCancelConfig;
+
+#[tokio::test]
+async fn given_generated_wallet_is_lost_bob_recovers_xmr() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        let bob_swap = tokio::spawn(bob::run_until(bob_swap, is_btc_redeemed));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+        let bob_state = bob_swap.await??;
+        assert!(matches!(bob_state, BobState::BtcRedeemed { .. }));
+
+        let (bob_swap, bob_join_handle) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+        bob_join_handle.abort();
+
+        // Bob claims the Monero but loses the generated wallet before sweeping it
+        if let BobState::BtcRedeemed(state5) = bob_swap.state.clone() {
+            state5.claim_xmr(&*bob_swap.monero_wallet, None).await?;
+        } else {
+            panic!("Bob in unexpected state {}", bob_swap.state);
+        }
+        bob_swap.monero_wallet.re_open().await?;
+        ctx.delete_generated_monero_wallets_of_bob()?;
+
+        let bob_state = bob::recover_xmr(
+            bob_swap.swap_id,
+            bob_swap.state,
+            bob_swap.monero_wallet.as_ref(),
+            bob_swap.env_config,
+            bob_swap.receive_monero_address,
+            None,
+            bob_swap.db,
+        )
+        .await?;
+
+        ctx.assert_bob_redeemed(bob_state).await;
+
+        let alice_state = alice_swap.await??;
+        ctx.assert_alice_redeemed(alice_state).await;
+
+        Ok(())
+    })
+    .await
+}
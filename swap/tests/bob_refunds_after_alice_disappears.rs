@@ -0,0 +1,32 @@
+pub mod testutils;
+
+use swap::protocol::bob;
+use swap::protocol::bob::BobState;
+use testutils::bob_run_until::is_btc_locked;
+use testutils::SlowCancelConfig;
+
+/// Bob locks Btc but Alice never locks Xmr. Once the chain advanced past the
+/// cancel timelock, Bob cancels and refunds on his own.
+#[tokio::test]
+async fn given_alice_disappears_after_btc_locked_bob_refunds() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+        let bob_state = bob::run_until(bob_swap, is_btc_locked).await?;
+        assert!(matches!(bob_state, BobState::BtcLocked { .. }));
+
+        // Alice's swap is never run, she does not respond from here on
+        let _alice_swap = ctx.alice_next_swap().await;
+
+        let (bob_swap, _) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+        assert!(matches!(bob_swap.state, BobState::BtcLocked { .. }));
+
+        ctx.mine_bitcoin_blocks(SlowCancelConfig::CANCEL_TIMELOCK)
+            .await?;
+
+        let bob_state = bob::run(bob_swap).await?;
+        ctx.assert_bob_refunded(bob_state).await;
+
+        Ok(())
+    })
+    .await;
+}
@@ -22,7 +22,7 @@ async fn given_bob_manually_refunds_after_btc_locked_bob_refunds() {
         // Ensure Bob's timelock is expired
         if let BobState::BtcLocked(state3) = bob_swap.state.clone() {
             state3
-                .wait_for_cancel_timelock_to_expire(bob_swap.bitcoin_wallet.as_ref())
+                .wait_for_cancel_timelock_to_expire(&*bob_swap.bitcoin_wallet)
                 .await?;
         } else {
             panic!("Bob in unexpected state {}", bob_swap.state);
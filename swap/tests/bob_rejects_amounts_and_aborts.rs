@@ -0,0 +1,43 @@
+pub mod testutils;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use swap::protocol::alice::AliceState;
+use swap::protocol::bob::{SwapAmounts, VerifyAmounts};
+use swap::protocol::{alice, bob};
+use testutils::SlowCancelConfig;
+
+struct RejectAmounts;
+
+#[async_trait]
+impl VerifyAmounts for RejectAmounts {
+    async fn accept(&self, _: SwapAmounts) -> bool {
+        false
+    }
+}
+
+/// Bob rejects the amounts offered by Alice before locking any Bitcoin, both
+/// parties abort the swap without funds having moved.
+#[tokio::test]
+async fn given_bob_rejects_amounts_swap_is_safely_aborted() {
+    testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+        let (bob_swap, _) = ctx.bob_swap().await;
+        let bob_swap = bob::Swap {
+            verifier: Arc::new(RejectAmounts),
+            ..bob_swap
+        };
+        let bob_swap = tokio::spawn(bob::run(bob_swap));
+
+        let alice_swap = ctx.alice_next_swap().await;
+        let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+        let bob_state = bob_swap.await??;
+        ctx.assert_bob_safely_aborted(bob_state).await;
+
+        let alice_state = alice_swap.await??;
+        assert!(matches!(alice_state, AliceState::SafelyAborted));
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,118 @@
+pub mod testutils;
+
+use swap::protocol::{alice, bob};
+use testutils::bob_run_until::*;
+use testutils::SlowCancelConfig;
+
+/// Bob stops in the given state and resumes from the database while Alice
+/// keeps going, the swap completes as usual.
+///
+/// `Started` is not covered because it is never persisted, a swap that stops
+/// before the execution setup is done cannot be resumed.
+macro_rules! resume_and_redeem {
+    ($test:ident, $is_state:path) => {
+        #[tokio::test]
+        async fn $test() {
+            testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+                let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+                let bob_swap = tokio::spawn(bob::run_until(bob_swap, $is_state));
+
+                let alice_swap = ctx.alice_next_swap().await;
+                let alice_swap = tokio::spawn(alice::run(alice_swap));
+
+                let bob_state = bob_swap.await??;
+                assert!($is_state(&bob_state), "Bob stopped in state {}", bob_state);
+
+                let (bob_swap, _) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+                assert!(
+                    $is_state(&bob_swap.state),
+                    "Bob resumed in state {}",
+                    bob_swap.state
+                );
+
+                let bob_state = bob::run(bob_swap).await?;
+                ctx.assert_bob_redeemed(bob_state).await;
+
+                let alice_state = alice_swap.await??;
+                ctx.assert_alice_redeemed(alice_state).await;
+
+                Ok(())
+            })
+            .await;
+        }
+    };
+}
+
+/// Bob locks Btc but Alice never locks Xmr. Bob stops in the given state
+/// after the cancel timelock expired and resumes from the database, the swap
+/// is refunded.
+macro_rules! resume_and_refund {
+    ($test:ident, $is_state:path) => {
+        #[tokio::test]
+        async fn $test() {
+            testutils::setup_test(SlowCancelConfig, |mut ctx| async move {
+                let (bob_swap, bob_join_handle) = ctx.bob_swap().await;
+                let bob_state = bob::run_until(bob_swap, is_btc_locked).await?;
+                assert!(is_btc_locked(&bob_state));
+
+                // Alice's swap is never run, she does not respond from here on
+                let _alice_swap = ctx.alice_next_swap().await;
+
+                let (bob_swap, bob_join_handle) =
+                    ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+                ctx.mine_bitcoin_blocks(SlowCancelConfig::CANCEL_TIMELOCK)
+                    .await?;
+
+                let bob_state = bob::run_until(bob_swap, $is_state).await?;
+                assert!($is_state(&bob_state), "Bob stopped in state {}", bob_state);
+
+                let (bob_swap, _) = ctx.stop_and_resume_bob_from_db(bob_join_handle).await;
+                assert!(
+                    $is_state(&bob_swap.state),
+                    "Bob resumed in state {}",
+                    bob_swap.state
+                );
+
+                let bob_state = bob::run(bob_swap).await?;
+                ctx.assert_bob_refunded(bob_state).await;
+
+                Ok(())
+            })
+            .await;
+        }
+    };
+}
+
+resume_and_redeem!(
+    given_bob_restarts_after_execution_setup_swap_completes,
+    is_execution_setup_done
+);
+resume_and_redeem!(
+    given_bob_restarts_after_btc_locked_swap_completes,
+    is_btc_locked
+);
+resume_and_redeem!(
+    given_bob_restarts_after_lock_proof_received_swap_completes,
+    is_lock_proof_received
+);
+resume_and_redeem!(
+    given_bob_restarts_after_xmr_locked_swap_completes,
+    is_xmr_locked
+);
+resume_and_redeem!(
+    given_bob_restarts_after_encsig_sent_swap_completes,
+    is_encsig_sent
+);
+resume_and_redeem!(
+    given_bob_restarts_after_btc_redeemed_swap_completes,
+    is_btc_redeemed
+);
+
+resume_and_refund!(
+    given_bob_restarts_after_cancel_timelock_expired_swap_refunds,
+    is_cancel_timelock_expired
+);
+resume_and_refund!(
+    given_bob_restarts_after_btc_cancelled_swap_refunds,
+    is_btc_cancelled
+);
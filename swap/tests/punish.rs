@@ -5,7 +5,7 @@ use swap::protocol::{alice, bob};
 use testutils::bob_run_until::is_btc_locked;
 use testutils::FastPunishConfig;
 
-/// Bob locks Btc and Alice locks Xmr. Bob does not act; he fails to send Alice
+/// Bob locks Btc and Alice locks Xmr. Bob goes offline; he fails to send Alice
 /// the encsig and fail to refund or redeem. Alice punishes.
 #[tokio::test]
 async fn alice_punishes_if_bob_never_acts_after_fund() {
@@ -18,6 +18,7 @@ async fn alice_punishes_if_bob_never_acts_after_fund() {
 
         let bob_state = bob_swap.await??;
         assert!(matches!(bob_state, BobState::BtcLocked { .. }));
+        bob_join_handle.abort();
 
         let alice_state = alice_swap.await??;
         ctx.assert_alice_punished(alice_state).await;
@@ -2,7 +2,7 @@ mod bitcoind;
 mod electrs;
 
 use crate::testutils;
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use bitcoin_harness::{BitcoindRpcApi, Client};
 use futures::Future;
 use get_port::get_port;
@@ -13,8 +13,7 @@ use std::convert::Infallible;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use swap::asb::FixedRate;
-use swap::bitcoin::{CancelTimelock, PunishTimelock};
+use swap::asb::{FixedRate, PauseSwitch, PeerAllowlist};
 use swap::database::Database;
 use swap::env::{Config, GetConfig};
 use swap::protocol::alice::{AliceState, Swap};
@@ -76,6 +75,7 @@ impl BobParams {
             self.alice_peer_id,
             self.alice_address.clone(),
             self.bitcoin_wallet.clone(),
+            self.env_config,
         )
     }
 }
@@ -98,18 +98,79 @@ pub struct TestContext {
     alice_bitcoin_wallet: Arc<bitcoin::Wallet>,
     alice_monero_wallet: Arc<monero::Wallet>,
     alice_swap_handle: mpsc::Receiver<Swap>,
+    alice_pause: PauseSwitch,
 
     bob_params: BobParams,
     bob_starting_balances: StartingBalances,
     bob_bitcoin_wallet: Arc<bitcoin::Wallet>,
     bob_monero_wallet: Arc<monero::Wallet>,
+
+    bitcoind_url: Url,
 }
 
 impl TestContext {
+    /// Mine the given number of blocks right away, on top of the block mined
+    /// every second, e.g. to let a timelock expire.
+    pub async fn mine_bitcoin_blocks(&self, count: u32) -> Result<()> {
+        let bitcoind_client = Client::new(self.bitcoind_url.clone());
+        let reward_address = bitcoind_client
+            .with_wallet(BITCOIN_TEST_WALLET_NAME)?
+            .getnewaddress(None, None)
+            .await?;
+
+        bitcoind_client
+            .generatetoaddress(count, reward_address, None)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete the wallets Bob's monero-wallet-rpc generated from keys, i.e.
+    /// all wallet files but the ones of his default wallet.
+    pub fn delete_generated_monero_wallets_of_bob(&self) -> Result<()> {
+        let status = std::process::Command::new("docker")
+            .args(&[
+                "exec",
+                MONERO_WALLET_NAME_BOB,
+                "sh",
+                "-c",
+                &format!(
+                    "find / -xdev -path '*/{0}/*' -type f ! -name '{0}' ! -name '{0}.keys' -delete",
+                    MONERO_WALLET_NAME_BOB
+                ),
+            ])
+            .status()?;
+
+        if !status.success() {
+            bail!("Failed to delete generated wallets: {}", status);
+        }
+
+        Ok(())
+    }
+
     pub async fn alice_next_swap(&mut self) -> alice::Swap {
         self.alice_swap_handle.recv().await.unwrap()
     }
 
+    /// Let Alice reject new swaps, the ones in flight continue.
+    pub fn pause_alice(&self) {
+        self.alice_pause.pause()
+    }
+
+    pub fn resume_alice(&self) {
+        self.alice_pause.resume()
+    }
+
+    /// An event loop to Alice under a new identity, independent of the swap
+    /// of Bob.
+    pub fn new_bob_eventloop(&self) -> Result<(bob::EventLoop, bob::EventLoopHandle)> {
+        BobParams {
+            seed: Seed::random()?,
+            ..self.bob_params.clone()
+        }
+        .new_eventloop()
+    }
+
     pub async fn bob_swap(&mut self) -> (bob::Swap, BobEventLoopJoinHandle) {
         let (event_loop, event_loop_handle) = self.bob_params.new_eventloop().unwrap();
 
@@ -202,6 +263,15 @@ impl TestContext {
         );
     }
 
+    pub async fn assert_alice_declined_punish(&self, state: AliceState) {
+        assert!(matches!(state, AliceState::BtcPunishDeclined));
+
+        self.alice_bitcoin_wallet.sync().await.unwrap();
+
+        let btc_balance_after_swap = self.alice_bitcoin_wallet.as_ref().balance().await.unwrap();
+        assert_eq!(btc_balance_after_swap, self.alice_starting_balances.btc);
+    }
+
     pub async fn assert_alice_punished(&self, state: AliceState) {
         assert!(matches!(state, AliceState::BtcPunished));
 
@@ -313,13 +383,37 @@ impl TestContext {
         let xmr_balance_after_swap = self.bob_monero_wallet.as_ref().get_balance().await.unwrap();
         assert_eq!(xmr_balance_after_swap, self.bob_starting_balances.xmr);
     }
+
+    pub async fn assert_bob_safely_aborted(&self, state: BobState) {
+        assert!(matches!(state, BobState::SafelyAborted));
+
+        self.bob_bitcoin_wallet.sync().await.unwrap();
+
+        let btc_balance_after_swap = self.bob_bitcoin_wallet.as_ref().balance().await.unwrap();
+        assert_eq!(btc_balance_after_swap, self.bob_starting_balances.btc);
+
+        let xmr_balance_after_swap = self.bob_monero_wallet.as_ref().get_balance().await.unwrap();
+        assert_eq!(xmr_balance_after_swap, self.bob_starting_balances.xmr);
+    }
 }
 
-pub async fn setup_test<T, F, C>(_config: C, testfn: T)
+pub async fn setup_test<T, F, C>(config: C, testfn: T)
 where
     T: Fn(TestContext) -> F,
     F: Future<Output = Result<()>>,
     C: GetConfig,
+{
+    setup_test_with_asb(config, |event_loop| event_loop, testfn).await
+}
+
+/// Like [`setup_test`], configuring Alice's event loop the way the ASB does
+/// from its config file.
+pub async fn setup_test_with_asb<T, F, C, A>(_config: C, configure_asb: A, testfn: T)
+where
+    T: Fn(TestContext) -> F,
+    F: Future<Output = Result<()>>,
+    C: GetConfig,
+    A: FnOnce(alice::EventLoop) -> alice::EventLoop,
 {
     let cli = Cli::default();
 
@@ -375,7 +469,7 @@ where
 
     let (bob_bitcoin_wallet, bob_monero_wallet) = init_test_wallets(
         MONERO_WALLET_NAME_BOB,
-        containers.bitcoind_url,
+        containers.bitcoind_url.clone(),
         &monero,
         bob_starting_balances.clone(),
         tempdir().unwrap().path(),
@@ -393,11 +487,15 @@ where
         alice_monero_wallet.clone(),
         alice_db,
         FixedRate::default(),
+        bitcoin::Amount::ZERO,
         bitcoin::Amount::ONE_BTC,
+        PeerAllowlist::default(),
     )
     .unwrap();
+    let alice_event_loop = configure_asb(alice_event_loop);
 
     let alice_peer_id = alice_event_loop.peer_id();
+    let alice_pause = alice_event_loop.pause_switch();
 
     tokio::spawn(alice_event_loop.run());
 
@@ -419,10 +517,12 @@ where
         alice_bitcoin_wallet,
         alice_monero_wallet,
         alice_swap_handle,
+        alice_pause,
         bob_params,
         bob_starting_balances,
         bob_bitcoin_wallet,
         bob_monero_wallet,
+        bitcoind_url: containers.bitcoind_url.clone(),
     };
 
     testfn(test).await.unwrap()
@@ -514,6 +614,27 @@ pub async fn init_electrs_container(
     Ok(docker)
 }
 
+/// Wait until the cancel transaction of Bob's swap has been seen, failing if
+/// nobody publishes it in time.
+pub async fn wait_for_cancel_seen(
+    state6: &bob::State6,
+    bitcoin_wallet: &bitcoin::Wallet,
+) -> Result<()> {
+    tokio::time::timeout(Duration::from_secs(120), async {
+        while !state6
+            .tx_cancel_status(bitcoin_wallet)
+            .await?
+            .has_been_seen()
+        {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        Ok(())
+    })
+    .await
+    .context("Cancel transaction was not seen in time")?
+}
+
 async fn mine(bitcoind_client: Client, reward_address: bitcoin::Address) -> Result<()> {
     loop {
         tokio::time::sleep(Duration::from_secs(1)).await;
@@ -609,7 +730,7 @@ async fn init_test_wallets(
     };
 
     let btc_wallet = swap::bitcoin::Wallet::new(
-        electrum_rpc_url,
+        &[electrum_rpc_url],
         datadir,
         seed.derive_extended_private_key(env_config.bitcoin_network)
             .expect("Could not create extended private key from seed"),
@@ -693,6 +814,10 @@ pub mod alice_run_until {
 pub mod bob_run_until {
     use swap::protocol::bob::BobState;
 
+    pub fn is_execution_setup_done(state: &BobState) -> bool {
+        matches!(state, BobState::ExecutionSetupDone(..))
+    }
+
     pub fn is_btc_locked(state: &BobState) -> bool {
         matches!(state, BobState::BtcLocked(..))
     }
@@ -708,16 +833,29 @@ pub mod bob_run_until {
     pub fn is_encsig_sent(state: &BobState) -> bool {
         matches!(state, BobState::EncSigSent(..))
     }
+
+    pub fn is_btc_redeemed(state: &BobState) -> bool {
+        matches!(state, BobState::BtcRedeemed(..))
+    }
+
+    pub fn is_cancel_timelock_expired(state: &BobState) -> bool {
+        matches!(state, BobState::CancelTimelockExpired(..))
+    }
+
+    pub fn is_btc_cancelled(state: &BobState) -> bool {
+        matches!(state, BobState::BtcCancelled(..))
+    }
 }
 
 pub struct SlowCancelConfig;
 
+impl SlowCancelConfig {
+    pub const CANCEL_TIMELOCK: u32 = 180;
+}
+
 impl GetConfig for SlowCancelConfig {
     fn get_config() -> Config {
-        Config {
-            bitcoin_cancel_timelock: CancelTimelock::new(180),
-            ..env::Regtest::get_config()
-        }
+        env::Regtest::with_timelocks(Self::CANCEL_TIMELOCK, env::Regtest::PUNISH_TIMELOCK)
     }
 }
 
@@ -725,10 +863,7 @@ pub struct FastCancelConfig;
 
 impl GetConfig for FastCancelConfig {
     fn get_config() -> Config {
-        Config {
-            bitcoin_cancel_timelock: CancelTimelock::new(1),
-            ..env::Regtest::get_config()
-        }
+        env::Regtest::with_timelocks(1, env::Regtest::PUNISH_TIMELOCK)
     }
 }
 
@@ -736,10 +871,19 @@ pub struct FastPunishConfig;
 
 impl GetConfig for FastPunishConfig {
     fn get_config() -> Config {
-        Config {
-            bitcoin_cancel_timelock: CancelTimelock::new(1),
-            bitcoin_punish_timelock: PunishTimelock::new(1),
-            ..env::Regtest::get_config()
-        }
+        env::Regtest::with_timelocks(1, 1)
+    }
+}
+
+pub struct SlowPunishConfig;
+
+impl SlowPunishConfig {
+    pub const CANCEL_TIMELOCK: u32 = 180;
+    pub const PUNISH_TIMELOCK: u32 = 180;
+}
+
+impl GetConfig for SlowPunishConfig {
+    fn get_config() -> Config {
+        env::Regtest::with_timelocks(Self::CANCEL_TIMELOCK, Self::PUNISH_TIMELOCK)
     }
 }